@@ -1,8 +1,11 @@
 use rpg_battle_monitor_lib::{
     cdn::filesystem::local_adapter::Local,
-    webserver::router::{
-        app_state::{AppState, AppStateConfig},
-        battle_monitor_server::BattleMonitorWebServer,
+    webserver::{
+        router::{
+            app_state::{AppState, AppStateConfig},
+            battle_monitor_server::BattleMonitorWebServer,
+        },
+        services::shutdown::{ShutdownHandle, ShutdownState},
     },
 };
 use tauri::Manager;
@@ -22,27 +25,53 @@ pub fn run() {
         .setup(|app| {
             let db = db_file_path(app);
             let assets = assets_file_path(app);
+            let journal = entity_journal_path(app);
             std::env::set_var("DATABASE_URL", format!("sqlite://{db}"));
             std::env::set_var("ASSETS_BASE_PATH", assets);
+            std::env::set_var("ENTITY_JOURNAL_PATH", journal);
+
+            let shutdown = ShutdownHandle::new();
+            app.manage(shutdown.clone());
 
             tauri::async_runtime::spawn(async move {
-                start_server().await;
+                start_server(shutdown).await;
             });
 
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![greet])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::ExitRequested { api, .. } = event {
+                // Keep the app alive until the last edits are saved
+                let shutdown = app.state::<ShutdownHandle>().inner().clone();
+                if shutdown.state() == ShutdownState::Finished {
+                    return;
+                }
+
+                api.prevent_exit();
+                let app = app.clone();
+                tauri::async_runtime::spawn(async move {
+                    shutdown.shutdown();
+                    shutdown.finished().await;
+                    app.exit(0);
+                });
+            }
+        });
 }
 
-async fn start_server() {
+async fn start_server(shutdown: ShutdownHandle) {
     tracing_subscriber::fmt()
         .with_max_level(LevelFilter::DEBUG)
         .with_writer(std::io::stderr)
         .init();
 
-    let state: AppState<Local> = AppState::new(AppStateConfig::get_default_config().await).await;
+    let config = AppStateConfig {
+        shutdown,
+        ..AppStateConfig::get_default_config().await
+    };
+    let state: AppState<Local> = AppState::new(config).await;
 
     BattleMonitorWebServer::new(state).serve(None).await;
 }
@@ -64,3 +93,12 @@ fn assets_file_path(app: &tauri::App) -> String {
         .expect("Assets file path")
         .to_string()
 }
+
+fn entity_journal_path(app: &tauri::App) -> String {
+    app.path()
+        .resolve("entity-journal/", tauri::path::BaseDirectory::AppData)
+        .expect("Entity journal path")
+        .to_str()
+        .expect("Entity journal path")
+        .to_string()
+}
@@ -1,11 +1,14 @@
 import { AxiosProgressEvent } from "axios";
 import { AssetPostRequests, axiosInstance } from "./axios_config";
+import { USER_TOKEN, USER_TOKEN_HEADER } from "../utils/utils";
 
 export type AssetUploadResponse = {
     url: string;
     filename: string;
     thumbnails: string[];
     originalFilename: string;
+    animated: boolean;
+    poster?: string;
 };
 
 export const uploadAsset = async (
@@ -22,6 +25,7 @@ export const uploadAsset = async (
         {
             headers: {
                 "Content-Type": "multipart/form-data",
+                [USER_TOKEN_HEADER]: USER_TOKEN,
             },
             onUploadProgress: onProgress,
         },
@@ -1,6 +1,9 @@
 import { connectionStore } from "../stores/connection_store";
 import { GAtomStore } from "../stores/state_store";
 
+export const USER_TOKEN_HEADER = "x-user-token";
+export const USER_TOKEN = "some-session-token";
+
 export const getBaseUrl = (): string => {
     return (
         GAtomStore.get(connectionStore.getConnectionInfo)?.baseUrl ??
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Action } from "./Action";
+import type { ClientsideEntity } from "./ClientsideEntity";
+
+export type ActionMessage = { action: Action, data: Array<ClientsideEntity>, };
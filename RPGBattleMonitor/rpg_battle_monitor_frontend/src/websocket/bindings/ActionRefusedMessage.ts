@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Action } from "./Action";
+import type { UId } from "./UId";
+
+/**
+ * Sent back to the socket with entities it was not allowed to change
+ */
+export type ActionRefusedMessage = { action: Action, uids: Array<UId>, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { EntityKind } from "./EntityKind";
+import type { UId } from "./UId";
+import type { UtcTimestamp } from "./UtcTimestamp";
+
+export type ClientsideEntity = { uid: UId, kind: EntityKind, timestamp: UtcTimestamp, };
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Action } from "./Action";
+import type { EntityKind } from "./EntityKind";
+import type { UId } from "./UId";
+import type { UtcTimestamp } from "./UtcTimestamp";
+
+export type Entity = { uid: UId, game: number, kind: EntityKind, timestamp: UtcTimestamp, action: Action | null, };
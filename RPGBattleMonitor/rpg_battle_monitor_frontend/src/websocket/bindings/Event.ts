@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type Event = "protocol" | "join" | "join-finished" | "join-cancelled" | "leave" | "left" | "switch-game" | "switch-game-refused" | "action" | "action-refused" | "presence" | "lock" | "unlock" | "lock-state" | "server-shutting-down";
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Entity } from "./Entity";
+import type { JoinProgress } from "./JoinProgress";
+
+export type JoinChunk = { progress: JoinProgress, data: Array<Entity>, };
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { UId } from "./UId";
+
+/**
+ * Payload of both lock and unlock events
+ */
+export type LockMessage = { uid: UId, };
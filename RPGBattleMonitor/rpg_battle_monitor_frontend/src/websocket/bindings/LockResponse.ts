@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LockState } from "./LockState";
+
+/**
+ * Acknowledgement of a lock or unlock event
+ */
+export type LockResponse = { granted: boolean, state: LockState, };
@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { UId } from "./UId";
+
+export type LockState = { uid: UId, 
+/**
+ * Socket id of the lock holder, entity is unlocked when missing
+ */
+holder: string | null, expiresInMs: number | null, };
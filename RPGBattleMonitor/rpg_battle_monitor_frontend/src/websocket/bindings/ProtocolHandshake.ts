@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Sent to the client right after it connects
+ */
+export type ProtocolHandshake = { version: number, };
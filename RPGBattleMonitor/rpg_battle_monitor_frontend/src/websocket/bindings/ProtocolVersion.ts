@@ -0,0 +1,3 @@
+// This file was generated by `export_protocol_version` test. Do not edit this file manually.
+
+export const PROTOCOL_VERSION = 2;
@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ConnectionRole } from "./ConnectionRole";
+
+export type WebsocketAuthMessage = { userToken: string, game: number, role?: ConnectionRole, 
+/**
+ * Version of the socket protocol the client was built against
+ */
+protocolVersion?: number, };
@@ -1,14 +1,24 @@
 import { io, ManagerOptions, Socket, SocketOptions } from "socket.io-client";
 import { GBoard, GEventEmitter } from "../board_core/board";
 import { IMessagable, TypedJson } from "../board_core/interfaces/messagable";
-import { getBaseUrl } from "@/board_react_wrapper/utils/utils";
-
-export type JoinData = {
+import { getBaseUrl, USER_TOKEN } from "@/board_react_wrapper/utils/utils";
+
+import type { ActionRefusedMessage } from "./bindings/ActionRefusedMessage";
+import type { Event } from "./bindings/Event";
+import type { JoinChunk } from "./bindings/JoinChunk";
+import type { JoinChunkAck } from "./bindings/JoinChunkAck";
+import type { LockMessage } from "./bindings/LockMessage";
+import type { LockResponse } from "./bindings/LockResponse";
+import type { LockState } from "./bindings/LockState";
+import type { Presence } from "./bindings/Presence";
+import type { ProtocolHandshake } from "./bindings/ProtocolHandshake";
+import type { SwitchGameMessage } from "./bindings/SwitchGameMessage";
+import type { ConnectionRole } from "./bindings/ConnectionRole";
+import type { WebsocketAuthMessage } from "./bindings/WebsocketAuthMessage";
+import { PROTOCOL_VERSION } from "./bindings/ProtocolVersion";
+
+export type JoinData = Omit<JoinChunk, "data"> & {
     data: TypedJson[];
-    progress: {
-        sent: number;
-        total: number;
-    };
 };
 
 export enum WebsocketQueues {
@@ -20,16 +30,32 @@ export enum WebsocketQueues {
 type QueueMap = Record<keyof typeof WebsocketQueues, IMessagable[]>;
 
 export type ListenEvents = {
+    protocol: (handshake: ProtocolHandshake) => void;
     "join-finished": () => void;
-    join: (joinData: JoinData) => void;
+    "join-cancelled": () => void;
+    join: (joinData: JoinData, ack: (response: JoinChunkAck) => void) => void;
     action: (data: ActionMessageListen) => void;
+    "action-refused": (refused: ActionRefusedMessage) => void;
+    "lock-state": (state: LockState) => void;
+    presence: (presence: Presence) => void;
+    left: (gameId: number) => void;
+    "switch-game-refused": (gameId: number) => void;
+    "server-shutting-down": () => void;
 };
 
 export type EmitEvents = {
     join: () => void;
+    leave: () => void;
+    "switch-game": (data: SwitchGameMessage) => void;
     action: (data: ActionMessageEmit) => void;
+    lock: (data: LockMessage, ack: (response: LockResponse) => void) => void;
+    unlock: (data: LockMessage, ack: (response: LockResponse) => void) => void;
 };
 
+// Fails to compile when an event name does not exist on the server
+type AssertEvents<T extends Event> = T;
+export type ServerEvents = AssertEvents<keyof ListenEvents | keyof EmitEvents>;
+
 export type ActionMessageEmit = {
     action: WebsocketQueues;
     data: IMessagable[];
@@ -43,6 +69,7 @@ export type ActionMessageListen = {
 export class Websocket {
     protected _socket: Socket<ListenEvents, EmitEvents>;
     public joined: boolean = false;
+    protected joinCancelled: boolean = false;
 
     protected actionQueue: ActionMessageEmit[] = [];
 
@@ -51,19 +78,35 @@ export class Websocket {
         opts?: Partial<ManagerOptions & SocketOptions>,
     ) {
         const socket = io(uri, opts);
+        socket.once("protocol", (handshake: ProtocolHandshake) => {
+            if (handshake.version !== PROTOCOL_VERSION) {
+                console.warn(
+                    `Server uses protocol version ${handshake.version}, client uses ${PROTOCOL_VERSION}`,
+                );
+            }
+        });
+        socket.on("server-shutting-down", () => {
+            this.joined = false;
+            console.warn("Server is shutting down");
+        });
 
         this._socket = socket;
     }
 
-    public static createDefaultSocket(gameId: number): Websocket {
+    public static createDefaultSocket(
+        gameId: number,
+        role: ConnectionRole = "player",
+    ): Websocket {
         const websocketProtocol =
             window.location.protocol === "https:" ? "wss" : "ws";
         return new Websocket(`${websocketProtocol}://${getBaseUrl()}`, {
             path: "/api/socket.io",
             auth: {
-                userToken: "some-session-token",
+                userToken: USER_TOKEN,
                 game: gameId,
-            },
+                role,
+                protocolVersion: PROTOCOL_VERSION,
+            } satisfies WebsocketAuthMessage,
         });
     }
 
@@ -95,26 +138,74 @@ export class Websocket {
         this.actionQueue = [];
     }
 
-    public initJoin() {
+    public initJoin(startJoin: () => void = () => this.socket.emit("join")) {
         GEventEmitter.emit("socket-join-started");
-        const join = (joinData: JoinData) => {
+        this.joinCancelled = false;
+
+        const join = (
+            joinData: JoinData,
+            ack: (response: JoinChunkAck) => void,
+        ) => {
+            if (this.joinCancelled) {
+                ack({ cancel: true });
+                return;
+            }
+
             GEventEmitter.emit("socket-join", joinData);
             GBoard.entityRegistry.queue(joinData.data);
+            ack({ cancel: false });
         };
 
         const joinFinished = () => {
             this.joined = true;
             this.socket.off("join", join);
+            this.socket.off("join-cancelled", joinCancelled);
             GBoard.entityRegistry.convertQueuedEntities();
 
             GEventEmitter.emit("socket-join-finished");
         };
 
+        const joinCancelled = () => {
+            this.socket.off("join", join);
+            this.socket.off("join-finished", joinFinished);
+        };
+
         this.socket.once("join-finished", joinFinished);
+        this.socket.once("join-cancelled", joinCancelled);
 
         this.socket.on("join", join);
 
-        this.socket.emit("join");
+        startJoin();
+    }
+
+    public leave() {
+        this.joined = false;
+        this.socket.emit("leave");
+    }
+
+    /**
+     * Switches to another game on the same connection, join events
+     * for the new game follow the same flow as `initJoin`
+     */
+    public switchGame(gameId: number) {
+        this.joined = false;
+        this.initJoin(() => this.socket.emit("switch-game", { game: gameId }));
+    }
+
+    /**
+     * Requests an exclusive lease on the entity, updates and deletes of the
+     * entity from other sockets are refused while the lease is held
+     */
+    public lock(uid: string): Promise<LockResponse> {
+        return this.socket.emitWithAck("lock", { uid });
+    }
+
+    public unlock(uid: string): Promise<LockResponse> {
+        return this.socket.emitWithAck("unlock", { uid });
+    }
+
+    public cancelJoin() {
+        this.joinCancelled = true;
     }
 
     public clear(queue: keyof QueueMap) {
@@ -0,0 +1,94 @@
+use std::{hint::black_box, sync::Arc};
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use rpg_battle_monitor_lib::{
+    api::websockets::Action,
+    database::setup::{create_database, run_migrations},
+    entity::{Entity, UId, UtcTimestamp, kind::EntityKind},
+    models::game::GameManager,
+    webserver::services::entity_queues::EntityQueues,
+};
+use sea_orm::{Database, DatabaseConnection};
+
+const ENTITIES_PER_GAME: usize = 64;
+const ACTIONS_PER_GAME: usize = 8;
+const MAX_GAMES: i32 = 256;
+
+/// Games get the ids `1..=MAX_GAMES`, queues are only created for existing games
+async fn create_database_connection() -> DatabaseConnection {
+    const URL: &str = "sqlite::memory:";
+    create_database(URL).await;
+
+    let db = Database::connect(URL).await.unwrap();
+    let mut pool = db.get_sqlite_connection_pool().clone();
+    run_migrations(&mut pool).await;
+
+    for _ in 0..MAX_GAMES {
+        GameManager::new().create_game(&db).await.unwrap();
+    }
+
+    db
+}
+
+fn entities(game: i32, action: usize) -> Vec<Entity> {
+    let shared_action = Arc::new(Action::Update);
+
+    (0..ENTITIES_PER_GAME / ACTIONS_PER_GAME)
+        .map(|i| Entity {
+            uid: UId(format!("{action}-{i}")),
+            game,
+            kind: EntityKind("token".to_string()),
+            timestamp: UtcTimestamp(action as i64),
+            action: Some(shared_action.clone()),
+            other_values: serde_json::json!({ "x": i, "y": action }),
+        })
+        .collect()
+}
+
+/// Every game pushes its actions concurrently and is flushed like on a join
+async fn push_and_flush(queues: Arc<EntityQueues>, games: i32) {
+    let tasks = (1..=games).map(|game| {
+        let queues = queues.clone();
+        tokio::spawn(async move {
+            for action in 0..ACTIONS_PER_GAME {
+                let queue = queues.get(game).await.unwrap();
+                queue.lock().await.push_all(entities(game, action)).unwrap();
+            }
+
+            queues.flush_game(game).await;
+        })
+    });
+
+    for task in futures_util::future::join_all(tasks).await {
+        task.unwrap();
+    }
+}
+
+fn concurrent_games(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let db = runtime.block_on(create_database_connection());
+
+    let mut group = c.benchmark_group("entity_queues");
+    for games in [1, 8, 64, MAX_GAMES] {
+        group.throughput(Throughput::Elements(
+            games as u64 * ENTITIES_PER_GAME as u64,
+        ));
+        group.bench_with_input(
+            BenchmarkId::new("push_and_flush", games),
+            &games,
+            |b, &games| {
+                b.to_async(&runtime).iter(|| {
+                    let queues = Arc::new(EntityQueues::new(db.clone()));
+                    push_and_flush(black_box(queues), games)
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_games);
+criterion_main!(benches);
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS entity_idx_deleted_at;
+
+DELETE FROM entity WHERE deleted_at IS NOT NULL;
+ALTER TABLE entity DROP COLUMN deleted_at;
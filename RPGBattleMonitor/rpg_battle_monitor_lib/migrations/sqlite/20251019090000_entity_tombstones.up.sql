@@ -0,0 +1,3 @@
+ALTER TABLE entity ADD COLUMN deleted_at INTEGER;
+
+CREATE INDEX IF NOT EXISTS entity_idx_deleted_at ON entity (deleted_at);
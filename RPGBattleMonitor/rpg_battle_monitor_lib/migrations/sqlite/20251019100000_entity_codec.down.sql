@@ -0,0 +1,19 @@
+DROP INDEX IF EXISTS entity_idx_codec;
+
+-- Payloads that are not gzipped can not be read without the codec column, the rollback
+-- fails while there are any. Run the server with ENTITY_CODEC=gzip until the recompressor
+-- has rewritten them
+CREATE TEMP TABLE entity_codec_rollback (
+    codec TEXT NOT NULL
+    , CONSTRAINT entities_have_to_be_recompressed_to_gzip CHECK (codec = 'gzip')
+);
+INSERT INTO entity_codec_rollback SELECT codec FROM entity WHERE codec != 'gzip' AND deleted_at IS NULL LIMIT 1;
+DROP TABLE entity_codec_rollback;
+
+-- Tombstones are not recompressed and can not be read anymore
+DELETE FROM entity WHERE codec != 'gzip' AND deleted_at IS NOT NULL;
+ALTER TABLE entity DROP COLUMN dictionary;
+ALTER TABLE entity DROP COLUMN codec;
+
+DROP INDEX IF EXISTS entity_dictionary_idx_kind;
+DROP TABLE IF EXISTS entity_dictionary;
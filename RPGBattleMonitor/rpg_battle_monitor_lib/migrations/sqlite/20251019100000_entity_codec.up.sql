@@ -0,0 +1,14 @@
+CREATE TABLE entity_dictionary (
+    id INTEGER PRIMARY KEY AUTOINCREMENT
+    , kind TEXT NOT NULL
+    , data BLOB NOT NULL
+    , created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS entity_dictionary_idx_kind ON entity_dictionary (kind);
+
+-- Payloads written before codecs existed are gzipped
+ALTER TABLE entity ADD COLUMN codec TEXT NOT NULL DEFAULT 'gzip';
+ALTER TABLE entity ADD COLUMN dictionary INTEGER REFERENCES entity_dictionary (id);
+
+CREATE INDEX IF NOT EXISTS entity_idx_codec ON entity (codec);
@@ -0,0 +1,2 @@
+-- Generated field columns depend on the document and have to be dropped first
+ALTER TABLE entity DROP COLUMN document;
@@ -0,0 +1,3 @@
+-- Uncompressed copy of the kind specific values of kinds with indexed fields,
+-- the indexed field columns are generated from it at startup
+ALTER TABLE entity ADD COLUMN document TEXT;
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS assets_idx_created_at;
+DROP INDEX IF EXISTS thumbnails_idx_asset_id;
+
+ALTER TABLE thumbnails DROP COLUMN asset_id;
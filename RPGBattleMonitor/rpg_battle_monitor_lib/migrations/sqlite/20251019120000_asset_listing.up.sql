@@ -0,0 +1,5 @@
+-- Thumbnails created before this migration are not linked to their asset
+ALTER TABLE thumbnails ADD COLUMN asset_id INTEGER REFERENCES assets(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS thumbnails_idx_asset_id ON thumbnails (asset_id);
+CREATE INDEX IF NOT EXISTS assets_idx_created_at ON assets (created_at, id);
@@ -0,0 +1,3 @@
+-- Set by the asset garbage collector when an asset is found unreferenced,
+-- the asset is deleted once it stays unreferenced for the grace period
+ALTER TABLE assets ADD COLUMN unreferenced_since TIMESTAMP;
@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS asset_entry_idx_asset_id;
+DROP INDEX IF EXISTS asset_entry_idx_folder;
+DROP INDEX IF EXISTS asset_entry_idx_owner_game_folder;
+DROP TABLE IF EXISTS asset_entry;
+
+DROP INDEX IF EXISTS asset_folder_idx_parent;
+DROP INDEX IF EXISTS asset_folder_idx_owner_game_parent;
+DROP TABLE IF EXISTS asset_folder;
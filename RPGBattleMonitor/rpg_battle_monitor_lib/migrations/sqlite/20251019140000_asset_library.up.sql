@@ -0,0 +1,26 @@
+CREATE TABLE asset_folder (
+    id INTEGER PRIMARY KEY AUTOINCREMENT
+    , owner TEXT NOT NULL
+    , game INTEGER REFERENCES game (id) ON DELETE CASCADE
+    , parent INTEGER REFERENCES asset_folder (id) ON DELETE CASCADE
+    , name TEXT NOT NULL
+    , created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS asset_folder_idx_owner_game_parent ON asset_folder (owner, game, parent);
+CREATE INDEX IF NOT EXISTS asset_folder_idx_parent ON asset_folder (parent);
+
+-- Asset as seen by its owner, owners uploading the same content share the asset
+CREATE TABLE asset_entry (
+    id INTEGER PRIMARY KEY AUTOINCREMENT
+    , owner TEXT NOT NULL
+    , game INTEGER REFERENCES game (id) ON DELETE CASCADE
+    , folder INTEGER REFERENCES asset_folder (id) ON DELETE CASCADE
+    , asset_id INTEGER NOT NULL REFERENCES assets (id) ON DELETE CASCADE
+    , name TEXT NOT NULL
+    , created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS asset_entry_idx_owner_game_folder ON asset_entry (owner, game, folder);
+CREATE INDEX IF NOT EXISTS asset_entry_idx_folder ON asset_entry (folder);
+CREATE INDEX IF NOT EXISTS asset_entry_idx_asset_id ON asset_entry (asset_id);
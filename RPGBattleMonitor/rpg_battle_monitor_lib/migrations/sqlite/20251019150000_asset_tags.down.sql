@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS asset_tags_idx_tag_id;
+DROP TABLE IF EXISTS asset_tags;
+
+DROP TABLE IF EXISTS tags;
@@ -0,0 +1,12 @@
+CREATE TABLE tags (
+    id INTEGER PRIMARY KEY AUTOINCREMENT
+    , name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE asset_tags (
+    asset_id INTEGER NOT NULL REFERENCES assets (id) ON DELETE CASCADE
+    , tag_id INTEGER NOT NULL REFERENCES tags (id) ON DELETE CASCADE
+    , PRIMARY KEY (asset_id, tag_id)
+);
+
+CREATE INDEX IF NOT EXISTS asset_tags_idx_tag_id ON asset_tags (tag_id);
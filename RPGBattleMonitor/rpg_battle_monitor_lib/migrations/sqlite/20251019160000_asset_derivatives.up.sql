@@ -0,0 +1,7 @@
+CREATE TABLE asset_derivatives (
+    id INTEGER PRIMARY KEY AUTOINCREMENT
+    , source_hash TEXT NOT NULL
+    , transform TEXT NOT NULL
+    , asset_id INTEGER NOT NULL REFERENCES assets (id) ON DELETE CASCADE
+    , UNIQUE (source_hash, transform)
+);
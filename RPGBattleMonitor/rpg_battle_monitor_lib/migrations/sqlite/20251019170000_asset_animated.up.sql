@@ -0,0 +1 @@
+ALTER TABLE assets ADD COLUMN animated BOOLEAN NOT NULL DEFAULT FALSE;
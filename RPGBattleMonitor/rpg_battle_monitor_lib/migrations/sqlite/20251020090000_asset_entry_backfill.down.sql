@@ -0,0 +1 @@
+DELETE FROM asset_entry WHERE owner = 'legacy';
@@ -0,0 +1,10 @@
+-- Files uploaded before the asset library existed go into the library of the legacy owner,
+-- otherwise they are listed for nobody and collected as unreferenced
+INSERT INTO asset_entry (owner, asset_id, name, created_at)
+SELECT 'legacy', assets.id, COALESCE(assets.original_filename, assets.name), assets.created_at
+FROM assets
+WHERE assets.asset_type = 'file'
+    AND NOT EXISTS (SELECT 1 FROM asset_entry WHERE asset_entry.asset_id = assets.id);
+
+UPDATE assets SET unreferenced_since = NULL
+WHERE id IN (SELECT asset_id FROM asset_entry WHERE owner = 'legacy');
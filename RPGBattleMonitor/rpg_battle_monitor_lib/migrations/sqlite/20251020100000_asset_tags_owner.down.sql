@@ -0,0 +1,15 @@
+CREATE TABLE asset_tags_of_asset (
+    asset_id INTEGER NOT NULL REFERENCES assets (id) ON DELETE CASCADE
+    , tag_id INTEGER NOT NULL REFERENCES tags (id) ON DELETE CASCADE
+    , PRIMARY KEY (asset_id, tag_id)
+);
+
+INSERT INTO asset_tags_of_asset (asset_id, tag_id)
+SELECT DISTINCT asset_id, tag_id FROM asset_tags;
+
+DROP INDEX IF EXISTS asset_tags_idx_owner_tag_id;
+DROP INDEX IF EXISTS asset_tags_idx_tag_id;
+DROP TABLE asset_tags;
+ALTER TABLE asset_tags_of_asset RENAME TO asset_tags;
+
+CREATE INDEX IF NOT EXISTS asset_tags_idx_tag_id ON asset_tags (tag_id);
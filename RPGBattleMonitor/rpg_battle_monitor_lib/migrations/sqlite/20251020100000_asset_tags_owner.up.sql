@@ -0,0 +1,22 @@
+-- Tags are part of the library of an owner, every owner of an asset gets the tags it had
+CREATE TABLE asset_tags_of_owner (
+    asset_id INTEGER NOT NULL REFERENCES assets (id) ON DELETE CASCADE
+    , owner TEXT NOT NULL
+    , tag_id INTEGER NOT NULL REFERENCES tags (id) ON DELETE CASCADE
+    , PRIMARY KEY (asset_id, owner, tag_id)
+);
+
+INSERT INTO asset_tags_of_owner (asset_id, owner, tag_id)
+SELECT DISTINCT asset_tags.asset_id, asset_entry.owner, asset_tags.tag_id
+FROM asset_tags
+INNER JOIN asset_entry ON asset_entry.asset_id = asset_tags.asset_id;
+
+DROP INDEX IF EXISTS asset_tags_idx_tag_id;
+DROP TABLE asset_tags;
+ALTER TABLE asset_tags_of_owner RENAME TO asset_tags;
+
+CREATE INDEX IF NOT EXISTS asset_tags_idx_tag_id ON asset_tags (tag_id);
+CREATE INDEX IF NOT EXISTS asset_tags_idx_owner_tag_id ON asset_tags (owner, tag_id);
+
+-- Tags of assets without owner are gone
+DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM asset_tags);
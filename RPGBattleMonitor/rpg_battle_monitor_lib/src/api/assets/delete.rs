@@ -0,0 +1,276 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use sea_orm::TransactionTrait;
+
+use crate::{
+    api::error::{Error, Result},
+    cdn::filesystem::Adapter,
+    models::{asset_entry::AssetEntryManager, assets::AssetManager},
+    webserver::{
+        extractors::{
+            database_connection_extractor::DbConn, local_fs_extractor::FSAdapter,
+            user_extractor::CurrentUser,
+        },
+        router::app_state::AppStateTrait,
+        services::asset_gc::{AssetGc, AssetGcReport},
+    },
+};
+
+#[cfg(feature = "api_doc")]
+mod doc {
+    use utoipa::OpenApi;
+
+    use crate::api::assets::delete;
+
+    #[derive(OpenApi)]
+    #[openapi(paths(delete::delete_asset, delete::gc_report))]
+    pub(crate) struct ApiDoc;
+}
+#[cfg(feature = "api_doc")]
+pub(crate) use doc::ApiDoc;
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        delete,
+        description = "Removes the asset from the library of the user, the asset, its thumbnails and their files are deleted once it is in no library",
+        path = "/{id}",
+        responses(
+            (status = 204, description = "Asset was removed from the library of the user"),
+            (status = 401, description = "User token is missing"),
+            (status = 404, description = "Id does not match any asset in the library of the user")
+        ),
+        params(
+            ("id" = i32, description = "Id of the asset")
+        )
+    )
+)]
+pub async fn delete_asset<F: Adapter>(
+    conn: DbConn,
+    CurrentUser(owner): CurrentUser,
+    Path(id): Path<i32>,
+    asset_manager: AssetManager<F>,
+) -> Result<StatusCode> {
+    let transaction = conn.begin().await?;
+
+    let entry_manager = AssetEntryManager::new();
+    let removed = entry_manager
+        .delete_entries_of_asset(&transaction, &owner, id)
+        .await?;
+    if removed == 0 {
+        return Err(Error::FileNotFound { id: id.to_string() });
+    }
+
+    // The content is shared with the entries of other owners
+    let deleted = if entry_manager.has_entries(&transaction, id).await? {
+        Vec::new()
+    } else {
+        asset_manager.delete_asset(&transaction, id).await?
+    };
+
+    transaction.commit().await?;
+
+    asset_manager.delete_files(&deleted).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        description = "Report of the assets the garbage collector would delete, nothing is changed",
+        get,
+        path = "/gc/report",
+        responses(
+            (status = 200, description = "Dry run of the asset garbage collector", body = AssetGcReport),
+            (status = 401, description = "User token is missing"),
+            (status = 403, description = "User is not in `ASSET_GC_ADMINS`")
+        )
+    )
+)]
+pub async fn gc_report<T: AppStateTrait>(
+    State(state): State<T>,
+    CurrentUser(user): CurrentUser,
+) -> Result<Json<AssetGcReport>> {
+    // The report lists assets of every library
+    if !state.get_asset_gc_config().admins.contains(&user) {
+        return Err(Error::Forbidden);
+    }
+
+    let gc = AssetGc::new(
+        state.get_db(),
+        state.get_entity_codecs(),
+        AssetManager::new(FSAdapter::new(state.get_fs_handler())),
+        state.get_asset_gc_config(),
+    )
+    .with_dry_run(true);
+
+    Ok(Json(gc.run().await?))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use axum::{
+        Router,
+        http::{HeaderName, HeaderValue, StatusCode},
+    };
+
+    use crate::{
+        api::assets::delete::{delete_asset, gc_report},
+        cdn::filesystem::FileSystem,
+        models::{
+            asset_entry::AssetEntryManager,
+            assets::{AssetManager, AssetType},
+        },
+        utils::test_utils::{TEST_IMAGE_BYTES, get_app_state_with_temp_file_store, new_test_app},
+        webserver::{
+            extractors::user_extractor::CurrentUser,
+            router::app_state::{AppState, AppStateTrait},
+            services::asset_gc::AssetGcReport,
+        },
+    };
+
+    #[tokio::test]
+    async fn asset_is_deleted_with_thumbnails_when_no_library_has_it() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+        let asset_manager = AssetManager::from(state.clone());
+
+        let image = asset_manager
+            .create(
+                &db,
+                "goblin.png".to_string(),
+                TEST_IMAGE_BYTES,
+                AssetType::File,
+            )
+            .await
+            .unwrap();
+        let thumbnails = asset_manager
+            .create_thumbnail_assets(&db, &image, Some(TEST_IMAGE_BYTES))
+            .await
+            .unwrap();
+        assert!(!thumbnails.is_empty());
+        for owner in ["alice", "bob"] {
+            AssetEntryManager::new()
+                .create_entry(&db, owner, None, None, image.id, "Goblin".to_string())
+                .await
+                .unwrap();
+        }
+
+        let router = Router::new()
+            .route("/assets/{id}", axum::routing::delete(delete_asset))
+            .with_state(state.clone());
+        let server = new_test_app(router);
+        let path = format!("/assets/{}", image.id);
+        let user = |token: &str| {
+            (
+                HeaderName::from_static(CurrentUser::HEADER),
+                HeaderValue::from_str(token).unwrap(),
+            )
+        };
+        let exists = |name: String| {
+            let fs_handler = state.fs_handler.clone();
+            async move { fs_handler.file_exists(Path::new(&name)).await.unwrap() }
+        };
+
+        server
+            .delete(&path)
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        let (header, carol) = user("carol");
+        server
+            .delete(&path)
+            .add_header(header, carol)
+            .expect_failure()
+            .await;
+
+        // Bob still has the asset in the library
+        let (header, alice) = user("alice");
+        server
+            .delete(&path)
+            .add_header(header.clone(), alice.clone())
+            .await;
+        assert!(
+            asset_manager
+                .get_by_id(&db, image.id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(exists(image.name.clone()).await);
+        server
+            .delete(&path)
+            .add_header(header, alice)
+            .expect_failure()
+            .await;
+
+        let (header, bob) = user("bob");
+        server.delete(&path).add_header(header, bob).await;
+        for asset in thumbnails.iter().chain([&image]) {
+            assert!(
+                asset_manager
+                    .get_by_id(&db, asset.id)
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+            assert!(!exists(asset.name.clone()).await);
+        }
+        assert!(
+            asset_manager
+                .get_thumbnails(&db, image.id)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn gc_report_is_only_for_admins() {
+        let mut state = get_app_state_with_temp_file_store().await;
+        state.asset_gc_config.admins = vec!["admin".to_string()];
+        let db = state.get_db();
+        AssetManager::from(state.clone())
+            .create(
+                &db,
+                "goblin.png".to_string(),
+                TEST_IMAGE_BYTES,
+                AssetType::File,
+            )
+            .await
+            .unwrap();
+
+        let router = Router::new()
+            .route(
+                "/assets/gc/report",
+                axum::routing::get(gc_report::<AppState<_>>),
+            )
+            .with_state(state);
+        let server = new_test_app(router);
+        let header = HeaderName::from_static(CurrentUser::HEADER);
+
+        server
+            .get("/assets/gc/report")
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        let response = server
+            .get("/assets/gc/report")
+            .add_header(header.clone(), HeaderValue::from_static("alice"))
+            .expect_failure()
+            .await;
+        assert!(response.text().contains("Forbidden"));
+
+        let report = server
+            .get("/assets/gc/report")
+            .add_header(header, HeaderValue::from_static("admin"))
+            .await
+            .json::<AssetGcReport>();
+        assert!(report.dry_run);
+        assert_eq!(1, report.unreferenced.len());
+    }
+}
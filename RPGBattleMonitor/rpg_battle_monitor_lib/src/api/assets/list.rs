@@ -0,0 +1,395 @@
+use axum::{Json, extract::Query};
+use chrono::{DateTime, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{
+        assets::gen_partial_asset_url,
+        error::{Error, Result},
+    },
+    cdn::filesystem::Adapter,
+    models::{
+        assets::{Asset, AssetCursor, AssetManager, AssetQuery, AssetType, SortOrder},
+        tags::{TagManager, TagMatch},
+        thumbnails::Model as ThumbnailModel,
+    },
+    webserver::extractors::{database_connection_extractor::DbConn, user_extractor::CurrentUser},
+};
+
+#[cfg(feature = "api_doc")]
+use utoipa::{IntoParams, ToSchema};
+#[cfg(feature = "api_doc")]
+mod doc {
+    use utoipa::OpenApi;
+
+    use crate::api::assets::list;
+
+    #[derive(OpenApi)]
+    #[openapi(paths(list::list_assets))]
+    pub(crate) struct ApiDoc;
+}
+#[cfg(feature = "api_doc")]
+pub(crate) use doc::ApiDoc;
+
+const DEFAULT_LIMIT: u64 = 50;
+const MAX_LIMIT: u64 = 200;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[cfg_attr(feature = "api_doc", derive(IntoParams))]
+#[cfg_attr(feature = "api_doc", into_params(parameter_in = Query))]
+#[serde(rename_all = "camelCase")]
+pub struct ListAssetsQuery {
+    /// Cursor of the previous page
+    cursor: Option<String>,
+    /// Number of assets per page, at most 200
+    limit: Option<u64>,
+    /// Mime type prefix, e.g. `image/`
+    mime: Option<String>,
+    #[cfg_attr(feature = "api_doc", param(value_type = Option<String>, example = "file"))]
+    asset_type: Option<AssetType>,
+    /// Part of the original filename
+    search: Option<String>,
+    /// Comma separated tags, e.g. `undead,forest map`
+    tags: Option<String>,
+    /// Whether assets need all of the tags or any of them, all by default
+    #[cfg_attr(feature = "api_doc", param(value_type = Option<String>, example = "all"))]
+    tag_match: Option<TagMatch>,
+    /// Order by creation time, newest first by default
+    #[cfg_attr(feature = "api_doc", param(value_type = Option<String>, example = "desc"))]
+    order: Option<SortOrder>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct ThumbnailUrl {
+    #[cfg_attr(feature = "api_doc", schema(example = "512x512"))]
+    dimensions: String,
+    #[cfg_attr(feature = "api_doc", schema(example = "/api/assets/thumbnail-1.png"))]
+    url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct AssetListItem {
+    #[cfg_attr(feature = "api_doc", schema(example = 1))]
+    id: i32,
+    #[cfg_attr(feature = "api_doc", schema(example = "/api/assets/filename.png"))]
+    url: String,
+    #[cfg_attr(feature = "api_doc", schema(example = "filename.png"))]
+    filename: String,
+    #[cfg_attr(feature = "api_doc", schema(example = "goblin.png"))]
+    original_filename: String,
+    #[cfg_attr(feature = "api_doc", schema(example = "image/png"))]
+    mime: String,
+    #[cfg_attr(feature = "api_doc", schema(example = "file"))]
+    asset_type: String,
+    #[cfg_attr(feature = "api_doc", schema(value_type = String, example = "2025-07-02T21:03:30"))]
+    created_at: NaiveDateTime,
+    /// Image has several frames, the static poster is served with `?format=png`
+    animated: bool,
+    thumbnails: Vec<ThumbnailUrl>,
+    #[cfg_attr(feature = "api_doc", schema(example = json!(["forest map", "night"])))]
+    tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct AssetListResponse {
+    items: Vec<AssetListItem>,
+    /// Cursor of the next page, missing on the last page
+    #[cfg_attr(feature = "api_doc", schema(example = "1751490210000000_7"))]
+    next_cursor: Option<String>,
+}
+
+fn encode_cursor(cursor: AssetCursor) -> String {
+    format!(
+        "{}_{}",
+        cursor.created_at.and_utc().timestamp_micros(),
+        cursor.id
+    )
+}
+
+fn decode_cursor(cursor: &str) -> Result<AssetCursor> {
+    let invalid = || Error::InvalidCursor(cursor.to_string());
+
+    let (created_at, id) = cursor.split_once('_').ok_or_else(invalid)?;
+    let created_at = created_at
+        .parse::<i64>()
+        .ok()
+        .and_then(DateTime::from_timestamp_micros)
+        .ok_or_else(invalid)?;
+
+    Ok(AssetCursor {
+        created_at: created_at.naive_utc(),
+        id: id.parse().map_err(|_| invalid())?,
+    })
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        get,
+        path = "",
+        params(ListAssetsQuery),
+        responses(
+            (status = 200, description = "Page of the assets in the library of the user with their thumbnails", body = AssetListResponse),
+            (status = 400, description = "Cursor or tag is invalid"),
+            (status = 401, description = "User token is missing")
+        )
+    )
+)]
+pub async fn list_assets<F: Adapter>(
+    conn: DbConn,
+    CurrentUser(owner): CurrentUser,
+    asset_manager: AssetManager<F>,
+    Query(query): Query<ListAssetsQuery>,
+) -> Result<Json<AssetListResponse>> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let tag_manager = TagManager::new();
+    let tags = query
+        .tags
+        .as_deref()
+        .map(|tags| {
+            tags.split(',')
+                .filter(|tag| !tag.trim().is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let asset_query = AssetQuery {
+        mime_prefix: query.mime.filter(|mime| !mime.is_empty()),
+        asset_type: query.asset_type,
+        search: query.search.filter(|search| !search.is_empty()),
+        tags: tag_manager.normalize(&tags)?,
+        tag_match: query.tag_match.unwrap_or_default(),
+        owner: Some(owner.clone()),
+        order: query.order.unwrap_or_default(),
+        after: query.cursor.as_deref().map(decode_cursor).transpose()?,
+        // One more asset tells whether there is a next page
+        limit: limit + 1,
+    };
+
+    let mut assets = asset_manager
+        .list_assets(conn.as_ref(), &asset_query)
+        .await?;
+    let next_cursor = if assets.len() as u64 > limit {
+        assets.truncate(limit as usize);
+        assets.last().map(|asset| encode_cursor(asset.into()))
+    } else {
+        None
+    };
+
+    let asset_ids = assets.iter().map(|asset| asset.id).collect::<Vec<_>>();
+    let mut thumbnails = asset_manager
+        .get_thumbnails_of_assets(conn.as_ref(), &asset_ids)
+        .await?;
+    let mut tags = tag_manager
+        .get_tags_of_assets(conn.as_ref(), &owner, &asset_ids)
+        .await?;
+
+    let items = assets
+        .into_iter()
+        .map(|asset| {
+            let thumbnails = thumbnail_urls(thumbnails.remove(&asset.id).unwrap_or_default());
+            let tags = tags.remove(&asset.id).unwrap_or_default();
+
+            to_list_item(asset, thumbnails, tags)
+        })
+        .collect();
+
+    Ok(Json(AssetListResponse { items, next_cursor }))
+}
+
+/// Urls of the thumbnails returned by [`AssetManager::get_thumbnails_of_assets`]
+pub(crate) fn thumbnail_urls(thumbnails: Vec<(ThumbnailModel, Asset)>) -> Vec<ThumbnailUrl> {
+    thumbnails
+        .into_iter()
+        .map(|(thumbnail, thumbnail_asset)| ThumbnailUrl {
+            dimensions: thumbnail.dimensions,
+            url: gen_partial_asset_url(&thumbnail_asset.name),
+        })
+        .collect()
+}
+
+fn to_list_item(asset: Asset, thumbnails: Vec<ThumbnailUrl>, tags: Vec<String>) -> AssetListItem {
+    AssetListItem {
+        id: asset.id,
+        url: gen_partial_asset_url(&asset.name),
+        filename: asset.name,
+        original_filename: asset.original_filename,
+        mime: asset.mime,
+        asset_type: asset.asset_type,
+        created_at: asset.created_at,
+        animated: asset.animated,
+        thumbnails,
+        tags,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        Router,
+        http::{HeaderName, HeaderValue},
+    };
+    use sea_orm::TransactionTrait;
+
+    use crate::{
+        api::assets::list::{AssetListResponse, list_assets},
+        models::{
+            asset_entry::AssetEntryManager,
+            assets::{AssetManager, AssetType},
+        },
+        utils::test_utils::{
+            TEST_IMAGE_BYTES, TEST_PDF_BYTES, get_app_state_with_temp_file_store, new_test_app,
+        },
+        webserver::{extractors::user_extractor::CurrentUser, router::app_state::AppStateTrait},
+    };
+
+    #[tokio::test]
+    async fn assets_are_listed_in_pages() {
+        let state = get_app_state_with_temp_file_store().await;
+        let asset_manager = AssetManager::from(state.clone());
+        let db = state.get_db();
+
+        let transaction = db.begin().await.unwrap();
+        let image = asset_manager
+            .create(
+                &transaction,
+                "goblin.png".to_string(),
+                TEST_IMAGE_BYTES,
+                AssetType::File,
+            )
+            .await
+            .unwrap();
+        asset_manager
+            .create_thumbnail_assets(&transaction, &image, Some(TEST_IMAGE_BYTES))
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        let pdf = asset_manager
+            .create(
+                &db,
+                "rules.pdf".to_string(),
+                TEST_PDF_BYTES,
+                AssetType::File,
+            )
+            .await
+            .unwrap();
+        let notes = asset_manager
+            .create(
+                &db,
+                "notes.txt".to_string(),
+                b"goblin notes",
+                AssetType::File,
+            )
+            .await
+            .unwrap();
+        let other = asset_manager
+            .create(
+                &db,
+                "secret.txt".to_string(),
+                b"secret notes",
+                AssetType::File,
+            )
+            .await
+            .unwrap();
+        let entry_manager = AssetEntryManager::new();
+        for asset in [&image, &pdf, &notes] {
+            entry_manager
+                .create_entry(
+                    &db,
+                    "alice",
+                    None,
+                    None,
+                    asset.id,
+                    asset.original_filename.clone(),
+                )
+                .await
+                .unwrap();
+        }
+        // Assets in the library of other users are not listed
+        entry_manager
+            .create_entry(&db, "bob", None, None, other.id, "Secret".to_string())
+            .await
+            .unwrap();
+
+        let router = Router::new()
+            .route("/assets", axum::routing::get(list_assets))
+            .with_state(state);
+        let mut server = new_test_app(router);
+        server.get("/assets").expect_failure().await;
+        server.add_header(
+            HeaderName::from_static(CurrentUser::HEADER),
+            HeaderValue::from_static("alice"),
+        );
+
+        let mut listed = Vec::new();
+        let mut cursor = None::<String>;
+        loop {
+            let mut request = server
+                .get("/assets")
+                .add_query_param("assetType", "file")
+                .add_query_param("limit", 2);
+            if let Some(cursor) = &cursor {
+                request = request.add_query_param("cursor", cursor);
+            }
+
+            let page = request.await.json::<AssetListResponse>();
+            assert!(page.items.len() <= 2);
+            listed.extend(page.items);
+
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        // Newest first
+        let names = listed
+            .iter()
+            .map(|item| item.original_filename.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["notes.txt", "rules.pdf", "goblin.png"], names);
+        assert_eq!(3, listed[2].thumbnails.len());
+        assert!(
+            listed[2]
+                .thumbnails
+                .iter()
+                .all(|t| t.url.starts_with("/api/assets/"))
+        );
+
+        let images = server
+            .get("/assets")
+            .add_query_param("mime", "image/")
+            .add_query_param("assetType", "file")
+            .await
+            .json::<AssetListResponse>();
+        assert_eq!(1, images.items.len());
+        assert_eq!(image.id, images.items[0].id);
+
+        let searched = server
+            .get("/assets")
+            .add_query_param("search", "N")
+            .add_query_param("order", "asc")
+            .await
+            .json::<AssetListResponse>();
+        let names = searched
+            .items
+            .iter()
+            .map(|item| item.original_filename.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["goblin.png", "notes.txt"], names);
+
+        server
+            .get("/assets")
+            .add_query_param("cursor", "not-a-cursor")
+            .expect_failure()
+            .await;
+    }
+}
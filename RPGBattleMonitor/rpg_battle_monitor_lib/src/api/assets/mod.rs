@@ -2,10 +2,14 @@ use crate::webserver::router::app_state::AppStateTrait;
 use axum::{extract::DefaultBodyLimit, routing};
 use tower_http::limit::RequestBodyLimitLayer;
 
+pub mod delete;
+pub mod list;
 pub mod serve;
+pub mod tags;
 pub mod upload;
 
-const FILE_SIZE_LIMIT: usize = 10 * 1024 * 1024; // 10MB
+/// Room for the multipart boundaries and the other fields of an upload
+const MULTIPART_OVERHEAD: u64 = 1024 * 1024; // 1MB
 
 #[cfg(feature = "api_doc")]
 use utoipa::{Modify, OpenApi};
@@ -15,6 +19,9 @@ use utoipa::{Modify, OpenApi};
 #[openapi(info(description = "Assets API"), 
     modifiers(&ModifyDoc),
     nest(
+        (path = "/assets", api = list::ApiDoc, tags = ["List"]),
+        (path = "/assets", api = delete::ApiDoc, tags = ["Delete"]),
+        (path = "/assets", api = tags::ApiDoc, tags = ["Tags"]),
         (path = "/assets", api = upload::ApiDoc, tags = ["Upload"]),
         (path = "/assets", api = serve::ApiDoc, tags = ["Serve"])
     ))]
@@ -29,6 +36,9 @@ impl Modify for ModifyDoc {
 
         use crate::api::doc::taggroups::tag_groups_config;
 
+        tag_groups_config().add("Assets".to_string(), HashSet::from(["List".to_string()]));
+        tag_groups_config().add("Assets".to_string(), HashSet::from(["Delete".to_string()]));
+        tag_groups_config().add("Assets".to_string(), HashSet::from(["Tags".to_string()]));
         tag_groups_config().add("Assets".to_string(), HashSet::from(["Upload".to_string()]));
         tag_groups_config().add("Assets".to_string(), HashSet::from(["Serve".to_string()]));
     }
@@ -36,6 +46,7 @@ impl Modify for ModifyDoc {
 
 pub fn get_router<T: AppStateTrait>(state: T) -> axum::Router {
     axum::Router::new()
+        .route("/assets", routing::get(list::list_assets))
         .route(
             "/assets/upload",
             #[cfg(debug_assertions)]
@@ -51,9 +62,31 @@ pub fn get_router<T: AppStateTrait>(state: T) -> axum::Router {
             "/assets/thumbnails/{image_id}",
             routing::get(serve::thumbnails),
         )
-        .route("/assets/{filename}", routing::get(serve::serve_file))
+        .route("/assets/gc/report", routing::get(delete::gc_report::<T>))
+        .route("/assets/tags", routing::get(tags::list_tags))
+        .route(
+            "/assets/tags/{id}",
+            routing::get(tags::get_tags).post(tags::add_tags),
+        )
+        .route("/assets/tags/{id}/{tag}", routing::delete(tags::remove_tag))
+        .route(
+            "/assets/{filename}",
+            routing::get(serve::serve_file).delete(delete::delete_asset),
+        )
         .layer(DefaultBodyLimit::disable())
-        .layer(RequestBodyLimitLayer::new(FILE_SIZE_LIMIT))
+        // Uploads are checked against the limit of their mime type while they are streamed
+        .layer(RequestBodyLimitLayer::new(
+            (state.get_upload_config().max_limit() + MULTIPART_OVERHEAD) as usize,
+        ))
+        // Merged after the layers above to get its own body limit
+        .merge(
+            axum::Router::new()
+                .route("/assets/upload/batch", routing::post(upload::batch_upload))
+                .layer(DefaultBodyLimit::disable())
+                .layer(RequestBodyLimitLayer::new(
+                    (state.get_upload_config().batch_limit + MULTIPART_OVERHEAD) as usize,
+                )),
+        )
         .with_state(state.clone())
 }
 
@@ -1,17 +1,32 @@
+use std::{io::SeekFrom, time::SystemTime};
+
 use axum::{
     Json,
-    extract::Path,
-    http::{StatusCode, header},
-    response::IntoResponse,
+    body::Body,
+    extract::{Path, Query},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
 };
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use serde::Deserialize;
 
 use crate::{
     api::error::{Error, Result},
     cdn::filesystem::Adapter,
-    models::assets::{AssetManager, AssetThumbnail},
+    config::ImageTransformConfig,
+    models::assets::{Asset, AssetManager, AssetThumbnail},
+    thumbnail::{
+        svg::SVG_MIME,
+        transform::{Fit, OutputFormat, Transform},
+    },
     webserver::extractors::database_connection_extractor::DbConn,
 };
 
+#[cfg(feature = "api_doc")]
+use utoipa::IntoParams;
+
 #[cfg(feature = "api_doc")]
 mod doc {
     use utoipa::OpenApi;
@@ -25,32 +40,273 @@ mod doc {
 #[cfg(feature = "api_doc")]
 pub(crate) use doc::ApiDoc;
 
+/// Assets are content addressed and never change, clients may cache them forever
+const CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+/// SVG documents and other files opened from our origin can not run scripts or load
+/// anything but embedded images, even if the sanitizer missed something
+const CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; img-src data:; style-src 'unsafe-inline'; sandbox";
+
+/// Transform of an image, the file is served as uploaded without any of the parameters
+#[derive(Debug, Clone, Default, Deserialize)]
+#[cfg_attr(feature = "api_doc", derive(IntoParams))]
+#[cfg_attr(feature = "api_doc", into_params(parameter_in = Query))]
+pub struct TransformQuery {
+    /// Width in pixels, follows the aspect ratio when missing
+    width: Option<u32>,
+    /// Height in pixels, follows the aspect ratio when missing
+    height: Option<u32>,
+    /// How the image is fitted into width and height, `contain` by default
+    #[cfg_attr(feature = "api_doc", param(value_type = Option<String>, example = "cover"))]
+    fit: Option<Fit>,
+    /// Format of the served image, the format of the upload by default if it is one
+    /// of `png`, `jpeg` and `webp`, otherwise `png`
+    #[cfg_attr(feature = "api_doc", param(value_type = Option<String>, example = "webp"))]
+    format: Option<OutputFormat>,
+}
+
+impl TransformQuery {
+    fn is_empty(&self) -> bool {
+        self.width.is_none() && self.height.is_none() && self.fit.is_none() && self.format.is_none()
+    }
+
+    fn to_transform(&self, source: &Asset, config: &ImageTransformConfig) -> Result<Transform> {
+        for (name, dimension) in [("width", self.width), ("height", self.height)] {
+            if let Some(dimension) = dimension
+                && !(1..=config.max_dimension).contains(&dimension)
+            {
+                return Err(Error::InvalidFieldValue(format!(
+                    "{name} has to be between 1 and {}",
+                    config.max_dimension
+                )));
+            }
+        }
+
+        Ok(Transform {
+            width: self.width,
+            height: self.height,
+            fit: self.fit.unwrap_or_default(),
+            format: self
+                .format
+                .or_else(|| OutputFormat::from_mime(&source.mime))
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// Single byte range of a `Range` header, the end is inclusive like in `Content-Range`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ByteRange {
+    start: u64,
+    end: u64,
+}
+
+impl ByteRange {
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RangeRequest {
+    /// No range, several ranges or an unknown unit, the whole file is served
+    Full,
+    Partial(ByteRange),
+    Unsatisfiable,
+}
+
+/// Parses `bytes=start-end`, `bytes=start-` and `bytes=-suffix_length` ranges
+fn parse_range(header: &str, size: u64) -> RangeRequest {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(size.saturating_sub(1))),
+        (Ok(start), Err(_)) if end.is_empty() => (start, size.saturating_sub(1)),
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix == 0 {
+                return RangeRequest::Unsatisfiable;
+            }
+            (size.saturating_sub(suffix), size.saturating_sub(1))
+        }
+        _ => return RangeRequest::Full,
+    };
+
+    if size == 0 || range.0 >= size {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    RangeRequest::Partial(ByteRange {
+        start: range.0,
+        end: range.1,
+    })
+}
+
+/// Weak comparison of `If-None-Match` as required for GET requests
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+fn is_not_modified(headers: &HeaderMap, etag: &str, last_modified: SystemTime) -> bool {
+    // If-Modified-Since is ignored when If-None-Match is present
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        return if_none_match
+            .to_str()
+            .is_ok_and(|value| etag_matches(value, etag));
+    }
+
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+        .is_some_and(|since| last_modified <= since)
+}
+
+/// Range of the request, ranges are ignored when `If-Range` names an older version
+fn requested_range(headers: &HeaderMap, etag: &str, size: u64) -> RangeRequest {
+    let Some(range) = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return RangeRequest::Full;
+    };
+
+    if let Some(if_range) = headers.get(header::IF_RANGE)
+        && if_range.to_str().ok().map(str::trim) != Some(etag)
+    {
+        return RangeRequest::Full;
+    }
+
+    parse_range(range, size)
+}
+
 #[cfg_attr(feature = "api_doc",
     utoipa::path(
-        post,
+        get,
         path = "/{filename}", 
         responses(
             (status = 200, description = "Returns file data"),
-            (status = 404, description = "Filename does not match any file")
+            (status = 206, description = "Returns the requested byte range of the file"),
+            (status = 304, description = "File matches the ETag of the client"),
+            (status = 400, description = "Transform is invalid, the file is no image or has too many variants"),
+            (status = 404, description = "Filename does not match any file"),
+            (status = 416, description = "Range is outside of the file")
         ),
         params(
-            ("filename" = String, description = "Filename gotten from upload")
+            ("filename" = String, description = "Filename gotten from upload"),
+            TransformQuery
         )
     )
 )]
 pub async fn serve_file<F: Adapter>(
     conn: DbConn,
     Path(file_name): Path<String>,
+    Query(transform): Query<TransformQuery>,
+    headers: HeaderMap,
     asset_manager: AssetManager<F>,
-) -> Result<impl IntoResponse> {
-    let asset = match asset_manager.get_by_name(conn.as_ref(), &file_name).await? {
+    transform_config: ImageTransformConfig,
+) -> Result<Response> {
+    let mut asset = match asset_manager.get_by_name(conn.as_ref(), &file_name).await? {
         Some(asset) => asset,
         None => return Err(Error::FileNotFound { id: file_name }),
     };
+    // Variants are assets of their own with their own hash and file
+    if !transform.is_empty() {
+        let transform = transform.to_transform(&asset, &transform_config)?;
+        asset = asset_manager
+            .get_or_create_derived_asset(conn.as_ref(), &asset, &transform, &transform_config)
+            .await?;
+    }
 
-    let data = asset_manager.load_file_data(&file_name).await?;
+    let etag = format!("\"{}\"", asset.hash);
+    let last_modified = SystemTime::from(asset.created_at.and_utc());
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, CACHE_CONTROL.to_string()),
+        (
+            header::LAST_MODIFIED,
+            httpdate::fmt_http_date(last_modified),
+        ),
+    ];
 
-    Ok((StatusCode::OK, [(header::CONTENT_TYPE, asset.mime)], data).into_response())
+    if is_not_modified(&headers, &etag, last_modified) {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    let (mut file, size) = asset_manager.open_file(&asset.name).await?;
+
+    let range = match requested_range(&headers, &etag, size) {
+        RangeRequest::Full => None,
+        RangeRequest::Partial(range) => Some(range),
+        RangeRequest::Unsatisfiable => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{size}"))],
+            )
+                .into_response());
+        }
+    };
+
+    // Raster images are shown as they are, SVG documents and any other file could hold
+    // markup a browser renders
+    let is_raster_image = asset.mime.starts_with("image/") && asset.mime != SVG_MIME;
+    let is_svg = asset.mime == SVG_MIME;
+    let (status, start, length) = match range {
+        Some(range) => (StatusCode::PARTIAL_CONTENT, range.start, range.len()),
+        None => (StatusCode::OK, 0, size),
+    };
+    if start > 0 {
+        file.seek(SeekFrom::Start(start)).await?;
+    }
+    let body = Body::from_stream(ReaderStream::new(file.take(length)));
+
+    let mut response = (
+        status,
+        cache_headers,
+        [
+            (header::CONTENT_TYPE, asset.mime),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (header::CONTENT_LENGTH, length.to_string()),
+        ],
+        body,
+    )
+        .into_response();
+    if let Some(range) = range {
+        response.headers_mut().insert(
+            header::CONTENT_RANGE,
+            HeaderValue::from_str(&format!("bytes {}-{}/{size}", range.start, range.end))
+                .expect("Content range is a valid header value"),
+        );
+    }
+    let headers = response.headers_mut();
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    if !is_raster_image {
+        headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(CONTENT_SECURITY_POLICY),
+        );
+    }
+    if !is_raster_image && !is_svg {
+        headers.insert(
+            header::CONTENT_DISPOSITION,
+            HeaderValue::from_static("attachment"),
+        );
+    }
+
+    Ok(response)
 }
 
 #[cfg_attr(feature = "api_doc",
@@ -88,3 +344,298 @@ pub async fn thumbnails<F: Adapter>(
 
     Ok(Json(thumbnails))
 }
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        Router,
+        http::{HeaderName, HeaderValue, StatusCode, header},
+    };
+    use image::GenericImageView;
+    use sea_orm::{ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter};
+
+    use crate::{
+        api::assets::serve::{ByteRange, RangeRequest, parse_range, serve_file},
+        config::ImageTransformConfig,
+        models::assets::{self, AssetManager, AssetType},
+        thumbnail::svg::SVG_MIME,
+        utils::test_utils::{
+            TEST_IMAGE_BYTES, TEST_PDF_BYTES, get_app_state_with_temp_file_store, new_test_app,
+        },
+        webserver::router::app_state::AppStateTrait,
+    };
+
+    #[test]
+    fn ranges_are_parsed() {
+        let partial = |start, end| RangeRequest::Partial(ByteRange { start, end });
+
+        assert_eq!(partial(0, 99), parse_range("bytes=0-99", 1000));
+        assert_eq!(partial(900, 999), parse_range("bytes=900-", 1000));
+        assert_eq!(partial(900, 999), parse_range("bytes=-100", 1000));
+        assert_eq!(partial(0, 999), parse_range("bytes=-5000", 1000));
+        assert_eq!(partial(500, 999), parse_range("bytes=500-5000", 1000));
+        assert_eq!(
+            RangeRequest::Unsatisfiable,
+            parse_range("bytes=1000-", 1000)
+        );
+        assert_eq!(RangeRequest::Unsatisfiable, parse_range("bytes=-0", 1000));
+        assert_eq!(RangeRequest::Full, parse_range("bytes=0-1,5-6", 1000));
+        assert_eq!(RangeRequest::Full, parse_range("bytes=9-1", 1000));
+        assert_eq!(RangeRequest::Full, parse_range("items=0-1", 1000));
+    }
+
+    #[tokio::test]
+    async fn files_are_served_with_cache_headers_and_ranges() {
+        let state = get_app_state_with_temp_file_store().await;
+        let asset = AssetManager::from(state.clone())
+            .create(
+                &state.get_db(),
+                "rules.pdf".to_string(),
+                TEST_PDF_BYTES,
+                AssetType::File,
+            )
+            .await
+            .unwrap();
+
+        let router = Router::new()
+            .route("/assets/{filename}", axum::routing::get(serve_file))
+            .with_state(state);
+        let server = new_test_app(router);
+        let path = format!("/assets/{}", asset.name);
+        let header = |name: HeaderName, value: &str| (name, HeaderValue::from_str(value).unwrap());
+
+        let response = server.get(&path).await;
+        response.assert_status_ok();
+        assert_eq!(TEST_PDF_BYTES, response.as_bytes().as_ref());
+        let etag = response.header(header::ETAG);
+        assert_eq!(format!("\"{}\"", asset.hash), etag.to_str().unwrap());
+        assert!(
+            response
+                .header(header::CACHE_CONTROL)
+                .to_str()
+                .unwrap()
+                .contains("immutable")
+        );
+        assert_eq!("bytes", response.header(header::ACCEPT_RANGES));
+        // Files that are not images are downloaded and can not run scripts
+        assert_eq!("attachment", response.header(header::CONTENT_DISPOSITION));
+        assert_eq!("nosniff", response.header(header::X_CONTENT_TYPE_OPTIONS));
+        assert!(
+            response
+                .maybe_header(header::CONTENT_SECURITY_POLICY)
+                .is_some()
+        );
+        let last_modified = response.header(header::LAST_MODIFIED);
+
+        let (name, value) = header(header::IF_NONE_MATCH, etag.to_str().unwrap());
+        let response = server
+            .get(&path)
+            .add_header(name, value)
+            .expect_failure()
+            .await;
+        response.assert_status(StatusCode::NOT_MODIFIED);
+        assert!(response.as_bytes().is_empty());
+
+        let (name, value) = header(header::IF_MODIFIED_SINCE, last_modified.to_str().unwrap());
+        server
+            .get(&path)
+            .add_header(name, value)
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::NOT_MODIFIED);
+
+        let (name, value) = header(header::IF_NONE_MATCH, "\"other\"");
+        server
+            .get(&path)
+            .add_header(name, value)
+            .await
+            .assert_status_ok();
+
+        let (name, value) = header(header::RANGE, "bytes=10-19");
+        let response = server.get(&path).add_header(name, value).await;
+        response.assert_status(StatusCode::PARTIAL_CONTENT);
+        assert_eq!(&TEST_PDF_BYTES[10..20], response.as_bytes().as_ref());
+        assert_eq!(
+            format!("bytes 10-19/{}", TEST_PDF_BYTES.len()),
+            response.header(header::CONTENT_RANGE).to_str().unwrap()
+        );
+
+        let (name, value) = header(header::RANGE, "bytes=-4");
+        let response = server.get(&path).add_header(name, value).await;
+        assert_eq!(
+            &TEST_PDF_BYTES[TEST_PDF_BYTES.len() - 4..],
+            response.as_bytes().as_ref()
+        );
+
+        // A range of an older version of the file is not served
+        let (name, value) = header(header::RANGE, "bytes=10-19");
+        let (if_range, old_etag) = header(header::IF_RANGE, "\"old\"");
+        let response = server
+            .get(&path)
+            .add_header(name, value)
+            .add_header(if_range, old_etag)
+            .await;
+        response.assert_status_ok();
+        assert_eq!(TEST_PDF_BYTES.len(), response.as_bytes().len());
+
+        let (name, value) = header(header::RANGE, &format!("bytes={}-", TEST_PDF_BYTES.len()));
+        let response = server
+            .get(&path)
+            .add_header(name, value)
+            .expect_failure()
+            .await;
+        response.assert_status(StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            format!("bytes */{}", TEST_PDF_BYTES.len()),
+            response.header(header::CONTENT_RANGE).to_str().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn images_are_transformed_and_cached() {
+        let mut state = get_app_state_with_temp_file_store().await;
+        state.image_transform_config = ImageTransformConfig {
+            max_dimension: 512,
+            max_variants: 2,
+            ..Default::default()
+        };
+        let asset_manager = AssetManager::from(state.clone());
+        let db = state.get_db();
+        let image = asset_manager
+            .create(
+                &db,
+                "goblin.png".to_string(),
+                TEST_IMAGE_BYTES,
+                AssetType::File,
+            )
+            .await
+            .unwrap();
+        let pdf = asset_manager
+            .create(
+                &db,
+                "rules.pdf".to_string(),
+                TEST_PDF_BYTES,
+                AssetType::File,
+            )
+            .await
+            .unwrap();
+
+        let router = Router::new()
+            .route("/assets/{filename}", axum::routing::get(serve_file))
+            .with_state(state.clone());
+        let server = new_test_app(router);
+        let path = format!("/assets/{}", image.name);
+
+        let response = server
+            .get(&path)
+            .add_query_param("width", 64)
+            .add_query_param("height", 32)
+            .add_query_param("fit", "cover")
+            .add_query_param("format", "webp")
+            .await;
+        assert_eq!("image/webp", response.header(header::CONTENT_TYPE));
+        let etag = response.header(header::ETAG);
+        assert_ne!(format!("\"{}\"", image.hash), etag.to_str().unwrap());
+        let variant = image::load_from_memory(response.as_bytes()).unwrap();
+        assert_eq!((64, 32), variant.dimensions());
+
+        // The variant is created once
+        let response = server
+            .get(&path)
+            .add_query_param("width", 64)
+            .add_query_param("height", 32)
+            .add_query_param("fit", "cover")
+            .add_query_param("format", "webp")
+            .await;
+        assert_eq!(etag, response.header(header::ETAG));
+        let derived = || async {
+            assets::Entity::find()
+                .filter(assets::Column::AssetType.eq(AssetType::Derived.to_string()))
+                .count(&db)
+                .await
+                .unwrap()
+        };
+        assert_eq!(1, derived().await);
+
+        // The format of the upload is kept
+        let response = server.get(&path).add_query_param("width", 16).await;
+        assert_eq!("image/png", response.header(header::CONTENT_TYPE));
+        let variant = image::load_from_memory(response.as_bytes()).unwrap();
+        assert_eq!(16, variant.width());
+
+        for (name, value) in [("width", "0"), ("height", "513"), ("fit", "zoom")] {
+            server
+                .get(&path)
+                .add_query_param(name, value)
+                .expect_failure()
+                .await;
+        }
+        // Only two variants are kept per image
+        server
+            .get(&path)
+            .add_query_param("width", 8)
+            .expect_failure()
+            .await;
+        server
+            .get(&format!("/assets/{}", pdf.name))
+            .add_query_param("width", 8)
+            .expect_failure()
+            .await;
+
+        let deleted = asset_manager.delete_asset(&db, image.id).await.unwrap();
+        assert_eq!(3, deleted.len());
+        assert_eq!(0, derived().await);
+    }
+
+    #[tokio::test]
+    async fn svg_is_served_sanitized_with_content_security_policy() {
+        let state = get_app_state_with_temp_file_store().await;
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10" onload="alert(1)"><script>alert(1)</script><rect width="20" height="10" fill="red"/></svg>"#;
+        let asset = AssetManager::from(state.clone())
+            .create(
+                &state.get_db(),
+                "icon.svg".to_string(),
+                svg,
+                AssetType::File,
+            )
+            .await
+            .unwrap();
+        assert_eq!(SVG_MIME, asset.mime);
+
+        let router = Router::new()
+            .route("/assets/{filename}", axum::routing::get(serve_file))
+            .with_state(state);
+        let server = new_test_app(router);
+        let path = format!("/assets/{}", asset.name);
+
+        let response = server.get(&path).await;
+        assert_eq!(SVG_MIME, response.header(header::CONTENT_TYPE));
+        assert!(
+            response
+                .header(header::CONTENT_SECURITY_POLICY)
+                .to_str()
+                .unwrap()
+                .contains("default-src 'none'")
+        );
+        assert_eq!("nosniff", response.header(header::X_CONTENT_TYPE_OPTIONS));
+        assert!(response.maybe_header(header::CONTENT_DISPOSITION).is_none());
+        let body = response.text();
+        assert!(!body.contains("script") && !body.contains("onload"));
+        assert!(body.contains("<rect"));
+
+        // Variants of vector images are rendered
+        let response = server
+            .get(&path)
+            .add_query_param("width", 64)
+            .add_query_param("format", "webp")
+            .await;
+        assert_eq!("image/webp", response.header(header::CONTENT_TYPE));
+        assert!(
+            response
+                .maybe_header(header::CONTENT_SECURITY_POLICY)
+                .is_none()
+        );
+        let variant = image::load_from_memory(response.as_bytes()).unwrap();
+        assert_eq!((64, 32), variant.dimensions());
+    }
+}
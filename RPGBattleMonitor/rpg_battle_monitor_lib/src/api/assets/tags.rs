@@ -0,0 +1,393 @@
+use axum::{Json, extract::Path};
+use sea_orm::TransactionTrait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::error::Result,
+    models::tags::TagManager,
+    webserver::extractors::{database_connection_extractor::DbConn, user_extractor::CurrentUser},
+};
+
+#[cfg(feature = "api_doc")]
+use utoipa::ToSchema;
+#[cfg(feature = "api_doc")]
+mod doc {
+    use utoipa::OpenApi;
+
+    use crate::api::assets::tags;
+
+    #[derive(OpenApi)]
+    #[openapi(paths(tags::list_tags, tags::get_tags, tags::add_tags, tags::remove_tag))]
+    pub(crate) struct ApiDoc;
+}
+#[cfg(feature = "api_doc")]
+pub(crate) use doc::ApiDoc;
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct TagCount {
+    #[cfg_attr(feature = "api_doc", schema(example = "forest map"))]
+    name: String,
+    #[cfg_attr(feature = "api_doc", schema(example = 12))]
+    asset_count: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+pub struct AddTagsRequest {
+    #[cfg_attr(feature = "api_doc", schema(example = json!(["undead", "npc portrait"])))]
+    tags: Vec<String>,
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        get,
+        path = "/tags",
+        responses(
+            (status = 200, description = "Tags the user gave at least one asset", body = Vec<TagCount>),
+            (status = 401, description = "User token is missing")
+        )
+    )
+)]
+pub async fn list_tags(
+    conn: DbConn,
+    CurrentUser(owner): CurrentUser,
+) -> Result<Json<Vec<TagCount>>> {
+    let tags = TagManager::new().list_tags(conn.as_ref(), &owner).await?;
+
+    Ok(Json(
+        tags.into_iter()
+            .map(|(name, asset_count)| TagCount { name, asset_count })
+            .collect(),
+    ))
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        get,
+        path = "/tags/{id}",
+        responses(
+            (status = 200, description = "Tags the user gave the asset", body = Vec<String>),
+            (status = 401, description = "User token is missing"),
+            (status = 404, description = "Asset is not in the library of the user")
+        ),
+        params(
+            ("id" = i32, description = "Id of the asset")
+        )
+    )
+)]
+pub async fn get_tags(
+    conn: DbConn,
+    CurrentUser(owner): CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<String>>> {
+    let tags = TagManager::new()
+        .get_tags_of_asset(conn.as_ref(), &owner, id)
+        .await?;
+
+    Ok(Json(tags))
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        post,
+        path = "/tags/{id}",
+        request_body = AddTagsRequest,
+        responses(
+            (status = 200, description = "All tags the user gave the asset", body = Vec<String>),
+            (status = 400, description = "Tag is empty or too long"),
+            (status = 401, description = "User token is missing"),
+            (status = 404, description = "Asset is not in the library of the user")
+        ),
+        params(
+            ("id" = i32, description = "Id of the asset")
+        )
+    )
+)]
+pub async fn add_tags(
+    conn: DbConn,
+    CurrentUser(owner): CurrentUser,
+    Path(id): Path<i32>,
+    Json(request): Json<AddTagsRequest>,
+) -> Result<Json<Vec<String>>> {
+    let transaction = conn.begin().await?;
+    let tags = TagManager::new()
+        .add_tags(&transaction, &owner, id, &request.tags)
+        .await?;
+    transaction.commit().await?;
+
+    Ok(Json(tags))
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        delete,
+        path = "/tags/{id}/{tag}",
+        responses(
+            (status = 200, description = "Remaining tags the user gave the asset", body = Vec<String>),
+            (status = 401, description = "User token is missing"),
+            (status = 404, description = "Asset is not in the library of the user")
+        ),
+        params(
+            ("id" = i32, description = "Id of the asset"),
+            ("tag" = String, description = "Tag to remove")
+        )
+    )
+)]
+pub async fn remove_tag(
+    conn: DbConn,
+    CurrentUser(owner): CurrentUser,
+    Path((id, tag)): Path<(i32, String)>,
+) -> Result<Json<Vec<String>>> {
+    let transaction = conn.begin().await?;
+    let tags = TagManager::new()
+        .remove_tags(&transaction, &owner, id, &[tag])
+        .await?;
+    transaction.commit().await?;
+
+    Ok(Json(tags))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        Router,
+        http::{HeaderName, HeaderValue},
+        routing,
+    };
+    use serde_json::{Value, json};
+
+    use crate::{
+        api::assets::{
+            list::list_assets,
+            tags::{TagCount, add_tags, get_tags, list_tags, remove_tag},
+        },
+        models::{
+            asset_entry::AssetEntryManager,
+            assets::{AssetManager, AssetType},
+        },
+        utils::test_utils::{get_app_state_with_temp_file_store, new_test_app},
+        webserver::{extractors::user_extractor::CurrentUser, router::app_state::AppStateTrait},
+    };
+
+    fn item_ids(page: &Value) -> Value {
+        page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["id"].clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn assets_are_filtered_by_tags() {
+        let state = get_app_state_with_temp_file_store().await;
+        let asset_manager = AssetManager::from(state.clone());
+        let db = state.get_db();
+
+        let mut ids = Vec::new();
+        for name in ["skeleton.txt", "forest.txt", "ghost-forest.txt"] {
+            let asset = asset_manager
+                .create(&db, name.to_string(), name.as_bytes(), AssetType::File)
+                .await
+                .unwrap();
+            AssetEntryManager::new()
+                .create_entry(&db, "alice", None, None, asset.id, name.to_string())
+                .await
+                .unwrap();
+            ids.push(asset.id);
+        }
+        let [skeleton, forest, ghost_forest] = ids[..] else {
+            unreachable!()
+        };
+
+        let router = Router::new()
+            .route("/assets", routing::get(list_assets))
+            .route("/assets/tags", routing::get(list_tags))
+            .route("/assets/tags/{id}", routing::get(get_tags).post(add_tags))
+            .route("/assets/tags/{id}/{tag}", routing::delete(remove_tag))
+            .with_state(state);
+        let mut server = new_test_app(router);
+        server.add_header(
+            HeaderName::from_static(CurrentUser::HEADER),
+            HeaderValue::from_static("alice"),
+        );
+
+        for (id, tags) in [
+            (skeleton, json!(["Undead"])),
+            (forest, json!(["forest  map"])),
+            (ghost_forest, json!(["undead", "Forest Map", "night"])),
+        ] {
+            server
+                .post(&format!("/assets/tags/{id}"))
+                .json(&json!({ "tags": tags }))
+                .await;
+        }
+
+        let tags = server
+            .get(&format!("/assets/tags/{ghost_forest}"))
+            .await
+            .json::<Vec<String>>();
+        assert_eq!(vec!["forest map", "night", "undead"], tags);
+
+        let listed = |tags: &str, tag_match: &str| {
+            server
+                .get("/assets")
+                .add_query_param("tags", tags)
+                .add_query_param("tagMatch", tag_match)
+                .add_query_param("order", "asc")
+        };
+
+        let all = listed("undead,forest map", "all").await.json::<Value>();
+        assert_eq!(json!([ghost_forest]), item_ids(&all));
+
+        let any = listed("undead, forest map", "any").await.json::<Value>();
+        assert_eq!(json!([skeleton, forest, ghost_forest]), item_ids(&any));
+        assert_eq!(json!(["undead"]), any["items"][0]["tags"]);
+
+        let remaining = server
+            .delete(&format!("/assets/tags/{ghost_forest}/night"))
+            .await
+            .json::<Vec<String>>();
+        assert_eq!(vec!["forest map", "undead"], remaining);
+
+        let counts = server.get("/assets/tags").await.json::<Vec<TagCount>>();
+        assert_eq!(
+            vec![("forest map", 2), ("undead", 2)],
+            counts
+                .iter()
+                .map(|tag| (tag.name.as_str(), tag.asset_count))
+                .collect::<Vec<_>>()
+        );
+
+        server
+            .post("/assets/tags/9999")
+            .json(&json!({ "tags": ["undead"] }))
+            .expect_failure()
+            .await;
+        server
+            .post(&format!("/assets/tags/{skeleton}"))
+            .json(&json!({ "tags": ["  "] }))
+            .expect_failure()
+            .await;
+    }
+
+    #[tokio::test]
+    async fn tags_belong_to_the_library_of_their_owner() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+        let goblin = AssetManager::from(state.clone())
+            .create(&db, "goblin.txt".to_string(), b"goblin", AssetType::File)
+            .await
+            .unwrap();
+        AssetEntryManager::new()
+            .create_entry(&db, "alice", None, None, goblin.id, "goblin".to_string())
+            .await
+            .unwrap();
+
+        let router = Router::new()
+            .route("/assets", routing::get(list_assets))
+            .route("/assets/tags", routing::get(list_tags))
+            .route("/assets/tags/{id}", routing::get(get_tags).post(add_tags))
+            .route("/assets/tags/{id}/{tag}", routing::delete(remove_tag))
+            .with_state(state);
+        let server = new_test_app(router);
+        let as_user = |user: &'static str| {
+            (
+                HeaderName::from_static(CurrentUser::HEADER),
+                HeaderValue::from_static(user),
+            )
+        };
+        let (header, alice) = as_user("alice");
+        let (_, bob) = as_user("bob");
+
+        server
+            .post(&format!("/assets/tags/{}", goblin.id))
+            .add_header(header.clone(), alice.clone())
+            .json(&json!({ "tags": ["undead", "npc"] }))
+            .await;
+
+        // Bob has no entry of the asset
+        for request in [
+            server.get(&format!("/assets/tags/{}", goblin.id)),
+            server
+                .post(&format!("/assets/tags/{}", goblin.id))
+                .json(&json!({ "tags": ["hero"] })),
+            server.delete(&format!("/assets/tags/{}/undead", goblin.id)),
+        ] {
+            let response = request
+                .add_header(header.clone(), bob.clone())
+                .expect_failure()
+                .await;
+            assert!(response.text().contains("AssetNotFound"));
+        }
+        let counts = server
+            .get("/assets/tags")
+            .add_header(header.clone(), bob.clone())
+            .await
+            .json::<Vec<TagCount>>();
+        assert!(counts.is_empty());
+
+        // With an own entry bob tags the asset independently
+        AssetEntryManager::new()
+            .create_entry(&db, "bob", None, None, goblin.id, "goblin".to_string())
+            .await
+            .unwrap();
+        let bob_tags = server
+            .get(&format!("/assets/tags/{}", goblin.id))
+            .add_header(header.clone(), bob.clone())
+            .await
+            .json::<Vec<String>>();
+        assert!(bob_tags.is_empty());
+        let bob_tags = server
+            .post(&format!("/assets/tags/{}", goblin.id))
+            .add_header(header.clone(), bob.clone())
+            .json(&json!({ "tags": ["hero", "undead"] }))
+            .await
+            .json::<Vec<String>>();
+        assert_eq!(vec!["hero", "undead"], bob_tags);
+        let bob_tags = server
+            .delete(&format!("/assets/tags/{}/undead", goblin.id))
+            .add_header(header.clone(), bob.clone())
+            .await
+            .json::<Vec<String>>();
+        assert_eq!(vec!["hero"], bob_tags);
+
+        let alice_tags = server
+            .get(&format!("/assets/tags/{}", goblin.id))
+            .add_header(header.clone(), alice.clone())
+            .await
+            .json::<Vec<String>>();
+        assert_eq!(vec!["npc", "undead"], alice_tags);
+
+        let bob_undead = server
+            .get("/assets")
+            .add_header(header.clone(), bob.clone())
+            .add_query_param("tags", "undead")
+            .await
+            .json::<Value>();
+        assert_eq!(json!([]), item_ids(&bob_undead));
+        let bob_counts = server
+            .get("/assets/tags")
+            .add_header(header.clone(), bob)
+            .await
+            .json::<Vec<TagCount>>();
+        assert_eq!(
+            vec![("hero", 1)],
+            bob_counts
+                .iter()
+                .map(|tag| (tag.name.as_str(), tag.asset_count))
+                .collect::<Vec<_>>()
+        );
+        let alice_undead = server
+            .get("/assets")
+            .add_header(header, alice)
+            .add_query_param("tags", "undead")
+            .await
+            .json::<Value>();
+        assert_eq!(json!([goblin.id]), item_ids(&alice_undead));
+        assert_eq!(json!(["npc", "undead"]), alice_undead["items"][0]["tags"]);
+    }
+}
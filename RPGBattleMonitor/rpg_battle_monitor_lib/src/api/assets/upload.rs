@@ -1,13 +1,20 @@
+use std::collections::HashSet;
+
 use axum::{Json, extract};
-use sea_orm::TransactionTrait;
+use futures_util::StreamExt;
+use sea_orm::{DatabaseConnection, TransactionTrait};
 use serde::Serialize;
 
 use crate::api::assets::gen_partial_asset_url;
 use crate::cdn::filesystem::Adapter;
-use crate::models::assets::{AssetManager, AssetType};
+use crate::config::UploadConfig;
+use crate::models::asset_entry::AssetEntryManager;
+use crate::models::assets::{AssetManager, AssetType, RenderedThumbnails, StagedFile};
+use crate::models::tags::TagManager;
 
 use crate::api::error::{Error, Result};
 use crate::webserver::extractors::database_connection_extractor::DbConn;
+use crate::webserver::extractors::user_extractor::CurrentUser;
 
 #[cfg(feature = "api_doc")]
 use utoipa::ToSchema;
@@ -18,8 +25,14 @@ mod doc {
     use crate::api::assets::upload;
 
     #[derive(OpenApi)]
-    #[cfg_attr(debug_assertions, openapi(paths(upload::upload, upload::upload_form)))]
-    #[cfg_attr(not(debug_assertions), openapi(paths(upload::upload)))]
+    #[cfg_attr(
+        debug_assertions,
+        openapi(paths(upload::upload, upload::batch_upload, upload::upload_form))
+    )]
+    #[cfg_attr(
+        not(debug_assertions),
+        openapi(paths(upload::upload, upload::batch_upload))
+    )]
     pub(crate) struct ApiDoc;
 }
 #[cfg(feature = "api_doc")]
@@ -40,35 +53,80 @@ pub struct UploadResponse {
     thumbnails: Vec<String>,
     #[cfg_attr(feature = "api_doc", schema(example = "filename.png"))]
     original_filename: String,
+    /// Image has several frames, its thumbnails are animated too unless it has more
+    /// frames than `IMAGE_TRANSFORM_MAX_ANIMATION_FRAMES`
+    animated: bool,
+    /// Static first frame of an animated image
+    #[cfg_attr(feature = "api_doc", schema(example = "/api/assets/poster.png"))]
+    poster: Option<String>,
+    /// Tags for images suggested from the filename, they are not added to the asset
+    #[cfg_attr(feature = "api_doc", schema(example = json!(["forest map", "night"])))]
+    suggested_tags: Vec<String>,
+    /// Library entry of the uploading user
+    #[cfg_attr(feature = "api_doc", schema(example = 1))]
+    entry_id: i32,
 }
 
 #[derive(Debug, Default)]
 struct PartialUploadedFile {
     name: Option<String>,
-    data: Option<Vec<u8>>,
+    file: Option<StagedFile>,
+    game: Option<i32>,
+    folder: Option<i32>,
 }
 
-impl TryInto<UploadedFile> for PartialUploadedFile {
-    type Error = Error;
-
-    fn try_into(self) -> std::result::Result<UploadedFile, Self::Error> {
-        Ok(UploadedFile {
-            name: self.name.ok_or(Error::FilenameEmpty)?,
-            data: self.data.ok_or(Error::DataEmpty)?,
-        })
+impl PartialUploadedFile {
+    async fn discard<F: Adapter>(self, asset_manager: &AssetManager<F>) {
+        if let Some(file) = self.file {
+            asset_manager.discard_staged(file).await;
+        }
     }
 }
 
 #[derive(Debug)]
 struct UploadedFile {
     name: String,
-    data: Vec<u8>,
+    file: StagedFile,
+    game: Option<i32>,
+    folder: Option<i32>,
 }
 
 impl UploadedFile {
-    async fn from_multipart(mut multipart: extract::Multipart) -> Result<Self> {
+    /// The file field is streamed into a staged file, the other fields may come before
+    /// or after it
+    async fn from_multipart<F: Adapter>(
+        mut multipart: extract::Multipart,
+        asset_manager: &AssetManager<F>,
+        config: &UploadConfig,
+    ) -> Result<Self> {
         let mut partial_file = PartialUploadedFile::default();
 
+        let read =
+            Self::read_fields(&mut multipart, &mut partial_file, asset_manager, config).await;
+        let missing = match (&partial_file.name, &partial_file.file) {
+            (None, _) => Some(Error::FilenameEmpty),
+            (_, None) => Some(Error::DataEmpty),
+            _ => None,
+        };
+        if let Some(error) = read.err().or(missing) {
+            partial_file.discard(asset_manager).await;
+            return Err(error);
+        }
+
+        Ok(Self {
+            name: partial_file.name.expect("Filename was checked"),
+            file: partial_file.file.expect("File was checked"),
+            game: partial_file.game,
+            folder: partial_file.folder,
+        })
+    }
+
+    async fn read_fields<F: Adapter>(
+        multipart: &mut extract::Multipart,
+        partial_file: &mut PartialUploadedFile,
+        asset_manager: &AssetManager<F>,
+        config: &UploadConfig,
+    ) -> Result<()> {
         while let Some(field) = multipart.next_field().await? {
             let field_name = field.name().ok_or(Error::FieldHasNoName)?;
             match field_name {
@@ -79,13 +137,32 @@ impl UploadedFile {
                     }
                 }
                 "file" => {
-                    partial_file.data = Some(field.bytes().await?.into());
+                    let file = asset_manager.stage_file(field, config).await?;
+                    if let Some(previous) = partial_file.file.replace(file) {
+                        asset_manager.discard_staged(previous).await;
+                    }
+                }
+                "game" => {
+                    partial_file.game = Self::parse_id(field.text().await?)?;
+                }
+                "folder" => {
+                    partial_file.folder = Self::parse_id(field.text().await?)?;
                 }
                 _ => {}
             }
         }
 
-        partial_file.try_into()
+        Ok(())
+    }
+
+    fn parse_id(text: String) -> Result<Option<i32>> {
+        if text.is_empty() {
+            return Ok(None);
+        }
+
+        text.parse()
+            .map(Some)
+            .map_err(|_| Error::InvalidFieldValue(text))
     }
 }
 
@@ -94,40 +171,137 @@ impl UploadedFile {
         post,
         path = "/upload", 
         responses(
-            (status = 200, description = "Uploaded asset", body = UploadResponse)
+            (status = 200, description = "Uploaded asset", body = UploadResponse),
+            (status = 400, description = "Animation is too large to create thumbnails of"),
+            (status = 401, description = "User token is missing"),
+            (status = 413, description = "File is larger than the limit of its mime type")
         )
     )
 )]
 pub async fn upload<F: Adapter>(
     asset_manager: AssetManager<F>,
     conn: DbConn,
+    CurrentUser(owner): CurrentUser,
+    config: UploadConfig,
     multipart: extract::Multipart,
 ) -> Result<Json<UploadResponse>> {
-    let file = UploadedFile::from_multipart(multipart).await?;
+    let file = UploadedFile::from_multipart(multipart, &asset_manager, &config).await?;
+
+    let file = prepare_file(&asset_manager, &conn, file).await?;
+    let (response, _) = save_file(&asset_manager, &conn, &owner, file).await?;
+
+    Ok(Json(response))
+}
+
+#[derive(Debug)]
+struct PreparedFile {
+    file: UploadedFile,
+    /// Missing for files that are not images and for content that is stored already
+    thumbnails: Option<RenderedThumbnails>,
+}
+
+/// Does the CPU heavy work of a file outside of any transaction, so files of a batch
+/// can be prepared concurrently while they are saved one at a time
+async fn prepare_file<F: Adapter>(
+    asset_manager: &AssetManager<F>,
+    conn: &DatabaseConnection,
+    mut file: UploadedFile,
+) -> Result<PreparedFile> {
+    let thumbnails = async {
+        asset_manager.prepare_staged(&mut file.file).await?;
+
+        // Stored content has its thumbnails already
+        let is_image = file.file.mime().starts_with("image/");
+        if !is_image
+            || asset_manager
+                .get_by_hash(conn, file.file.hash())
+                .await?
+                .is_some()
+        {
+            return Ok::<_, Error>(None);
+        }
+
+        Ok(Some(
+            asset_manager.render_staged_thumbnails(&file.file).await?,
+        ))
+    }
+    .await;
+
+    match thumbnails {
+        Ok(thumbnails) => Ok(PreparedFile { file, thumbnails }),
+        Err(e) => {
+            asset_manager.discard_staged(file.file).await;
+            Err(e)
+        }
+    }
+}
+
+/// Turns the prepared file into an asset and adds it to the library of the owner, returns
+/// whether an asset with the same content existed already
+async fn save_file<F: Adapter>(
+    asset_manager: &AssetManager<F>,
+    conn: &DatabaseConnection,
+    owner: &str,
+    prepared: PreparedFile,
+) -> Result<(UploadResponse, bool)> {
+    let PreparedFile { file, thumbnails } = prepared;
+    let is_image = file.file.mime().starts_with("image/");
 
     let transaction = conn.begin().await?;
 
-    // TODO: use name to identify how asset is called for some user uploading it
+    let duplicate = asset_manager
+        .get_by_hash(&transaction, file.file.hash())
+        .await?
+        .is_some();
     let asset = asset_manager
-        .create(
+        .create_from_staged(
             &transaction,
             file.name.to_string(),
-            &file.data,
+            file.file,
             AssetType::File,
         )
         .await?;
 
-    let thumbnails = if infer::is_image(&file.data) {
-        asset_manager
-            .create_thumbnail_assets(&transaction, &asset, Some(&file.data))
-            .await?
+    // Content stored meanwhile by another upload is read again only when it has no thumbnails
+    let (thumbnails, suggested_tags) = if is_image {
+        let thumbnails = match thumbnails {
+            Some(rendered) => {
+                asset_manager
+                    .save_thumbnail_assets(&transaction, &asset, rendered)
+                    .await?
+            }
+            None => {
+                asset_manager
+                    .create_thumbnail_assets(&transaction, &asset, None)
+                    .await?
+            }
+        };
+        (
+            thumbnails,
+            TagManager::new()
+                .suggest_tags(&transaction, owner, &file.name)
+                .await?,
+        )
     } else {
-        vec![]
+        (vec![], vec![])
     };
+    let poster = asset_manager.get_poster(&transaction, &asset).await?;
+
+    // The same content is stored once, every uploader gets an own entry with the given name
+    let entry = AssetEntryManager::new()
+        .create_entry(
+            &transaction,
+            owner,
+            file.game,
+            file.folder,
+            asset.id,
+            file.name,
+        )
+        .await?;
 
     transaction.commit().await?;
 
-    Ok(Json(UploadResponse {
+    let response = UploadResponse {
         id: asset.id,
         thumbnails: thumbnails
             .into_iter()
@@ -136,7 +310,223 @@ pub async fn upload<F: Adapter>(
         url: gen_partial_asset_url(&asset.name),
         filename: asset.name,
         original_filename: asset.original_filename,
-    }))
+        animated: asset.animated,
+        poster: poster.map(|poster| gen_partial_asset_url(&poster.name)),
+        suggested_tags,
+        entry_id: entry.id,
+    };
+
+    Ok((response, duplicate))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum BatchUploadStatus {
+    Created,
+    /// Content was uploaded before, the existing asset is returned
+    Duplicate,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct BatchUploadItem {
+    /// Filename of the file part
+    #[cfg_attr(feature = "api_doc", schema(example = "goblin.png"))]
+    filename: String,
+    status: BatchUploadStatus,
+    /// Missing when the file failed
+    asset: Option<UploadResponse>,
+    #[cfg_attr(
+        feature = "api_doc",
+        schema(example = "File of type image/png is larger than 52428800 bytes")
+    )]
+    error: Option<String>,
+}
+
+impl BatchUploadItem {
+    fn new(filename: String, stored: Result<(UploadResponse, bool)>) -> Self {
+        match stored {
+            Ok((asset, duplicate)) => Self {
+                filename,
+                status: if duplicate {
+                    BatchUploadStatus::Duplicate
+                } else {
+                    BatchUploadStatus::Created
+                },
+                asset: Some(asset),
+                error: None,
+            },
+            Err(error) => Self {
+                filename,
+                status: BatchUploadStatus::Failed,
+                asset: None,
+                error: Some(error.to_string()),
+            },
+        }
+    }
+}
+
+/// Staged files of a batch upload with the filenames of their parts, files that failed
+/// to stage keep their error
+struct BatchUpload {
+    files: Vec<(String, Result<StagedFile>)>,
+    game: Option<i32>,
+    folder: Option<i32>,
+}
+
+impl BatchUpload {
+    async fn from_multipart<F: Adapter>(
+        mut multipart: extract::Multipart,
+        asset_manager: &AssetManager<F>,
+        config: &UploadConfig,
+    ) -> Result<Self> {
+        let mut batch = Self {
+            files: Vec::new(),
+            game: None,
+            folder: None,
+        };
+
+        if let Err(e) = batch
+            .read_fields(&mut multipart, asset_manager, config)
+            .await
+        {
+            batch.discard(asset_manager).await;
+            return Err(e);
+        }
+
+        Ok(batch)
+    }
+
+    async fn read_fields<F: Adapter>(
+        &mut self,
+        multipart: &mut extract::Multipart,
+        asset_manager: &AssetManager<F>,
+        config: &UploadConfig,
+    ) -> Result<()> {
+        while let Some(field) = multipart.next_field().await? {
+            let field_name = field.name().ok_or(Error::FieldHasNoName)?;
+            match field_name {
+                "file" => {
+                    let filename = field.file_name().unwrap_or_default().to_string();
+                    let staged = if filename.is_empty() {
+                        Err(Error::FilenameEmpty)
+                    } else {
+                        asset_manager
+                            .stage_file(field, config)
+                            .await
+                            .map_err(Error::from)
+                    };
+                    self.files.push((filename, staged));
+                }
+                "game" => {
+                    self.game = UploadedFile::parse_id(field.text().await?)?;
+                }
+                "folder" => {
+                    self.folder = UploadedFile::parse_id(field.text().await?)?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn discard<F: Adapter>(self, asset_manager: &AssetManager<F>) {
+        for (_, staged) in self.files {
+            if let Ok(staged) = staged {
+                asset_manager.discard_staged(staged).await;
+            }
+        }
+    }
+}
+
+#[cfg_attr(all(feature = "api_doc"),
+    utoipa::path(
+        description = "Uploads every `file` part with the filename of the part, `game` and `folder` apply to all files",
+        post,
+        path = "/upload/batch",
+        responses(
+            (status = 200, description = "Result of every file in the order of the parts", body = Vec<BatchUploadItem>),
+            (status = 401, description = "User token is missing")
+        )
+    )
+)]
+pub async fn batch_upload<F: Adapter>(
+    asset_manager: AssetManager<F>,
+    conn: DbConn,
+    CurrentUser(owner): CurrentUser,
+    config: UploadConfig,
+    multipart: extract::Multipart,
+) -> Result<Json<Vec<BatchUploadItem>>> {
+    let batch = BatchUpload::from_multipart(multipart, &asset_manager, &config).await?;
+
+    let mut results = Vec::with_capacity(batch.files.len());
+    let mut first_of_hash = Vec::new();
+    let mut same_hash = Vec::new();
+    let mut hashes = HashSet::new();
+    for (index, (filename, staged)) in batch.files.into_iter().enumerate() {
+        let staged = match staged {
+            Ok(staged) => staged,
+            Err(e) => {
+                results.push(Some(BatchUploadItem::new(filename, Err(e))));
+                continue;
+            }
+        };
+        results.push(None);
+
+        let file = UploadedFile {
+            name: filename,
+            file: staged,
+            game: batch.game,
+            folder: batch.folder,
+        };
+        // Files with the same content would race to create the same asset
+        if hashes.insert(file.file.hash().to_string()) {
+            first_of_hash.push((index, file));
+        } else {
+            same_hash.push((index, file));
+        }
+    }
+
+    // Sqlite has a single writer, files are saved one at a time in short transactions
+    let write_lock = tokio::sync::Mutex::new(());
+    let store = |(index, file): (usize, UploadedFile)| {
+        let (asset_manager, conn, owner, write_lock) =
+            (&asset_manager, &conn, owner.as_str(), &write_lock);
+        async move {
+            let filename = file.name.clone();
+            let stored = match prepare_file(asset_manager, conn, file).await {
+                Ok(prepared) => {
+                    let _writing = write_lock.lock().await;
+                    save_file(asset_manager, conn, owner, prepared).await
+                }
+                Err(e) => Err(e),
+            };
+
+            (index, BatchUploadItem::new(filename, stored))
+        }
+    };
+
+    let stored = futures_util::stream::iter(first_of_hash)
+        .map(store)
+        .buffer_unordered(config.batch_concurrency)
+        .collect::<Vec<_>>()
+        .await;
+    let stored_again = futures_util::stream::iter(same_hash)
+        .map(store)
+        .buffer_unordered(config.batch_concurrency)
+        .collect::<Vec<_>>()
+        .await;
+    for (index, item) in stored.into_iter().chain(stored_again) {
+        results[index] = Some(item);
+    }
+
+    Ok(Json(results.into_iter().flatten().collect()))
 }
 
 #[cfg_attr(all(feature = "api_doc", debug_assertions),
@@ -178,26 +568,49 @@ pub async fn upload_form() -> axum::response::Html<&'static str> {
 mod test {
     use std::path::Path;
 
-    use axum::Router;
+    use axum::{
+        Router,
+        http::{HeaderName, HeaderValue, StatusCode},
+    };
     use axum_test::{
         TestServer,
         multipart::{MultipartForm, Part},
     };
+    use image::{GenericImageView, ImageFormat};
     use sea_orm::EntityTrait;
     use serde_json::Value;
 
     use crate::{
-        api::assets::upload::{UploadResponse, upload},
+        api::assets::upload::{
+            BatchUploadItem, BatchUploadStatus, UploadResponse, batch_upload, upload,
+        },
         cdn::filesystem::{FileSystem, temp_file_adapter::TempFileStore},
+        config::{ImageTransformConfig, UploadConfig},
         models::assets::{self, AssetManager},
+        thumbnail::animation::is_animated,
         utils::test_utils::{
-            TEST_IMAGE_BYTES, get_app_state_with_temp_file_store, get_random_filename, new_test_app,
+            TEST_IMAGE_BYTES, TEST_PDF_BYTES, animated_gif, get_app_state_with_temp_file_store,
+            get_random_filename, new_test_app,
+        },
+        webserver::{
+            extractors::user_extractor::CurrentUser,
+            router::app_state::{AppState, AppStateTrait},
         },
-        webserver::router::app_state::{AppState, AppStateTrait},
     };
 
     const UPLOAD_PATH: &str = "/upload";
 
+    /// Test server which uploads as alice
+    fn new_upload_test_app(router: Router) -> TestServer {
+        let mut server = new_test_app(router);
+        server.add_header(
+            HeaderName::from_static(CurrentUser::HEADER),
+            HeaderValue::from_static("alice"),
+        );
+
+        server
+    }
+
     async fn get_upload_router() -> (Router, AppState<TempFileStore>) {
         let state = get_app_state_with_temp_file_store().await;
 
@@ -211,7 +624,7 @@ mod test {
     async fn get_upload_test_app() -> (TestServer, AppState<TempFileStore>) {
         let (test_router, state) = get_upload_router().await;
 
-        (new_test_app(test_router), state)
+        (new_upload_test_app(test_router), state)
     }
 
     #[tokio::test]
@@ -245,6 +658,394 @@ mod test {
         assert!(!file.is_empty());
     }
 
+    #[tokio::test]
+    async fn animated_image_gets_animated_thumbnails_and_poster() {
+        let (server, state) = get_upload_test_app().await;
+
+        let form = MultipartForm::new()
+            .add_text("filename", "dancing goblin.gif")
+            .add_part("file", Part::bytes(animated_gif(&[100, 200])));
+        let response = server
+            .post(UPLOAD_PATH)
+            .multipart(form)
+            .await
+            .json::<UploadResponse>();
+        assert!(response.animated);
+        assert_eq!(3, response.thumbnails.len());
+
+        let fs_handler = state.fs_handler;
+        let filename = |url: &str| url.rsplit('/').next().unwrap().to_string();
+        for thumbnail in &response.thumbnails {
+            let data = fs_handler
+                .read_file(Path::new(&filename(thumbnail)))
+                .await
+                .unwrap();
+            assert!(is_animated(&data));
+        }
+
+        let poster = fs_handler
+            .read_file(Path::new(&filename(&response.poster.unwrap())))
+            .await
+            .unwrap();
+        assert_eq!(ImageFormat::Png, image::guess_format(&poster).unwrap());
+        assert_eq!(
+            (400, 200),
+            image::load_from_memory(&poster).unwrap().dimensions()
+        );
+
+        let form = MultipartForm::new()
+            .add_text("filename", "goblin.png")
+            .add_part("file", Part::bytes(TEST_IMAGE_BYTES));
+        let response = server
+            .post(UPLOAD_PATH)
+            .multipart(form)
+            .await
+            .json::<UploadResponse>();
+        assert!(!response.animated);
+        assert!(response.poster.is_none());
+    }
+
+    #[tokio::test]
+    async fn animation_limits_apply_to_uploads() {
+        let mut state = get_app_state_with_temp_file_store().await;
+        state.image_transform_config = ImageTransformConfig {
+            max_animation_frames: 2,
+            ..Default::default()
+        };
+        let server = new_upload_test_app(
+            Router::new()
+                .route(UPLOAD_PATH, axum::routing::post(upload))
+                .with_state(state.clone()),
+        );
+
+        // Animations with too many frames get static thumbnails and keep their poster
+        let form = MultipartForm::new()
+            .add_text("filename", "long goblin dance.gif")
+            .add_part("file", Part::bytes(animated_gif(&[100, 100, 100])));
+        let response = server
+            .post(UPLOAD_PATH)
+            .multipart(form)
+            .await
+            .json::<UploadResponse>();
+        assert!(response.animated);
+        assert!(response.poster.is_some());
+        assert_eq!(3, response.thumbnails.len());
+
+        let filename = |url: &str| url.rsplit('/').next().unwrap().to_string();
+        for thumbnail in &response.thumbnails {
+            let data = state
+                .fs_handler
+                .read_file(Path::new(&filename(thumbnail)))
+                .await
+                .unwrap();
+            assert!(!is_animated(&data));
+        }
+
+        // The canvas of the animation is 400x200 pixels
+        state.image_transform_config.max_source_pixels = 400 * 199;
+        let server = new_upload_test_app(
+            Router::new()
+                .route(UPLOAD_PATH, axum::routing::post(upload))
+                .with_state(state.clone()),
+        );
+        let form = MultipartForm::new()
+            .add_text("filename", "huge goblin dance.gif")
+            .add_part("file", Part::bytes(animated_gif(&[100, 200])));
+        let response = server
+            .post(UPLOAD_PATH)
+            .multipart(form)
+            .expect_failure()
+            .await;
+        assert!(response.text().contains("ImageTooLarge"));
+    }
+
+    #[tokio::test]
+    async fn svg_upload_is_sanitized_and_gets_thumbnails() {
+        let (server, state) = get_upload_test_app().await;
+        let svg = br#"<?xml version="1.0"?>
+<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24">
+  <script>fetch("https://example.com")</script>
+  <circle cx="12" cy="12" r="10" fill="green" onclick="alert(1)"/>
+</svg>"#;
+
+        let form = MultipartForm::new()
+            .add_text("filename", "shield.svg")
+            .add_part("file", Part::bytes(svg.as_slice()));
+        let response = server
+            .post(UPLOAD_PATH)
+            .multipart(form)
+            .await
+            .json::<UploadResponse>();
+        assert!(response.filename.ends_with(".svg"));
+
+        let fs_handler = state.fs_handler;
+        let stored = fs_handler
+            .read_file(Path::new(&response.filename))
+            .await
+            .unwrap();
+        let stored = String::from_utf8(stored).unwrap();
+        assert!(!stored.contains("script") && !stored.contains("onclick"));
+        assert!(stored.contains("<circle"));
+
+        // Thumbnails are rendered in their full size
+        assert_eq!(3, response.thumbnails.len());
+        let thumbnail = response.thumbnails[0].rsplit('/').next().unwrap();
+        let thumbnail = fs_handler.read_file(Path::new(thumbnail)).await.unwrap();
+        assert_eq!(ImageFormat::Png, image::guess_format(&thumbnail).unwrap());
+        assert_eq!(
+            (512, 512),
+            image::load_from_memory(&thumbnail).unwrap().dimensions()
+        );
+
+        let form = MultipartForm::new()
+            .add_text("filename", "broken.svg")
+            .add_part("file", Part::bytes(b"<svg><g></svg>".as_slice()));
+        server
+            .post(UPLOAD_PATH)
+            .multipart(form)
+            .expect_failure()
+            .await;
+        // The rejected upload leaves no staged file, the svg and its thumbnails
+        assert_eq!(4, fs_handler.file_count());
+
+        // The root element is found after a prolog longer than the start of the file
+        // that tells the type of other uploads
+        let mut svg = b"<?xml version=\"1.0\"?>\n<!--".to_vec();
+        svg.extend(std::iter::repeat_n(b' ', 16 * 1024));
+        svg.extend_from_slice(
+            b"-->\n<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"4\" height=\"4\" onload=\"alert(1)\"/>",
+        );
+        let form = MultipartForm::new()
+            .add_text("filename", "padded.svg")
+            .add_part("file", Part::bytes(svg));
+        let response = server
+            .post(UPLOAD_PATH)
+            .multipart(form)
+            .await
+            .json::<UploadResponse>();
+        assert!(response.filename.ends_with(".svg"));
+        let stored = fs_handler
+            .read_file(Path::new(&response.filename))
+            .await
+            .unwrap();
+        assert!(!String::from_utf8(stored).unwrap().contains("onload"));
+
+        for (filename, markup) in [
+            (
+                "page.html",
+                b"<html><body onload=\"alert(1)\"></body></html>".as_slice(),
+            ),
+            ("feed.xml", b"<?xml version=\"1.0\"?><feed/>".as_slice()),
+        ] {
+            let form = MultipartForm::new()
+                .add_text("filename", filename)
+                .add_part("file", Part::bytes(markup));
+            server
+                .post(UPLOAD_PATH)
+                .multipart(form)
+                .expect_failure()
+                .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_upload_returns_result_of_every_file() {
+        let mut state = get_app_state_with_temp_file_store().await;
+        state.upload_config = UploadConfig {
+            mime_limits: vec![("application/pdf".to_string(), 1024)],
+            ..Default::default()
+        };
+        let server = new_upload_test_app(
+            Router::new()
+                .route("/upload/batch", axum::routing::post(batch_upload))
+                .with_state(state.clone()),
+        );
+
+        let form = MultipartForm::new()
+            .add_part(
+                "file",
+                Part::bytes(TEST_IMAGE_BYTES).file_name("goblin.png"),
+            )
+            .add_part("file", Part::bytes(TEST_PDF_BYTES).file_name("rules.pdf"))
+            .add_part(
+                "file",
+                Part::bytes(b"goblin notes".as_slice()).file_name("notes.txt"),
+            )
+            .add_part(
+                "file",
+                Part::bytes(TEST_IMAGE_BYTES).file_name("goblin copy.png"),
+            )
+            .add_part("file", Part::bytes(b"".as_slice()).file_name("empty.txt"));
+
+        let items = server
+            .post("/upload/batch")
+            .multipart(form)
+            .await
+            .json::<Vec<BatchUploadItem>>();
+
+        let statuses = items
+            .iter()
+            .map(|item| (item.filename.as_str(), item.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("goblin.png", BatchUploadStatus::Created),
+                ("rules.pdf", BatchUploadStatus::Failed),
+                ("notes.txt", BatchUploadStatus::Created),
+                ("goblin copy.png", BatchUploadStatus::Duplicate),
+                ("empty.txt", BatchUploadStatus::Failed),
+            ],
+            statuses
+        );
+        assert!(items[1].error.is_some());
+
+        let goblin = items[0].asset.as_ref().unwrap();
+        let goblin_copy = items[3].asset.as_ref().unwrap();
+        assert_eq!(goblin.id, goblin_copy.id);
+        assert_eq!(3, goblin.thumbnails.len());
+        // Every file gets an own entry in the library of the uploader
+        assert_ne!(goblin.entry_id, goblin_copy.entry_id);
+
+        let assets = assets::Entity::find().all(&state.get_db()).await.unwrap();
+        // The image, its thumbnails and the notes
+        assert_eq!(5, assets.len());
+        assert_eq!(5, state.fs_handler.file_count());
+    }
+
+    #[tokio::test]
+    async fn batch_of_images_is_saved_while_others_are_prepared() {
+        let mut state = get_app_state_with_temp_file_store().await;
+        state.upload_config = UploadConfig {
+            batch_concurrency: 4,
+            ..Default::default()
+        };
+        let server = new_upload_test_app(
+            Router::new()
+                .route("/upload/batch", axum::routing::post(batch_upload))
+                .with_state(state.clone()),
+        );
+
+        let form = [
+            ("goblin.png", TEST_IMAGE_BYTES.to_vec()),
+            ("fire.gif", animated_gif(&[100, 100])),
+            ("water.gif", animated_gif(&[50, 50, 50])),
+            ("smoke.gif", animated_gif(&[200, 100, 200, 100])),
+        ]
+        .into_iter()
+        .fold(MultipartForm::new(), |form, (filename, data)| {
+            form.add_part("file", Part::bytes(data).file_name(filename))
+        });
+
+        let items = server
+            .post("/upload/batch")
+            .multipart(form)
+            .await
+            .json::<Vec<BatchUploadItem>>();
+
+        assert_eq!(4, items.len());
+        for item in &items {
+            assert_eq!(BatchUploadStatus::Created, item.status, "{}", item.filename);
+            let asset = item.asset.as_ref().unwrap();
+            assert_eq!(3, asset.thumbnails.len());
+            assert_eq!(item.filename != "goblin.png", asset.animated);
+            assert_eq!(asset.animated, asset.poster.is_some());
+        }
+
+        let assets = assets::Entity::find().all(&state.get_db()).await.unwrap();
+        // Every image with its thumbnails, the gifs share their first frame and so their poster
+        assert_eq!(4 * 4 + 1, assets.len());
+    }
+
+    #[tokio::test]
+    async fn image_upload_suggests_tags_from_filename() {
+        let (server, _state) = get_upload_test_app().await;
+
+        let form = MultipartForm::new()
+            .add_text("filename", "Undead_Knight-portrait_02.png")
+            .add_part("file", Part::bytes(TEST_IMAGE_BYTES));
+
+        let response = server
+            .post(UPLOAD_PATH)
+            .multipart(form)
+            .await
+            .json::<UploadResponse>();
+
+        assert_eq!(
+            vec!["undead", "knight", "portrait"],
+            response.suggested_tags
+        );
+    }
+
+    #[tokio::test]
+    async fn upload_is_limited_by_mime_type() {
+        let mut state = get_app_state_with_temp_file_store().await;
+        state.upload_config = UploadConfig {
+            default_limit: 1024 * 1024,
+            mime_limits: vec![("application/pdf".to_string(), 1024)],
+            ..Default::default()
+        };
+        let server = new_upload_test_app(
+            Router::new()
+                .route(UPLOAD_PATH, axum::routing::post(upload))
+                .with_state(state.clone()),
+        );
+
+        // The file may come before its filename
+        let form = MultipartForm::new()
+            .add_part("file", Part::bytes(TEST_PDF_BYTES))
+            .add_text("filename", "rules.pdf");
+        server
+            .post(UPLOAD_PATH)
+            .multipart(form)
+            .expect_failure()
+            .await;
+
+        let assets = assets::Entity::find().all(&state.get_db()).await.unwrap();
+        assert!(assets.is_empty());
+        assert_eq!(0, state.fs_handler.file_count());
+
+        let form = MultipartForm::new()
+            .add_part("file", Part::bytes(TEST_IMAGE_BYTES))
+            .add_text("filename", "map.png");
+        let response = server
+            .post(UPLOAD_PATH)
+            .multipart(form)
+            .await
+            .json::<UploadResponse>();
+
+        let file = state
+            .fs_handler
+            .read_file(Path::new(&response.filename))
+            .await
+            .unwrap();
+        assert_eq!(TEST_IMAGE_BYTES, file.as_slice());
+        // The image and its thumbnails, the staged file was moved
+        assert_eq!(4, state.fs_handler.file_count());
+    }
+
+    #[tokio::test]
+    async fn upload_requires_user_token() {
+        let state = get_app_state_with_temp_file_store().await;
+        let server = new_test_app(
+            Router::new()
+                .route(UPLOAD_PATH, axum::routing::post(upload))
+                .with_state(state.clone()),
+        );
+
+        let form = MultipartForm::new()
+            .add_text("filename", get_random_filename())
+            .add_part("file", Part::bytes(TEST_IMAGE_BYTES));
+        let response = server
+            .post(UPLOAD_PATH)
+            .multipart(form)
+            .expect_failure()
+            .await;
+
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status_code());
+        let assets = assets::Entity::find().all(&state.get_db()).await.unwrap();
+        assert!(assets.is_empty());
+    }
+
     #[tokio::test]
     async fn failed_upload_is_not_saved_to_db() {
         const INVALID_IMAGE_BYTES: &[u8] = b"";
@@ -11,6 +11,8 @@ pub mod taggroups;
     modifiers(&ApiDocMod),
     nest(
         (path = "/api", api = crate::api::assets::ApiDoc, tags = []),
+        (path = "/api", api = crate::api::entity_queue::ApiDoc, tags = []),
+        (path = "/api", api = crate::api::library::ApiDoc, tags = []),
         (path = "/api", api = self::ApiDoc, tags = []),
         (path = "/public", api = crate::webserver::router::public_files_router::ApiDoc, tags = [])
     )
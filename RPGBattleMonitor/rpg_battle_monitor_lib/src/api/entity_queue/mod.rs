@@ -0,0 +1,139 @@
+use axum::{Json, extract::State, routing};
+use serde::Serialize;
+
+use crate::{
+    api::error::Result,
+    webserver::{
+        router::app_state::AppStateTrait,
+        services::{entity_dead_letters::DeadLetter, entity_queues::EntityQueuesHealth},
+    },
+};
+
+#[cfg(feature = "api_doc")]
+use utoipa::{Modify, OpenApi, ToSchema};
+
+#[cfg(feature = "api_doc")]
+#[derive(OpenApi)]
+#[openapi(
+    info(description = "Entity queue API"),
+    modifiers(&ModifyDoc),
+    paths(health, dead_letters, replay_dead_letters),
+    tags((name = "Entity queue"))
+)]
+pub struct ApiDoc;
+
+#[cfg(feature = "api_doc")]
+struct ModifyDoc;
+#[cfg(feature = "api_doc")]
+impl Modify for ModifyDoc {
+    fn modify(&self, _openapi: &mut utoipa::openapi::OpenApi) {
+        use std::collections::HashSet;
+
+        use crate::api::doc::taggroups::tag_groups_config;
+
+        tag_groups_config().add(
+            "Entities".to_string(),
+            HashSet::from(["Entity queue".to_string()]),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+pub struct ReplayResponse {
+    #[cfg_attr(feature = "api_doc", schema(example = 3))]
+    replayed: usize,
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        get,
+        path = "/entity-queue/health",
+        tag = "Entity queue",
+        responses(
+            (status = 200, description = "Entity queue health of every game", body = EntityQueuesHealth)
+        )
+    )
+)]
+pub async fn health<T: AppStateTrait>(State(state): State<T>) -> Json<EntityQueuesHealth> {
+    let health = state.get_entity_queues().health().await;
+
+    Json(health)
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        get,
+        path = "/entity-queue/dead-letters",
+        tag = "Entity queue",
+        responses(
+            (status = 200, description = "Entities that failed to save", body = Vec<DeadLetter>)
+        )
+    )
+)]
+pub async fn dead_letters<T: AppStateTrait>(State(state): State<T>) -> Json<Vec<DeadLetter>> {
+    let dead_letters = state.get_entity_queues().dead_letters().await;
+
+    Json(dead_letters)
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        post,
+        path = "/entity-queue/dead-letters/replay",
+        tag = "Entity queue",
+        responses(
+            (status = 200, description = "Dead letters queued again", body = ReplayResponse)
+        )
+    )
+)]
+pub async fn replay_dead_letters<T: AppStateTrait>(
+    State(state): State<T>,
+) -> Result<Json<ReplayResponse>> {
+    let replayed = state.get_entity_queues().replay_dead_letters().await?;
+
+    Ok(Json(ReplayResponse { replayed }))
+}
+
+pub fn get_router<T: AppStateTrait>(state: T) -> axum::Router {
+    axum::Router::new()
+        .route("/entity-queue/health", routing::get(health::<T>))
+        .route(
+            "/entity-queue/dead-letters",
+            routing::get(dead_letters::<T>),
+        )
+        .route(
+            "/entity-queue/dead-letters/replay",
+            routing::post(replay_dead_letters::<T>),
+        )
+        .with_state(state.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        api::entity_queue::{ReplayResponse, get_router},
+        utils::test_utils::{get_app_state_with_temp_file_store, new_test_app},
+    };
+
+    #[tokio::test]
+    async fn health_reports_empty_queue() {
+        let state = get_app_state_with_temp_file_store().await;
+        let server = new_test_app(get_router(state));
+
+        let health = server
+            .get("/entity-queue/health")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!("healthy", health["status"]);
+        assert_eq!(0, health["queued"]);
+        assert!(health["games"].as_object().unwrap().is_empty());
+
+        let replay = server
+            .post("/entity-queue/dead-letters/replay")
+            .await
+            .json::<ReplayResponse>();
+        assert_eq!(0, replay.replayed);
+    }
+}
@@ -19,6 +19,9 @@ pub enum Error {
     #[error("Data is empty")]
     DataEmpty,
 
+    #[error("Invalid field value: {0}")]
+    InvalidFieldValue(String),
+
     #[error(transparent)]
     DatabaseError(#[from] crate::database::error::Error),
 
@@ -28,6 +31,12 @@ pub enum Error {
     #[error("File not found")]
     FileNotFound { id: String },
 
+    #[error("User is not allowed to do this")]
+    Forbidden,
+
+    #[error("Invalid cursor: {0}")]
+    InvalidCursor(String),
+
     #[error(transparent)]
     JoinError(#[from] tokio::task::JoinError),
 
@@ -40,6 +49,9 @@ pub enum Error {
     #[error(transparent)]
     DbError(#[from] sea_orm::DbErr),
 
+    #[error(transparent)]
+    EntityError(#[from] crate::entity::error::Error),
+
     #[error("SocketIo state not found")]
     SocketIoStateNotFound,
 }
@@ -73,10 +85,35 @@ impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
         use axum::http::StatusCode;
 
+        use crate::models::error::Error as ModelsError;
+
         tracing::error!(error = %self);
 
         match self {
             Self::FileNotFound { id: _ } => (StatusCode::NOT_FOUND).into_response(),
+            Self::Forbidden => (StatusCode::FORBIDDEN).into_response(),
+            Self::InvalidCursor(_) | Self::InvalidFieldValue(_) => {
+                (StatusCode::BAD_REQUEST).into_response()
+            }
+            Self::ModelsError(ModelsError::FileTooLarge { .. }) => {
+                (StatusCode::PAYLOAD_TOO_LARGE).into_response()
+            }
+            Self::ModelsError(
+                ModelsError::AssetNotFound(_)
+                | ModelsError::AssetEntryNotFound(_)
+                | ModelsError::FolderNotFound(_),
+            ) => (StatusCode::NOT_FOUND).into_response(),
+            Self::ModelsError(
+                ModelsError::FolderInOtherGame(_)
+                | ModelsError::InvalidFolderMove(_)
+                | ModelsError::NameEmpty
+                | ModelsError::InvalidTag(_)
+                | ModelsError::InvalidSvg(_)
+                | ModelsError::UnsupportedMarkup
+                | ModelsError::NotAnImage(_)
+                | ModelsError::ImageTooLarge { .. }
+                | ModelsError::TooManyVariants(_),
+            ) => (StatusCode::BAD_REQUEST).into_response(),
             _ => (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response(),
         }
     }
@@ -0,0 +1,568 @@
+use axum::{
+    Json,
+    extract::{Path, Query},
+    routing,
+};
+use sea_orm::TransactionTrait;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    api::{
+        assets::{
+            gen_partial_asset_url,
+            list::{ThumbnailUrl, thumbnail_urls},
+        },
+        error::Result,
+    },
+    cdn::filesystem::Adapter,
+    models::{
+        asset_entry::{AssetEntryManager, AssetEntryModel},
+        asset_folder::{AssetFolderManager, AssetFolderModel},
+        assets::{Asset, AssetManager},
+    },
+    webserver::{
+        extractors::{database_connection_extractor::DbConn, user_extractor::CurrentUser},
+        router::app_state::AppStateTrait,
+    },
+};
+
+#[cfg(feature = "api_doc")]
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+
+#[cfg(feature = "api_doc")]
+#[derive(OpenApi)]
+#[openapi(
+    info(description = "Asset library API"),
+    modifiers(&ModifyDoc),
+    paths(list_library, create_folder, update_folder, create_entry, update_entry),
+    tags((name = "Library"))
+)]
+pub struct ApiDoc;
+
+#[cfg(feature = "api_doc")]
+struct ModifyDoc;
+#[cfg(feature = "api_doc")]
+impl Modify for ModifyDoc {
+    fn modify(&self, _openapi: &mut utoipa::openapi::OpenApi) {
+        use std::collections::HashSet;
+
+        use crate::api::doc::taggroups::tag_groups_config;
+
+        tag_groups_config().add("Assets".to_string(), HashSet::from(["Library".to_string()]));
+    }
+}
+
+/// Distinguishes a missing field from an explicit `null`
+fn deserialize_some<'de, T, D>(deserializer: D) -> core::result::Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[cfg_attr(feature = "api_doc", derive(IntoParams))]
+#[cfg_attr(feature = "api_doc", into_params(parameter_in = Query))]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryQuery {
+    /// Game of the library, the personal library of the user if missing
+    game: Option<i32>,
+    /// Folder to list, the top level if missing
+    folder: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryEntry {
+    #[cfg_attr(feature = "api_doc", schema(example = 1))]
+    id: i32,
+    #[cfg_attr(feature = "api_doc", schema(example = "Goblin"))]
+    name: String,
+    folder: Option<i32>,
+    game: Option<i32>,
+    #[cfg_attr(feature = "api_doc", schema(example = 7))]
+    asset_id: i32,
+    #[cfg_attr(feature = "api_doc", schema(example = "/api/assets/filename.png"))]
+    url: String,
+    #[cfg_attr(feature = "api_doc", schema(example = "image/png"))]
+    mime: String,
+    thumbnails: Vec<ThumbnailUrl>,
+}
+
+impl LibraryEntry {
+    fn new(entry: AssetEntryModel, asset: &Asset, thumbnails: Vec<ThumbnailUrl>) -> Self {
+        Self {
+            id: entry.id,
+            name: entry.name,
+            folder: entry.folder,
+            game: entry.game,
+            asset_id: entry.asset_id,
+            url: gen_partial_asset_url(&asset.name),
+            mime: asset.mime.clone(),
+            thumbnails,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryResponse {
+    folders: Vec<AssetFolderModel>,
+    entries: Vec<LibraryEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct CreateFolderRequest {
+    name: String,
+    parent: Option<i32>,
+    game: Option<i32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateFolderRequest {
+    name: Option<String>,
+    /// Folder to move the folder into, `null` moves it to the top level
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[cfg_attr(feature = "api_doc", schema(value_type = Option<i32>))]
+    parent: Option<Option<i32>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct CreateEntryRequest {
+    asset_id: i32,
+    /// Defaults to the original filename of the asset
+    name: Option<String>,
+    folder: Option<i32>,
+    game: Option<i32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateEntryRequest {
+    name: Option<String>,
+    /// Folder to move the entry into, `null` moves it out of all folders
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[cfg_attr(feature = "api_doc", schema(value_type = Option<i32>))]
+    folder: Option<Option<i32>>,
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        get,
+        path = "/library",
+        tag = "Library",
+        params(LibraryQuery),
+        responses(
+            (status = 200, description = "Folders and assets of the user in a folder", body = LibraryResponse),
+            (status = 401, description = "User token is missing")
+        )
+    )
+)]
+pub async fn list_library<F: Adapter>(
+    conn: DbConn,
+    CurrentUser(owner): CurrentUser,
+    asset_manager: AssetManager<F>,
+    Query(query): Query<LibraryQuery>,
+) -> Result<Json<LibraryResponse>> {
+    if let Some(folder) = query.folder {
+        AssetFolderManager::new()
+            .get_folder_in_game(conn.as_ref(), &owner, query.game, folder)
+            .await?;
+    }
+
+    let folders = AssetFolderManager::new()
+        .list_folders(conn.as_ref(), &owner, query.game, query.folder)
+        .await?;
+    let entries = AssetEntryManager::new()
+        .list_entries(conn.as_ref(), &owner, query.game, query.folder)
+        .await?;
+
+    let asset_ids = entries
+        .iter()
+        .map(|(_, asset)| asset.id)
+        .collect::<Vec<_>>();
+    let thumbnails = asset_manager
+        .get_thumbnails_of_assets(conn.as_ref(), &asset_ids)
+        .await?;
+
+    let entries = entries
+        .into_iter()
+        .map(|(entry, asset)| {
+            // Entries of the same asset share its thumbnails
+            let thumbnails = thumbnail_urls(thumbnails.get(&asset.id).cloned().unwrap_or_default());
+            LibraryEntry::new(entry, &asset, thumbnails)
+        })
+        .collect();
+
+    Ok(Json(LibraryResponse { folders, entries }))
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        post,
+        path = "/library/folders",
+        tag = "Library",
+        request_body = CreateFolderRequest,
+        responses(
+            (status = 200, description = "Created folder", body = AssetFolderModel),
+            (status = 401, description = "User token is missing")
+        )
+    )
+)]
+pub async fn create_folder(
+    conn: DbConn,
+    CurrentUser(owner): CurrentUser,
+    Json(request): Json<CreateFolderRequest>,
+) -> Result<Json<AssetFolderModel>> {
+    let folder = AssetFolderManager::new()
+        .create_folder(
+            conn.as_ref(),
+            &owner,
+            request.game,
+            request.parent,
+            request.name,
+        )
+        .await?;
+
+    Ok(Json(folder))
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        patch,
+        path = "/library/folders/{id}",
+        tag = "Library",
+        request_body = UpdateFolderRequest,
+        responses(
+            (status = 200, description = "Renamed or moved folder", body = AssetFolderModel),
+            (status = 400, description = "Folder can not be moved there"),
+            (status = 401, description = "User token is missing"),
+            (status = 404, description = "Folder not found")
+        ),
+        params(
+            ("id" = i32, description = "Id of the folder")
+        )
+    )
+)]
+pub async fn update_folder(
+    conn: DbConn,
+    CurrentUser(owner): CurrentUser,
+    Path(id): Path<i32>,
+    Json(request): Json<UpdateFolderRequest>,
+) -> Result<Json<AssetFolderModel>> {
+    let folder_manager = AssetFolderManager::new();
+    let transaction = conn.begin().await?;
+
+    let mut folder = folder_manager.get_folder(&transaction, &owner, id).await?;
+    if let Some(name) = request.name {
+        folder = folder_manager
+            .rename_folder(&transaction, &owner, id, name)
+            .await?;
+    }
+    if let Some(parent) = request.parent {
+        folder = folder_manager
+            .move_folder(&transaction, &owner, id, parent)
+            .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(Json(folder))
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        post,
+        path = "/library/entries",
+        tag = "Library",
+        request_body = CreateEntryRequest,
+        responses(
+            (status = 200, description = "Asset added to the library", body = AssetEntryModel),
+            (status = 401, description = "User token is missing"),
+            (status = 404, description = "Asset or folder not found")
+        )
+    )
+)]
+pub async fn create_entry<F: Adapter>(
+    conn: DbConn,
+    CurrentUser(owner): CurrentUser,
+    asset_manager: AssetManager<F>,
+    Json(request): Json<CreateEntryRequest>,
+) -> Result<Json<AssetEntryModel>> {
+    let name = match request.name {
+        Some(name) => name,
+        None => asset_manager
+            .get_by_id(conn.as_ref(), request.asset_id)
+            .await?
+            .map(|asset| asset.original_filename)
+            .unwrap_or_default(),
+    };
+
+    let entry = AssetEntryManager::new()
+        .create_entry(
+            conn.as_ref(),
+            &owner,
+            request.game,
+            request.folder,
+            request.asset_id,
+            name,
+        )
+        .await?;
+
+    Ok(Json(entry))
+}
+
+#[cfg_attr(feature = "api_doc",
+    utoipa::path(
+        patch,
+        path = "/library/entries/{id}",
+        tag = "Library",
+        request_body = UpdateEntryRequest,
+        responses(
+            (status = 200, description = "Renamed or moved asset", body = AssetEntryModel),
+            (status = 401, description = "User token is missing"),
+            (status = 404, description = "Asset or folder not found")
+        ),
+        params(
+            ("id" = i32, description = "Id of the library entry")
+        )
+    )
+)]
+pub async fn update_entry(
+    conn: DbConn,
+    CurrentUser(owner): CurrentUser,
+    Path(id): Path<i32>,
+    Json(request): Json<UpdateEntryRequest>,
+) -> Result<Json<AssetEntryModel>> {
+    let entry_manager = AssetEntryManager::new();
+    let transaction = conn.begin().await?;
+
+    let mut entry = entry_manager.get_entry(&transaction, &owner, id).await?;
+    if let Some(name) = request.name {
+        entry = entry_manager
+            .rename_entry(&transaction, &owner, id, name)
+            .await?;
+    }
+    if let Some(folder) = request.folder {
+        entry = entry_manager
+            .move_entry(&transaction, &owner, id, folder)
+            .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(Json(entry))
+}
+
+pub fn get_router<T: AppStateTrait>(state: T) -> axum::Router {
+    axum::Router::new()
+        .route("/library", routing::get(list_library))
+        .route("/library/folders", routing::post(create_folder))
+        .route("/library/folders/{id}", routing::patch(update_folder))
+        .route("/library/entries", routing::post(create_entry))
+        .route("/library/entries/{id}", routing::patch(update_entry))
+        .with_state(state.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        Router,
+        http::{HeaderName, HeaderValue, StatusCode},
+    };
+    use axum_test::{
+        TestServer,
+        multipart::{MultipartForm, Part},
+    };
+    use serde_json::json;
+
+    use crate::{
+        api::{
+            assets::upload::upload,
+            library::{LibraryResponse, get_router},
+        },
+        models::{asset_entry::AssetEntryModel, asset_folder::AssetFolderModel},
+        utils::test_utils::{TEST_IMAGE_BYTES, get_app_state_with_temp_file_store, new_test_app},
+        webserver::extractors::user_extractor::CurrentUser,
+    };
+
+    async fn get_library_test_app() -> TestServer {
+        let state = get_app_state_with_temp_file_store().await;
+
+        let router = get_router(state.clone()).merge(
+            Router::new()
+                .route("/upload", axum::routing::post(upload))
+                .with_state(state),
+        );
+
+        new_test_app(router)
+    }
+
+    fn user(token: &str) -> (HeaderName, HeaderValue) {
+        (
+            HeaderName::from_static(CurrentUser::HEADER),
+            HeaderValue::from_str(token).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn folders_are_created_renamed_and_moved() {
+        let server = get_library_test_app().await;
+        let (header, alice) = user("alice");
+
+        let maps = server
+            .post("/library/folders")
+            .add_header(header.clone(), alice.clone())
+            .json(&json!({ "name": "Maps" }))
+            .await
+            .json::<AssetFolderModel>();
+        let dungeons = server
+            .post("/library/folders")
+            .add_header(header.clone(), alice.clone())
+            .json(&json!({ "name": "Dungeons", "parent": maps.id }))
+            .await
+            .json::<AssetFolderModel>();
+
+        let renamed = server
+            .patch(&format!("/library/folders/{}", dungeons.id))
+            .add_header(header.clone(), alice.clone())
+            .json(&json!({ "name": "Caves" }))
+            .await
+            .json::<AssetFolderModel>();
+        assert_eq!("Caves", renamed.name);
+        assert_eq!(Some(maps.id), renamed.parent);
+
+        // A folder can not be moved into its own subfolder
+        server
+            .patch(&format!("/library/folders/{}", maps.id))
+            .add_header(header.clone(), alice.clone())
+            .json(&json!({ "parent": dungeons.id }))
+            .expect_failure()
+            .await;
+
+        let moved = server
+            .patch(&format!("/library/folders/{}", dungeons.id))
+            .add_header(header.clone(), alice.clone())
+            .json(&json!({ "parent": null }))
+            .await
+            .json::<AssetFolderModel>();
+        assert_eq!(None, moved.parent);
+
+        let library = server
+            .get("/library")
+            .add_header(header.clone(), alice)
+            .await
+            .json::<LibraryResponse>();
+        let names = library
+            .folders
+            .iter()
+            .map(|folder| folder.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["Caves", "Maps"], names);
+
+        // Folders of other users are not found
+        let (_, bob) = user("bob");
+        server
+            .patch(&format!("/library/folders/{}", maps.id))
+            .add_header(header, bob)
+            .json(&json!({ "name": "Mine" }))
+            .expect_failure()
+            .await;
+
+        let response = server.get("/library").expect_failure().await;
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status_code());
+    }
+
+    #[tokio::test]
+    async fn owners_see_their_own_entries_of_the_same_asset() {
+        let server = get_library_test_app().await;
+        let (header, alice) = user("alice");
+        let (_, bob) = user("bob");
+
+        let folder = server
+            .post("/library/folders")
+            .add_header(header.clone(), alice.clone())
+            .json(&json!({ "name": "Tokens" }))
+            .await
+            .json::<AssetFolderModel>();
+
+        let upload_as = |token: HeaderValue, name: &str, folder: Option<i32>| {
+            let mut form = MultipartForm::new()
+                .add_text("filename", name.to_string())
+                .add_part("file", Part::bytes(TEST_IMAGE_BYTES));
+            if let Some(folder) = folder {
+                form = form.add_text("folder", folder.to_string());
+            }
+
+            server
+                .post("/upload")
+                .add_header(header.clone(), token)
+                .multipart(form)
+        };
+
+        let alice_upload = upload_as(alice.clone(), "goblin.png", Some(folder.id))
+            .await
+            .json::<serde_json::Value>();
+        let bob_upload = upload_as(bob.clone(), "orc.png", None)
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(alice_upload["id"], bob_upload["id"]);
+        assert_ne!(alice_upload["entryId"], bob_upload["entryId"]);
+
+        let alice_library = server
+            .get("/library")
+            .add_query_param("folder", folder.id)
+            .add_header(header.clone(), alice.clone())
+            .await
+            .json::<LibraryResponse>();
+        assert_eq!(1, alice_library.entries.len());
+        assert_eq!("goblin.png", alice_library.entries[0].name);
+        assert!(!alice_library.entries[0].thumbnails.is_empty());
+
+        let bob_library = server
+            .get("/library")
+            .add_header(header.clone(), bob.clone())
+            .await
+            .json::<LibraryResponse>();
+        assert_eq!(1, bob_library.entries.len());
+        assert_eq!("orc.png", bob_library.entries[0].name);
+
+        // Bob can not put entries into folders of alice
+        server
+            .patch(&format!("/library/entries/{}", bob_library.entries[0].id))
+            .add_header(header.clone(), bob.clone())
+            .json(&json!({ "folder": folder.id }))
+            .expect_failure()
+            .await;
+
+        let entry = server
+            .post("/library/entries")
+            .add_header(header.clone(), bob)
+            .json(&json!({ "assetId": bob_upload["id"] }))
+            .await
+            .json::<AssetEntryModel>();
+        // Without a name the entry is called like the first upload of the content
+        assert_eq!("goblin.png", entry.name);
+
+        let moved = server
+            .patch(&format!("/library/entries/{}", alice_library.entries[0].id))
+            .add_header(header, alice)
+            .json(&json!({ "name": "Goblin", "folder": null }))
+            .await
+            .json::<AssetEntryModel>();
+        assert_eq!("Goblin", moved.name);
+        assert_eq!(None, moved.folder);
+    }
+}
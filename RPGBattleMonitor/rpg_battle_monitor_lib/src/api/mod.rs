@@ -5,8 +5,10 @@ use crate::webserver::router::app_state::AppStateTrait;
 pub mod assets;
 #[cfg(feature = "api_doc")]
 pub mod doc;
+pub mod entity_queue;
 pub mod error;
 pub mod game;
+pub mod library;
 pub mod websockets;
 
 pub fn get_router<T: AppStateTrait>(state: T) -> axum::Router {
@@ -19,6 +21,8 @@ pub fn get_router<T: AppStateTrait>(state: T) -> axum::Router {
         .merge(websockets::get_router(state.clone()))
         .merge(assets::get_router(state.clone()))
         .merge(game::get_router(state.clone()))
+        .merge(library::get_router(state.clone()))
+        .merge(entity_queue::get_router(state.clone()))
         .layer(CorsLayer::permissive());
 
     axum::Router::new().nest("/api", router)
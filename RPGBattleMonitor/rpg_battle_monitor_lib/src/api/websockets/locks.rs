@@ -0,0 +1,163 @@
+use std::{sync::Arc, time::Instant};
+
+use socketioxide::extract::{AckSender, Data, Extension, SocketRef, State};
+
+use crate::{
+    api::websockets::{
+        game_room,
+        protocol::{Event, LockMessage, LockResponse, LockState},
+    },
+    entity::UId,
+    webserver::{
+        router::app_state::AppStateTrait,
+        services::{
+            broadcast::RoomBroadcaster,
+            entity_locks::{EntityLock, EntityLocks},
+            entity_queue::GameIdAndUIdCombo,
+            websocket_auth::WebsocketAuthMessage,
+        },
+    },
+};
+
+fn locked_state(uid: UId, lock: &EntityLock) -> LockState {
+    LockState::locked(
+        uid,
+        lock.holder.clone(),
+        lock.expires_at.saturating_duration_since(Instant::now()),
+    )
+}
+
+pub(super) async fn lock_handler<T: AppStateTrait>(
+    socket: SocketRef,
+    Data(data): Data<LockMessage>,
+    State(app_state): State<T>,
+    Extension(auth): Extension<WebsocketAuthMessage>,
+    ack: AckSender,
+) {
+    let locks = app_state.get_entity_locks();
+    let lease = app_state.get_websocket_config().entity_lock_timeout;
+    let key = GameIdAndUIdCombo::new(auth.game, data.uid.clone());
+
+    match locks.lock(key.clone(), &socket.id.to_string(), lease) {
+        Ok(lock) => {
+            let state = locked_state(data.uid, &lock);
+            ack.send(&LockResponse {
+                granted: true,
+                state: state.clone(),
+            })
+            .ok();
+
+            let broadcaster = app_state.get_broadcaster();
+            broadcaster
+                .emit_within(&socket, game_room(auth.game), Event::LockState, &state)
+                .await;
+
+            schedule_lock_expiry(socket, locks, broadcaster, key, lock.expires_at);
+        }
+        Err(existing) => {
+            ack.send(&LockResponse {
+                granted: false,
+                state: locked_state(data.uid, &existing),
+            })
+            .ok();
+        }
+    }
+}
+
+pub(super) async fn unlock_handler<T: AppStateTrait>(
+    socket: SocketRef,
+    Data(data): Data<LockMessage>,
+    State(app_state): State<T>,
+    Extension(auth): Extension<WebsocketAuthMessage>,
+    ack: AckSender,
+) {
+    let locks = app_state.get_entity_locks();
+    let key = GameIdAndUIdCombo::new(auth.game, data.uid.clone());
+
+    let granted = locks.unlock(&key, &socket.id.to_string());
+    let state = LockState::unlocked(data.uid);
+    ack.send(&LockResponse {
+        granted,
+        state: state.clone(),
+    })
+    .ok();
+
+    if granted {
+        app_state
+            .get_broadcaster()
+            .emit_within(&socket, game_room(auth.game), Event::LockState, &state)
+            .await;
+    }
+}
+
+pub(super) async fn refused_lock_handler(Data(data): Data<LockMessage>, ack: AckSender) {
+    ack.send(&LockResponse {
+        granted: false,
+        state: LockState::unlocked(data.uid),
+    })
+    .ok();
+}
+
+/// Releases the locks of deleted entities and lets the whole room know
+pub(super) async fn release_deleted_locks<T: AppStateTrait>(
+    socket: &SocketRef,
+    app_state: &T,
+    game: i32,
+    uids: Vec<UId>,
+) {
+    let locks = app_state.get_entity_locks();
+    let broadcaster = app_state.get_broadcaster();
+    for uid in uids {
+        if locks.release(&GameIdAndUIdCombo::new(game, uid.clone())) {
+            broadcaster
+                .emit_within(
+                    socket,
+                    game_room(game),
+                    Event::LockState,
+                    &LockState::unlocked(uid),
+                )
+                .await;
+        }
+    }
+}
+
+/// Releases every lock held by the socket and lets the rest of the room know
+pub(super) async fn release_socket_locks<T: AppStateTrait>(socket: &SocketRef, app_state: &T) {
+    let broadcaster = app_state.get_broadcaster();
+    for key in app_state
+        .get_entity_locks()
+        .release_holder(&socket.id.to_string())
+    {
+        broadcaster
+            .emit(
+                socket,
+                game_room(key.game_id),
+                Event::LockState,
+                &LockState::unlocked(key.uid),
+            )
+            .await;
+    }
+}
+
+fn schedule_lock_expiry(
+    socket: SocketRef,
+    locks: Arc<EntityLocks>,
+    broadcaster: RoomBroadcaster,
+    key: GameIdAndUIdCombo,
+    expires_at: Instant,
+) {
+    tokio::spawn(async move {
+        tokio::time::sleep_until(expires_at.into()).await;
+
+        if locks.expire(&key, expires_at) {
+            broadcaster
+                .emit_within(
+                    &socket,
+                    game_room(key.game_id),
+                    Event::LockState,
+                    &LockState::unlocked(key.uid),
+                )
+                .await;
+        }
+    });
+}
@@ -1,98 +1,371 @@
-use std::{sync::Arc, time::Duration};
+use std::{collections::VecDeque, sync::Arc, time::Duration};
 
-use sea_orm::TransactionTrait;
-use serde::{Deserialize, Serialize};
+use sea_orm::DatabaseConnection;
 use socketioxide::{
-    SocketIoBuilder,
+    AckError, SocketIo, SocketIoBuilder,
+    ack::AckStream,
     extract::{Data, Extension, SocketRef, State},
     handler::ConnectHandler,
     socket::DisconnectReason,
+    socket::Sid,
 };
 use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
 
+mod locks;
+pub mod protocol;
+
+pub use protocol::{Action, ActionMessage};
+
 use crate::{
-    entity::{ClientsideEntity, Entity},
+    api::websockets::protocol::{
+        ActionRefusedMessage, Event, JoinChunk, JoinChunkAck, JoinProgress, PROTOCOL_VERSION,
+        Presence, ProtocolCompatibility, ProtocolHandshake, SwitchGameMessage,
+    },
+    config::WebsocketConfig,
+    entity::{self, ClientsideEntity, Entity, codec::EntityCodecs},
     models::entity::EntityManager,
     webserver::{
         router::app_state::AppStateTrait,
-        services::websocket_auth::{self, WebsocketAuthMessage},
+        services::{
+            broadcast::RoomBroadcaster,
+            entity_queue::GameIdAndUIdCombo,
+            shutdown::ShutdownHandle,
+            transitive_buffer::TransitiveBuffer,
+            websocket_auth::{self, ConnectionRole, WebsocketAuthMessage},
+        },
     },
 };
 
-const CHUNK_SIZE: usize = 50;
-
 #[derive(Clone)]
 struct JoinedFlag;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "kebab-case")]
-pub enum Action {
-    Update,
-    Create,
-    Delete,
-    Transitive,
-    Other(serde_json::Value),
-}
+/// Set while the entities of the game are streamed to the socket, a second join
+/// is ignored until the first one is finished
+#[derive(Clone)]
+struct JoiningFlag;
 
-impl Action {
-    pub fn is_other(&self) -> bool {
-        matches!(self, Action::Other(_))
-    }
+fn game_room(game_id: i32) -> String {
+    format!("room-{game_id}")
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ActionMessage {
-    action: Action,
-    data: Vec<ClientsideEntity>,
+impl Presence {
+    /// Counts joined sockets in the game room, `ignore` is left out of the count
+    /// so a disconnecting socket is not counted while it is still being removed
+    fn of_room(socket: &SocketRef, room: &str, ignore: Option<Sid>) -> Self {
+        socket
+            .within(room.to_string())
+            .sockets()
+            .into_iter()
+            .filter(|s| Some(s.id) != ignore && s.extensions.get::<JoinedFlag>().is_some())
+            .filter_map(|s| s.extensions.get::<WebsocketAuthMessage>())
+            .fold(Presence::default(), |mut presence, auth| {
+                match auth.role {
+                    ConnectionRole::Player => presence.players += 1,
+                    ConnectionRole::Spectator => presence.spectators += 1,
+                }
+                presence
+            })
+    }
 }
 
-const ACTION: &str = "action";
-const JOIN_EVENT: &str = "join";
-const JOIN_FINISHED_EVENT: &str = "join-finished";
-
+/// Pushes entities to the queue, entities are journaled before this returns
 async fn entity_handler<T: AppStateTrait>(
-    data: ActionMessage,
-    app_state: T,
-    auth: WebsocketAuthMessage,
-) {
-    let queue = app_state.get_entity_queue();
+    data: &ActionMessage,
+    app_state: &T,
+    auth: &WebsocketAuthMessage,
+) -> entity::error::Result<()> {
+    let queue = app_state.get_entity_queues().get(auth.game).await?;
     let mut lock = queue.lock().await;
 
-    let shared_action = Arc::new(data.action);
+    let shared_action = Arc::new(data.action.clone());
 
-    for entity in data.data {
-        let entity = Entity {
+    let entities = data
+        .data
+        .iter()
+        .cloned()
+        .map(|entity| Entity {
             game: auth.game,
             uid: entity.uid,
             kind: entity.kind,
             timestamp: entity.timestamp,
             other_values: entity.other_values,
             action: Some(shared_action.clone()),
-        };
+        })
+        .collect();
+
+    lock.push_all(entities)
+}
 
-        if let Err(e) = lock.push(entity) {
-            tracing::error!(error = %e, "Failed to push entity to queue");
+async fn refused_action_handler(socket: SocketRef, Data(data): Data<ActionMessage>) {
+    tracing::warn!(
+        "Refused {:?} action from socket {} that can not write to a game",
+        data.action,
+        socket.id
+    );
+    refuse_action(&socket, data);
+}
+
+fn refuse_action(socket: &SocketRef, data: ActionMessage) {
+    let message = ActionRefusedMessage {
+        action: data.action,
+        uids: data.data.into_iter().map(|entity| entity.uid).collect(),
+    };
+    socket.emit(Event::ActionRefused, &message).ok();
+}
+
+fn register_game_handlers<T: AppStateTrait>(
+    socket: &SocketRef,
+    role: ConnectionRole,
+    config: &WebsocketConfig,
+) {
+    if role.can_write() {
+        socket.on(Event::Action, action_handler::<T>);
+        if config.entity_locks {
+            socket.on(Event::Lock, locks::lock_handler::<T>);
+            socket.on(Event::Unlock, locks::unlock_handler::<T>);
+        } else {
+            socket.on(Event::Lock, locks::refused_lock_handler);
+            socket.on(Event::Unlock, locks::refused_lock_handler);
         }
+    } else {
+        register_read_only_handlers(socket);
     }
 }
 
+fn register_read_only_handlers(socket: &SocketRef) {
+    socket.on(Event::Action, refused_action_handler);
+    socket.on(Event::Lock, locks::refused_lock_handler);
+    socket.on(Event::Unlock, locks::refused_lock_handler);
+}
+
+/// Buffers transitive entities and schedules a single forward of the latest
+/// states when none is pending
+fn coalesce_transitive(
+    socket: SocketRef,
+    broadcaster: RoomBroadcaster,
+    buffer: Arc<TransitiveBuffer>,
+    entities: Vec<ClientsideEntity>,
+) {
+    let Some(delay) = buffer.push(entities) else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+
+        let _flushing = buffer.start_flush().await;
+        let message = ActionMessage {
+            action: Action::Transitive,
+            data: buffer.take(),
+        };
+        if message.data.is_empty() {
+            return;
+        }
+
+        broadcaster
+            .emit(&socket, buffer.room().to_string(), Event::Action, &message)
+            .await;
+    });
+}
+
 async fn action_handler<T: AppStateTrait>(
     socket: SocketRef,
-    Data(data): Data<ActionMessage>,
+    Data(mut data): Data<ActionMessage>,
     State(app_state): State<T>,
     Extension(auth): Extension<WebsocketAuthMessage>,
 ) {
-    let rooms = socket.rooms();
-    socket.to(rooms).emit(ACTION, &data).await.ok();
+    if app_state.get_shutdown().is_shutting_down() {
+        tracing::debug!("Refused action from socket {} during shutdown", socket.id);
+        refuse_action(&socket, data);
+        return;
+    }
+
+    // Entities locked by others can not be recreated either
+    if matches!(
+        data.action,
+        Action::Update | Action::Create | Action::Delete
+    ) {
+        let locks = app_state.get_entity_locks();
+        let holder = socket.id.to_string();
+        let (allowed, refused): (Vec<_>, Vec<_>) = data.data.into_iter().partition(|entity| {
+            locks.can_modify(
+                &GameIdAndUIdCombo::new(auth.game, entity.uid.clone()),
+                &holder,
+            )
+        });
+
+        if !refused.is_empty() {
+            tracing::debug!(
+                "Socket {} tried to modify {} entities locked by others",
+                socket.id,
+                refused.len()
+            );
+            let message = ActionRefusedMessage {
+                action: data.action.clone(),
+                uids: refused.into_iter().map(|entity| entity.uid).collect(),
+            };
+            socket.emit(Event::ActionRefused, &message).ok();
+        }
+
+        if allowed.is_empty() {
+            return;
+        }
+        data.data = allowed;
+    }
+
+    if data.action == Action::Transitive
+        && let Some(buffer) = socket.extensions.get::<Arc<TransitiveBuffer>>()
+    {
+        coalesce_transitive(socket, app_state.get_broadcaster(), buffer, data.data);
+        return;
+    }
 
     match data.action {
         Action::Update | Action::Create | Action::Delete => {
-            entity_handler(data, app_state, auth).await
+            if let Some(buffer) = socket.extensions.get::<Arc<TransitiveBuffer>>() {
+                buffer
+                    .discard(data.data.iter().map(|entity| &entity.uid))
+                    .await;
+            }
+
+            if let Err(e) = entity_handler(&data, &app_state, &auth).await {
+                tracing::error!(error = %e, "Failed to push entities to queue");
+                refuse_action(&socket, data);
+                return;
+            }
         }
         Action::Transitive => (),
         Action::Other(_) => tracing::warn!("Received unknown action: {:?}", data.action),
     }
+
+    app_state
+        .get_broadcaster()
+        .emit(&socket, game_room(auth.game), Event::Action, &data)
+        .await;
+
+    if data.action == Action::Delete {
+        let uids = data.data.into_iter().map(|entity| entity.uid).collect();
+        locks::release_deleted_locks(&socket, &app_state, auth.game, uids).await;
+    }
+}
+
+enum JoinOutcome {
+    Finished,
+    Cancelled,
+    Failed,
+}
+
+/// Streams entities of a game to the socket page by page, each page is sent
+/// as one chunk and at most `join_in_flight_chunks` chunks are left unacknowledged
+/// before waiting on the client
+async fn stream_entities(
+    socket: &SocketRef,
+    db: &DatabaseConnection,
+    codecs: &EntityCodecs,
+    game_id: i32,
+    config: &WebsocketConfig,
+) -> JoinOutcome {
+    let entity_manager = EntityManager::new();
+
+    let total = match entity_manager.count_entities(db, game_id).await {
+        Ok(t) => t,
+        Err(e) => {
+            tracing::error!("Failed to count entities: {}", e);
+            return JoinOutcome::Failed;
+        }
+    };
+
+    tracing::debug!(
+        "Sending {} entities in chunks of {}",
+        total,
+        config.join_chunk_size
+    );
+
+    let mut in_flight = VecDeque::with_capacity(config.join_in_flight_chunks);
+    let mut last_uid: Option<String> = None;
+    let mut sent = 0;
+    loop {
+        let page = match entity_manager
+            .load_entities_page(db, game_id, last_uid.as_deref(), config.join_chunk_size)
+            .await
+        {
+            Ok(p) => p,
+            Err(e) => {
+                tracing::error!("Failed to load entities: {}", e);
+                return JoinOutcome::Failed;
+            }
+        };
+
+        let Some(last) = page.last() else {
+            break;
+        };
+        last_uid = Some(last.uid.clone());
+
+        // Dictionaries can be trained by other nodes
+        if let Err(e) = codecs.load_missing_dictionaries(db, &page).await {
+            tracing::error!("Failed to load entity dictionaries: {}", e);
+            return JoinOutcome::Failed;
+        }
+
+        let chunk = match codecs.decompress_vec(page) {
+            Ok(e) => e,
+            Err(e) => {
+                tracing::error!("Failed to decompress entities: {}", e);
+                return JoinOutcome::Failed;
+            }
+        };
+
+        sent += chunk.len() as u64;
+        let ack = socket.emit_with_ack::<_, JoinChunkAck>(
+            Event::Join,
+            &JoinChunk {
+                progress: JoinProgress { sent, total },
+                data: chunk,
+            },
+        );
+
+        match ack {
+            Ok(ack) => in_flight.push_back(ack),
+            Err(e) => {
+                tracing::error!("Failed to send join chunk: {}", e);
+                return JoinOutcome::Failed;
+            }
+        }
+
+        if in_flight.len() >= config.join_in_flight_chunks {
+            let ack = in_flight
+                .pop_front()
+                .expect("In flight chunks are not empty");
+            if let Some(outcome) = await_join_ack(ack).await {
+                return outcome;
+            }
+        }
+    }
+
+    while let Some(ack) = in_flight.pop_front() {
+        if let Some(outcome) = await_join_ack(ack).await {
+            return outcome;
+        }
+    }
+
+    JoinOutcome::Finished
+}
+
+/// Returns an outcome only when the join should stop
+async fn await_join_ack(ack: AckStream<JoinChunkAck>) -> Option<JoinOutcome> {
+    match ack.await {
+        Ok(JoinChunkAck { cancel: true }) => Some(JoinOutcome::Cancelled),
+        Ok(_) => None,
+        Err(AckError::Decode(e)) => {
+            tracing::warn!(error = %e, "Failed to decode join chunk ack, continuing");
+            None
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Join chunk was not acknowledged");
+            Some(JoinOutcome::Failed)
+        }
+    }
 }
 
 #[tracing::instrument(skip(socket, app_state))]
@@ -100,88 +373,166 @@ async fn join_handler<T: AppStateTrait>(
     socket: SocketRef,
     State(app_state): State<T>,
     Extension(auth): Extension<WebsocketAuthMessage>,
+) {
+    join_game(&socket, &app_state, &auth).await;
+}
+
+async fn join_game<T: AppStateTrait>(
+    socket: &SocketRef,
+    app_state: &T,
+    auth: &WebsocketAuthMessage,
 ) {
     let game_id = auth.game;
-    let room = format!("room-{game_id}");
+    let room = game_room(game_id);
 
     tracing::debug!("Socket {} joining room {}", socket.id, auth.game);
-    if socket.extensions.get::<JoinedFlag>().is_some() {
+    if socket.extensions.get::<JoinedFlag>().is_some()
+        || socket.extensions.insert(JoiningFlag).is_some()
+    {
         tracing::debug!("Socket {} double join, ignoring", socket.id);
         return;
     }
 
-    socket.on(ACTION, action_handler::<T>);
-    socket.join(room);
+    let config = app_state.get_websocket_config();
+    register_game_handlers::<T>(socket, auth.role, &config);
+    socket.join(room.clone());
 
-    tracing::debug!("Fetching queued entities");
-    let queue = app_state.get_entity_queue();
-    {
-        let mut lock = queue.lock().await;
-        if let Some(handle) = lock.flush().await {
-            handle.await.ok();
-        }
+    if let Some(interval) = config.transitive_interval() {
+        socket
+            .extensions
+            .insert(Arc::new(TransitiveBuffer::new(room.clone(), interval)));
     }
 
-    tracing::debug!("Starting database entity fetch");
+    tracing::debug!("Fetching queued entities");
+    let (entity_queues, broadcaster) = (app_state.get_entity_queues(), app_state.get_broadcaster());
+    tokio::join!(
+        entity_queues.flush_game(game_id),
+        broadcaster.flush_remote(game_id)
+    );
+
+    tracing::debug!("Starting database entity stream");
     let db = app_state.get_db();
-    let transaction = match db.begin().await {
-        Ok(t) => t,
-        Err(e) => {
-            tracing::error!("Failed to begin EntityQueue flush transaction: {}", e);
+    let codecs = app_state.get_entity_codecs();
+    match stream_entities(socket, &db, &codecs, game_id, &config).await {
+        JoinOutcome::Finished => (),
+        JoinOutcome::Cancelled => {
+            tracing::debug!("Socket {} cancelled join", socket.id);
+            leave_game(socket, app_state, auth).await;
+            socket.emit(Event::JoinCancelled, &()).ok();
             return;
         }
-    };
-    let entity_manager = EntityManager::new();
-
-    let db_comporessed_entities = match entity_manager.load_entities(&transaction, game_id).await {
-        Ok(e) => e,
-        Err(e) => {
-            tracing::error!("Failed to load entities: {}", e);
+        JoinOutcome::Failed => {
+            leave_game(socket, app_state, auth).await;
             return;
         }
-    };
+    }
+    tracing::debug!("Finished database entity stream");
 
-    match transaction.commit().await {
-        Ok(_) => (),
-        Err(e) => {
-            tracing::error!("Failed to commit EntityQueue flush transaction: {}", e);
-            return;
-        }
+    tracing::debug!("Socket join finished sent");
+    socket.emit(Event::JoinFinished, &()).ok();
+
+    socket.extensions.insert(JoinedFlag);
+    socket.extensions.remove::<JoiningFlag>();
+    tracing::debug!("Socket joined");
+
+    let presence = Presence::of_room(socket, &room, None);
+    app_state
+        .get_broadcaster()
+        .emit_presence(socket, room, presence)
+        .await;
+}
+
+/// Removes the socket from the game room and stops accepting its actions,
+/// the socket itself stays connected and can join again
+async fn leave_game<T: AppStateTrait>(
+    socket: &SocketRef,
+    app_state: &T,
+    auth: &WebsocketAuthMessage,
+) {
+    let room = game_room(auth.game);
+
+    register_read_only_handlers(socket);
+    locks::release_socket_locks(socket, app_state).await;
+    socket.leave(room.clone());
+    if let Some(buffer) = socket.extensions.remove::<Arc<TransitiveBuffer>>() {
+        buffer.close().await;
     }
-    tracing::debug!("Finished database entity fetch");
+    socket.extensions.remove::<JoiningFlag>();
+
+    if socket.extensions.remove::<JoinedFlag>().is_some() {
+        let presence = Presence::of_room(socket, &room, Some(socket.id));
+        app_state
+            .get_broadcaster()
+            .emit_presence(socket, room, presence)
+            .await;
+    }
+}
 
-    let entities = match Entity::decompress_vec(db_comporessed_entities) {
-        Ok(e) => e,
-        Err(e) => {
-            tracing::error!("Failed to decompress entities: {}", e);
-            return;
-        }
+#[tracing::instrument(skip(socket, app_state))]
+async fn leave_handler<T: AppStateTrait>(
+    socket: SocketRef,
+    State(app_state): State<T>,
+    Extension(auth): Extension<WebsocketAuthMessage>,
+) {
+    tracing::debug!("Socket {} leaving room {}", socket.id, auth.game);
+    leave_game(&socket, &app_state, &auth).await;
+    socket.emit(Event::Left, &auth.game).ok();
+}
+
+#[tracing::instrument(skip(socket, app_state))]
+async fn switch_game_handler<T: AppStateTrait>(
+    socket: SocketRef,
+    Data(data): Data<SwitchGameMessage>,
+    State(app_state): State<T>,
+    Extension(auth): Extension<WebsocketAuthMessage>,
+) {
+    let new_auth = WebsocketAuthMessage {
+        game: data.game,
+        ..auth.clone()
     };
 
-    let total = entities.len();
-    let mut sent = 0;
-    tracing::debug!("Sending entities in {} chunks", total / CHUNK_SIZE);
-    entities.chunks(CHUNK_SIZE).for_each(|chunk| {
-        sent += chunk.len();
-        socket
-            .emit(
-                JOIN_EVENT,
-                &serde_json::json!({
-                    "progress": {
-                        "sent": sent,
-                        "total": total
-                    },
-                    "data": chunk
-                }),
-            )
-            .ok();
-    });
+    if let Err(e) = new_auth.authenticate(&app_state) {
+        tracing::warn!(error = %e, "Socket {} is not allowed to switch to game {}", socket.id, data.game);
+        socket.emit(Event::SwitchGameRefused, &data.game).ok();
+        return;
+    }
 
-    tracing::debug!("Socket join finished sent");
-    socket.emit(JOIN_FINISHED_EVENT, &()).ok();
+    tracing::debug!(
+        "Socket {} switching from game {} to game {}",
+        socket.id,
+        auth.game,
+        new_auth.game
+    );
+    leave_game(&socket, &app_state, &auth).await;
+    socket.extensions.insert(new_auth.clone());
 
-    socket.extensions.insert(JoinedFlag);
-    tracing::debug!("Socket joined");
+    join_game(&socket, &app_state, &new_auth).await;
+}
+
+async fn disconnect_handler<T: AppStateTrait>(
+    socket: SocketRef,
+    reason: DisconnectReason,
+    State(app_state): State<T>,
+) {
+    tracing::info!("Socket disconnected: {}, reason: {:?}", socket.id, reason);
+
+    locks::release_socket_locks(&socket, &app_state).await;
+    if let Some(buffer) = socket.extensions.remove::<Arc<TransitiveBuffer>>() {
+        buffer.close().await;
+    }
+
+    if socket.extensions.remove::<JoinedFlag>().is_none() {
+        return;
+    }
+
+    if let Some(auth) = socket.extensions.get::<WebsocketAuthMessage>() {
+        let room = game_room(auth.game);
+        let presence = Presence::of_room(&socket, &room, Some(socket.id));
+        app_state
+            .get_broadcaster()
+            .emit_presence(&socket, room, presence)
+            .await;
+    }
 }
 
 pub fn on_connect<T: AppStateTrait>(socket: SocketRef) {
@@ -190,17 +541,14 @@ pub fn on_connect<T: AppStateTrait>(socket: SocketRef) {
         socket.ns()
     );
 
-    let socket_clone = socket.clone();
-    socket.on_disconnect(move |reason: DisconnectReason| {
-        tracing::info!(
-            "Socket disconnected: {}, reason: {:?}",
-            socket_clone.id,
-            reason
-        );
-        socket_clone.extensions.remove::<JoinedFlag>();
-    });
+    socket.on_disconnect(disconnect_handler::<T>);
+    socket
+        .emit(Event::Protocol, &ProtocolHandshake::default())
+        .ok();
 
-    socket.on(JOIN_EVENT, join_handler::<T>);
+    socket.on(Event::Join, join_handler::<T>);
+    socket.on(Event::Leave, leave_handler::<T>);
+    socket.on(Event::SwitchGame, switch_game_handler::<T>);
 }
 
 fn auth_middleware<T: AppStateTrait>(
@@ -209,6 +557,23 @@ fn auth_middleware<T: AppStateTrait>(
     Data(auth): Data<WebsocketAuthMessage>,
 ) -> Result<(), websocket_auth::Error> {
     tracing::debug!("Authenticating user: {}", auth.user_token);
+    match ProtocolCompatibility::check(auth.protocol_version) {
+        ProtocolCompatibility::Compatible => (),
+        ProtocolCompatibility::Unknown => {
+            tracing::warn!(
+                "Socket {} did not send a protocol version, expected version {}",
+                socket.id,
+                PROTOCOL_VERSION
+            );
+        }
+        ProtocolCompatibility::Incompatible { client } => {
+            return Err(websocket_auth::Error::IncompatibleProtocolVersion {
+                client,
+                server: PROTOCOL_VERSION,
+            });
+        }
+    }
+
     match auth.authenticate(&app_state) {
         Ok(()) => {
             socket.extensions.insert(auth);
@@ -218,6 +583,17 @@ fn auth_middleware<T: AppStateTrait>(
     }
 }
 
+/// Notifies every socket once a shutdown is requested and closes them
+/// after queued entities are saved
+async fn close_on_shutdown(io: SocketIo, shutdown: ShutdownHandle) {
+    shutdown.requested().await;
+    io.emit(Event::ServerShuttingDown, &()).await.ok();
+
+    shutdown.finished().await;
+    tracing::info!("Closing sockets");
+    io.close().await;
+}
+
 pub fn get_router<T: AppStateTrait>(state: T) -> axum::Router {
     let (service, io) = SocketIoBuilder::new()
         .with_state(state.clone())
@@ -227,6 +603,10 @@ pub fn get_router<T: AppStateTrait>(state: T) -> axum::Router {
         .build_svc();
 
     io.ns("/", on_connect::<T>.with(auth_middleware::<T>));
+    let broadcaster = state.get_broadcaster();
+    broadcaster.forward_remote(io.clone(), state.get_entity_queues());
+    broadcaster.keep_alive(io.clone());
+    tokio::spawn(close_on_shutdown(io, state.get_shutdown()));
 
     axum::Router::new().route_service(
         "/socket.io/",
@@ -235,3 +615,363 @@ pub fn get_router<T: AppStateTrait>(state: T) -> axum::Router {
             .service(service),
     )
 }
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use axum_test::TestServer;
+    use serde_json::{Value, json};
+
+    use crate::{
+        api::websockets::{
+            get_router,
+            protocol::{Event, PROTOCOL_VERSION},
+        },
+        cdn::filesystem::temp_file_adapter::TempFileStore,
+        models::game::GameManager,
+        utils::test_utils::{TestSocket, new_test_socket_app},
+        webserver::{
+            router::app_state::{AppState, AppStateConfig, AppStateTrait},
+            services::{
+                broadcast::{RoomBroadcaster, memory_adapter::MemoryAdapter},
+                entity_queues::EntityQueues,
+            },
+        },
+    };
+
+    async fn socket_server(
+        configure: impl FnOnce(&mut AppStateConfig<TempFileStore>),
+    ) -> (TestServer, i32) {
+        let mut config = AppStateConfig::get_test_config().await;
+        configure(&mut config);
+        let state = AppState::new(config).await;
+        let game = GameManager::new()
+            .create_game(&state.get_db())
+            .await
+            .unwrap();
+
+        (new_test_socket_app(get_router(state)), game.id)
+    }
+
+    fn auth(game: i32, role: &str) -> Value {
+        json!({
+            "userToken": "test-token",
+            "game": game,
+            "role": role,
+            "protocolVersion": PROTOCOL_VERSION,
+        })
+    }
+
+    fn action(action: &str, uid: &str, timestamp: i64) -> Value {
+        json!({
+            "action": action,
+            "data": [{ "uid": uid, "kind": "token", "timestamp": timestamp }],
+        })
+    }
+
+    /// Asserts no action reaches the socket within `within`, other events are skipped
+    async fn assert_no_action(socket: &mut TestSocket, within: Duration) {
+        while let Some(received) = socket.next_event_within(within).await {
+            assert_ne!(
+                Event::Action.as_str(),
+                received.event,
+                "Unexpected action {}",
+                received.data
+            );
+        }
+    }
+
+    /// Waits until the socket is sent the expected presence, earlier presences are skipped
+    async fn wait_for_presence(socket: &mut TestSocket, players: u32, spectators: u32) {
+        let expected = json!({ "players": players, "spectators": spectators });
+        while socket.wait_for(Event::Presence).await != expected {}
+    }
+
+    #[tokio::test]
+    async fn buffered_transitive_frames_are_not_forwarded_after_persisted_actions() {
+        let (server, game) =
+            socket_server(|config| config.websocket_config.transitive_rate = 1).await;
+
+        let mut player = TestSocket::connect(&server, auth(game, "player")).await;
+        player.join().await;
+        let mut peer = TestSocket::connect(&server, auth(game, "player")).await;
+        peer.join().await;
+
+        player
+            .emit(Event::Action, action("transitive", "token-1", 1))
+            .await;
+        let forwarded = peer.wait_for(Event::Action).await;
+        assert_eq!(action("transitive", "token-1", 1), forwarded);
+
+        // Buffered until the next flush in a second
+        player
+            .emit(Event::Action, action("transitive", "token-1", 2))
+            .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        player
+            .emit(Event::Action, action("update", "token-1", 3))
+            .await;
+
+        let persisted = peer.wait_for(Event::Action).await;
+        assert_eq!(action("update", "token-1", 3), persisted);
+        assert_no_action(&mut peer, Duration::from_millis(1500)).await;
+
+        player
+            .emit(Event::Action, action("transitive", "token-2", 4))
+            .await;
+        peer.wait_for(Event::Action).await;
+
+        // Buffered frame must not reach the room after the player left it
+        player
+            .emit(Event::Action, action("transitive", "token-2", 5))
+            .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        player.emit(Event::Leave, ()).await;
+        player.wait_for(Event::Left).await;
+
+        assert_no_action(&mut peer, Duration::from_millis(1500)).await;
+    }
+
+    #[tokio::test]
+    async fn second_join_during_the_entity_stream_is_ignored() {
+        let (server, game) = socket_server(|_| ()).await;
+
+        let mut player = TestSocket::connect(&server, auth(game, "player")).await;
+        player.join().await;
+        player
+            .emit(Event::Action, action("create", "token-1", 1))
+            .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // The stream waits for the ack of its only chunk
+        let mut joining = TestSocket::connect(&server, auth(game, "player")).await;
+        joining.emit(Event::Join, ()).await;
+        joining.emit(Event::Join, ()).await;
+
+        let mut chunks = Vec::new();
+        while let Some(received) = joining.next_event_within(Duration::from_millis(500)).await {
+            assert_ne!(Event::JoinFinished.as_str(), received.event);
+            if received.event == Event::Join.as_str() {
+                chunks.push(received.ack.unwrap());
+            }
+        }
+        assert_eq!(1, chunks.len());
+
+        joining.ack(chunks[0], json!({ "cancel": false })).await;
+        joining.wait_for(Event::JoinFinished).await;
+        while let Some(received) = joining.next_event_within(Duration::from_millis(500)).await {
+            assert_ne!(Event::Join.as_str(), received.event);
+            assert_ne!(Event::JoinFinished.as_str(), received.event);
+        }
+    }
+
+    #[tokio::test]
+    async fn spectator_actions_and_locks_are_refused() {
+        let (server, game) = socket_server(|_| ()).await;
+
+        let mut player = TestSocket::connect(&server, auth(game, "player")).await;
+        player.join().await;
+        let mut spectator = TestSocket::connect(&server, auth(game, "spectator")).await;
+        spectator.join().await;
+
+        spectator
+            .emit(Event::Action, action("update", "token-1", 1))
+            .await;
+        let refused = spectator.wait_for(Event::ActionRefused).await;
+        assert_eq!(json!({ "action": "update", "uids": ["token-1"] }), refused);
+        assert_no_action(&mut player, Duration::from_millis(500)).await;
+
+        let response = spectator
+            .emit_with_ack(Event::Lock, json!({ "uid": "token-1" }))
+            .await;
+        assert_eq!(json!(false), response["granted"]);
+        assert!(response["state"]["holder"].is_null());
+
+        // The spectator holds no lock the player would have to wait for
+        let response = player
+            .emit_with_ack(Event::Lock, json!({ "uid": "token-1" }))
+            .await;
+        assert_eq!(json!(true), response["granted"]);
+    }
+
+    #[tokio::test]
+    async fn locks_are_refused_when_disabled() {
+        let (server, game) =
+            socket_server(|config| config.websocket_config.entity_locks = false).await;
+
+        let mut player = TestSocket::connect(&server, auth(game, "player")).await;
+        player.join().await;
+
+        let response = player
+            .emit_with_ack(Event::Lock, json!({ "uid": "token-1" }))
+            .await;
+        assert_eq!(json!(false), response["granted"]);
+        assert!(response["state"]["holder"].is_null());
+
+        // Actions still go through without locks
+        let mut other = TestSocket::connect(&server, auth(game, "player")).await;
+        other.join().await;
+        player
+            .emit(Event::Action, action("update", "token-1", 1))
+            .await;
+        other.wait_for(Event::Action).await;
+    }
+
+    #[tokio::test]
+    async fn presence_follows_join_leave_and_disconnect() {
+        let (server, game) = socket_server(|_| ()).await;
+
+        let mut player = TestSocket::connect(&server, auth(game, "player")).await;
+        player.join().await;
+        wait_for_presence(&mut player, 1, 0).await;
+
+        let mut spectator = TestSocket::connect(&server, auth(game, "spectator")).await;
+        spectator.join().await;
+        wait_for_presence(&mut spectator, 1, 1).await;
+        wait_for_presence(&mut player, 1, 1).await;
+
+        spectator.emit(Event::Leave, ()).await;
+        spectator.wait_for(Event::Left).await;
+        wait_for_presence(&mut player, 1, 0).await;
+
+        spectator.join().await;
+        wait_for_presence(&mut player, 1, 1).await;
+
+        spectator.close().await;
+        wait_for_presence(&mut player, 1, 0).await;
+    }
+
+    #[tokio::test]
+    async fn locked_entities_can_not_be_recreated_and_deletes_release_locks() {
+        let (server, game) = socket_server(|_| ()).await;
+
+        let mut holder = TestSocket::connect(&server, auth(game, "player")).await;
+        holder.join().await;
+        let mut other = TestSocket::connect(&server, auth(game, "player")).await;
+        other.join().await;
+
+        holder
+            .emit(Event::Action, action("create", "token-1", 1))
+            .await;
+        other.wait_for(Event::Action).await;
+        let response = holder
+            .emit_with_ack(Event::Lock, json!({ "uid": "token-1" }))
+            .await;
+        assert_eq!(json!(true), response["granted"]);
+
+        other
+            .emit(Event::Action, action("create", "token-1", 2))
+            .await;
+        let refused = other.wait_for(Event::ActionRefused).await;
+        assert_eq!(json!({ "action": "create", "uids": ["token-1"] }), refused);
+        assert_no_action(&mut holder, Duration::from_millis(500)).await;
+
+        holder
+            .emit(Event::Action, action("delete", "token-1", 3))
+            .await;
+        assert_eq!(
+            action("delete", "token-1", 3),
+            other.wait_for(Event::Action).await
+        );
+        let state = other.wait_for(Event::LockState).await;
+        assert_eq!(
+            json!({ "uid": "token-1", "holder": null, "expiresInMs": null }),
+            state
+        );
+
+        other
+            .emit(Event::Action, action("create", "token-1", 4))
+            .await;
+        assert_eq!(
+            action("create", "token-1", 4),
+            holder.wait_for(Event::Action).await
+        );
+    }
+
+    #[tokio::test]
+    async fn presence_and_lock_state_reach_sockets_on_other_nodes() {
+        let adapter = Arc::new(MemoryAdapter::new());
+        let (first, game) = socket_server(|config| {
+            config.broadcaster = RoomBroadcaster::new(adapter.clone());
+        })
+        .await;
+        let (second, _) = socket_server(|config| {
+            config.broadcaster = RoomBroadcaster::new(adapter.clone());
+        })
+        .await;
+
+        let mut player = TestSocket::connect(&first, auth(game, "player")).await;
+        player.join().await;
+        wait_for_presence(&mut player, 1, 0).await;
+
+        let mut spectator = TestSocket::connect(&second, auth(game, "spectator")).await;
+        spectator.join().await;
+        wait_for_presence(&mut spectator, 1, 1).await;
+        wait_for_presence(&mut player, 1, 1).await;
+
+        player.emit(Event::Lock, json!({ "uid": "token-1" })).await;
+        let state = spectator.wait_for(Event::LockState).await;
+        assert_eq!("token-1", state["uid"]);
+        assert!(state["holder"].is_string());
+
+        player.close().await;
+        let state = spectator.wait_for(Event::LockState).await;
+        assert!(state["holder"].is_null());
+        wait_for_presence(&mut spectator, 0, 1).await;
+    }
+
+    #[tokio::test]
+    async fn join_streams_entities_queued_on_other_nodes() {
+        let adapter = Arc::new(MemoryAdapter::new());
+        let first_config = AppStateConfig::get_test_config().await;
+        let db = first_config.database.clone();
+        let mut second_config = AppStateConfig::get_test_config().await;
+        second_config.database = db.clone();
+        second_config.entity_queues = Arc::new(
+            EntityQueues::new(db.clone()).with_codecs(second_config.entity_codecs.clone()),
+        );
+        let game = GameManager::new().create_game(&db).await.unwrap();
+
+        let [first, second] = [first_config, second_config].map(|mut config| {
+            config.broadcaster = RoomBroadcaster::new(adapter.clone());
+            config
+        });
+        let first = new_test_socket_app(get_router(AppState::new(first).await));
+        let second = new_test_socket_app(get_router(AppState::new(second).await));
+
+        let mut watcher = TestSocket::connect(&first, auth(game.id, "player")).await;
+        watcher.join().await;
+        let mut player = TestSocket::connect(&second, auth(game.id, "player")).await;
+        player.join().await;
+
+        // Queued on the second node until it is asked to flush
+        player
+            .emit(Event::Action, action("create", "token-1", 1))
+            .await;
+        watcher.wait_for(Event::Action).await;
+
+        let mut joining = TestSocket::connect(&first, auth(game.id, "spectator")).await;
+        joining.emit(Event::Join, ()).await;
+        let mut streamed = Vec::new();
+        loop {
+            let received = joining.next_event().await;
+            if received.event == Event::JoinFinished.as_str() {
+                break;
+            }
+            if received.event == Event::Join.as_str() {
+                streamed.extend(
+                    received.data["data"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|entity| entity["uid"].clone()),
+                );
+            }
+            if let Some(id) = received.ack {
+                joining.ack(id, json!({ "cancel": false })).await;
+            }
+        }
+        assert_eq!(vec![json!("token-1")], streamed);
+    }
+}
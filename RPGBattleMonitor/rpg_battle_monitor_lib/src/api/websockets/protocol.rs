@@ -0,0 +1,325 @@
+//! Payloads of every socket event exchanged with the client
+//!
+//! TypeScript bindings for these types are generated with `ts-rs` by running
+//! `cargo test` and are written to `TS_RS_EXPORT_DIR`, the frontend websocket
+//! bindings directory.
+
+use std::{borrow::Cow, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::entity::{ClientsideEntity, Entity, UId};
+
+/// Version of the socket protocol, bump it on any breaking change to
+/// the events or payloads in this module
+pub const PROTOCOL_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "kebab-case")]
+#[ts(export)]
+pub enum Event {
+    Protocol,
+    Join,
+    JoinFinished,
+    JoinCancelled,
+    Leave,
+    Left,
+    SwitchGame,
+    SwitchGameRefused,
+    Action,
+    ActionRefused,
+    Presence,
+    Lock,
+    Unlock,
+    LockState,
+    ServerShuttingDown,
+}
+
+impl Event {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Event::Protocol => "protocol",
+            Event::Join => "join",
+            Event::JoinFinished => "join-finished",
+            Event::JoinCancelled => "join-cancelled",
+            Event::Leave => "leave",
+            Event::Left => "left",
+            Event::SwitchGame => "switch-game",
+            Event::SwitchGameRefused => "switch-game-refused",
+            Event::Action => "action",
+            Event::ActionRefused => "action-refused",
+            Event::Presence => "presence",
+            Event::Lock => "lock",
+            Event::Unlock => "unlock",
+            Event::LockState => "lock-state",
+            Event::ServerShuttingDown => "server-shutting-down",
+        }
+    }
+}
+
+impl AsRef<str> for Event {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<Event> for Cow<'static, str> {
+    fn from(value: Event) -> Self {
+        Cow::Borrowed(value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolCompatibility {
+    Compatible,
+    /// Client did not send its protocol version
+    Unknown,
+    Incompatible {
+        client: u32,
+    },
+}
+
+impl ProtocolCompatibility {
+    pub fn check(client_version: Option<u32>) -> Self {
+        match client_version {
+            Some(PROTOCOL_VERSION) => Self::Compatible,
+            Some(client) => Self::Incompatible { client },
+            None => Self::Unknown,
+        }
+    }
+}
+
+/// Sent to the client right after it connects
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct ProtocolHandshake {
+    pub version: u32,
+}
+
+impl Default for ProtocolHandshake {
+    fn default() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, TS)]
+#[serde(rename_all = "kebab-case")]
+#[ts(export)]
+pub enum Action {
+    Update,
+    Create,
+    Delete,
+    Transitive,
+    Other(serde_json::Value),
+}
+
+impl Action {
+    pub fn is_other(&self) -> bool {
+        matches!(self, Action::Other(_))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ActionMessage {
+    pub action: Action,
+    pub data: Vec<ClientsideEntity>,
+}
+
+/// Sent back to the socket with entities it was not allowed to change
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct ActionRefusedMessage {
+    pub action: Action,
+    pub uids: Vec<UId>,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct JoinProgress {
+    #[ts(type = "number")]
+    pub sent: u64,
+    #[ts(type = "number")]
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct JoinChunk {
+    pub progress: JoinProgress,
+    pub data: Vec<Entity>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[serde(default)]
+#[ts(export)]
+pub struct JoinChunkAck {
+    pub cancel: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct SwitchGameMessage {
+    pub game: i32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct Presence {
+    pub players: u32,
+    pub spectators: u32,
+}
+
+/// Payload of both lock and unlock events
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct LockMessage {
+    pub uid: UId,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct LockState {
+    pub uid: UId,
+    /// Socket id of the lock holder, entity is unlocked when missing
+    pub holder: Option<String>,
+    #[ts(type = "number | null")]
+    pub expires_in_ms: Option<u64>,
+}
+
+impl LockState {
+    pub fn locked(uid: UId, holder: String, expires_in: Duration) -> Self {
+        Self {
+            uid,
+            holder: Some(holder),
+            expires_in_ms: Some(expires_in.as_millis() as u64),
+        }
+    }
+
+    pub fn unlocked(uid: UId) -> Self {
+        Self {
+            uid,
+            holder: None,
+            expires_in_ms: None,
+        }
+    }
+}
+
+/// Acknowledgement of a lock or unlock event
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct LockResponse {
+    pub granted: bool,
+    pub state: LockState,
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::api::websockets::protocol::{Event, PROTOCOL_VERSION, ProtocolCompatibility};
+
+    const EVENTS: [Event; 15] = [
+        Event::Protocol,
+        Event::Join,
+        Event::JoinFinished,
+        Event::JoinCancelled,
+        Event::Leave,
+        Event::Left,
+        Event::SwitchGame,
+        Event::SwitchGameRefused,
+        Event::Action,
+        Event::ActionRefused,
+        Event::Presence,
+        Event::Lock,
+        Event::Unlock,
+        Event::LockState,
+        Event::ServerShuttingDown,
+    ];
+
+    /// Fails to compile when an event is added without adding it to `EVENTS`
+    fn _every_event_is_listed(event: Event) {
+        match event {
+            Event::Protocol
+            | Event::Join
+            | Event::JoinFinished
+            | Event::JoinCancelled
+            | Event::Leave
+            | Event::Left
+            | Event::SwitchGame
+            | Event::SwitchGameRefused
+            | Event::Action
+            | Event::ActionRefused
+            | Event::Presence
+            | Event::Lock
+            | Event::Unlock
+            | Event::LockState
+            | Event::ServerShuttingDown => (),
+        }
+    }
+
+    /// Sockets emit events by `as_str`, the bindings name them by their serde name
+    #[test]
+    fn event_names_match_serialized_events() {
+        for event in EVENTS {
+            assert_eq!(
+                serde_json::Value::String(event.as_str().to_string()),
+                serde_json::to_value(event).unwrap()
+            );
+            assert_eq!(
+                event,
+                serde_json::from_value::<Event>(event.as_str().into()).unwrap()
+            );
+        }
+    }
+
+    /// `ts-rs` can not export constants so the protocol version is written next to
+    /// the generated bindings by hand
+    #[test]
+    fn export_protocol_version() {
+        let dir = PathBuf::from(
+            std::env::var("TS_RS_EXPORT_DIR").unwrap_or_else(|_| "./bindings".to_string()),
+        );
+        let path = dir.join("ProtocolVersion.ts");
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            &path,
+            format!(
+                "// This file was generated by `export_protocol_version` test. Do not edit this file manually.\n\nexport const PROTOCOL_VERSION = {PROTOCOL_VERSION};\n"
+            ),
+        )
+        .unwrap();
+
+        let exported = std::fs::read_to_string(&path).unwrap();
+        let version = exported
+            .lines()
+            .find_map(|line| line.strip_prefix("export const PROTOCOL_VERSION = "))
+            .and_then(|value| value.strip_suffix(';'))
+            .map(|value| value.parse::<u32>().unwrap());
+        assert_eq!(Some(PROTOCOL_VERSION), version);
+    }
+
+    #[test]
+    fn protocol_compatibility() {
+        assert_eq!(
+            ProtocolCompatibility::Compatible,
+            ProtocolCompatibility::check(Some(PROTOCOL_VERSION))
+        );
+        assert_eq!(
+            ProtocolCompatibility::Unknown,
+            ProtocolCompatibility::check(None)
+        );
+        assert_eq!(
+            ProtocolCompatibility::Incompatible {
+                client: PROTOCOL_VERSION + 1
+            },
+            ProtocolCompatibility::check(Some(PROTOCOL_VERSION + 1))
+        );
+    }
+}
@@ -3,7 +3,9 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use tokio::io::{AsyncSeek, AsyncWrite};
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, AsyncWriteExt};
 
 use super::error::{Error, Result};
 use crate::cdn::filesystem::{FileSystem, Writeable};
@@ -71,6 +73,48 @@ impl FileSystem for Local {
         Ok(())
     }
 
+    async fn write_stream<S>(&self, path: &Path, mut stream: S) -> Result<()>
+    where
+        S: Stream<Item = std::io::Result<Bytes>> + Send + Unpin,
+    {
+        let path = self.get_path(path);
+        self.create_parent(&path).await?;
+
+        let mut file = tokio::fs::File::create(&path).await?;
+        let written = async {
+            while let Some(chunk) = stream.next().await {
+                file.write_all(&chunk?).await?;
+            }
+            file.sync_all().await
+        }
+        .await;
+
+        if let Err(e) = written {
+            drop(file);
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    async fn rename_file(&self, from: &Path, to: &Path) -> Result<()> {
+        let (from, to) = (self.get_path(from), self.get_path(to));
+        self.create_parent(&to).await?;
+
+        tokio::fs::rename(from, to).await?;
+
+        Ok(())
+    }
+
+    async fn delete_file(&self, path: &Path) -> Result<()> {
+        let path = self.get_path(path);
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
     async fn read_file(&self, path: &Path) -> Result<Vec<u8>> {
         let path = self.get_path(path);
         if path.is_file() {
@@ -80,6 +124,24 @@ impl FileSystem for Local {
         }
     }
 
+    async fn open_file(
+        &self,
+        path: &Path,
+    ) -> Result<(
+        impl AsyncRead + AsyncSeek + Send + Unpin + 'static + use<>,
+        u64,
+    )> {
+        let path = self.get_path(path);
+        if !path.is_file() {
+            return Err(Error::FileNotFound { path });
+        }
+
+        let file = tokio::fs::File::open(path).await?;
+        let size = file.metadata().await?.len();
+
+        Ok((file, size))
+    }
+
     async fn checksum(&self, path: &Path) -> Result<String> {
         let data = self.read_file(path).await?;
 
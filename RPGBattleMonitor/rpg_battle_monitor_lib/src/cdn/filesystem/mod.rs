@@ -8,15 +8,36 @@ pub mod local_adapter;
 #[cfg(test)]
 pub(crate) mod temp_file_adapter;
 
+use axum::body::Bytes;
 use error::Result;
+use futures_util::Stream;
 use sha2::{Digest, Sha256};
-use tokio::io::{AsyncSeek, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
 
 pub trait FileSystem {
     fn file_exists(&self, path: &Path) -> impl Future<Output = Result<bool>> + Send;
     fn directory_exists(&self, path: &Path) -> impl Future<Output = Result<bool>> + Send;
     fn read_file(&self, path: &Path) -> impl Future<Output = Result<Vec<u8>>> + Send;
+    /// Opens the file for reading parts of it together with its size in bytes
+    fn open_file(
+        &self,
+        path: &Path,
+    ) -> impl Future<
+        Output = Result<(
+            impl AsyncRead + AsyncSeek + Send + Unpin + 'static + use<Self>,
+            u64,
+        )>,
+    > + Send;
     fn write_file(&self, path: &Path, data: &[u8]) -> impl Future<Output = Result<()>> + Send;
+    /// Writes the chunks into the file as they arrive, the file is removed again when
+    /// a chunk fails
+    fn write_stream<S>(&self, path: &Path, stream: S) -> impl Future<Output = Result<()>> + Send
+    where
+        S: Stream<Item = std::io::Result<Bytes>> + Send + Unpin;
+    /// Moves the file, replacing the file at `to` at once
+    fn rename_file(&self, from: &Path, to: &Path) -> impl Future<Output = Result<()>> + Send;
+    /// Deletes the file, deleting a file that does not exist is not an error
+    fn delete_file(&self, path: &Path) -> impl Future<Output = Result<()>> + Send;
     fn checksum(&self, path: &Path) -> impl Future<Output = Result<String>> + Send;
 }
 
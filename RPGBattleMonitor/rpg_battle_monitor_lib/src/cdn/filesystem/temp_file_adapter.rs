@@ -4,7 +4,9 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use tokio::io::{AsyncSeek, AsyncWrite};
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
 
 use super::error::{Error, Result};
 use crate::cdn::filesystem::{FileSystem, Writeable};
@@ -27,6 +29,10 @@ impl TempFileStore {
         }
     }
 
+    pub fn file_count(&self) -> usize {
+        self.files.lock().unwrap().len()
+    }
+
     fn read_file_sync(&self, path: &Path) -> Result<Vec<u8>> {
         if let Some(file) = self.files.lock().unwrap().iter().find(|f| f.path == path) {
             Ok(file.data.clone())
@@ -62,10 +68,59 @@ impl FileSystem for TempFileStore {
         Ok(())
     }
 
+    async fn write_stream<S>(&self, path: &Path, mut stream: S) -> Result<()>
+    where
+        S: Stream<Item = std::io::Result<Bytes>> + Send + Unpin,
+    {
+        let mut data = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk?);
+        }
+
+        self.write_file(path, &data).await
+    }
+
+    async fn rename_file(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let index =
+            files
+                .iter()
+                .position(|f| f.path == from)
+                .ok_or_else(|| Error::FileNotFound {
+                    path: from.to_path_buf(),
+                })?;
+
+        let mut file = files.remove(index);
+        file.path = to.to_path_buf();
+        files.retain(|f| f.path != to);
+        files.push(file);
+
+        Ok(())
+    }
+
+    async fn delete_file(&self, path: &Path) -> Result<()> {
+        self.files.lock().unwrap().retain(|f| f.path != path);
+
+        Ok(())
+    }
+
     async fn read_file(&self, path: &Path) -> Result<Vec<u8>> {
         self.read_file_sync(path)
     }
 
+    async fn open_file(
+        &self,
+        path: &Path,
+    ) -> Result<(
+        impl AsyncRead + AsyncSeek + Send + Unpin + 'static + use<>,
+        u64,
+    )> {
+        let data = self.read_file_sync(path)?;
+        let size = data.len() as u64;
+
+        Ok((Cursor::new(data), size))
+    }
+
     async fn checksum(&self, path: &Path) -> Result<String> {
         let data = self.read_file(path).await?;
 
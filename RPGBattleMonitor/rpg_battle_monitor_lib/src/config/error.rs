@@ -11,4 +11,9 @@ pub enum Error {
 
     #[error("DATABASE_URL env variable is not set")]
     EnvMissingDatabaseUrl,
+
+    #[error(
+        "Entity locks are held by a single server node, set ENTITY_LOCKS=false to share broadcasts between nodes"
+    )]
+    EntityLocksOnSeveralNodes,
 }
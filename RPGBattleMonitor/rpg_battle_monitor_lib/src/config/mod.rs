@@ -1,5 +1,10 @@
 pub use error::{Error, Result};
-use std::{env, sync::OnceLock};
+use std::{env, sync::OnceLock, time::Duration};
+
+use crate::entity::{
+    codec::{EntityCodec, EntityCodecs},
+    fields::IndexedField,
+};
 
 pub mod error;
 
@@ -11,13 +16,33 @@ pub struct DatabaseConfig {
 pub struct Config {
     pub database: DatabaseConfig,
     pub assets: AssetsConfig,
+    pub upload: UploadConfig,
+    pub image_transform: ImageTransformConfig,
+    pub asset_gc: AssetGcConfig,
+    pub websocket: WebsocketConfig,
+    pub broadcast: BroadcastConfig,
+    pub entity_queue: EntityQueueConfig,
+    pub entity_codec: EntityCodecConfig,
+    pub entity_fields: EntityFieldsConfig,
 }
 
 impl Config {
     pub fn load_from_env() -> Result<Self> {
+        let websocket = WebsocketConfig::load_from_env();
+        let broadcast = BroadcastConfig::load_from_env();
+        websocket.check_entity_locks(&broadcast)?;
+
         Ok(Self {
             database: DatabaseConfig::load_from_env()?,
             assets: AssetsConfig::load_from_env(),
+            upload: UploadConfig::load_from_env(),
+            image_transform: ImageTransformConfig::load_from_env(),
+            asset_gc: AssetGcConfig::load_from_env(),
+            websocket,
+            broadcast,
+            entity_queue: EntityQueueConfig::load_from_env(),
+            entity_codec: EntityCodecConfig::load_from_env(),
+            entity_fields: EntityFieldsConfig::load_from_env(),
         })
     }
 }
@@ -72,3 +97,359 @@ impl AssetsConfig {
         }
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    /// Size limit in bytes of uploads whose mime type has no own limit
+    pub default_limit: u64,
+    /// Size limits in bytes by mime type prefix, set as a comma separated list of
+    /// `prefix=bytes`, e.g. `image/=52428800,audio/=52428800`
+    pub mime_limits: Vec<(String, u64)>,
+    /// Size limit in bytes of all files of a batch upload together
+    pub batch_limit: u64,
+    /// Files of a batch upload that are stored at the same time
+    pub batch_concurrency: usize,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            default_limit: Self::DEFAULT_LIMIT,
+            mime_limits: vec![
+                ("image/".to_string(), Self::DEFAULT_MEDIA_LIMIT),
+                ("audio/".to_string(), Self::DEFAULT_MEDIA_LIMIT),
+            ],
+            batch_limit: Self::DEFAULT_BATCH_LIMIT,
+            batch_concurrency: Self::DEFAULT_BATCH_CONCURRENCY,
+        }
+    }
+}
+
+impl UploadConfig {
+    const DEFAULT_LIMIT: u64 = 10 * 1024 * 1024;
+    const DEFAULT_MEDIA_LIMIT: u64 = 50 * 1024 * 1024;
+    const DEFAULT_BATCH_LIMIT: u64 = 200 * 1024 * 1024;
+    const DEFAULT_BATCH_CONCURRENCY: usize = 4;
+
+    pub fn load_from_env() -> Self {
+        let default = Self::default();
+
+        Self {
+            default_limit: parse_env("UPLOAD_SIZE_LIMIT").unwrap_or(default.default_limit),
+            mime_limits: env::var("UPLOAD_MIME_SIZE_LIMITS")
+                .map(|limits| {
+                    limits
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|limit| !limit.is_empty())
+                        .filter_map(|limit| {
+                            let parsed = limit.split_once('=').and_then(|(prefix, bytes)| {
+                                Some((prefix.trim().to_string(), bytes.trim().parse().ok()?))
+                            });
+                            if parsed.is_none() {
+                                tracing::warn!(
+                                    "Skipping size limit {limit} of UPLOAD_MIME_SIZE_LIMITS"
+                                );
+                            }
+                            parsed
+                        })
+                        .collect()
+                })
+                .unwrap_or(default.mime_limits),
+            batch_limit: parse_env("UPLOAD_BATCH_SIZE_LIMIT").unwrap_or(default.batch_limit),
+            batch_concurrency: parse_env("UPLOAD_BATCH_CONCURRENCY")
+                .filter(|concurrency| *concurrency > 0)
+                .unwrap_or(default.batch_concurrency),
+        }
+    }
+
+    /// Limit of the longest matching mime type prefix
+    pub fn limit_for(&self, mime: &str) -> u64 {
+        self.mime_limits
+            .iter()
+            .filter(|(prefix, _)| mime.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, limit)| *limit)
+            .unwrap_or(self.default_limit)
+    }
+
+    pub fn max_limit(&self) -> u64 {
+        self.mime_limits
+            .iter()
+            .map(|(_, limit)| *limit)
+            .fold(self.default_limit, u64::max)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageTransformConfig {
+    /// Largest width or height of a transformed image
+    pub max_dimension: u32,
+    /// Images with more pixels are not transformed since decoding them takes too much memory
+    pub max_source_pixels: u64,
+    /// Transformed variants that are kept per image, further variants are rejected
+    pub max_variants: u64,
+    /// Animations with more frames get a static thumbnail
+    pub max_animation_frames: usize,
+}
+
+impl Default for ImageTransformConfig {
+    fn default() -> Self {
+        Self {
+            max_dimension: Self::DEFAULT_MAX_DIMENSION,
+            max_source_pixels: Self::DEFAULT_MAX_SOURCE_PIXELS,
+            max_variants: Self::DEFAULT_MAX_VARIANTS,
+            max_animation_frames: Self::DEFAULT_MAX_ANIMATION_FRAMES,
+        }
+    }
+}
+
+impl ImageTransformConfig {
+    const DEFAULT_MAX_DIMENSION: u32 = 2048;
+    const DEFAULT_MAX_SOURCE_PIXELS: u64 = 50_000_000;
+    const DEFAULT_MAX_VARIANTS: u64 = 16;
+    const DEFAULT_MAX_ANIMATION_FRAMES: usize = 500;
+
+    pub fn load_from_env() -> Self {
+        Self {
+            max_dimension: parse_env("IMAGE_TRANSFORM_MAX_DIMENSION")
+                .filter(|dimension| *dimension > 0)
+                .unwrap_or(Self::DEFAULT_MAX_DIMENSION),
+            max_source_pixels: parse_env("IMAGE_TRANSFORM_MAX_SOURCE_PIXELS")
+                .unwrap_or(Self::DEFAULT_MAX_SOURCE_PIXELS),
+            max_variants: parse_env("IMAGE_TRANSFORM_MAX_VARIANTS")
+                .unwrap_or(Self::DEFAULT_MAX_VARIANTS),
+            max_animation_frames: parse_env("IMAGE_TRANSFORM_MAX_ANIMATION_FRAMES")
+                .unwrap_or(Self::DEFAULT_MAX_ANIMATION_FRAMES),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AssetGcConfig {
+    /// How long an asset has to stay unreferenced before it is deleted, has to be longer
+    /// than entities can stay queued since only saved entities are scanned
+    pub grace_period: Duration,
+    /// Only report the assets that would be deleted
+    pub dry_run: bool,
+    pub interval: Duration,
+    /// User tokens that can read the report of the garbage collector, it lists the
+    /// assets of every library
+    pub admins: Vec<String>,
+}
+
+impl Default for AssetGcConfig {
+    fn default() -> Self {
+        Self {
+            grace_period: Self::DEFAULT_GRACE_PERIOD,
+            dry_run: false,
+            interval: Self::DEFAULT_INTERVAL,
+            admins: Vec::new(),
+        }
+    }
+}
+
+impl AssetGcConfig {
+    const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+    const DEFAULT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+    pub fn load_from_env() -> Self {
+        Self {
+            grace_period: parse_env("ASSET_GC_GRACE_PERIOD")
+                .map(Duration::from_secs)
+                .unwrap_or(Self::DEFAULT_GRACE_PERIOD),
+            dry_run: parse_env("ASSET_GC_DRY_RUN").unwrap_or(false),
+            interval: parse_env("ASSET_GC_INTERVAL")
+                .filter(|interval| *interval > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(Self::DEFAULT_INTERVAL),
+            admins: env::var("ASSET_GC_ADMINS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|admin| !admin.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebsocketConfig {
+    /// Number of entities sent in a single join chunk
+    pub join_chunk_size: u64,
+    /// Number of join chunks that can be sent before waiting on client ack
+    pub join_in_flight_chunks: usize,
+    /// Rate in Hz at which coalesced transitive actions are forwarded to the room,
+    /// 0 forwards every transitive action as it arrives
+    pub transitive_rate: u32,
+    /// How long an entity lock is held before it expires unless renewed
+    pub entity_lock_timeout: Duration,
+    /// Lets writers lock entities, locks are held in the memory of the node and can not
+    /// be enabled while broadcasts are shared between nodes
+    pub entity_locks: bool,
+}
+
+impl Default for WebsocketConfig {
+    fn default() -> Self {
+        Self {
+            join_chunk_size: Self::DEFAULT_JOIN_CHUNK_SIZE,
+            join_in_flight_chunks: Self::DEFAULT_JOIN_IN_FLIGHT_CHUNKS,
+            transitive_rate: Self::DEFAULT_TRANSITIVE_RATE,
+            entity_lock_timeout: Self::DEFAULT_ENTITY_LOCK_TIMEOUT,
+            entity_locks: true,
+        }
+    }
+}
+
+impl WebsocketConfig {
+    const DEFAULT_JOIN_CHUNK_SIZE: u64 = 50;
+    const DEFAULT_JOIN_IN_FLIGHT_CHUNKS: usize = 4;
+    const DEFAULT_TRANSITIVE_RATE: u32 = 20;
+    const DEFAULT_ENTITY_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub fn load_from_env() -> Self {
+        Self {
+            join_chunk_size: parse_env("JOIN_CHUNK_SIZE")
+                .filter(|size| *size > 0)
+                .unwrap_or(Self::DEFAULT_JOIN_CHUNK_SIZE),
+            join_in_flight_chunks: parse_env("JOIN_IN_FLIGHT_CHUNKS")
+                .filter(|chunks| *chunks > 0)
+                .unwrap_or(Self::DEFAULT_JOIN_IN_FLIGHT_CHUNKS),
+            transitive_rate: parse_env("TRANSITIVE_RATE").unwrap_or(Self::DEFAULT_TRANSITIVE_RATE),
+            entity_lock_timeout: parse_env("ENTITY_LOCK_TIMEOUT")
+                .map(Duration::from_secs)
+                .unwrap_or(Self::DEFAULT_ENTITY_LOCK_TIMEOUT),
+            entity_locks: parse_env("ENTITY_LOCKS").unwrap_or(true),
+        }
+    }
+
+    /// Nodes sharing broadcasts would each grant the same entity lock
+    pub fn check_entity_locks(&self, broadcast: &BroadcastConfig) -> Result<()> {
+        let several_nodes =
+            cfg!(feature = "broadcast_postgres") && broadcast.postgres_url.is_some();
+        if self.entity_locks && several_nodes {
+            return Err(Error::EntityLocksOnSeveralNodes);
+        }
+
+        Ok(())
+    }
+
+    pub fn transitive_interval(&self) -> Option<Duration> {
+        if self.transitive_rate == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(1) / self.transitive_rate)
+        }
+    }
+}
+
+pub struct BroadcastConfig {
+    /// Postgres database used to share room broadcasts between server nodes,
+    /// broadcasts stay within the process when not set
+    pub postgres_url: Option<String>,
+    pub channel: String,
+}
+
+impl BroadcastConfig {
+    pub fn load_from_env() -> Self {
+        Self {
+            postgres_url: env::var("BROADCAST_POSTGRES_URL").ok(),
+            channel: env::var("BROADCAST_CHANNEL")
+                .unwrap_or_else(|_| "rpg_battle_monitor_broadcast".to_string()),
+        }
+    }
+}
+
+pub struct EntityQueueConfig {
+    /// Directory of the write-ahead journal of queued entities
+    pub journal_path: String,
+    /// Failed flushes after which entities are moved to the dead letters
+    pub max_flush_attempts: u32,
+    /// Backoff after the first failed flush, doubled with every further failure
+    pub retry_backoff: Duration,
+    pub max_retry_backoff: Duration,
+    /// How long tombstones of deleted entities are kept before they are purged
+    pub tombstone_retention: Duration,
+}
+
+impl EntityQueueConfig {
+    const DEFAULT_MAX_FLUSH_ATTEMPTS: u32 = 5;
+    const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+    const DEFAULT_MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5 * 60);
+    const DEFAULT_TOMBSTONE_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+    pub fn load_from_env() -> Self {
+        Self {
+            journal_path: env::var("ENTITY_JOURNAL_PATH")
+                .unwrap_or_else(|_| "./entity-journal".to_string()),
+            max_flush_attempts: parse_env("ENTITY_QUEUE_MAX_FLUSH_ATTEMPTS")
+                .filter(|attempts| *attempts > 0)
+                .unwrap_or(Self::DEFAULT_MAX_FLUSH_ATTEMPTS),
+            retry_backoff: parse_env("ENTITY_QUEUE_RETRY_BACKOFF")
+                .map(Duration::from_secs)
+                .unwrap_or(Self::DEFAULT_RETRY_BACKOFF),
+            max_retry_backoff: parse_env("ENTITY_QUEUE_MAX_RETRY_BACKOFF")
+                .map(Duration::from_secs)
+                .unwrap_or(Self::DEFAULT_MAX_RETRY_BACKOFF),
+            tombstone_retention: parse_env("ENTITY_TOMBSTONE_RETENTION")
+                .map(Duration::from_secs)
+                .unwrap_or(Self::DEFAULT_TOMBSTONE_RETENTION),
+        }
+    }
+}
+
+pub struct EntityCodecConfig {
+    /// Codec of newly written entity payloads, gzip unless `ENTITY_CODEC=zstd` opts
+    /// into zstd. After switching, existing payloads are recompressed in the
+    /// background one batch per minute and stay readable until then
+    pub codec: EntityCodec,
+    pub zstd_level: i32,
+}
+
+impl EntityCodecConfig {
+    pub fn load_from_env() -> Self {
+        Self {
+            codec: parse_env("ENTITY_CODEC").unwrap_or_default(),
+            zstd_level: parse_env("ENTITY_ZSTD_LEVEL").unwrap_or(EntityCodecs::DEFAULT_ZSTD_LEVEL),
+        }
+    }
+}
+
+pub struct EntityFieldsConfig {
+    /// Entity fields extracted into indexed columns, set as a comma separated
+    /// list of `kind.path:type`, e.g. `token.stats.hp:integer,token.image:text`
+    pub indexed: Vec<IndexedField>,
+}
+
+impl EntityFieldsConfig {
+    pub fn load_from_env() -> Self {
+        let indexed = env::var("ENTITY_INDEXED_FIELDS").unwrap_or_default();
+
+        Self {
+            indexed: indexed
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .filter_map(|field| match field.parse() {
+                    Ok(field) => Some(field),
+                    Err(error) => {
+                        tracing::warn!(error = %error, "Skipping entity field {field} of ENTITY_INDEXED_FIELDS");
+                        None
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+fn parse_env<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let value = env::var(name).ok()?;
+    match value.parse::<T>() {
+        Ok(v) => Some(v),
+        Err(_) => {
+            tracing::warn!("Failed to parse {name}, using default");
+            None
+        }
+    }
+}
@@ -168,3 +168,143 @@ where
 
     custom_options.into_options()
 }
+
+#[cfg(all(test, feature = "db_sqlite"))]
+mod test {
+    use sqlx::{Row, SqlitePool, migrate::Migrator, sqlite::SqlitePoolOptions};
+
+    /// Database with the migrations before `version`
+    async fn migrated_before(version: i64) -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        let before = Migrator {
+            migrations: sqlx::migrate!("./migrations/sqlite")
+                .iter()
+                .filter(|migration| migration.version < version)
+                .cloned()
+                .collect::<Vec<_>>()
+                .into(),
+            ..Migrator::DEFAULT
+        };
+        before.run(&pool).await.unwrap();
+
+        pool
+    }
+
+    async fn execute(pool: &SqlitePool, sql: &str) {
+        sqlx::query(sql).execute(pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn files_without_entry_are_backfilled_into_the_legacy_library() {
+        let pool = migrated_before(20251020090000).await;
+        execute(
+            &pool,
+            "INSERT INTO assets (name, hash, mime, asset_type, original_filename) VALUES
+                ('a.png', 'a', 'image/png', 'file', 'goblin.png'),
+                ('b.png', 'b', 'image/png', 'thumbnail', NULL),
+                ('c.pdf', 'c', 'application/pdf', 'file', NULL),
+                ('d.png', 'd', 'image/png', 'file', 'orc.png')",
+        )
+        .await;
+        execute(
+            &pool,
+            "INSERT INTO asset_entry (owner, asset_id, name) VALUES ('alice', 4, 'orc')",
+        )
+        .await;
+
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&pool)
+            .await
+            .unwrap();
+
+        let entries = sqlx::query("SELECT owner, asset_id, name FROM asset_entry ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| {
+                (
+                    row.get::<String, _>(0),
+                    row.get::<i64, _>(1),
+                    row.get::<String, _>(2),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("alice".to_string(), 4, "orc".to_string()),
+                ("legacy".to_string(), 1, "goblin.png".to_string()),
+                ("legacy".to_string(), 3, "c.pdf".to_string()),
+            ],
+            entries
+        );
+    }
+
+    #[tokio::test]
+    async fn tags_are_copied_to_every_owner_of_the_asset() {
+        let pool = migrated_before(20251020100000).await;
+        execute(
+            &pool,
+            "INSERT INTO assets (name, hash, mime, asset_type) VALUES
+                ('a.png', 'a', 'image/png', 'file'),
+                ('b.png', 'b', 'image/png', 'file')",
+        )
+        .await;
+        execute(
+            &pool,
+            "INSERT INTO asset_entry (owner, asset_id, name) VALUES
+                ('alice', 1, 'goblin'), ('bob', 1, 'goblin'), ('bob', 1, 'goblin copy')",
+        )
+        .await;
+        execute(
+            &pool,
+            "INSERT INTO tags (name) VALUES ('undead'), ('forest')",
+        )
+        .await;
+        execute(
+            &pool,
+            "INSERT INTO asset_tags (asset_id, tag_id) VALUES (1, 1), (2, 2)",
+        )
+        .await;
+
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&pool)
+            .await
+            .unwrap();
+
+        let tags = sqlx::query(
+            "SELECT asset_id, owner, name FROM asset_tags
+            INNER JOIN tags ON tags.id = asset_tags.tag_id ORDER BY owner",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| {
+            (
+                row.get::<i64, _>(0),
+                row.get::<String, _>(1),
+                row.get::<String, _>(2),
+            )
+        })
+        .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (1, "alice".to_string(), "undead".to_string()),
+                (1, "bob".to_string(), "undead".to_string()),
+            ],
+            tags
+        );
+        let tag_count = sqlx::query("SELECT COUNT(*) FROM tags")
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .get::<i64, _>(0);
+        assert_eq!(1, tag_count);
+    }
+}
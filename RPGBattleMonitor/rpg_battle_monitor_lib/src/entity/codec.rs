@@ -0,0 +1,386 @@
+use std::{
+    io::{Read, Write},
+    str::FromStr,
+    sync::Arc,
+};
+
+use dashmap::DashMap;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use sea_orm::{ConnectionTrait, DeriveActiveEnum, EnumIter};
+use serde::{Deserialize, Serialize};
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+use crate::{
+    entity::{
+        Entity, UId, UtcTimestamp,
+        error::{Error, Result},
+        fields::EntityFields,
+        kind::EntityKind,
+    },
+    models::{
+        entity::CompressedEntityModel,
+        entity_dictionary::{EntityDictionaryManager, EntityDictionaryModel},
+    },
+};
+
+/// Codec of the entity payload, stored next to every entity so rows
+/// written with another codec stay readable
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    EnumIter,
+    DeriveActiveEnum,
+    Serialize,
+    Deserialize,
+)]
+#[sea_orm(rs_type = "String", db_type = "Text")]
+#[serde(rename_all = "kebab-case")]
+pub enum EntityCodec {
+    #[sea_orm(string_value = "none")]
+    None,
+    #[default]
+    #[sea_orm(string_value = "gzip")]
+    Gzip,
+    #[sea_orm(string_value = "zstd")]
+    Zstd,
+}
+
+impl FromStr for EntityCodec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(Error::InvalidEntityCodec(s.to_string())),
+        }
+    }
+}
+
+struct PreparedDictionary {
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+}
+
+impl std::fmt::Debug for PreparedDictionary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreparedDictionary").finish_non_exhaustive()
+    }
+}
+
+/// Compresses entity payloads with the default codec and decompresses them with
+/// the codec they were written with.
+///
+/// Zstd payloads of a kind that has a trained dictionary are compressed with the
+/// newest dictionary of that kind, older dictionaries are kept to read older rows.
+#[derive(Debug, Clone)]
+pub struct EntityCodecs {
+    default: EntityCodec,
+    zstd_level: i32,
+    dictionaries: Arc<DashMap<i32, Arc<PreparedDictionary>>>,
+    /// Newest dictionary id of every kind
+    kind_dictionaries: Arc<DashMap<String, i32>>,
+    fields: EntityFields,
+}
+
+impl Default for EntityCodecs {
+    fn default() -> Self {
+        Self::new(EntityCodec::default())
+    }
+}
+
+impl EntityCodecs {
+    pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+    pub fn new(default: EntityCodec) -> Self {
+        Self {
+            default,
+            zstd_level: Self::DEFAULT_ZSTD_LEVEL,
+            dictionaries: Arc::new(DashMap::new()),
+            kind_dictionaries: Arc::new(DashMap::new()),
+            fields: EntityFields::default(),
+        }
+    }
+
+    pub fn with_zstd_level(mut self, zstd_level: i32) -> Self {
+        self.zstd_level = zstd_level;
+        self
+    }
+
+    /// Entities of kinds with indexed fields are compressed with their document
+    pub fn with_fields(mut self, fields: EntityFields) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    pub fn fields(&self) -> &EntityFields {
+        &self.fields
+    }
+
+    pub fn default_codec(&self) -> EntityCodec {
+        self.default
+    }
+
+    /// Dictionary new zstd payloads of the kind are compressed with
+    pub fn dictionary_of_kind(&self, kind: &str) -> Option<i32> {
+        self.kind_dictionaries.get(kind).map(|id| *id)
+    }
+
+    /// Newest dictionary of every kind that has one
+    pub fn kind_dictionaries(&self) -> Vec<(String, i32)> {
+        self.kind_dictionaries
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    pub fn add_dictionary(&self, dictionary: &EntityDictionaryModel) {
+        let prepared = PreparedDictionary {
+            encoder: EncoderDictionary::copy(&dictionary.data, self.zstd_level),
+            decoder: DecoderDictionary::copy(&dictionary.data),
+        };
+        self.dictionaries.insert(dictionary.id, Arc::new(prepared));
+
+        self.kind_dictionaries
+            .entry(dictionary.kind.clone())
+            .and_modify(|id| *id = (*id).max(dictionary.id))
+            .or_insert(dictionary.id);
+    }
+
+    /// Loads dictionaries that are not known yet, dictionaries can be trained by other nodes
+    pub async fn load_dictionaries(&self, conn: &impl ConnectionTrait) -> Result<()> {
+        let dictionaries = EntityDictionaryManager::new()
+            .list_dictionaries(conn)
+            .await
+            .map_err(Error::EntityDictionaryLoadFailed)?;
+
+        for dictionary in dictionaries
+            .iter()
+            .filter(|dictionary| !self.dictionaries.contains_key(&dictionary.id))
+        {
+            self.add_dictionary(dictionary);
+        }
+
+        Ok(())
+    }
+
+    /// Loads dictionaries if any of the entities uses an unknown one
+    pub async fn load_missing_dictionaries(
+        &self,
+        conn: &impl ConnectionTrait,
+        entities: &[CompressedEntityModel],
+    ) -> Result<()> {
+        let missing = entities.iter().any(|entity| {
+            entity
+                .dictionary
+                .is_some_and(|id| !self.dictionaries.contains_key(&id))
+        });
+
+        if missing {
+            self.load_dictionaries(conn).await?;
+        }
+
+        Ok(())
+    }
+
+    pub fn compress(&self, entity: Entity) -> Result<CompressedEntityModel> {
+        self.compress_with(entity, self.default)
+    }
+
+    pub fn compress_with(
+        &self,
+        entity: Entity,
+        codec: EntityCodec,
+    ) -> Result<CompressedEntityModel> {
+        let payload = entity.other_values.to_string();
+        let document = self.fields.document(&entity);
+        let dictionary = match codec {
+            EntityCodec::Zstd => self.dictionary_of_kind(&entity.kind.0),
+            EntityCodec::None | EntityCodec::Gzip => None,
+        };
+
+        Ok(CompressedEntityModel {
+            uid: entity.uid.0,
+            game: entity.game,
+            kind: entity.kind.0,
+            timestamp: entity.timestamp.0,
+            action: entity.action,
+            data: self
+                .encode(payload.as_bytes(), codec, dictionary)
+                .map_err(Error::EntityCompressionFailed)?,
+            codec,
+            dictionary,
+            document,
+            deleted_at: None,
+        })
+    }
+
+    pub fn decompress(&self, entity: CompressedEntityModel) -> Result<Entity> {
+        let payload = self.decode(&entity.data, entity.codec, entity.dictionary)?;
+
+        Ok(Entity {
+            uid: UId(entity.uid),
+            game: entity.game,
+            kind: EntityKind(entity.kind),
+            timestamp: UtcTimestamp(entity.timestamp),
+            action: entity.action,
+            other_values: serde_json::from_slice(&payload)?,
+        })
+    }
+
+    pub fn decompress_vec(&self, compressed: Vec<CompressedEntityModel>) -> Result<Vec<Entity>> {
+        compressed
+            .into_iter()
+            .map(|entity| self.decompress(entity))
+            .collect()
+    }
+
+    /// Decompresses the raw payload of an entity
+    pub fn decode(
+        &self,
+        data: &[u8],
+        codec: EntityCodec,
+        dictionary: Option<i32>,
+    ) -> Result<Vec<u8>> {
+        let mut payload = Vec::new();
+        match codec {
+            EntityCodec::None => payload.extend_from_slice(data),
+            EntityCodec::Gzip => {
+                GzDecoder::new(data)
+                    .read_to_end(&mut payload)
+                    .map_err(Error::EntityDecompressionFailed)?;
+            }
+            EntityCodec::Zstd => match dictionary {
+                Some(id) => {
+                    let prepared = self
+                        .dictionaries
+                        .get(&id)
+                        .map(|prepared| prepared.clone())
+                        .ok_or(Error::UnknownEntityDictionary(id))?;
+
+                    zstd::stream::read::Decoder::with_prepared_dictionary(data, &prepared.decoder)
+                        .and_then(|mut decoder| decoder.read_to_end(&mut payload))
+                        .map_err(Error::EntityDecompressionFailed)?;
+                }
+                None => {
+                    payload = zstd::decode_all(data).map_err(Error::EntityDecompressionFailed)?;
+                }
+            },
+        }
+
+        Ok(payload)
+    }
+
+    fn encode(
+        &self,
+        payload: &[u8],
+        codec: EntityCodec,
+        dictionary: Option<i32>,
+    ) -> std::io::Result<Vec<u8>> {
+        match codec {
+            EntityCodec::None => Ok(payload.to_vec()),
+            EntityCodec::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(payload)?;
+                encoder.finish()
+            }
+            EntityCodec::Zstd => {
+                let prepared = dictionary
+                    .and_then(|id| self.dictionaries.get(&id).map(|prepared| prepared.clone()));
+
+                match prepared {
+                    Some(prepared) => {
+                        let mut encoder = zstd::stream::write::Encoder::with_prepared_dictionary(
+                            Vec::new(),
+                            &prepared.encoder,
+                        )?;
+                        encoder.write_all(payload)?;
+                        encoder.finish()
+                    }
+                    None => zstd::encode_all(payload, self.zstd_level),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        api::websockets::Action,
+        entity::{
+            Entity, UId, UtcTimestamp,
+            codec::{EntityCodec, EntityCodecs},
+            kind::EntityKind,
+        },
+        models::entity_dictionary::EntityDictionaryModel,
+    };
+
+    fn token(i: usize) -> Entity {
+        Entity {
+            uid: UId(format!("token-{i}")),
+            game: 1,
+            kind: EntityKind("token".to_string()),
+            timestamp: UtcTimestamp(1),
+            action: Some(Arc::new(Action::Update)),
+            other_values: serde_json::json!({
+                "x": i * 32,
+                "y": i * 16,
+                "width": 64,
+                "height": 64,
+                "image": format!("/assets/token-{}.png", i % 7),
+                "layer": "tokens",
+            }),
+        }
+    }
+
+    #[test]
+    fn every_codec_roundtrips() {
+        let codecs = EntityCodecs::default();
+
+        for codec in [EntityCodec::None, EntityCodec::Gzip, EntityCodec::Zstd] {
+            let compressed = codecs.compress_with(token(1), codec).unwrap();
+            assert_eq!(codec, compressed.codec);
+
+            let entity = codecs.decompress(compressed).unwrap();
+            assert_eq!(token(1).other_values, entity.other_values);
+        }
+    }
+
+    #[test]
+    fn dictionary_is_used_for_its_kind() {
+        let samples = (0..500)
+            .map(|i| token(i).other_values.to_string().into_bytes())
+            .collect::<Vec<_>>();
+        let dictionary = zstd::dict::from_samples(&samples, 4096).unwrap();
+
+        let codecs = EntityCodecs::new(EntityCodec::Zstd);
+        let without_dictionary = codecs.compress(token(3)).unwrap();
+
+        codecs.add_dictionary(&EntityDictionaryModel {
+            id: 1,
+            kind: "token".to_string(),
+            data: dictionary,
+            created_at: 0,
+        });
+        let with_dictionary = codecs.compress(token(3)).unwrap();
+
+        assert_eq!(None, without_dictionary.dictionary);
+        assert_eq!(Some(1), with_dictionary.dictionary);
+        assert!(with_dictionary.data.len() < without_dictionary.data.len());
+
+        // Rows written before the dictionary was trained stay readable
+        for compressed in [without_dictionary, with_dictionary] {
+            let entity = codecs.decompress(compressed).unwrap();
+            assert_eq!(token(3).other_values, entity.other_values);
+        }
+    }
+}
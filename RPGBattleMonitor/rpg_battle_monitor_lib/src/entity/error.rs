@@ -13,6 +13,30 @@ pub enum Error {
     #[error("Failed to decompress entity")]
     EntityDecompressionFailed(std::io::Error),
 
+    #[error("Failed to write entity journal")]
+    EntityJournalFailed(std::io::Error),
+
     #[error("Invalid entity kind: {0}")]
     InvalidEntityKind(String),
+
+    #[error("Invalid entity codec: {0}")]
+    InvalidEntityCodec(String),
+
+    #[error("Invalid indexed entity field: {0}")]
+    InvalidIndexedField(String),
+
+    #[error("Invalid entity field type: {0}")]
+    InvalidFieldType(String),
+
+    #[error("Unknown entity dictionary: {0}")]
+    UnknownEntityDictionary(i32),
+
+    #[error("Failed to load entity dictionaries")]
+    EntityDictionaryLoadFailed(#[source] crate::models::error::Error),
+
+    #[error("Game not found: {0}")]
+    GameNotFound(i32),
+
+    #[error("Failed to look up game")]
+    GameLookupFailed(#[source] crate::models::error::Error),
 }
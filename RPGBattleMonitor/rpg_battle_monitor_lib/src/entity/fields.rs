@@ -0,0 +1,315 @@
+use std::{str::FromStr, sync::Arc};
+
+use crate::entity::{
+    Entity,
+    error::{Error, Result},
+};
+
+/// Type of an indexed field, values of another type are indexed as null
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    Integer,
+    Real,
+    Text,
+    Boolean,
+}
+
+impl FromStr for FieldType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "integer" => Ok(Self::Integer),
+            "real" => Ok(Self::Real),
+            "text" => Ok(Self::Text),
+            "boolean" => Ok(Self::Boolean),
+            _ => Err(Error::InvalidFieldType(s.to_string())),
+        }
+    }
+}
+
+/// Field of an entity kind that is extracted from the entity document
+/// into an indexed generated column
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedField {
+    pub kind: String,
+    /// Dot separated path of the field in the kind specific values
+    pub path: String,
+    pub field_type: FieldType,
+}
+
+impl IndexedField {
+    pub fn new(kind: impl Into<String>, path: impl Into<String>, field_type: FieldType) -> Self {
+        Self {
+            kind: kind.into(),
+            path: path.into(),
+            field_type,
+        }
+    }
+
+    /// Name of the generated column of the field
+    pub fn column_name(&self) -> String {
+        format!("field_{}_{}", self.kind, self.path)
+            .replace(['-', '.'], "_")
+            .to_lowercase()
+    }
+
+    pub fn path_segments(&self) -> impl Iterator<Item = &str> {
+        self.path.split('.')
+    }
+
+    fn is_valid_name(name: &str, allowed: &[char]) -> bool {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || allowed.contains(&c))
+    }
+}
+
+/// Parses fields written as `kind.path:type`, e.g. `token.stats.hp:integer`
+impl FromStr for IndexedField {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidIndexedField(s.to_string());
+
+        let (field, field_type) = s.split_once(':').ok_or_else(invalid)?;
+        let (kind, path) = field.split_once('.').ok_or_else(invalid)?;
+
+        // Names end up in the generated column definitions
+        if !Self::is_valid_name(kind, &['_', '-'])
+            || !path
+                .split('.')
+                .all(|segment| Self::is_valid_name(segment, &['_']))
+        {
+            return Err(invalid());
+        }
+
+        Ok(Self::new(kind, path, field_type.parse()?))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Boolean(bool),
+}
+
+impl FieldValue {
+    pub fn field_type(&self) -> FieldType {
+        match self {
+            Self::Integer(_) => FieldType::Integer,
+            Self::Real(_) => FieldType::Real,
+            Self::Text(_) => FieldType::Text,
+            Self::Boolean(_) => FieldType::Boolean,
+        }
+    }
+}
+
+impl From<i64> for FieldValue {
+    fn from(value: i64) -> Self {
+        Self::Integer(value)
+    }
+}
+
+impl From<f64> for FieldValue {
+    fn from(value: f64) -> Self {
+        Self::Real(value)
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(value: &str) -> Self {
+        Self::Text(value.to_string())
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(value: String) -> Self {
+        Self::Text(value)
+    }
+}
+
+impl From<bool> for FieldValue {
+    fn from(value: bool) -> Self {
+        Self::Boolean(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldOperator {
+    Eq(FieldValue),
+    Ne(FieldValue),
+    Lt(FieldValue),
+    Le(FieldValue),
+    Gt(FieldValue),
+    Ge(FieldValue),
+    In(Vec<FieldValue>),
+    IsNull,
+    IsNotNull,
+}
+
+impl FieldOperator {
+    pub fn values(&self) -> &[FieldValue] {
+        match self {
+            Self::Eq(value)
+            | Self::Ne(value)
+            | Self::Lt(value)
+            | Self::Le(value)
+            | Self::Gt(value)
+            | Self::Ge(value) => std::slice::from_ref(value),
+            Self::In(values) => values,
+            Self::IsNull | Self::IsNotNull => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldCondition {
+    pub path: String,
+    pub operator: FieldOperator,
+}
+
+/// Filter on indexed fields of a single entity kind, all conditions have to match
+///
+/// ```ignore
+/// let filter = EntityFilter::new("token").lt("stats.hp", 0);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityFilter {
+    pub kind: String,
+    pub conditions: Vec<FieldCondition>,
+}
+
+impl EntityFilter {
+    pub fn new(kind: impl Into<String>) -> Self {
+        Self {
+            kind: kind.into(),
+            conditions: Vec::new(),
+        }
+    }
+
+    pub fn condition(mut self, path: impl Into<String>, operator: FieldOperator) -> Self {
+        self.conditions.push(FieldCondition {
+            path: path.into(),
+            operator,
+        });
+        self
+    }
+
+    pub fn eq(self, path: impl Into<String>, value: impl Into<FieldValue>) -> Self {
+        self.condition(path, FieldOperator::Eq(value.into()))
+    }
+
+    pub fn ne(self, path: impl Into<String>, value: impl Into<FieldValue>) -> Self {
+        self.condition(path, FieldOperator::Ne(value.into()))
+    }
+
+    pub fn lt(self, path: impl Into<String>, value: impl Into<FieldValue>) -> Self {
+        self.condition(path, FieldOperator::Lt(value.into()))
+    }
+
+    pub fn le(self, path: impl Into<String>, value: impl Into<FieldValue>) -> Self {
+        self.condition(path, FieldOperator::Le(value.into()))
+    }
+
+    pub fn gt(self, path: impl Into<String>, value: impl Into<FieldValue>) -> Self {
+        self.condition(path, FieldOperator::Gt(value.into()))
+    }
+
+    pub fn ge(self, path: impl Into<String>, value: impl Into<FieldValue>) -> Self {
+        self.condition(path, FieldOperator::Ge(value.into()))
+    }
+
+    pub fn is_in<V: Into<FieldValue>>(
+        self,
+        path: impl Into<String>,
+        values: impl IntoIterator<Item = V>,
+    ) -> Self {
+        self.condition(
+            path,
+            FieldOperator::In(values.into_iter().map(Into::into).collect()),
+        )
+    }
+
+    pub fn is_null(self, path: impl Into<String>) -> Self {
+        self.condition(path, FieldOperator::IsNull)
+    }
+
+    pub fn is_not_null(self, path: impl Into<String>) -> Self {
+        self.condition(path, FieldOperator::IsNotNull)
+    }
+}
+
+/// Indexed fields of every entity kind, entities of kinds with indexed fields
+/// are saved with an uncompressed JSON document the fields are extracted from
+#[derive(Debug, Clone, Default)]
+pub struct EntityFields {
+    fields: Arc<Vec<IndexedField>>,
+}
+
+impl EntityFields {
+    pub fn new(fields: Vec<IndexedField>) -> Self {
+        Self {
+            fields: Arc::new(fields),
+        }
+    }
+
+    pub fn fields(&self) -> &[IndexedField] {
+        &self.fields
+    }
+
+    pub fn get(&self, kind: &str, path: &str) -> Option<&IndexedField> {
+        self.fields
+            .iter()
+            .find(|field| field.kind == kind && field.path == path)
+    }
+
+    pub fn has_document(&self, kind: &str) -> bool {
+        self.fields.iter().any(|field| field.kind == kind)
+    }
+
+    /// Kinds that are saved with a document
+    pub fn document_kinds(&self) -> Vec<String> {
+        let mut kinds = self
+            .fields
+            .iter()
+            .map(|field| field.kind.clone())
+            .collect::<Vec<_>>();
+        kinds.sort();
+        kinds.dedup();
+        kinds
+    }
+
+    pub fn document(&self, entity: &Entity) -> Option<serde_json::Value> {
+        self.has_document(&entity.kind.0)
+            .then(|| entity.other_values.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::entity::fields::{FieldType, IndexedField};
+
+    #[test]
+    fn indexed_fields_are_parsed() {
+        let field = "token.stats.hp:integer".parse::<IndexedField>().unwrap();
+        assert_eq!(
+            IndexedField::new("token", "stats.hp", FieldType::Integer),
+            field
+        );
+        assert_eq!("field_token_stats_hp", field.column_name());
+
+        for invalid in [
+            "token:integer",
+            "token.hp",
+            "token.hp:number",
+            "token.hp');--:integer",
+            "token..hp:integer",
+        ] {
+            assert!(invalid.parse::<IndexedField>().is_err(), "{invalid}");
+        }
+    }
+}
@@ -1,96 +1,45 @@
-use std::{
-    io::{Read, Write},
-    sync::Arc,
-};
+use std::sync::Arc;
 
-use flate2::{Compression, read::GzDecoder, write::GzEncoder};
-use image::EncodableLayout;
 use serde::{Deserialize, Serialize};
+use ts_rs::TS;
 
+pub mod codec;
 pub mod error;
+pub mod fields;
 pub mod kind;
 
-use crate::{
-    api::websockets::Action, entity::kind::EntityKind, models::entity::CompressedEntityModel,
-};
+use crate::{api::websockets::Action, entity::kind::EntityKind};
 
-use self::error::{Error, Result};
-
-#[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq, TS)]
+#[ts(export)]
 pub struct UId(pub String);
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct UtcTimestamp(pub i64);
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct UtcTimestamp(#[ts(type = "number")] pub i64);
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
 pub struct ClientsideEntity {
     pub uid: UId,
     pub kind: EntityKind,
     pub timestamp: UtcTimestamp,
+    /// Kind specific values, these are not known to the server
     #[serde(flatten)]
+    #[ts(skip)]
     pub other_values: serde_json::Value,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
 pub struct Entity {
     pub uid: UId,
     pub game: i32,
     pub kind: EntityKind,
     pub timestamp: UtcTimestamp,
     pub action: Option<Arc<Action>>,
+    /// Kind specific values, these are not known to the server
     #[serde(flatten)]
+    #[ts(skip)]
     pub other_values: serde_json::Value,
 }
-
-impl Entity {
-    pub fn decompress_vec(compressed: Vec<CompressedEntityModel>) -> Result<Vec<Entity>> {
-        let mut decompressed = Vec::with_capacity(compressed.len());
-        for entity in compressed {
-            decompressed.push(entity.try_into()?);
-        }
-
-        Ok(decompressed)
-    }
-}
-
-impl TryFrom<Entity> for CompressedEntityModel {
-    type Error = Error;
-
-    fn try_from(value: Entity) -> std::result::Result<Self, Self::Error> {
-        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-        encoder
-            .write_all(value.other_values.to_string().as_bytes())
-            .map_err(Error::EntityCompressionFailed)?;
-        Ok(CompressedEntityModel {
-            uid: value.uid.0,
-            game: value.game,
-            kind: value.kind.0,
-            timestamp: value.timestamp.0,
-            action: value.action,
-            data: encoder.finish().map_err(Error::EntityCompressionFailed)?,
-        })
-    }
-}
-
-impl TryFrom<CompressedEntityModel> for Entity {
-    type Error = Error;
-
-    fn try_from(value: CompressedEntityModel) -> std::result::Result<Self, Self::Error> {
-        let mut decoder = GzDecoder::new(value.data.as_slice());
-        let mut data = Vec::new();
-        decoder
-            .read_to_end(&mut data)
-            .map_err(Error::EntityDecompressionFailed)?;
-
-        let other_values = serde_json::from_reader(data.as_bytes())?;
-
-        Ok(Entity {
-            uid: UId(value.uid),
-            game: value.game,
-            kind: EntityKind(value.kind),
-            timestamp: UtcTimestamp(value.timestamp),
-            action: value.action,
-            other_values,
-        })
-    }
-}
@@ -0,0 +1,33 @@
+use sea_orm::entity::prelude::*;
+
+/// Transformed variant of an image, see [`crate::thumbnail::transform::Transform`]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "asset_derivatives")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    /// Hash of the image the variant was made from
+    pub source_hash: String,
+    /// Key of the transform, see [`crate::thumbnail::transform::Transform::key`]
+    pub transform: String,
+    /// Asset of the variant itself
+    pub asset_id: i32,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::assets::Entity",
+        from = "Column::AssetId",
+        to = "super::assets::Column::Id"
+    )]
+    Asset,
+}
+
+impl Related<super::assets::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Asset.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
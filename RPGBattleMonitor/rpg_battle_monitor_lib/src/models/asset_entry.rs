@@ -0,0 +1,250 @@
+use chrono::NaiveDateTime;
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+pub use inner::*;
+
+#[cfg(feature = "api_doc")]
+use utoipa::ToSchema;
+
+/// Asset in the library of an owner, the content is shared by all entries
+/// with the same hash while every owner gives it their own name and folder.
+/// Files uploaded before the library existed belong to the `legacy` owner
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[sea_orm(table_name = "asset_entry")]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub owner: String,
+    pub game: Option<i32>,
+    pub folder: Option<i32>,
+    pub asset_id: i32,
+    pub name: String,
+    #[cfg_attr(feature = "api_doc", schema(value_type = String))]
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::assets::Entity",
+        from = "Column::AssetId",
+        to = "super::assets::Column::Id"
+    )]
+    Asset,
+    #[sea_orm(
+        belongs_to = "super::asset_folder::Entity",
+        from = "Column::Folder",
+        to = "super::asset_folder::Column::Id"
+    )]
+    Folder,
+}
+
+impl Related<super::assets::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Asset.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+mod inner {
+    use std::collections::HashSet;
+
+    use sea_orm::ActiveValue::Set;
+    use sea_orm::entity::prelude::*;
+    use sea_orm::{IntoActiveModel, PaginatorTrait, QueryOrder, QuerySelect};
+
+    use crate::models::asset_entry::{ActiveModel, Column, Entity, Model};
+    use crate::models::asset_folder::{AssetFolderManager, validate_name};
+    use crate::models::assets::{self, Asset};
+    use crate::models::error::{Error, Result};
+
+    pub type AssetEntryModel = Model;
+
+    #[derive(Default)]
+    pub struct AssetEntryManager {}
+
+    impl AssetEntryManager {
+        pub fn new() -> Self {
+            Self {}
+        }
+
+        /// Entries of the owner in `folder` with their asset, `None` lists the
+        /// entries that are not in a folder
+        pub async fn list_entries(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            game: Option<i32>,
+            folder: Option<i32>,
+        ) -> Result<Vec<(AssetEntryModel, Asset)>> {
+            let entries = Entity::find()
+                .find_also_related(assets::Entity)
+                .filter(Column::Owner.eq(owner))
+                .filter(match game {
+                    Some(game) => Column::Game.eq(game),
+                    None => Column::Game.is_null(),
+                })
+                .filter(match folder {
+                    Some(folder) => Column::Folder.eq(folder),
+                    None => Column::Folder.is_null(),
+                })
+                .order_by_asc(Column::Name)
+                .all(conn)
+                .await?;
+
+            Ok(entries
+                .into_iter()
+                .filter_map(|(entry, asset)| asset.map(|asset| (entry, asset)))
+                .collect())
+        }
+
+        /// Entries of other owners are not found
+        pub async fn get_entry(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            id: i32,
+        ) -> Result<AssetEntryModel> {
+            Entity::find_by_id(id)
+                .filter(Column::Owner.eq(owner))
+                .one(conn)
+                .await?
+                .ok_or(Error::AssetEntryNotFound(id))
+        }
+
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn create_entry(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            game: Option<i32>,
+            folder: Option<i32>,
+            asset_id: i32,
+            name: String,
+        ) -> Result<AssetEntryModel> {
+            let name = validate_name(name)?;
+            if let Some(folder) = folder {
+                AssetFolderManager::new()
+                    .get_folder_in_game(conn, owner, game, folder)
+                    .await?;
+            }
+            if assets::Entity::find_by_id(asset_id)
+                .one(conn)
+                .await?
+                .is_none()
+            {
+                return Err(Error::AssetNotFound(asset_id));
+            }
+
+            Ok(ActiveModel {
+                owner: Set(owner.to_string()),
+                game: Set(game),
+                folder: Set(folder),
+                asset_id: Set(asset_id),
+                name: Set(name),
+                ..Default::default()
+            }
+            .insert(conn)
+            .await?)
+        }
+
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn rename_entry(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            id: i32,
+            name: String,
+        ) -> Result<AssetEntryModel> {
+            let name = validate_name(name)?;
+            let mut entry = self.get_entry(conn, owner, id).await?.into_active_model();
+            entry.name = Set(name);
+
+            Ok(entry.update(conn).await?)
+        }
+
+        /// Moves the entry into `folder`, `None` moves it out of all folders
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn move_entry(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            id: i32,
+            folder: Option<i32>,
+        ) -> Result<AssetEntryModel> {
+            let entry = self.get_entry(conn, owner, id).await?;
+            if let Some(folder) = folder {
+                AssetFolderManager::new()
+                    .get_folder_in_game(conn, owner, entry.game, folder)
+                    .await?;
+            }
+
+            let mut entry = entry.into_active_model();
+            entry.folder = Set(folder);
+
+            Ok(entry.update(conn).await?)
+        }
+
+        /// Removes the entries of the owner for the asset, returns how many were removed
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn delete_entries_of_asset(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            asset_id: i32,
+        ) -> Result<u64> {
+            Ok(Entity::delete_many()
+                .filter(Column::Owner.eq(owner))
+                .filter(Column::AssetId.eq(asset_id))
+                .exec(conn)
+                .await?
+                .rows_affected)
+        }
+
+        /// Whether the asset is in the library of the owner
+        pub async fn owns_asset(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            asset_id: i32,
+        ) -> Result<bool> {
+            Ok(Entity::find()
+                .filter(Column::Owner.eq(owner))
+                .filter(Column::AssetId.eq(asset_id))
+                .count(conn)
+                .await?
+                > 0)
+        }
+
+        /// Whether the asset is in the library of any owner
+        pub async fn has_entries(
+            &self,
+            conn: &impl ConnectionTrait,
+            asset_id: i32,
+        ) -> Result<bool> {
+            Ok(Entity::find()
+                .filter(Column::AssetId.eq(asset_id))
+                .count(conn)
+                .await?
+                > 0)
+        }
+
+        /// Assets that are in the library of any owner
+        pub async fn list_asset_ids(&self, conn: &impl ConnectionTrait) -> Result<HashSet<i32>> {
+            Ok(Entity::find()
+                .select_only()
+                .column(Column::AssetId)
+                .distinct()
+                .into_tuple::<i32>()
+                .all(conn)
+                .await?
+                .into_iter()
+                .collect())
+        }
+    }
+}
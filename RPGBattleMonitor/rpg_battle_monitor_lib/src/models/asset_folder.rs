@@ -0,0 +1,184 @@
+use chrono::NaiveDateTime;
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+pub use inner::*;
+
+#[cfg(feature = "api_doc")]
+use utoipa::ToSchema;
+
+/// Folder of an owner, folders of a game are only visible within that game
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[sea_orm(table_name = "asset_folder")]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub owner: String,
+    pub game: Option<i32>,
+    pub parent: Option<i32>,
+    pub name: String,
+    #[cfg_attr(feature = "api_doc", schema(value_type = String))]
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(belongs_to = "Entity", from = "Column::Parent", to = "Column::Id")]
+    Parent,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+mod inner {
+    use sea_orm::ActiveValue::Set;
+    use sea_orm::entity::prelude::*;
+    use sea_orm::{IntoActiveModel, QueryOrder};
+
+    use crate::models::asset_folder::{ActiveModel, Column, Entity, Model};
+    use crate::models::error::{Error, Result};
+
+    pub type AssetFolderModel = Model;
+
+    #[derive(Default)]
+    pub struct AssetFolderManager {}
+
+    impl AssetFolderManager {
+        pub fn new() -> Self {
+            Self {}
+        }
+
+        /// Folders of the owner in `parent`, `None` lists the top level folders
+        pub async fn list_folders(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            game: Option<i32>,
+            parent: Option<i32>,
+        ) -> Result<Vec<AssetFolderModel>> {
+            Ok(Entity::find()
+                .filter(Column::Owner.eq(owner))
+                .filter(match game {
+                    Some(game) => Column::Game.eq(game),
+                    None => Column::Game.is_null(),
+                })
+                .filter(match parent {
+                    Some(parent) => Column::Parent.eq(parent),
+                    None => Column::Parent.is_null(),
+                })
+                .order_by_asc(Column::Name)
+                .all(conn)
+                .await?)
+        }
+
+        /// Folders of other owners are not found
+        pub async fn get_folder(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            id: i32,
+        ) -> Result<AssetFolderModel> {
+            Entity::find_by_id(id)
+                .filter(Column::Owner.eq(owner))
+                .one(conn)
+                .await?
+                .ok_or(Error::FolderNotFound(id))
+        }
+
+        /// Checks that the folder belongs to the owner and is in the game
+        pub async fn get_folder_in_game(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            game: Option<i32>,
+            id: i32,
+        ) -> Result<AssetFolderModel> {
+            let folder = self.get_folder(conn, owner, id).await?;
+            if folder.game != game {
+                return Err(Error::FolderInOtherGame(id));
+            }
+
+            Ok(folder)
+        }
+
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn create_folder(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            game: Option<i32>,
+            parent: Option<i32>,
+            name: String,
+        ) -> Result<AssetFolderModel> {
+            let name = validate_name(name)?;
+            if let Some(parent) = parent {
+                self.get_folder_in_game(conn, owner, game, parent).await?;
+            }
+
+            Ok(ActiveModel {
+                owner: Set(owner.to_string()),
+                game: Set(game),
+                parent: Set(parent),
+                name: Set(name),
+                ..Default::default()
+            }
+            .insert(conn)
+            .await?)
+        }
+
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn rename_folder(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            id: i32,
+            name: String,
+        ) -> Result<AssetFolderModel> {
+            let name = validate_name(name)?;
+            let mut folder = self.get_folder(conn, owner, id).await?.into_active_model();
+            folder.name = Set(name);
+
+            Ok(folder.update(conn).await?)
+        }
+
+        /// Moves the folder into `parent`, `None` moves it to the top level.
+        /// A folder can not be moved into itself or one of its subfolders.
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn move_folder(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            id: i32,
+            parent: Option<i32>,
+        ) -> Result<AssetFolderModel> {
+            let folder = self.get_folder(conn, owner, id).await?;
+
+            let mut ancestor = parent;
+            while let Some(ancestor_id) = ancestor {
+                if ancestor_id == id {
+                    return Err(Error::InvalidFolderMove(id));
+                }
+                ancestor = self
+                    .get_folder_in_game(conn, owner, folder.game, ancestor_id)
+                    .await?
+                    .parent;
+            }
+
+            let mut folder = folder.into_active_model();
+            folder.parent = Set(parent);
+
+            Ok(folder.update(conn).await?)
+        }
+    }
+
+    pub(crate) fn validate_name(name: String) -> Result<String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(Error::NameEmpty);
+        }
+
+        Ok(name.to_string())
+    }
+}
@@ -4,7 +4,7 @@ pub use assets_inner::*;
 
 use chrono::NaiveDateTime;
 use sea_orm::{FromQueryResult, entity::prelude::*};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
 #[sea_orm(table_name = "assets")]
@@ -17,6 +17,10 @@ pub struct Model {
     pub asset_type: String,
     pub created_at: NaiveDateTime,
     pub original_filename: String,
+    /// Time since which no entity references the asset
+    pub unreferenced_since: Option<NaiveDateTime>,
+    /// Image has several frames, animated images get animated thumbnails and a poster
+    pub animated: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -41,10 +45,13 @@ pub struct AssetThumbnail {
     thumbnail: super::thumbnails::Model,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum AssetType {
     File,
     Thumbnail,
+    /// Transformed variant of an image
+    Derived,
 }
 
 impl Display for AssetType {
@@ -52,6 +59,7 @@ impl Display for AssetType {
         match self {
             AssetType::File => write!(f, "file"),
             AssetType::Thumbnail => write!(f, "thumbnail"),
+            AssetType::Derived => write!(f, "derived"),
         }
     }
 }
@@ -65,36 +73,290 @@ impl From<Option<AssetType>> for AssetType {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Position of an asset in the listing, the id orders assets created at the same time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssetCursor {
+    pub created_at: NaiveDateTime,
+    pub id: i32,
+}
+
+impl From<&Asset> for AssetCursor {
+    fn from(asset: &Asset) -> Self {
+        Self {
+            created_at: asset.created_at,
+            id: asset.id,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AssetQuery {
+    /// Mime type prefix, e.g. `image/`
+    pub mime_prefix: Option<String>,
+    pub asset_type: Option<AssetType>,
+    /// Part of the original filename
+    pub search: Option<String>,
+    /// Normalized tag names, see [`super::tags::normalize_tag`]
+    pub tags: Vec<String>,
+    pub tag_match: super::tags::TagMatch,
+    /// Only assets in the library of the owner
+    pub owner: Option<String>,
+    /// Order by creation time
+    pub order: SortOrder,
+    pub after: Option<AssetCursor>,
+    pub limit: u64,
+}
+
+impl Default for AssetQuery {
+    fn default() -> Self {
+        Self {
+            mime_prefix: None,
+            asset_type: None,
+            search: None,
+            tags: Vec::new(),
+            tag_match: super::tags::TagMatch::default(),
+            owner: None,
+            order: SortOrder::default(),
+            after: None,
+            limit: 50,
+        }
+    }
+}
+
+/// Links thumbnails to the asset of the thumbnail itself
+#[derive(Debug)]
+pub struct ThumbnailAsset;
+
+impl Linked for ThumbnailAsset {
+    type FromEntity = super::thumbnails::Entity;
+    type ToEntity = Entity;
+
+    fn link(&self) -> Vec<RelationDef> {
+        vec![super::thumbnails::Relation::Asset.def()]
+    }
+}
+
 mod assets_inner {
 
+    use std::collections::HashMap;
     use std::io::Cursor;
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
+
+    use axum::body::Bytes;
+    use futures_util::{Stream, StreamExt};
+    use sha2::{Digest, Sha256};
+
+    use crate::config::{ImageTransformConfig, UploadConfig};
 
     use crate::models::error::{Error, Result};
 
     use crate::cdn::filesystem::{Adapter, sha256_hash};
     use crate::models::assets::Model;
-    use crate::models::assets::{ActiveModel, AssetThumbnail, AssetType, Column, Entity, Relation};
-    use crate::models::thumbnails;
-    use crate::thumbnail::{configuration, create_thumbnails};
+    use crate::models::assets::{
+        ActiveModel, AssetCursor, AssetQuery, AssetThumbnail, AssetType, Column, Entity, Relation,
+        SortOrder, ThumbnailAsset,
+    };
+    use crate::models::tags::TagMatch;
+    use crate::models::{asset_derivatives, asset_entry, asset_tags, tags, thumbnails};
+    use crate::thumbnail::animation::{
+        POSTER, check_canvas, create_animated_thumbnails, is_animated, may_be_animated,
+    };
+    use crate::thumbnail::configuration::Configuration;
+    use crate::thumbnail::svg::{SVG_MIME, is_markup, is_svg, render_svg, sanitize_svg};
+    use crate::thumbnail::transform::{Transform, image_dimensions};
+    use crate::thumbnail::{self, configuration, create_thumbnails};
     use crate::utils::{gen_uuid, run_blocking, unknown_mime_type};
     use crate::webserver::extractors::local_fs_extractor::FSAdapter;
 
     pub type Asset = Model;
+    use chrono::NaiveDateTime;
     use infer::MatcherType;
     use sea_orm::ActiveValue::Set;
+    use sea_orm::sea_query::{Expr, LikeExpr, OnConflict, Query, SelectStatement};
     use sea_orm::{
-        ActiveModelTrait, ColumnTrait, ConnectionTrait, EntityTrait, JoinType, QueryFilter,
-        QuerySelect, RelationTrait, SelectColumns,
+        ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, EntityTrait, JoinType, Order,
+        PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, RelationTrait, SelectColumns,
     };
+    use tokio::io::{AsyncRead, AsyncSeek};
+
+    /// Bytes of a streamed file that are used to tell its type
+    const STAGED_HEAD_SIZE: usize = 8 * 1024;
+    /// Directory of uploads that are still being written
+    const STAGED_DIRECTORY: &str = "tmp";
+    const THUMBNAIL_CONFIGURATIONS: [Configuration; 3] = [
+        configuration::X512,
+        configuration::X256,
+        configuration::X128,
+    ];
+
+    /// Uploaded file in a temporary location of the file system
+    #[derive(Debug)]
+    pub struct StagedFile {
+        temp_path: PathBuf,
+        hash: String,
+        mime: String,
+        extension: String,
+        size: u64,
+        /// Set once the whole file was checked for frames, see [`AssetManager::prepare_staged`]
+        animated: Option<bool>,
+    }
+
+    impl StagedFile {
+        pub fn size(&self) -> u64 {
+            self.size
+        }
+
+        pub fn mime(&self) -> &str {
+            &self.mime
+        }
+
+        pub fn hash(&self) -> &str {
+            &self.hash
+        }
+    }
+
+    /// Mime type and file extension from the start of a file, markup documents have to be
+    /// complete. Markup other than SVG could run scripts when it is opened and is rejected
+    fn file_type(head: &[u8]) -> Result<(String, String)> {
+        // Infer does not recognize SVG documents
+        if is_markup(head) {
+            if is_svg(head) {
+                return Ok((SVG_MIME.to_string(), "svg".to_string()));
+            }
+            return Err(Error::UnsupportedMarkup);
+        }
+
+        let mime_type = match infer::get(head) {
+            Some(m) => m,
+            None => unknown_mime_type(),
+        };
+
+        if mime_type.matcher_type() == MatcherType::Image {
+            let image_format = image::guess_format(head)?;
+            let extension = image_format.extensions_str().first().unwrap_or(&"");
+
+            Ok((
+                image_format.to_mime_type().to_string(),
+                extension.to_string(),
+            ))
+        } else {
+            Ok((
+                mime_type.mime_type().to_string(),
+                mime_type.extension().to_string(),
+            ))
+        }
+    }
+
+    /// Encoded thumbnails of an image in the order of `THUMBNAIL_CONFIGURATIONS`,
+    /// thumbnails that failed to encode are missing
+    #[derive(Debug)]
+    pub struct RenderedThumbnails {
+        thumbnails: Vec<Option<Vec<u8>>>,
+        poster: Option<Vec<u8>>,
+    }
+
+    /// Animations with too many frames get static thumbnails of their first frame
+    async fn render_thumbnails(
+        mime: String,
+        animated: bool,
+        data: Vec<u8>,
+        config: ImageTransformConfig,
+    ) -> Result<RenderedThumbnails> {
+        let (encoded_thumbnails, poster) = if mime == SVG_MIME {
+            run_blocking(move || {
+                let thumbnails = THUMBNAIL_CONFIGURATIONS
+                    .iter()
+                    .map(|configuration| {
+                        render_svg(&data, configuration.size.get_dimensions()).map(Some)
+                    })
+                    .collect::<core::result::Result<Vec<_>, _>>()?;
+
+                Ok::<_, thumbnail::error::Error>((thumbnails, None))
+            })
+            .await?
+        } else if animated {
+            run_blocking(move || {
+                let thumbnails =
+                    match create_animated_thumbnails(&data, &THUMBNAIL_CONFIGURATIONS, &config) {
+                        Ok(thumbnails) => thumbnails.into_iter().map(Some).collect::<Vec<_>>(),
+                        Err(thumbnail::error::Error::TooManyFrames(limit)) => {
+                            tracing::debug!(
+                                "Animation has more than {limit} frames, creating static thumbnails"
+                            );
+                            create_thumbnails(Cursor::new(&data), &THUMBNAIL_CONFIGURATIONS, None)?
+                                .into_iter()
+                                .map(encode_thumbnail)
+                                .collect()
+                        }
+                        Err(e) => return Err(e),
+                    };
+                let poster = POSTER.apply(&data)?;
+
+                Ok::<_, thumbnail::error::Error>((thumbnails, Some(poster)))
+            })
+            .await?
+        } else {
+            let thumbnails = run_blocking(|| {
+                let reader = Cursor::new(data);
+                create_thumbnails(reader, &THUMBNAIL_CONFIGURATIONS, None)
+            })
+            .await?;
+
+            let mut encoded_thumbnails = Vec::with_capacity(thumbnails.len());
+            for thumbnail in thumbnails {
+                encoded_thumbnails.push(run_blocking(|| encode_thumbnail(thumbnail)).await);
+            }
+
+            (encoded_thumbnails, None)
+        };
+
+        Ok(RenderedThumbnails {
+            thumbnails: encoded_thumbnails,
+            poster,
+        })
+    }
+
+    fn encode_thumbnail(thumbnail: thumbnail::Thumbnail) -> Option<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut writer = Cursor::new(&mut data);
+        match thumbnail.write_to(&mut writer) {
+            Ok(_) => Some(data),
+            Err(e) => {
+                tracing::error!(error = ?e, "Failed to write thumbnail to memory");
+                None
+            }
+        }
+    }
+
+    fn sanitize(data: &[u8]) -> Result<Vec<u8>> {
+        sanitize_svg(data).map_err(|e| Error::InvalidSvg(e.to_string()))
+    }
 
     pub struct AssetManager<F: Adapter> {
         fs_adapter: FSAdapter<F>,
+        /// Limits of the animations that thumbnails are created of
+        image_transform_config: ImageTransformConfig,
     }
 
     impl<F: Adapter> AssetManager<F> {
         pub fn new(fs_adapter: FSAdapter<F>) -> Self {
-            Self { fs_adapter }
+            Self {
+                fs_adapter,
+                image_transform_config: ImageTransformConfig::default(),
+            }
+        }
+
+        pub fn with_image_transform_config(mut self, config: ImageTransformConfig) -> Self {
+            self.image_transform_config = config;
+            self
         }
 
         #[tracing::instrument(skip(self, conn))]
@@ -121,6 +383,16 @@ mod assets_inner {
                 return Err(Error::DataEmpty);
             }
 
+            let (mime, extension) = file_type(data)?;
+            // SVG documents are stored sanitized, identical uploads share the hash
+            let sanitized;
+            let data = if mime == SVG_MIME {
+                sanitized = sanitize(data)?;
+                sanitized.as_slice()
+            } else {
+                data
+            };
+
             let hash = sha256_hash(data);
             let uuid = gen_uuid();
 
@@ -128,61 +400,196 @@ mod assets_inner {
                 return Ok(asset);
             }
 
-            let mime_type = match infer::get(data) {
-                Some(m) => m,
-                None => unknown_mime_type(),
+            let name = format!("{uuid}.{extension}");
+            let animated = may_be_animated(&mime) && {
+                check_canvas(data, self.image_transform_config.max_source_pixels)?;
+                is_animated(data)
             };
 
-            let (mime, name) = if mime_type.matcher_type() == MatcherType::Image {
-                let image_format = image::guess_format(data)?;
-                let extension = image_format.extensions_str().first().unwrap_or(&"");
+            let asset = ActiveModel {
+                name: Set(name.clone()),
+                hash: Set(hash),
+                mime: Set(mime),
+                asset_type: Set(asset_type.into().to_string()),
+                original_filename: Set(user_given_filename),
+                animated: Set(animated),
+                ..Default::default()
+            }
+            .insert(conn)
+            .await?;
 
-                let mime = image_format.to_mime_type().to_string();
-                let name = format!("{uuid}.{extension}");
+            self.write_file(&name, data).await?;
 
-                (mime, name)
-            } else {
-                let extension = mime_type.extension();
-                let name = format!("{uuid}.{extension}");
-                (mime_type.mime_type().to_string(), name)
-            };
+            Ok(asset)
+        }
 
-            let asset = self
-                .create_asset(
-                    conn,
-                    name.clone(),
-                    hash,
+        /// Streams the chunks into a temporary file while hashing them, the file is
+        /// turned into an asset with [`Self::create_from_staged`]
+        #[tracing::instrument(skip(self, chunks, config))]
+        pub async fn stage_file<S, E>(
+            &self,
+            mut chunks: S,
+            config: &UploadConfig,
+        ) -> Result<StagedFile>
+        where
+            S: Stream<Item = core::result::Result<Bytes, E>> + Send + Unpin,
+            E: Into<Box<dyn std::error::Error + Send + Sync>>,
+        {
+            // The start of the file tells its type and with it the size limit
+            let mut head = Vec::new();
+            while head.len() < STAGED_HEAD_SIZE
+                && let Some(chunk) = chunks.next().await
+            {
+                head.extend_from_slice(&chunk.map_err(std::io::Error::other)?);
+            }
+            if head.is_empty() {
+                return Err(Error::DataEmpty);
+            }
+
+            let temp_path = PathBuf::from(STAGED_DIRECTORY).join(format!("{}.upload", gen_uuid()));
+
+            // Markup is read as a whole, the root element can follow a prolog of any length,
+            // SVG documents are sanitized before they are written
+            if is_markup(&head) {
+                let mime = SVG_MIME.to_string();
+                let limit = config.limit_for(&mime);
+                let mut data = head;
+                while let Some(chunk) = chunks.next().await {
+                    data.extend_from_slice(&chunk.map_err(std::io::Error::other)?);
+                    if data.len() as u64 > limit {
+                        return Err(Error::FileTooLarge { mime, limit });
+                    }
+                }
+                if data.len() as u64 > limit {
+                    return Err(Error::FileTooLarge { mime, limit });
+                }
+
+                let (mime, extension) = file_type(&data)?;
+                let data = run_blocking(move || sanitize(&data)).await?;
+                self.fs_adapter.write_file(&temp_path, &data).await?;
+
+                return Ok(StagedFile {
+                    temp_path,
+                    hash: sha256_hash(&data),
                     mime,
-                    asset_type.into(),
-                    user_given_filename,
-                )
-                .await?;
+                    extension,
+                    size: data.len() as u64,
+                    animated: Some(false),
+                });
+            }
 
-            self.write_file(&name, data).await?;
+            let (mime, extension) = file_type(&head)?;
+            let limit = config.limit_for(&mime);
+
+            let mut hasher = Sha256::new();
+            let mut size = 0u64;
+            let mut too_large = false;
+            let stream = futures_util::stream::once(async { Ok(Bytes::from(head)) })
+                .chain(chunks.map(|chunk| chunk.map_err(std::io::Error::other)))
+                .map(|chunk| {
+                    let chunk = chunk?;
+                    size += chunk.len() as u64;
+                    if size > limit {
+                        too_large = true;
+                        return Err(std::io::Error::from(std::io::ErrorKind::FileTooLarge));
+                    }
+                    hasher.update(&chunk);
 
-            Ok(asset)
+                    Ok(chunk)
+                });
+
+            let written = self
+                .fs_adapter
+                .write_stream(&temp_path, Box::pin(stream))
+                .await;
+            if too_large {
+                return Err(Error::FileTooLarge { mime, limit });
+            }
+            written?;
+
+            Ok(StagedFile {
+                temp_path,
+                hash: format!("{:x}", hasher.finalize()),
+                mime,
+                extension,
+                size,
+                animated: None,
+            })
+        }
+
+        /// Reads the whole staged file to tell whether it has several frames, only its
+        /// head was read while it was staged. Animations with a canvas larger than
+        /// `max_source_pixels` are rejected before any frame is decoded
+        pub async fn prepare_staged(&self, staged: &mut StagedFile) -> Result<()> {
+            if staged.animated.is_some() {
+                return Ok(());
+            }
+
+            let animated = if may_be_animated(&staged.mime) {
+                let data = self.fs_adapter.read_file(&staged.temp_path).await?;
+                let max_source_pixels = self.image_transform_config.max_source_pixels;
+                run_blocking(move || {
+                    check_canvas(&data, max_source_pixels)?;
+                    Ok::<_, thumbnail::error::Error>(is_animated(&data))
+                })
+                .await?
+            } else {
+                false
+            };
+            staged.animated = Some(animated);
+
+            Ok(())
         }
 
+        /// Moves the staged file into place, the staged file is discarded when an asset
+        /// with the same content already exists
         #[tracing::instrument(skip(self, conn, asset_type))]
-        async fn create_asset(
+        pub async fn create_from_staged(
             &self,
             conn: &impl ConnectionTrait,
-            name: String,
-            hash: String,
-            mime: String,
-            asset_type: AssetType,
             user_given_filename: String,
+            mut staged: StagedFile,
+            asset_type: impl Into<AssetType>,
         ) -> Result<Asset> {
-            let asset = ActiveModel {
-                name: Set(name),
-                hash: Set(hash),
-                mime: Set(mime),
-                asset_type: Set(asset_type.to_string()),
-                original_filename: Set(user_given_filename),
-                ..Default::default()
-            };
+            if let Some(asset) = self.get_by_hash(conn, &staged.hash).await? {
+                self.discard_staged(staged).await;
+                return Ok(asset);
+            }
 
-            Ok(asset.insert(conn).await?)
+            let name = format!("{}.{}", gen_uuid(), staged.extension);
+            let temp_path = staged.temp_path.clone();
+            let created = async {
+                self.prepare_staged(&mut staged).await?;
+
+                let asset = ActiveModel {
+                    name: Set(name.clone()),
+                    hash: Set(staged.hash.clone()),
+                    mime: Set(staged.mime.clone()),
+                    asset_type: Set(asset_type.into().to_string()),
+                    original_filename: Set(user_given_filename),
+                    animated: Set(staged.animated.unwrap_or_default()),
+                    ..Default::default()
+                }
+                .insert(conn)
+                .await?;
+                self.fs_adapter
+                    .rename_file(&temp_path, Path::new(&name))
+                    .await?;
+
+                Ok(asset)
+            }
+            .await;
+
+            if created.is_err() {
+                self.discard_staged(staged).await;
+            }
+            created
+        }
+
+        pub async fn discard_staged(&self, staged: StagedFile) {
+            if let Err(e) = self.fs_adapter.delete_file(&staged.temp_path).await {
+                tracing::error!(error = %e, path = ?staged.temp_path, "Failed to delete staged file");
+            }
         }
 
         #[tracing::instrument(skip(self, data))]
@@ -193,6 +600,8 @@ mod assets_inner {
             Ok(())
         }
 
+        /// Thumbnails of the image, animated images get animated thumbnails and a poster,
+        /// see [`Self::get_poster`]
         #[tracing::instrument(skip(self, conn, data))]
         pub async fn create_thumbnail_assets(
             &self,
@@ -214,60 +623,232 @@ mod assets_inner {
                 }
             };
 
-            let thumbnail_configurations = run_blocking(|| {
-                let reader = Cursor::new(data);
-                create_thumbnails(
-                    reader,
-                    &[
-                        configuration::X512,
-                        configuration::X256,
-                        configuration::X128,
-                    ],
-                    None,
-                )
-            })
+            let rendered = render_thumbnails(
+                original_asset.mime.clone(),
+                original_asset.animated,
+                data,
+                self.image_transform_config.clone(),
+            )
             .await?;
+            self.save_thumbnail_assets(conn, original_asset, rendered)
+                .await
+        }
 
-            let mut assets = Vec::with_capacity(3);
+        /// Renders thumbnails of a prepared staged image without touching the database,
+        /// so they can be rendered before the transaction that saves them
+        pub async fn render_staged_thumbnails(
+            &self,
+            staged: &StagedFile,
+        ) -> Result<RenderedThumbnails> {
+            let data = self.fs_adapter.read_file(&staged.temp_path).await?;
+
+            render_thumbnails(
+                staged.mime.clone(),
+                staged.animated.unwrap_or_default(),
+                data,
+                self.image_transform_config.clone(),
+            )
+            .await
+        }
 
-            for thumbnail in thumbnail_configurations {
-                let thumbnail_active_model = thumbnails::ActiveModel {
-                    dimensions: Set(thumbnail.configuration.name.to_string()),
+        /// Saves rendered thumbnails of the image, thumbnails saved before are returned instead
+        #[tracing::instrument(skip(self, conn, rendered))]
+        pub async fn save_thumbnail_assets(
+            &self,
+            conn: &impl ConnectionTrait,
+            original_asset: &Asset,
+            rendered: RenderedThumbnails,
+        ) -> Result<Vec<Asset>> {
+            let existing_thumbnails = self.get_thumbnails(conn, original_asset.id).await?;
+            if !existing_thumbnails.is_empty() {
+                return Ok(existing_thumbnails.into_iter().map(|t| t.asset).collect());
+            }
+
+            let mut assets = Vec::with_capacity(THUMBNAIL_CONFIGURATIONS.len());
+
+            for (configuration, data) in THUMBNAIL_CONFIGURATIONS.iter().zip(rendered.thumbnails) {
+                let mut thumbnail_active_model = thumbnails::ActiveModel {
+                    dimensions: Set(configuration.name.to_string()),
                     image_id: Set(original_asset.id),
                     ..Default::default()
                 };
 
-                let data = run_blocking(|| {
-                    let mut data = Vec::new();
-                    let mut writer = Cursor::new(&mut data);
-                    match thumbnail.write_to(&mut writer) {
-                        Ok(_) => Some(data),
-                        Err(e) => {
-                            tracing::error!(error = ?e, "Failed to write thumbnail to memory");
-                            None
-                        }
-                    }
-                })
-                .await;
-
-                thumbnail_active_model.insert(conn).await?;
-
                 if let Some(data) = data {
                     match self
                         .create(conn, "".to_string(), &data, AssetType::Thumbnail)
                         .await
                     {
-                        Ok(asset) => assets.push(asset),
+                        Ok(asset) => {
+                            thumbnail_active_model.asset_id = Set(Some(asset.id));
+                            assets.push(asset);
+                        }
                         Err(e) => {
                             tracing::error!(error = ?e, "Failed to save thumbnail asset");
                         }
                     }
                 }
+
+                thumbnail_active_model.insert(conn).await?;
+            }
+
+            if let Some(poster) = rendered.poster {
+                self.create_derived_asset(conn, original_asset, &POSTER, &poster)
+                    .await?;
             }
 
             Ok(assets)
         }
 
+        /// Static first frame of an animated image, made with its thumbnails
+        pub async fn get_poster(
+            &self,
+            conn: &impl ConnectionTrait,
+            asset: &Asset,
+        ) -> Result<Option<Asset>> {
+            if !asset.animated {
+                return Ok(None);
+            }
+
+            self.get_derived_asset(conn, asset, &POSTER).await
+        }
+
+        async fn get_derived_asset(
+            &self,
+            conn: &impl ConnectionTrait,
+            source: &Asset,
+            transform: &Transform,
+        ) -> Result<Option<Asset>> {
+            let derivative = asset_derivatives::Entity::find()
+                .find_also_related(Entity)
+                .filter(asset_derivatives::Column::SourceHash.eq(&source.hash))
+                .filter(asset_derivatives::Column::Transform.eq(transform.key()))
+                .one(conn)
+                .await?;
+
+            Ok(derivative.and_then(|(_, asset)| asset))
+        }
+
+        /// Variant of the image made by the transform, the variant is created and kept
+        /// as a derived asset on first use
+        #[tracing::instrument(skip(self, conn, config))]
+        pub async fn get_or_create_derived_asset(
+            &self,
+            conn: &impl ConnectionTrait,
+            source: &Asset,
+            transform: &Transform,
+            config: &ImageTransformConfig,
+        ) -> Result<Asset> {
+            if let Some(asset) = self.get_derived_asset(conn, source, transform).await? {
+                return Ok(asset);
+            }
+
+            if !source.mime.starts_with("image/") {
+                return Err(Error::NotAnImage(source.id));
+            }
+            let variants = asset_derivatives::Entity::find()
+                .filter(asset_derivatives::Column::SourceHash.eq(&source.hash))
+                .count(conn)
+                .await?;
+            if variants >= config.max_variants {
+                return Err(Error::TooManyVariants(source.id));
+            }
+
+            let mut data = self.fs_adapter.read_file(source.name.as_ref()).await?;
+            // Vector images are rendered in the largest size a variant can have
+            if source.mime == SVG_MIME {
+                let size = (config.max_dimension, config.max_dimension);
+                data = run_blocking(move || render_svg(&data, size)).await?;
+            }
+            let (width, height) = image_dimensions(&data)?;
+            if width as u64 * height as u64 > config.max_source_pixels {
+                return Err(Error::ImageTooLarge { width, height });
+            }
+
+            let transform_copy = *transform;
+            let data = run_blocking(move || transform_copy.apply(&data)).await?;
+
+            self.create_derived_asset(conn, source, transform, &data)
+                .await
+        }
+
+        async fn create_derived_asset(
+            &self,
+            conn: &impl ConnectionTrait,
+            source: &Asset,
+            transform: &Transform,
+            data: &[u8],
+        ) -> Result<Asset> {
+            let asset = self
+                .create(conn, "".to_string(), data, AssetType::Derived)
+                .await?;
+
+            let inserted = asset_derivatives::Entity::insert(asset_derivatives::ActiveModel {
+                source_hash: Set(source.hash.clone()),
+                transform: Set(transform.key()),
+                asset_id: Set(asset.id),
+                ..Default::default()
+            })
+            .on_conflict(
+                OnConflict::columns([
+                    asset_derivatives::Column::SourceHash,
+                    asset_derivatives::Column::Transform,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .exec_without_returning(conn)
+            .await?;
+
+            // A concurrent request created the same variant first, the asset created here
+            // is not linked and collected by the asset gc
+            if inserted == 0
+                && let Some(existing) = self.get_derived_asset(conn, source, transform).await?
+            {
+                return Ok(existing);
+            }
+
+            Ok(asset)
+        }
+
+        /// Derived assets that are not a variant of any image, e.g. the variant of a
+        /// request that lost the race to create it. Identical uploads and thumbnails
+        /// share the derived asset and link it too
+        pub async fn list_unlinked_derived_assets(
+            &self,
+            conn: &impl ConnectionTrait,
+        ) -> Result<Vec<Asset>> {
+            Ok(Entity::find()
+                .filter(Column::AssetType.eq(AssetType::Derived.to_string()))
+                .filter(
+                    Column::Id.not_in_subquery(
+                        Query::select()
+                            .column(asset_derivatives::Column::AssetId)
+                            .from(asset_derivatives::Entity)
+                            .to_owned(),
+                    ),
+                )
+                .filter(
+                    Column::Id.not_in_subquery(
+                        Query::select()
+                            .column(thumbnails::Column::AssetId)
+                            .from(thumbnails::Entity)
+                            .and_where(thumbnails::Column::AssetId.is_not_null())
+                            .to_owned(),
+                    ),
+                )
+                .filter(
+                    Column::Id.not_in_subquery(
+                        Query::select()
+                            .column(asset_entry::Column::AssetId)
+                            .from(asset_entry::Entity)
+                            .to_owned(),
+                    ),
+                )
+                .order_by_asc(Column::Id)
+                .all(conn)
+                .await?)
+        }
+
         pub async fn get_by_name(
             &self,
             conn: &impl ConnectionTrait,
@@ -279,8 +860,15 @@ mod assets_inner {
                 .await?)
         }
 
-        pub async fn load_file_data<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
-            Ok(self.fs_adapter.read_file(path.as_ref()).await?)
+        /// Reader of the file with its size, large files are not loaded into memory
+        pub async fn open_file<P: AsRef<Path>>(
+            &self,
+            path: P,
+        ) -> Result<(
+            impl AsyncRead + AsyncSeek + Send + Unpin + 'static + use<F, P>,
+            u64,
+        )> {
+            Ok(self.fs_adapter.open_file(path.as_ref()).await?)
         }
 
         pub async fn get_thumbnails(
@@ -293,11 +881,270 @@ mod assets_inner {
                 .select_column(thumbnails::Column::Id)
                 .select_column(thumbnails::Column::Dimensions)
                 .select_column(thumbnails::Column::ImageId)
+                .select_column(thumbnails::Column::AssetId)
                 .filter(thumbnails::Column::ImageId.eq(image_id))
                 .into_model::<AssetThumbnail>()
                 .all(conn)
                 .await?)
         }
+
+        /// Lists at most `query.limit` assets ordered by creation time starting after
+        /// `query.after`, pass the last asset of the previous page to continue
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn list_assets(
+            &self,
+            conn: &impl ConnectionTrait,
+            query: &AssetQuery,
+        ) -> Result<Vec<Asset>> {
+            let mut condition = Condition::all();
+            if let Some(mime_prefix) = &query.mime_prefix {
+                condition = condition
+                    .add(Column::Mime.like(
+                        LikeExpr::new(format!("{}%", escape_like(mime_prefix))).escape('\\'),
+                    ));
+            }
+            if let Some(asset_type) = query.asset_type {
+                condition = condition.add(Column::AssetType.eq(asset_type.to_string()));
+            }
+            if let Some(search) = &query.search {
+                condition =
+                    condition
+                        .add(Column::OriginalFilename.like(
+                            LikeExpr::new(format!("%{}%", escape_like(search))).escape('\\'),
+                        ));
+            }
+            if !query.tags.is_empty() {
+                condition = condition.add(Column::Id.in_subquery(Self::tagged_asset_ids(query)));
+            }
+            if let Some(owner) = &query.owner {
+                condition = condition.add(
+                    Column::Id.in_subquery(
+                        Query::select()
+                            .column(asset_entry::Column::AssetId)
+                            .from(asset_entry::Entity)
+                            .and_where(asset_entry::Column::Owner.eq(owner))
+                            .to_owned(),
+                    ),
+                );
+            }
+            if let Some(AssetCursor { created_at, id }) = query.after {
+                let (created_at_cmp, id_cmp) = match query.order {
+                    SortOrder::Asc => (Column::CreatedAt.gt(created_at), Column::Id.gt(id)),
+                    SortOrder::Desc => (Column::CreatedAt.lt(created_at), Column::Id.lt(id)),
+                };
+                condition = condition.add(
+                    Condition::any().add(created_at_cmp).add(
+                        Condition::all()
+                            .add(Column::CreatedAt.eq(created_at))
+                            .add(id_cmp),
+                    ),
+                );
+            }
+
+            let order = match query.order {
+                SortOrder::Asc => Order::Asc,
+                SortOrder::Desc => Order::Desc,
+            };
+
+            Ok(Entity::find()
+                .filter(condition)
+                .order_by(Column::CreatedAt, order.clone())
+                .order_by(Column::Id, order)
+                .limit(query.limit)
+                .all(conn)
+                .await?)
+        }
+
+        /// Ids of the assets with all or any of the tags of the query
+        fn tagged_asset_ids(query: &AssetQuery) -> SelectStatement {
+            let mut select = Query::select();
+            select
+                .column(asset_tags::Column::AssetId)
+                .from(asset_tags::Entity)
+                .inner_join(
+                    tags::Entity,
+                    Expr::col((tags::Entity, tags::Column::Id))
+                        .equals((asset_tags::Entity, asset_tags::Column::TagId)),
+                )
+                .and_where(tags::Column::Name.is_in(query.tags.iter().cloned()));
+            // Owners see only their own tags
+            if let Some(owner) = &query.owner {
+                select.and_where(asset_tags::Column::Owner.eq(owner));
+            }
+
+            if query.tag_match == TagMatch::All {
+                select.group_by_col(asset_tags::Column::AssetId).and_having(
+                    Expr::col(asset_tags::Column::TagId)
+                        .count_distinct()
+                        .eq(query.tags.len() as i64),
+                );
+            }
+
+            select
+        }
+
+        pub async fn get_by_id(
+            &self,
+            conn: &impl ConnectionTrait,
+            id: i32,
+        ) -> Result<Option<Asset>> {
+            Ok(Entity::find_by_id(id).one(conn).await?)
+        }
+
+        pub async fn list_assets_of_type(
+            &self,
+            conn: &impl ConnectionTrait,
+            asset_type: AssetType,
+        ) -> Result<Vec<Asset>> {
+            Ok(Entity::find()
+                .filter(Column::AssetType.eq(asset_type.to_string()))
+                .order_by_asc(Column::Id)
+                .all(conn)
+                .await?)
+        }
+
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn set_unreferenced_since(
+            &self,
+            conn: &impl ConnectionTrait,
+            ids: &[i32],
+            unreferenced_since: Option<NaiveDateTime>,
+        ) -> Result<()> {
+            if ids.is_empty() {
+                return Ok(());
+            }
+
+            Entity::update_many()
+                .col_expr(Column::UnreferencedSince, Expr::value(unreferenced_since))
+                .filter(Column::Id.is_in(ids.iter().copied()))
+                .exec(conn)
+                .await?;
+
+            Ok(())
+        }
+
+        /// Deletes the asset with its thumbnail and derived assets, returns the deleted assets whose
+        /// files can be deleted with [`Self::delete_files`] once the transaction is committed
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn delete_asset(
+            &self,
+            conn: &impl ConnectionTrait,
+            id: i32,
+        ) -> Result<Vec<Asset>> {
+            let Some(asset) = self.get_by_id(conn, id).await? else {
+                return Ok(Vec::new());
+            };
+
+            let thumbnail_asset_ids = thumbnails::Entity::find()
+                .select_only()
+                .column(thumbnails::Column::AssetId)
+                .filter(thumbnails::Column::ImageId.eq(id))
+                .filter(thumbnails::Column::AssetId.is_not_null())
+                .into_tuple::<i32>()
+                .all(conn)
+                .await?;
+
+            let derived_asset_ids = asset_derivatives::Entity::find()
+                .select_only()
+                .column(asset_derivatives::Column::AssetId)
+                .filter(asset_derivatives::Column::SourceHash.eq(&asset.hash))
+                .into_tuple::<i32>()
+                .all(conn)
+                .await?;
+            asset_derivatives::Entity::delete_many()
+                .filter(asset_derivatives::Column::SourceHash.eq(&asset.hash))
+                .exec(conn)
+                .await?;
+
+            // Thumbnail rows of the asset are deleted by the cascade
+            Entity::delete_by_id(id).exec(conn).await?;
+            let mut deleted = vec![asset];
+
+            // Variants that equal an other asset share it and keep its type
+            let derived_assets = Entity::find()
+                .filter(Column::Id.is_in(derived_asset_ids))
+                .filter(Column::AssetType.eq(AssetType::Derived.to_string()))
+                .all(conn)
+                .await?;
+            if !derived_assets.is_empty() {
+                Entity::delete_many()
+                    .filter(Column::Id.is_in(derived_assets.iter().map(|asset| asset.id)))
+                    .exec(conn)
+                    .await?;
+            }
+            deleted.extend(derived_assets);
+
+            // Identical thumbnails of other images share their asset
+            let shared = thumbnails::Entity::find()
+                .select_only()
+                .column(thumbnails::Column::AssetId)
+                .filter(thumbnails::Column::AssetId.is_in(thumbnail_asset_ids.iter().copied()))
+                .into_tuple::<i32>()
+                .all(conn)
+                .await?;
+            let unshared = thumbnail_asset_ids
+                .into_iter()
+                .filter(|id| !shared.contains(id))
+                .collect::<Vec<_>>();
+
+            let thumbnail_assets = Entity::find()
+                .filter(Column::Id.is_in(unshared))
+                .filter(Column::AssetType.eq(AssetType::Thumbnail.to_string()))
+                .all(conn)
+                .await?;
+            if !thumbnail_assets.is_empty() {
+                Entity::delete_many()
+                    .filter(Column::Id.is_in(thumbnail_assets.iter().map(|asset| asset.id)))
+                    .exec(conn)
+                    .await?;
+            }
+            deleted.extend(thumbnail_assets);
+
+            Ok(deleted)
+        }
+
+        /// Deletes the files of deleted assets, failures are logged since the rows are gone
+        pub async fn delete_files(&self, assets: &[Asset]) {
+            for asset in assets {
+                if let Err(e) = self.fs_adapter.delete_file(Path::new(&asset.name)).await {
+                    tracing::error!(error = %e, "Failed to delete file of asset {}", asset.id);
+                }
+            }
+        }
+
+        /// Thumbnails of every asset in a single query, thumbnails without an asset are skipped
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn get_thumbnails_of_assets(
+            &self,
+            conn: &impl ConnectionTrait,
+            image_ids: &[i32],
+        ) -> Result<HashMap<i32, Vec<(thumbnails::Model, Asset)>>> {
+            let thumbnails = thumbnails::Entity::find()
+                .find_also_linked(ThumbnailAsset)
+                .filter(thumbnails::Column::ImageId.is_in(image_ids.iter().copied()))
+                .order_by_asc(thumbnails::Column::Id)
+                .all(conn)
+                .await?;
+
+            let mut by_image = HashMap::<i32, Vec<_>>::new();
+            for (thumbnail, asset) in thumbnails {
+                if let Some(asset) = asset {
+                    by_image
+                        .entry(thumbnail.image_id)
+                        .or_default()
+                        .push((thumbnail, asset));
+                }
+            }
+
+            Ok(by_image)
+        }
+    }
+
+    fn escape_like(value: &str) -> String {
+        value
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
     }
 }
 
@@ -5,7 +5,7 @@ use serde::Serialize;
 
 pub use inner::*;
 
-use crate::api::websockets::Action;
+use crate::{api::websockets::Action, entity::codec::EntityCodec};
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
 #[sea_orm(table_name = "entity")]
@@ -17,6 +17,14 @@ pub struct Model {
     pub timestamp: i64,
     pub kind: String,
     pub data: Vec<u8>,
+    pub codec: EntityCodec,
+    /// Zstd dictionary the payload was compressed with
+    pub dictionary: Option<i32>,
+    /// Uncompressed kind specific values, only saved for kinds with indexed fields
+    pub document: Option<Json>,
+    /// Server time in milliseconds at which the entity was deleted, deleted entities are
+    /// kept as tombstones so outdated updates can not bring them back
+    pub deleted_at: Option<i64>,
     #[sea_orm(ignore)]
     pub action: Option<Arc<Action>>,
 }
@@ -28,13 +36,23 @@ impl ActiveModelBehavior for ActiveModel {}
 
 mod inner {
 
+    use std::collections::{HashMap, HashSet};
+
+    use chrono::{DateTime, Utc};
     use sea_orm::ActiveValue::Set;
-    use sea_orm::sea_query::OnConflict;
-    use sea_orm::{ColumnTrait, Condition, ConnectionTrait, EntityTrait, QueryFilter};
+    use sea_orm::sea_query::{Alias, Expr, OnConflict};
+    use sea_orm::{
+        ColumnTrait, Condition, ConnectionTrait, DatabaseBackend, EntityTrait, PaginatorTrait,
+        QueryFilter, QueryOrder, QuerySelect, Statement, Value,
+    };
 
+    use crate::entity::codec::EntityCodec;
+    use crate::entity::fields::{
+        EntityFields, EntityFilter, FieldCondition, FieldOperator, FieldType, FieldValue,
+        IndexedField,
+    };
     use crate::models::entity::{ActiveModel, Column, Entity, Model};
     use crate::models::error::{Error, Result};
-    use crate::webserver::services::entity_queue::GameIdAndUIdCombo;
 
     pub type CompressedEntityModel = Model;
 
@@ -45,108 +63,129 @@ mod inner {
             Self {}
         }
 
-        #[tracing::instrument(skip(self, conn, valid_entities))]
-        pub async fn save_valid_entities(
+        /// Inserts or updates entities, an entity is only updated if it is newer
+        /// than the saved one or its tombstone so concurrent flushes can not
+        /// overwrite newer changes
+        #[tracing::instrument(skip(self, conn, entities))]
+        pub async fn save_entities(
             &self,
             conn: &impl ConnectionTrait,
-            valid_entities: Vec<CompressedEntityModel>,
+            entities: Vec<CompressedEntityModel>,
         ) -> Result<()> {
-            if valid_entities.is_empty() {
+            if entities.is_empty() {
                 return Ok(());
             }
 
-            let active_models = valid_entities.into_iter().map(|entity| ActiveModel {
+            let active_models = entities.into_iter().map(|entity| ActiveModel {
                 uid: Set(entity.uid),
                 game: Set(entity.game),
                 timestamp: Set(entity.timestamp),
                 kind: Set(entity.kind),
                 data: Set(entity.data),
+                codec: Set(entity.codec),
+                dictionary: Set(entity.dictionary),
+                document: Set(entity.document),
+                deleted_at: Set(None),
             });
 
             Entity::insert_many(active_models)
                 .on_conflict(
                     OnConflict::columns([Column::Uid, Column::Game])
-                        .update_columns([Column::Timestamp, Column::Kind, Column::Data])
+                        .update_columns([
+                            Column::Timestamp,
+                            Column::Kind,
+                            Column::Data,
+                            Column::Codec,
+                            Column::Dictionary,
+                            Column::Document,
+                            Column::DeletedAt,
+                        ])
+                        .action_and_where(
+                            Expr::col((Entity, Column::Timestamp))
+                                .lt(Expr::col((Alias::new("excluded"), Column::Timestamp))),
+                        )
                         .to_owned(),
                 )
-                .exec(conn)
+                .exec_without_returning(conn)
                 .await?;
 
             Ok(())
         }
 
-        #[tracing::instrument(skip(self, conn, compressed_entities))]
-        pub async fn filter_out_outdated_entities(
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn load_entities(
             &self,
             conn: &impl ConnectionTrait,
-            compressed_entities: Vec<(GameIdAndUIdCombo, CompressedEntityModel)>,
+            game_id: i32,
         ) -> Result<Vec<CompressedEntityModel>> {
-            let uids = compressed_entities
-                .iter()
-                .map(|(combo, _)| combo.uid.0.clone())
-                .collect::<Vec<_>>();
-
-            let game_ids = compressed_entities
-                .iter()
-                .map(|(combo, _)| combo.game_id)
-                .collect::<Vec<_>>();
-
-            let entities = compressed_entities
-                .into_iter()
-                .map(|(_, entity)| entity)
-                .collect::<Vec<_>>();
-
-            let saved_entities = Entity::find()
-                .filter(
-                    Condition::all()
-                        .add(Column::Uid.is_in(uids))
-                        .add(Column::Game.is_in(game_ids)),
-                )
+            Ok(Entity::find()
+                .filter(Self::alive_in_game(game_id))
                 .all(conn)
-                .await?;
-
-            let valid_entities = entities
-                .iter()
-                .filter(|entity| {
-                    if entity.action.is_none()
-                        || entity
-                            .action
-                            .as_ref()
-                            .expect("Action is not none")
-                            .is_other()
-                    {
-                        return false;
-                    }
-
-                    let saved_entity = saved_entities
-                        .iter()
-                        .find(|saved_entity| saved_entity.uid == entity.uid);
-
-                    if let Some(saved_entity) = saved_entity {
-                        saved_entity.timestamp < entity.timestamp
-                    } else {
-                        true
-                    }
-                })
-                .cloned()
-                .collect::<Vec<_>>();
+                .await?)
+        }
 
-            Ok(valid_entities)
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn count_entities(
+            &self,
+            conn: &impl ConnectionTrait,
+            game_id: i32,
+        ) -> Result<u64> {
+            Ok(Entity::find()
+                .filter(Self::alive_in_game(game_id))
+                .count(conn)
+                .await?)
         }
 
+        /// Loads at most `limit` entities of a game ordered by uid
+        /// starting after `after_uid`, pass the last uid of the previous
+        /// page to continue where it left off
         #[tracing::instrument(skip(self, conn))]
-        pub async fn load_entities(
+        pub async fn load_entities_page(
             &self,
             conn: &impl ConnectionTrait,
             game_id: i32,
+            after_uid: Option<&str>,
+            limit: u64,
         ) -> Result<Vec<CompressedEntityModel>> {
+            let mut condition = Self::alive_in_game(game_id);
+            if let Some(after_uid) = after_uid {
+                condition = condition.add(Column::Uid.gt(after_uid));
+            }
+
             Ok(Entity::find()
-                .filter(Column::Game.eq(game_id))
+                .filter(condition)
+                .order_by_asc(Column::Uid)
+                .limit(limit)
                 .all(conn)
                 .await?)
         }
 
+        /// Loads at most `limit` entities of all games ordered by game and uid
+        /// starting after `after`
         #[tracing::instrument(skip(self, conn))]
+        pub async fn load_all_entities_page(
+            &self,
+            conn: &impl ConnectionTrait,
+            after: Option<(i32, &str)>,
+            limit: u64,
+        ) -> Result<Vec<CompressedEntityModel>> {
+            let mut condition = Condition::all().add(Column::DeletedAt.is_null());
+            if let Some((game, uid)) = after {
+                condition = condition.add(Self::after_game_and_uid(game, uid));
+            }
+
+            Ok(Entity::find()
+                .filter(condition)
+                .order_by_asc(Column::Game)
+                .order_by_asc(Column::Uid)
+                .limit(limit)
+                .all(conn)
+                .await?)
+        }
+
+        /// Replaces entities that are older than the deletes with tombstones,
+        /// tombstones are also saved for entities that were never saved
+        #[tracing::instrument(skip(self, conn, delete_entities))]
         pub(crate) async fn delete_entities(
             &self,
             conn: &impl ConnectionTrait,
@@ -156,19 +195,747 @@ mod inner {
                 return Ok(());
             }
 
-            let deletes = delete_entities.into_iter().map(|entity| {
-                Entity::delete(ActiveModel {
-                    uid: Set(entity.uid),
-                    game: Set(entity.game),
-                    ..Default::default()
-                })
+            let deleted_at = Utc::now().timestamp_millis();
+            let tombstones = delete_entities.into_iter().map(|entity| ActiveModel {
+                uid: Set(entity.uid),
+                game: Set(entity.game),
+                timestamp: Set(entity.timestamp),
+                kind: Set(entity.kind),
+                data: Set(Vec::new()),
+                codec: Set(EntityCodec::None),
+                dictionary: Set(None),
+                document: Set(None),
+                deleted_at: Set(Some(deleted_at)),
             });
 
-            for delete in deletes {
-                delete.exec(conn).await?;
+            Entity::insert_many(tombstones)
+                .on_conflict(
+                    OnConflict::columns([Column::Uid, Column::Game])
+                        .update_columns([
+                            Column::Timestamp,
+                            Column::Data,
+                            Column::Codec,
+                            Column::Dictionary,
+                            Column::Document,
+                            Column::DeletedAt,
+                        ])
+                        .action_and_where(
+                            Expr::col((Entity, Column::Timestamp))
+                                .lt(Expr::col((Alias::new("excluded"), Column::Timestamp))),
+                        )
+                        .to_owned(),
+                )
+                .exec_without_returning(conn)
+                .await?;
+
+            Ok(())
+        }
+
+        /// Removes tombstones of entities deleted before `deleted_before`,
+        /// returns the number of removed tombstones
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn purge_tombstones(
+            &self,
+            conn: &impl ConnectionTrait,
+            deleted_before: DateTime<Utc>,
+        ) -> Result<u64> {
+            let result = Entity::delete_many()
+                .filter(Column::DeletedAt.lt(deleted_before.timestamp_millis()))
+                .exec(conn)
+                .await?;
+
+            Ok(result.rows_affected)
+        }
+
+        /// Kinds of all entities that are not deleted
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn list_kinds(&self, conn: &impl ConnectionTrait) -> Result<Vec<String>> {
+            Ok(Entity::find()
+                .select_only()
+                .column(Column::Kind)
+                .distinct()
+                .filter(Column::DeletedAt.is_null())
+                .into_tuple::<String>()
+                .all(conn)
+                .await?)
+        }
+
+        /// Loads at most `limit` entities of a kind across all games
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn load_entities_of_kind(
+            &self,
+            conn: &impl ConnectionTrait,
+            kind: &str,
+            limit: u64,
+        ) -> Result<Vec<CompressedEntityModel>> {
+            Ok(Entity::find()
+                .filter(Column::Kind.eq(kind))
+                .filter(Column::DeletedAt.is_null())
+                .limit(limit)
+                .all(conn)
+                .await?)
+        }
+
+        /// Loads at most `limit` entities ordered by game and uid starting after `after`,
+        /// whose payload is not compressed with `codec` and the dictionary of their kind
+        /// or that are missing the document of their kind
+        #[tracing::instrument(skip(self, conn, kind_dictionaries, document_kinds))]
+        pub async fn load_entities_to_recompress(
+            &self,
+            conn: &impl ConnectionTrait,
+            codec: EntityCodec,
+            kind_dictionaries: &[(String, i32)],
+            document_kinds: &[String],
+            after: Option<(i32, &str)>,
+            limit: u64,
+        ) -> Result<Vec<CompressedEntityModel>> {
+            let mut outdated = Condition::any().add(Column::Codec.ne(codec));
+            if codec == EntityCodec::Zstd {
+                for (kind, dictionary) in kind_dictionaries {
+                    outdated = outdated.add(
+                        Condition::all().add(Column::Kind.eq(kind.as_str())).add(
+                            Condition::any()
+                                .add(Column::Dictionary.is_null())
+                                .add(Column::Dictionary.ne(*dictionary)),
+                        ),
+                    );
+                }
+            }
+            if !document_kinds.is_empty() {
+                outdated = outdated.add(
+                    Condition::all()
+                        .add(Column::Kind.is_in(document_kinds))
+                        .add(Column::Document.is_null()),
+                );
+            }
+
+            let mut condition = Condition::all()
+                .add(Column::DeletedAt.is_null())
+                .add(outdated);
+            if let Some((game, uid)) = after {
+                condition = condition.add(Self::after_game_and_uid(game, uid));
+            }
+
+            Ok(Entity::find()
+                .filter(condition)
+                .order_by_asc(Column::Game)
+                .order_by_asc(Column::Uid)
+                .limit(limit)
+                .all(conn)
+                .await?)
+        }
+
+        /// Replaces the payload of an entity unless the entity was changed since
+        /// `entity` was loaded, returns false if it was changed
+        #[tracing::instrument(skip(self, conn, entity, recompressed))]
+        pub async fn replace_payload(
+            &self,
+            conn: &impl ConnectionTrait,
+            entity: &CompressedEntityModel,
+            recompressed: CompressedEntityModel,
+        ) -> Result<bool> {
+            let result = Entity::update_many()
+                .col_expr(Column::Data, Expr::value(recompressed.data))
+                .col_expr(Column::Codec, Expr::value(recompressed.codec))
+                .col_expr(Column::Dictionary, Expr::value(recompressed.dictionary))
+                .col_expr(Column::Document, Expr::value(recompressed.document))
+                .filter(Column::Uid.eq(entity.uid.as_str()))
+                .filter(Column::Game.eq(entity.game))
+                .filter(Column::Timestamp.eq(entity.timestamp))
+                .filter(Column::DeletedAt.is_null())
+                .exec(conn)
+                .await?;
+
+            Ok(result.rows_affected > 0)
+        }
+
+        /// Adds the generated column and index of every indexed field that does not have one yet,
+        /// fields that are no longer indexed keep their columns
+        #[tracing::instrument(skip(self, conn, fields))]
+        pub async fn create_field_columns(
+            &self,
+            conn: &impl ConnectionTrait,
+            fields: &EntityFields,
+        ) -> Result<()> {
+            let backend = conn.get_database_backend();
+            let existing = match backend {
+                DatabaseBackend::Sqlite => conn
+                    .query_all(Statement::from_string(
+                        backend,
+                        "PRAGMA table_xinfo(entity)",
+                    ))
+                    .await?
+                    .into_iter()
+                    .map(|row| row.try_get::<String>("", "name"))
+                    .collect::<std::result::Result<HashSet<_>, _>>()?,
+                DatabaseBackend::Postgres => conn
+                    .query_all(Statement::from_string(
+                        backend,
+                        "SELECT column_name AS name FROM information_schema.columns \
+                         WHERE table_schema = current_schema() AND table_name = 'entity'",
+                    ))
+                    .await?
+                    .into_iter()
+                    .map(|row| row.try_get::<String>("", "name"))
+                    .collect::<std::result::Result<HashSet<_>, _>>()?,
+                DatabaseBackend::MySql => return Err(Error::FieldIndexUnsupported(backend)),
+            };
+
+            // Fields like `a-b.hp` and `a_b.hp` would share a column
+            let mut columns = HashMap::new();
+            for field in fields.fields() {
+                let name = format!("{}.{}", field.kind, field.path);
+                if let Some(other) = columns.insert(field.column_name(), name.clone()) {
+                    return Err(Error::FieldColumnConflict(other, name));
+                }
+            }
+
+            for field in fields.fields() {
+                let column = field.column_name();
+                if !existing.contains(&column) {
+                    tracing::info!(
+                        "Adding column {column} for entity field {}.{}",
+                        field.kind,
+                        field.path
+                    );
+                    conn.execute(Statement::from_string(
+                        backend,
+                        format!(
+                            "ALTER TABLE entity ADD COLUMN {}",
+                            Self::field_column_definition(backend, field)
+                        ),
+                    ))
+                    .await?;
+                }
+
+                conn.execute(Statement::from_string(
+                    backend,
+                    format!(
+                        "CREATE INDEX IF NOT EXISTS entity_idx_{column} ON entity (game, {column})"
+                    ),
+                ))
+                .await?;
             }
 
             Ok(())
         }
+
+        /// Loads at most `limit` entities of a game ordered by uid starting after
+        /// `after_uid` that match the filter, only indexed fields can be filtered on
+        #[tracing::instrument(skip(self, conn, fields))]
+        pub async fn find_entities(
+            &self,
+            conn: &impl ConnectionTrait,
+            fields: &EntityFields,
+            game_id: i32,
+            filter: &EntityFilter,
+            after_uid: Option<&str>,
+            limit: u64,
+        ) -> Result<Vec<CompressedEntityModel>> {
+            let mut condition =
+                Self::alive_in_game(game_id).add(Column::Kind.eq(filter.kind.as_str()));
+
+            for FieldCondition { path, operator } in &filter.conditions {
+                let field = fields
+                    .get(&filter.kind, path)
+                    .ok_or_else(|| Error::FieldNotIndexed(format!("{}.{path}", filter.kind)))?;
+
+                let values = operator
+                    .values()
+                    .iter()
+                    .map(|value| Self::field_value(field, value))
+                    .collect::<Result<Vec<_>>>()?;
+                let column = Expr::col(Alias::new(field.column_name()));
+
+                condition = condition.add(match operator {
+                    FieldOperator::Eq(_) => column.eq(values[0].clone()),
+                    FieldOperator::Ne(_) => column.ne(values[0].clone()),
+                    FieldOperator::Lt(_) => column.lt(values[0].clone()),
+                    FieldOperator::Le(_) => column.lte(values[0].clone()),
+                    FieldOperator::Gt(_) => column.gt(values[0].clone()),
+                    FieldOperator::Ge(_) => column.gte(values[0].clone()),
+                    FieldOperator::In(_) => column.is_in(values),
+                    FieldOperator::IsNull => column.is_null(),
+                    FieldOperator::IsNotNull => column.is_not_null(),
+                });
+            }
+
+            if let Some(after_uid) = after_uid {
+                condition = condition.add(Column::Uid.gt(after_uid));
+            }
+
+            Ok(Entity::find()
+                .filter(condition)
+                .order_by_asc(Column::Uid)
+                .limit(limit)
+                .all(conn)
+                .await?)
+        }
+
+        /// Values of another type than the field are null, so the field only
+        /// matches values of its own type, integers also match real fields
+        fn field_value(field: &IndexedField, value: &FieldValue) -> Result<Value> {
+            match (field.field_type, value) {
+                (FieldType::Integer, FieldValue::Integer(v)) => Ok((*v).into()),
+                (FieldType::Real, FieldValue::Integer(v)) => Ok((*v as f64).into()),
+                (FieldType::Real, FieldValue::Real(v)) => Ok((*v).into()),
+                (FieldType::Text, FieldValue::Text(v)) => Ok(v.clone().into()),
+                (FieldType::Boolean, FieldValue::Boolean(v)) => Ok((*v).into()),
+                _ => Err(Error::FieldTypeMismatch {
+                    field: format!("{}.{}", field.kind, field.path),
+                    expected: field.field_type,
+                }),
+            }
+        }
+
+        /// Generated column that extracts the field from the document of entities of its kind
+        fn field_column_definition(backend: DatabaseBackend, field: &IndexedField) -> String {
+            let column = field.column_name();
+            let kind = &field.kind;
+
+            if backend == DatabaseBackend::Postgres {
+                let path = format!(
+                    "'{{{}}}'",
+                    field.path_segments().collect::<Vec<_>>().join(",")
+                );
+                let (column_type, json_type, cast) = match field.field_type {
+                    FieldType::Integer => ("BIGINT", "number", "::numeric::bigint"),
+                    FieldType::Real => ("DOUBLE PRECISION", "number", "::double precision"),
+                    FieldType::Text => ("TEXT", "string", ""),
+                    FieldType::Boolean => ("BOOLEAN", "boolean", "::boolean"),
+                };
+
+                return format!(
+                    "{column} {column_type} GENERATED ALWAYS AS (CASE WHEN kind = '{kind}' \
+                     AND jsonb_typeof(document #> {path}) = '{json_type}' \
+                     THEN (document #>> {path}){cast} END) STORED"
+                );
+            }
+
+            let path = format!(
+                "'${}'",
+                field
+                    .path_segments()
+                    .map(|segment| format!(".\"{segment}\""))
+                    .collect::<String>()
+            );
+            let (column_type, json_types, extracted) = match field.field_type {
+                FieldType::Integer => (
+                    "INTEGER",
+                    "'integer', 'real'",
+                    format!("CAST(json_extract(document, {path}) AS INTEGER)"),
+                ),
+                FieldType::Real => (
+                    "REAL",
+                    "'integer', 'real'",
+                    format!("CAST(json_extract(document, {path}) AS REAL)"),
+                ),
+                FieldType::Text => ("TEXT", "'text'", format!("json_extract(document, {path})")),
+                FieldType::Boolean => (
+                    "INTEGER",
+                    "'true', 'false'",
+                    format!("json_extract(document, {path})"),
+                ),
+            };
+
+            format!(
+                "{column} {column_type} GENERATED ALWAYS AS (CASE WHEN kind = '{kind}' \
+                 AND json_type(document, {path}) IN ({json_types}) \
+                 THEN {extracted} END) VIRTUAL"
+            )
+        }
+
+        fn after_game_and_uid(game: i32, uid: &str) -> Condition {
+            Condition::any().add(Column::Game.gt(game)).add(
+                Condition::all()
+                    .add(Column::Game.eq(game))
+                    .add(Column::Uid.gt(uid)),
+            )
+        }
+
+        fn alive_in_game(game_id: i32) -> Condition {
+            Condition::all()
+                .add(Column::Game.eq(game_id))
+                .add(Column::DeletedAt.is_null())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        api::websockets::Action,
+        entity::{
+            Entity, UId, UtcTimestamp,
+            codec::{EntityCodec, EntityCodecs},
+            fields::{EntityFields, EntityFilter},
+            kind::EntityKind,
+        },
+        models::{
+            entity::{CompressedEntityModel, EntityManager},
+            error::Error,
+        },
+        utils::test_utils::get_app_state_with_temp_file_store,
+        webserver::router::app_state::AppStateTrait,
+    };
+
+    fn compressed_entity(uid: &str, game: i32) -> CompressedEntityModel {
+        CompressedEntityModel {
+            uid: uid.to_string(),
+            game,
+            timestamp: 1,
+            kind: "token".to_string(),
+            data: b"{}".to_vec(),
+            codec: EntityCodec::None,
+            dictionary: None,
+            document: None,
+            deleted_at: None,
+            action: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn entities_are_loaded_in_pages() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+        let entity_manager = EntityManager::new();
+
+        let entities = (0..5)
+            .map(|i| compressed_entity(&format!("uid-{i}"), 1))
+            .chain([compressed_entity("uid-other-game", 2)])
+            .collect();
+        entity_manager.save_entities(&db, entities).await.unwrap();
+
+        assert_eq!(5, entity_manager.count_entities(&db, 1).await.unwrap());
+
+        let mut loaded = Vec::new();
+        let mut last_uid = None;
+        loop {
+            let page = entity_manager
+                .load_entities_page(&db, 1, last_uid.as_deref(), 2)
+                .await
+                .unwrap();
+            assert!(page.len() <= 2);
+
+            let Some(last) = page.last() else {
+                break;
+            };
+            last_uid = Some(last.uid.clone());
+            loaded.extend(page.into_iter().map(|e| e.uid));
+        }
+
+        assert_eq!(vec!["uid-0", "uid-1", "uid-2", "uid-3", "uid-4"], loaded);
+    }
+
+    fn saved_timestamps(entities: Vec<CompressedEntityModel>) -> Vec<(String, i64)> {
+        let mut timestamps = entities
+            .into_iter()
+            .map(|e| (e.uid, e.timestamp))
+            .collect::<Vec<_>>();
+        timestamps.sort();
+        timestamps
+    }
+
+    #[tokio::test]
+    async fn outdated_entities_are_not_saved() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+        let entity_manager = EntityManager::new();
+
+        entity_manager
+            .save_entities(
+                &db,
+                vec![
+                    CompressedEntityModel {
+                        timestamp: 5,
+                        ..compressed_entity("a", 1)
+                    },
+                    compressed_entity("b", 1),
+                    compressed_entity("a", 2),
+                ],
+            )
+            .await
+            .unwrap();
+
+        // Newer entity with the same uid in game 1 must not shadow game 2
+        entity_manager
+            .save_entities(
+                &db,
+                vec![
+                    CompressedEntityModel {
+                        timestamp: 3,
+                        ..compressed_entity("a", 1)
+                    },
+                    CompressedEntityModel {
+                        timestamp: 2,
+                        ..compressed_entity("b", 1)
+                    },
+                    CompressedEntityModel {
+                        timestamp: 2,
+                        ..compressed_entity("a", 2)
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+
+        let game_1 = entity_manager.load_entities(&db, 1).await.unwrap();
+        assert_eq!(
+            vec![("a".to_string(), 5), ("b".to_string(), 2)],
+            saved_timestamps(game_1)
+        );
+
+        let game_2 = entity_manager.load_entities(&db, 2).await.unwrap();
+        assert_eq!(vec![("a".to_string(), 2)], saved_timestamps(game_2));
+    }
+
+    #[tokio::test]
+    async fn outdated_deletes_are_skipped() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+        let entity_manager = EntityManager::new();
+
+        entity_manager
+            .save_entities(
+                &db,
+                vec![
+                    CompressedEntityModel {
+                        timestamp: 5,
+                        ..compressed_entity("a", 1)
+                    },
+                    CompressedEntityModel {
+                        timestamp: 5,
+                        ..compressed_entity("b", 1)
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+
+        entity_manager
+            .delete_entities(
+                &db,
+                vec![
+                    CompressedEntityModel {
+                        timestamp: 4,
+                        ..compressed_entity("a", 1)
+                    },
+                    CompressedEntityModel {
+                        timestamp: 6,
+                        ..compressed_entity("b", 1)
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+
+        let game_1 = entity_manager.load_entities(&db, 1).await.unwrap();
+        assert_eq!(vec![("a".to_string(), 5)], saved_timestamps(game_1));
+    }
+
+    #[tokio::test]
+    async fn outdated_updates_do_not_resurrect_deleted_entities() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+        let entity_manager = EntityManager::new();
+
+        entity_manager
+            .save_entities(&db, vec![compressed_entity("a", 1)])
+            .await
+            .unwrap();
+        // Delete can be flushed before the create of an entity
+        entity_manager
+            .delete_entities(
+                &db,
+                vec![
+                    CompressedEntityModel {
+                        timestamp: 5,
+                        ..compressed_entity("a", 1)
+                    },
+                    CompressedEntityModel {
+                        timestamp: 5,
+                        ..compressed_entity("b", 1)
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+
+        entity_manager
+            .save_entities(
+                &db,
+                vec![
+                    CompressedEntityModel {
+                        timestamp: 3,
+                        ..compressed_entity("a", 1)
+                    },
+                    CompressedEntityModel {
+                        timestamp: 2,
+                        ..compressed_entity("b", 1)
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(0, entity_manager.count_entities(&db, 1).await.unwrap());
+
+        entity_manager
+            .save_entities(
+                &db,
+                vec![CompressedEntityModel {
+                    timestamp: 6,
+                    ..compressed_entity("a", 1)
+                }],
+            )
+            .await
+            .unwrap();
+        let game_1 = entity_manager.load_entities(&db, 1).await.unwrap();
+        assert_eq!(vec![("a".to_string(), 6)], saved_timestamps(game_1));
+    }
+
+    #[tokio::test]
+    async fn tombstones_are_purged_after_retention() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+        let entity_manager = EntityManager::new();
+
+        entity_manager
+            .delete_entities(&db, vec![compressed_entity("a", 1)])
+            .await
+            .unwrap();
+
+        let now = chrono::Utc::now();
+        let purged = entity_manager
+            .purge_tombstones(&db, now - chrono::Duration::hours(1))
+            .await
+            .unwrap();
+        assert_eq!(0, purged);
+
+        let purged = entity_manager
+            .purge_tombstones(&db, now + chrono::Duration::hours(1))
+            .await
+            .unwrap();
+        assert_eq!(1, purged);
+    }
+
+    #[tokio::test]
+    async fn entities_are_filtered_by_indexed_fields() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+        let entity_manager = EntityManager::new();
+
+        let fields = EntityFields::new(vec![
+            "token.stats.hp:integer".parse().unwrap(),
+            "token.image:text".parse().unwrap(),
+        ]);
+        // Columns that already exist are skipped
+        for _ in 0..2 {
+            entity_manager
+                .create_field_columns(&db, &fields)
+                .await
+                .unwrap();
+        }
+        let conflicting = EntityFields::new(vec![
+            "a-b.hp:integer".parse().unwrap(),
+            "a_b.hp:text".parse().unwrap(),
+        ]);
+        assert!(matches!(
+            entity_manager.create_field_columns(&db, &conflicting).await,
+            Err(Error::FieldColumnConflict(..))
+        ));
+
+        let codecs = EntityCodecs::default().with_fields(fields.clone());
+        let entity = |uid: &str, kind: &str, values: serde_json::Value| {
+            codecs
+                .compress(Entity {
+                    uid: UId(uid.to_string()),
+                    game: 1,
+                    kind: EntityKind(kind.to_string()),
+                    timestamp: UtcTimestamp(1),
+                    action: Some(Arc::new(Action::Create)),
+                    other_values: values,
+                })
+                .unwrap()
+        };
+        entity_manager
+            .save_entities(
+                &db,
+                vec![
+                    entity(
+                        "a",
+                        "token",
+                        serde_json::json!({ "stats": { "hp": -3 }, "image": "/assets/1" }),
+                    ),
+                    entity(
+                        "b",
+                        "token",
+                        serde_json::json!({ "stats": { "hp": 10 }, "image": "/assets/2" }),
+                    ),
+                    entity(
+                        "c",
+                        "token",
+                        serde_json::json!({ "stats": { "hp": "dead" }, "image": "/assets/1" }),
+                    ),
+                    entity(
+                        "d",
+                        "board",
+                        serde_json::json!({ "stats": { "hp": -1 }, "image": "/assets/1" }),
+                    ),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let find = |filter: EntityFilter| {
+            let entity_manager = &entity_manager;
+            let db = &db;
+            let fields = &fields;
+            async move {
+                entity_manager
+                    .find_entities(db, fields, 1, &filter, None, 10)
+                    .await
+                    .map(|entities| entities.into_iter().map(|e| e.uid).collect::<Vec<_>>())
+            }
+        };
+
+        assert_eq!(
+            vec!["a"],
+            find(EntityFilter::new("token").lt("stats.hp", 0))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            vec!["a", "c"],
+            find(EntityFilter::new("token").eq("image", "/assets/1"))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            vec!["c"],
+            find(EntityFilter::new("token").is_null("stats.hp"))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            vec!["a", "b"],
+            find(
+                EntityFilter::new("token")
+                    .is_in("image", ["/assets/1", "/assets/2"])
+                    .gt("stats.hp", -10)
+            )
+            .await
+            .unwrap()
+        );
+
+        assert!(matches!(
+            find(EntityFilter::new("board").lt("stats.hp", 0)).await,
+            Err(Error::FieldNotIndexed(_))
+        ));
+        assert!(matches!(
+            find(EntityFilter::new("token").eq("stats.hp", "dead")).await,
+            Err(Error::FieldTypeMismatch { .. })
+        ));
     }
 }
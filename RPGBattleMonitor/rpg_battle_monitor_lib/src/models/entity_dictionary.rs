@@ -0,0 +1,65 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+pub use inner::*;
+
+/// Zstd dictionary trained on payloads of an entity kind
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "entity_dictionary")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub kind: String,
+    #[serde(skip)]
+    pub data: Vec<u8>,
+    pub created_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+mod inner {
+    use chrono::Utc;
+    use sea_orm::ActiveValue::{NotSet, Set};
+    use sea_orm::QueryOrder;
+    use sea_orm::entity::prelude::*;
+
+    use crate::models::entity_dictionary::{ActiveModel, Column, Entity, Model};
+    use crate::models::error::Result;
+
+    pub type EntityDictionaryModel = Model;
+
+    #[derive(Default)]
+    pub struct EntityDictionaryManager {}
+
+    impl EntityDictionaryManager {
+        pub fn new() -> Self {
+            Self {}
+        }
+
+        pub async fn list_dictionaries(
+            &self,
+            conn: &impl ConnectionTrait,
+        ) -> Result<Vec<EntityDictionaryModel>> {
+            Ok(Entity::find().order_by_asc(Column::Id).all(conn).await?)
+        }
+
+        pub async fn save_dictionary(
+            &self,
+            conn: &impl ConnectionTrait,
+            kind: String,
+            data: Vec<u8>,
+        ) -> Result<EntityDictionaryModel> {
+            Ok(ActiveModel {
+                id: NotSet,
+                kind: Set(kind),
+                data: Set(data),
+                created_at: Set(Utc::now().timestamp_millis()),
+            }
+            .insert(conn)
+            .await?)
+        }
+    }
+}
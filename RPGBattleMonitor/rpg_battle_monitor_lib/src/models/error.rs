@@ -14,8 +14,79 @@ pub enum Error {
     CdnError(#[from] crate::cdn::filesystem::error::Error),
 
     #[error(transparent)]
-    ThumbnailError(#[from] crate::thumbnail::error::Error),
+    IoError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    ThumbnailError(crate::thumbnail::error::Error),
+
+    #[error("Entity field {0} is not indexed")]
+    FieldNotIndexed(String),
+
+    #[error("Entity field {field} is indexed as {expected:?}")]
+    FieldTypeMismatch {
+        field: String,
+        expected: crate::entity::fields::FieldType,
+    },
+
+    #[error("Entity fields {0} and {1} have the same column")]
+    FieldColumnConflict(String, String),
+
+    #[error("Indexed entity fields are not supported on {0:?}")]
+    FieldIndexUnsupported(sea_orm::DatabaseBackend),
+
+    #[error("Entity can not be read")]
+    EntityUnreadable(#[source] Box<crate::entity::error::Error>),
+
+    #[error("Asset {0} not found")]
+    AssetNotFound(i32),
+
+    #[error("Asset entry {0} not found")]
+    AssetEntryNotFound(i32),
+
+    #[error("Folder {0} not found")]
+    FolderNotFound(i32),
+
+    #[error("Folder {0} belongs to another game")]
+    FolderInOtherGame(i32),
+
+    #[error("Folder {0} can not be moved into itself or one of its subfolders")]
+    InvalidFolderMove(i32),
+
+    #[error("Name is empty")]
+    NameEmpty,
+
+    #[error("Tag {0:?} is empty or too long")]
+    InvalidTag(String),
 
     #[error("Data is empty")]
     DataEmpty,
+
+    #[error("SVG document is invalid: {0}")]
+    InvalidSvg(String),
+
+    #[error("Markup documents other than SVG can not be uploaded")]
+    UnsupportedMarkup,
+
+    #[error("Asset {0} is not an image that can be transformed")]
+    NotAnImage(i32),
+
+    #[error("Image of {width}x{height} pixels is too large to be transformed")]
+    ImageTooLarge { width: u32, height: u32 },
+
+    #[error("Asset {0} has too many transformed variants")]
+    TooManyVariants(i32),
+
+    #[error("File of type {mime} is larger than {limit} bytes")]
+    FileTooLarge { mime: String, limit: u64 },
+}
+
+impl From<crate::thumbnail::error::Error> for Error {
+    fn from(value: crate::thumbnail::error::Error) -> Self {
+        match value {
+            crate::thumbnail::error::Error::TooLarge { width, height } => {
+                Self::ImageTooLarge { width, height }
+            }
+            e => Self::ThumbnailError(e),
+        }
+    }
 }
@@ -40,6 +40,10 @@ mod inner {
             Ok(Entity::find().all(conn).await?)
         }
 
+        pub async fn exists(&self, conn: &impl ConnectionTrait, id: i32) -> Result<bool> {
+            Ok(Entity::find_by_id(id).one(conn).await?.is_some())
+        }
+
         pub async fn create_game(&self, conn: &impl ConnectionTrait) -> Result<GameModel> {
             Ok(ActiveModel {
                 id: NotSet,
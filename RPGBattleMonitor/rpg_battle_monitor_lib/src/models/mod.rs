@@ -1,5 +1,11 @@
+pub mod asset_derivatives;
+pub mod asset_entry;
+pub mod asset_folder;
+pub mod asset_tags;
 pub mod assets;
 pub mod entity;
+pub mod entity_dictionary;
 pub mod error;
 pub mod game;
+pub mod tags;
 pub mod thumbnails;
@@ -0,0 +1,410 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+pub use inner::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "tags")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "super::asset_tags::Entity")]
+    AssetTag,
+}
+
+impl Related<super::asset_tags::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::AssetTag.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// How assets are matched against several tags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMatch {
+    /// Assets with every tag
+    #[default]
+    All,
+    /// Assets with at least one of the tags
+    Any,
+}
+
+/// Lowercases the tag and collapses its whitespace
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+
+    (!tag.is_empty() && tag.chars().count() <= MAX_TAG_LENGTH).then_some(tag)
+}
+
+pub const MAX_TAG_LENGTH: usize = 64;
+const MAX_SUGGESTED_TAGS: usize = 10;
+
+/// Words that say nothing about the content of a file
+const IGNORED_WORDS: &[&str] = &[
+    "and", "copy", "final", "for", "img", "image", "new", "the", "with",
+];
+
+/// Words of a filename without its extension, `ForestMap_02-night.png` has the
+/// words `forest`, `map` and `night`
+fn filename_words(filename: &str) -> Vec<String> {
+    let stem = std::path::Path::new(filename)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(filename);
+
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut previous = None::<char>;
+    for c in stem.chars() {
+        let camel_case_boundary = c.is_uppercase() && previous.is_some_and(char::is_lowercase);
+        if !c.is_alphanumeric() || camel_case_boundary {
+            words.push(std::mem::take(&mut word));
+        }
+        if c.is_alphanumeric() {
+            word.extend(c.to_lowercase());
+        }
+        previous = Some(c);
+    }
+    words.push(word);
+
+    words
+        .into_iter()
+        .filter(|word| word.chars().count() >= 3)
+        // Numbers, sizes and versions like `02`, `512x512` or `v2`
+        .filter(|word| !word.chars().any(|c| c.is_ascii_digit()))
+        .filter(|word| !IGNORED_WORDS.contains(&word.as_str()))
+        .collect()
+}
+
+/// Tags for an uploaded file, known tags whose words all appear in the filename come
+/// first followed by the remaining words of the filename
+pub fn suggest_tags(filename: &str, known_tags: &[String]) -> Vec<String> {
+    let words = filename_words(filename);
+
+    let mut suggested = known_tags
+        .iter()
+        .filter(|tag| {
+            tag.split(' ')
+                .all(|tag_word| words.iter().any(|word| word == tag_word))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    suggested.sort_by_key(|tag| std::cmp::Reverse(tag.split(' ').count()));
+
+    for word in words {
+        let covered = suggested
+            .iter()
+            .any(|tag| tag.split(' ').any(|tag_word| tag_word == word));
+        if !covered {
+            suggested.push(word);
+        }
+    }
+
+    suggested.truncate(MAX_SUGGESTED_TAGS);
+    suggested
+}
+
+mod inner {
+    use std::collections::HashMap;
+
+    use sea_orm::ActiveValue::Set;
+    use sea_orm::entity::prelude::*;
+    use sea_orm::sea_query::OnConflict;
+    use sea_orm::{JoinType, QueryOrder, QuerySelect};
+
+    use crate::models::asset_entry::AssetEntryManager;
+    use crate::models::asset_tags;
+    use crate::models::error::{Error, Result};
+    use crate::models::tags::{ActiveModel, Column, Entity, normalize_tag};
+
+    #[derive(Default)]
+    pub struct TagManager {}
+
+    impl TagManager {
+        pub fn new() -> Self {
+            Self {}
+        }
+
+        /// Normalized and deduplicated tags
+        pub fn normalize(&self, tags: &[String]) -> Result<Vec<String>> {
+            let mut normalized = Vec::with_capacity(tags.len());
+            for tag in tags {
+                let tag = normalize_tag(tag).ok_or_else(|| Error::InvalidTag(tag.clone()))?;
+                if !normalized.contains(&tag) {
+                    normalized.push(tag);
+                }
+            }
+
+            Ok(normalized)
+        }
+
+        /// Names of the tags the owner gave at least one asset with the number of their assets
+        pub async fn list_tags(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+        ) -> Result<Vec<(String, i64)>> {
+            Ok(Entity::find()
+                .select_only()
+                .column(Column::Name)
+                .column_as(asset_tags::Column::AssetId.count(), "asset_count")
+                .join(JoinType::InnerJoin, super::Relation::AssetTag.def())
+                .filter(asset_tags::Column::Owner.eq(owner))
+                .group_by(Column::Name)
+                .order_by_asc(Column::Name)
+                .into_tuple::<(String, i64)>()
+                .all(conn)
+                .await?)
+        }
+
+        /// Tags the owner gave the asset, assets outside of the library of the owner are not found
+        pub async fn get_tags_of_asset(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            asset_id: i32,
+        ) -> Result<Vec<String>> {
+            self.check_owner(conn, owner, asset_id).await?;
+
+            self.tags_of_asset(conn, owner, asset_id).await
+        }
+
+        /// Sorted tag names the owner gave each asset, assets without tags are missing
+        pub async fn get_tags_of_assets(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            asset_ids: &[i32],
+        ) -> Result<HashMap<i32, Vec<String>>> {
+            if asset_ids.is_empty() {
+                return Ok(HashMap::new());
+            }
+
+            let rows = asset_tags::Entity::find()
+                .select_only()
+                .column(asset_tags::Column::AssetId)
+                .column(Column::Name)
+                .join(JoinType::InnerJoin, asset_tags::Relation::Tag.def())
+                .filter(asset_tags::Column::Owner.eq(owner))
+                .filter(asset_tags::Column::AssetId.is_in(asset_ids.iter().copied()))
+                .order_by_asc(Column::Name)
+                .into_tuple::<(i32, String)>()
+                .all(conn)
+                .await?;
+
+            let mut tags = HashMap::<i32, Vec<String>>::new();
+            for (asset_id, name) in rows {
+                tags.entry(asset_id).or_default().push(name);
+            }
+
+            Ok(tags)
+        }
+
+        /// Adds the tags of the owner to the asset and returns all tags the owner gave the asset
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn add_tags(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            asset_id: i32,
+            tags: &[String],
+        ) -> Result<Vec<String>> {
+            let tags = self.normalize(tags)?;
+            self.check_owner(conn, owner, asset_id).await?;
+
+            if !tags.is_empty() {
+                Entity::insert_many(tags.iter().map(|name| ActiveModel {
+                    name: Set(name.clone()),
+                    ..Default::default()
+                }))
+                .on_conflict(OnConflict::column(Column::Name).do_nothing().to_owned())
+                .do_nothing()
+                .exec_without_returning(conn)
+                .await?;
+
+                let tag_ids = self.get_tag_ids(conn, &tags).await?;
+                asset_tags::Entity::insert_many(tag_ids.into_iter().map(|tag_id| {
+                    asset_tags::ActiveModel {
+                        asset_id: Set(asset_id),
+                        owner: Set(owner.to_string()),
+                        tag_id: Set(tag_id),
+                    }
+                }))
+                .on_conflict(
+                    OnConflict::columns([
+                        asset_tags::Column::AssetId,
+                        asset_tags::Column::Owner,
+                        asset_tags::Column::TagId,
+                    ])
+                    .do_nothing()
+                    .to_owned(),
+                )
+                .do_nothing()
+                .exec_without_returning(conn)
+                .await?;
+            }
+
+            self.tags_of_asset(conn, owner, asset_id).await
+        }
+
+        /// Removes the tags of the owner from the asset and returns the remaining tags the
+        /// owner gave the asset, tags without assets are deleted
+        #[tracing::instrument(skip(self, conn))]
+        pub async fn remove_tags(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            asset_id: i32,
+            tags: &[String],
+        ) -> Result<Vec<String>> {
+            let tags = self.normalize(tags)?;
+            self.check_owner(conn, owner, asset_id).await?;
+
+            let tag_ids = self.get_tag_ids(conn, &tags).await?;
+            if !tag_ids.is_empty() {
+                asset_tags::Entity::delete_many()
+                    .filter(asset_tags::Column::AssetId.eq(asset_id))
+                    .filter(asset_tags::Column::Owner.eq(owner))
+                    .filter(asset_tags::Column::TagId.is_in(tag_ids.iter().copied()))
+                    .exec(conn)
+                    .await?;
+
+                let used = asset_tags::Entity::find()
+                    .select_only()
+                    .column(asset_tags::Column::TagId)
+                    .filter(asset_tags::Column::TagId.is_in(tag_ids.iter().copied()))
+                    .distinct()
+                    .into_tuple::<i32>()
+                    .all(conn)
+                    .await?;
+                Entity::delete_many()
+                    .filter(Column::Id.is_in(tag_ids.into_iter().filter(|id| !used.contains(id))))
+                    .exec(conn)
+                    .await?;
+            }
+
+            self.tags_of_asset(conn, owner, asset_id).await
+        }
+
+        /// Tags for a file the owner uploads with the given filename, only tags of the owner
+        /// are known, see [`super::suggest_tags`]
+        pub async fn suggest_tags(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            filename: &str,
+        ) -> Result<Vec<String>> {
+            let known_tags = Entity::find()
+                .select_only()
+                .column(Column::Name)
+                .join(JoinType::InnerJoin, super::Relation::AssetTag.def())
+                .filter(asset_tags::Column::Owner.eq(owner))
+                .distinct()
+                .into_tuple::<String>()
+                .all(conn)
+                .await?;
+
+            Ok(super::suggest_tags(filename, &known_tags))
+        }
+
+        async fn tags_of_asset(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            asset_id: i32,
+        ) -> Result<Vec<String>> {
+            Ok(self
+                .get_tags_of_assets(conn, owner, &[asset_id])
+                .await?
+                .remove(&asset_id)
+                .unwrap_or_default())
+        }
+
+        /// Assets outside of the library of the owner are not found
+        async fn check_owner(
+            &self,
+            conn: &impl ConnectionTrait,
+            owner: &str,
+            asset_id: i32,
+        ) -> Result<()> {
+            if !AssetEntryManager::new()
+                .owns_asset(conn, owner, asset_id)
+                .await?
+            {
+                return Err(Error::AssetNotFound(asset_id));
+            }
+
+            Ok(())
+        }
+
+        async fn get_tag_ids(
+            &self,
+            conn: &impl ConnectionTrait,
+            tags: &[String],
+        ) -> Result<Vec<i32>> {
+            if tags.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            Ok(Entity::find()
+                .select_only()
+                .column(Column::Id)
+                .filter(Column::Name.is_in(tags.iter().cloned()))
+                .into_tuple::<i32>()
+                .all(conn)
+                .await?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::tags::{normalize_tag, suggest_tags};
+
+    #[test]
+    fn tags_are_normalized() {
+        assert_eq!(
+            Some("forest map".to_string()),
+            normalize_tag("  Forest   MAP ")
+        );
+        assert_eq!(None, normalize_tag("   "));
+        assert_eq!(None, normalize_tag(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn tags_are_suggested_from_filename() {
+        assert_eq!(
+            vec!["forest", "map", "night"],
+            suggest_tags("ForestMap_02-night.png", &[])
+        );
+        assert_eq!(
+            vec!["forest map", "night"],
+            suggest_tags(
+                "forest_map_512x512 night v2.png",
+                &["forest map".to_string()]
+            )
+        );
+        assert_eq!(
+            vec!["npc portrait", "undead", "goblin"],
+            suggest_tags(
+                "undead-goblin-npc-portrait (copy).webp",
+                &[
+                    "undead".to_string(),
+                    "npc portrait".to_string(),
+                    "dragon".to_string()
+                ]
+            )
+        );
+    }
+}
@@ -12,6 +12,8 @@ pub struct Model {
     pub id: i32,
     pub dimensions: String,
     pub image_id: i32,
+    /// Asset of the thumbnail itself
+    pub asset_id: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -22,6 +24,12 @@ pub enum Relation {
         to = "super::assets::Column::Id"
     )]
     Image,
+    #[sea_orm(
+        belongs_to = "super::assets::Entity",
+        from = "Column::AssetId",
+        to = "super::assets::Column::Id"
+    )]
+    Asset,
 }
 
 impl Related<super::assets::Entity> for Entity {
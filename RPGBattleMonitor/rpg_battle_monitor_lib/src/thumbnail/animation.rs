@@ -0,0 +1,221 @@
+use std::io::Cursor;
+
+use image::{
+    AnimationDecoder, Frame, Frames, ImageDecoder, ImageFormat,
+    codecs::{
+        gif::{GifDecoder, GifEncoder, Repeat},
+        webp::WebPDecoder,
+    },
+    imageops,
+};
+
+use crate::{
+    config::ImageTransformConfig,
+    thumbnail::{
+        self,
+        configuration::Configuration,
+        transform::{Fit, OutputFormat, Transform, image_dimensions},
+    },
+};
+
+/// Speed of the color quantization of GIF frames, from 1 (best quality) to 30 (fastest)
+const GIF_SPEED: i32 = 10;
+
+/// Static first frame of an animation in its full size
+pub const POSTER: Transform = Transform {
+    width: None,
+    height: None,
+    fit: Fit::Contain,
+    format: OutputFormat::Png,
+};
+
+/// Mime types of images that can have several frames
+pub fn may_be_animated(mime: &str) -> bool {
+    matches!(mime, "image/gif" | "image/webp")
+}
+
+pub fn is_animated(data: &[u8]) -> bool {
+    match image::guess_format(data) {
+        Ok(ImageFormat::Gif) => GifDecoder::new(Cursor::new(data))
+            .is_ok_and(|decoder| decoder.into_frames().take(2).count() == 2),
+        Ok(ImageFormat::WebP) => {
+            WebPDecoder::new(Cursor::new(data)).is_ok_and(|decoder| decoder.has_animation())
+        }
+        _ => false,
+    }
+}
+
+/// Every frame is decoded in the size of the canvas, animations with a larger canvas
+/// than `max_source_pixels` are not decoded
+pub fn check_canvas(data: &[u8], max_source_pixels: u64) -> Result<(), thumbnail::error::Error> {
+    fits_canvas(image_dimensions(data)?, max_source_pixels)
+}
+
+fn fits_canvas(
+    (width, height): (u32, u32),
+    max_source_pixels: u64,
+) -> Result<(), thumbnail::error::Error> {
+    if width as u64 * height as u64 > max_source_pixels {
+        return Err(thumbnail::error::Error::TooLarge { width, height });
+    }
+
+    Ok(())
+}
+
+fn frames(data: &[u8], max_source_pixels: u64) -> Result<Frames<'_>, thumbnail::error::Error> {
+    match image::guess_format(data)? {
+        ImageFormat::Gif => {
+            let decoder = GifDecoder::new(Cursor::new(data))?;
+            fits_canvas(decoder.dimensions(), max_source_pixels)?;
+            Ok(decoder.into_frames())
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(Cursor::new(data))?;
+            fits_canvas(decoder.dimensions(), max_source_pixels)?;
+            Ok(decoder.into_frames())
+        }
+        _ => Err(thumbnail::error::Error::NotAnimated),
+    }
+}
+
+/// Largest size within the bounds that keeps the aspect ratio, images are not made larger
+fn fit_dimensions((width, height): (u32, u32), (max_width, max_height): (u32, u32)) -> (u32, u32) {
+    if width <= max_width && height <= max_height {
+        return (width, height);
+    }
+
+    let ratio = f64::min(
+        max_width as f64 / width as f64,
+        max_height as f64 / height as f64,
+    );
+    (
+        ((width as f64 * ratio).round() as u32).max(1),
+        ((height as f64 * ratio).round() as u32).max(1),
+    )
+}
+
+/// Animated GIF thumbnails of the animation for every configuration, frames keep their
+/// delay and are resized one at a time so only a single frame is held in memory.
+/// Animations with more than `max_animation_frames` frames are rejected with
+/// [`thumbnail::error::Error::TooManyFrames`]
+pub fn create_animated_thumbnails(
+    data: &[u8],
+    configurations: &[Configuration],
+    config: &ImageTransformConfig,
+) -> Result<Vec<Vec<u8>>, thumbnail::error::Error> {
+    let mut thumbnails = vec![Vec::new(); configurations.len()];
+    let mut encoders = thumbnails
+        .iter_mut()
+        .map(|thumbnail| {
+            let mut encoder = GifEncoder::new_with_speed(thumbnail, GIF_SPEED);
+            encoder.set_repeat(Repeat::Infinite)?;
+            Ok(encoder)
+        })
+        .collect::<Result<Vec<_>, thumbnail::error::Error>>()?;
+
+    for (index, frame) in frames(data, config.max_source_pixels)?.enumerate() {
+        if index == config.max_animation_frames {
+            return Err(thumbnail::error::Error::TooManyFrames(
+                config.max_animation_frames,
+            ));
+        }
+
+        let frame = frame?;
+        let delay = frame.delay();
+        let buffer = frame.into_buffer();
+
+        for (encoder, configuration) in encoders.iter_mut().zip(configurations) {
+            let (width, height) =
+                fit_dimensions(buffer.dimensions(), configuration.size.get_dimensions());
+            let resized = imageops::thumbnail(&buffer, width, height);
+            encoder.encode_frame(Frame::from_parts(resized, 0, 0, delay))?;
+        }
+    }
+    // The encoders write the end of the GIF when they are dropped
+    drop(encoders);
+
+    Ok(thumbnails)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use image::{AnimationDecoder, codecs::gif::GifDecoder};
+
+    use crate::{
+        config::ImageTransformConfig,
+        thumbnail::{
+            animation::{check_canvas, create_animated_thumbnails, is_animated},
+            configuration,
+            error::Error,
+        },
+        utils::test_utils::{TEST_IMAGE_BYTES, animated_gif},
+    };
+
+    #[test]
+    fn animated_thumbnails_keep_frames_and_delays() {
+        let delays = [100, 250, 40];
+        let data = animated_gif(&delays);
+        assert!(is_animated(&data));
+        assert!(!is_animated(&animated_gif(&[100])));
+        assert!(!is_animated(TEST_IMAGE_BYTES));
+
+        let thumbnails = create_animated_thumbnails(
+            &data,
+            &[configuration::X512, configuration::X128],
+            &ImageTransformConfig::default(),
+        )
+        .unwrap();
+        let frames = |data: &[u8]| {
+            GifDecoder::new(Cursor::new(data))
+                .unwrap()
+                .into_frames()
+                .collect_frames()
+                .unwrap()
+        };
+
+        // Thumbnails are not larger than the animation
+        let large = frames(&thumbnails[0]);
+        assert_eq!(3, large.len());
+        assert_eq!((400, 200), large[0].buffer().dimensions());
+
+        let small = frames(&thumbnails[1]);
+        assert_eq!((128, 64), small[0].buffer().dimensions());
+        let small_delays = small
+            .iter()
+            .map(|frame| frame.delay().numer_denom_ms())
+            .map(|(numer, denom)| numer / denom)
+            .collect::<Vec<_>>();
+        assert_eq!(delays.to_vec(), small_delays);
+    }
+
+    #[test]
+    fn animations_over_the_limits_are_not_encoded() {
+        let data = animated_gif(&[100, 100, 100]);
+        let configurations = [configuration::X128];
+
+        let config = ImageTransformConfig {
+            max_animation_frames: 2,
+            ..Default::default()
+        };
+        assert!(matches!(
+            create_animated_thumbnails(&data, &configurations, &config),
+            Err(Error::TooManyFrames(2))
+        ));
+
+        let config = ImageTransformConfig {
+            max_source_pixels: 400 * 200 - 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            create_animated_thumbnails(&data, &configurations, &config),
+            Err(Error::TooLarge {
+                width: 400,
+                height: 200
+            })
+        ));
+        assert!(check_canvas(&data, config.max_source_pixels).is_err());
+        assert!(check_canvas(&data, 400 * 200).is_ok());
+    }
+}
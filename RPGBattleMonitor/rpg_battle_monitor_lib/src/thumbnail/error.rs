@@ -2,6 +2,10 @@
 pub enum Error {
     Image(image::ImageError),
     Io(std::io::Error),
+    NotAnimated,
+    Svg(String),
+    TooLarge { width: u32, height: u32 },
+    TooManyFrames(usize),
 }
 
 impl std::fmt::Display for Error {
@@ -9,6 +13,15 @@ impl std::fmt::Display for Error {
         match self {
             Error::Image(e) => write!(f, "Image error occurred: {e}"),
             Error::Io(e) => write!(f, "Io error occurred: {e}"),
+            Error::NotAnimated => write!(f, "Image is not an animation"),
+            Error::Svg(e) => write!(f, "Svg error occurred: {e}"),
+            Error::TooLarge { width, height } => {
+                write!(
+                    f,
+                    "Image of {width}x{height} pixels is too large to be decoded"
+                )
+            }
+            Error::TooManyFrames(limit) => write!(f, "Animation has more than {limit} frames"),
         }
     }
 }
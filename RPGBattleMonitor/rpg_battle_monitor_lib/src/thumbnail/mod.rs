@@ -7,9 +7,12 @@ use std::{
 use crate::thumbnail::{self, configuration::Configuration};
 use image::{DynamicImage, GenericImageView, ImageFormat, ImageReader};
 
+pub mod animation;
 pub mod configuration;
 pub mod error;
 pub mod size;
+pub mod svg;
+pub mod transform;
 
 pub struct Thumbnail<'a> {
     original_image: Arc<DynamicImage>,
@@ -0,0 +1,379 @@
+use quick_xml::{
+    Reader, Writer,
+    events::{BytesRef, BytesStart, BytesText, Event, attributes::Attribute},
+};
+use resvg::{tiny_skia, usvg};
+
+use crate::thumbnail;
+
+pub const SVG_MIME: &str = "image/svg+xml";
+
+/// Elements that run scripts or embed other documents, they are removed with their content
+const FORBIDDEN_ELEMENTS: &[&str] = &[
+    "script",
+    "foreignobject",
+    "iframe",
+    "object",
+    "embed",
+    "handler",
+    "listener",
+];
+/// Elements that can change other attributes, e.g. set a `javascript:` link
+const ANIMATION_ELEMENTS: &[&str] = &["set", "animate", "animatemotion", "animatetransform"];
+/// Embedded images that can be referenced, other data urls could hold documents
+const SAFE_DATA_PREFIXES: &[&str] = &[
+    "data:image/png",
+    "data:image/jpeg",
+    "data:image/gif",
+    "data:image/webp",
+];
+
+/// Whether the file could be a XML or HTML document, browsers render those and run their
+/// scripts. A start of only whitespace counts too, the markup could follow it
+pub fn is_markup(head: &[u8]) -> bool {
+    let head = head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head);
+    let head = head.trim_ascii_start();
+
+    head.is_empty() || head.starts_with(b"<")
+}
+
+/// Whether the complete document is a SVG document, the prolog, comments and doctype
+/// before the root element can be of any length
+pub fn is_svg(data: &[u8]) -> bool {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    if !data.trim_ascii_start().starts_with(b"<") {
+        return false;
+    }
+
+    let mut reader = Reader::from_reader(data);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(element) | Event::Empty(element)) => {
+                return element.local_name().as_ref().eq_ignore_ascii_case(b"svg");
+            }
+            Ok(Event::Eof) | Err(_) => return false,
+            Ok(_) => (),
+        }
+    }
+}
+
+/// Removes scripts, event handlers and references to other documents from a SVG
+/// document, comments, processing instructions and the doctype are removed as well
+pub fn sanitize_svg(data: &[u8]) -> Result<Vec<u8>, thumbnail::error::Error> {
+    let mut reader = Reader::from_reader(data);
+    let mut writer = Writer::new(Vec::with_capacity(data.len()));
+    let mut has_root = false;
+
+    loop {
+        let event = match reader.read_event().map_err(svg_error)? {
+            Event::Start(element) => {
+                if is_forbidden(&element) {
+                    reader.read_to_end(element.name()).map_err(svg_error)?;
+                    continue;
+                }
+                has_root |= element.local_name().as_ref().eq_ignore_ascii_case(b"svg");
+
+                if element.local_name().as_ref().eq_ignore_ascii_case(b"style") {
+                    let css = read_text(&mut reader, &element)?;
+                    writer
+                        .write_event(Event::Start(sanitize_attributes(&element)))
+                        .map_err(svg_error)?;
+                    if !has_external_reference(&css) {
+                        writer
+                            .write_event(Event::Text(BytesText::new(&css)))
+                            .map_err(svg_error)?;
+                    }
+                    Event::End(element.to_end().into_owned())
+                } else {
+                    Event::Start(sanitize_attributes(&element))
+                }
+            }
+            Event::Empty(element) => {
+                if is_forbidden(&element) {
+                    continue;
+                }
+                has_root |= element.local_name().as_ref().eq_ignore_ascii_case(b"svg");
+
+                Event::Empty(sanitize_attributes(&element))
+            }
+            Event::GeneralRef(reference) => {
+                // Entities of the removed doctype are unknown
+                if resolve_reference(&reference).is_none() {
+                    continue;
+                }
+                Event::GeneralRef(reference)
+            }
+            event @ (Event::End(_) | Event::Text(_) | Event::CData(_) | Event::Decl(_)) => event,
+            Event::Comment(_) | Event::PI(_) | Event::DocType(_) => continue,
+            Event::Eof => break,
+        };
+
+        writer.write_event(event).map_err(svg_error)?;
+    }
+
+    if !has_root {
+        return Err(thumbnail::error::Error::Svg(
+            "Document has no svg element".to_string(),
+        ));
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Renders the SVG document as PNG that fits into the size, smaller documents are scaled up
+pub fn render_svg(
+    data: &[u8],
+    (max_width, max_height): (u32, u32),
+) -> Result<Vec<u8>, thumbnail::error::Error> {
+    let tree = usvg::Tree::from_data(data, &usvg::Options::default()).map_err(svg_error)?;
+
+    let size = tree.size();
+    let scale = f32::min(
+        max_width as f32 / size.width(),
+        max_height as f32 / size.height(),
+    );
+    let width = ((size.width() * scale).round() as u32).max(1);
+    let height = ((size.height() * scale).round() as u32).max(1);
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or_else(|| {
+        thumbnail::error::Error::Svg(format!("Can not render {width}x{height} pixels"))
+    })?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    pixmap.encode_png().map_err(svg_error)
+}
+
+fn svg_error(error: impl std::fmt::Display) -> thumbnail::error::Error {
+    thumbnail::error::Error::Svg(error.to_string())
+}
+
+fn is_forbidden(element: &BytesStart) -> bool {
+    let name = String::from_utf8_lossy(element.local_name().as_ref()).to_ascii_lowercase();
+    if FORBIDDEN_ELEMENTS.contains(&name.as_str()) {
+        return true;
+    }
+
+    ANIMATION_ELEMENTS.contains(&name.as_str())
+        && element.attributes().any(|attribute| {
+            attribute.is_ok_and(|attribute| {
+                attribute
+                    .key
+                    .local_name()
+                    .as_ref()
+                    .eq_ignore_ascii_case(b"attributeName")
+                    && attribute.unescape_value().is_ok_and(|target| {
+                        let target = target.trim().to_ascii_lowercase();
+                        target.ends_with("href") || target.starts_with("on")
+                    })
+            })
+        })
+}
+
+/// Copy of the element without event handlers and references to other documents,
+/// attributes that can not be read are removed too
+fn sanitize_attributes(element: &BytesStart) -> BytesStart<'static> {
+    let mut sanitized = element.to_owned();
+    sanitized.clear_attributes();
+
+    for attribute in element.attributes().flatten() {
+        if is_safe_attribute(&attribute) {
+            sanitized.push_attribute(attribute);
+        }
+    }
+
+    sanitized.into_owned()
+}
+
+fn is_safe_attribute(attribute: &Attribute) -> bool {
+    let name = String::from_utf8_lossy(attribute.key.local_name().as_ref()).to_ascii_lowercase();
+    if name.starts_with("on") {
+        return false;
+    }
+    let Ok(value) = attribute.unescape_value() else {
+        return false;
+    };
+
+    // Browsers ignore whitespace and control characters in urls, e.g. `java\tscript:`
+    let normalized = value
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if normalized.contains("javascript:") {
+        return false;
+    }
+
+    if name == "href" || name == "src" {
+        return is_local_reference(&value);
+    }
+
+    !has_external_reference(&value)
+}
+
+/// Text content of the element up to its end tag, nested elements are dropped
+fn read_text(
+    reader: &mut Reader<&[u8]>,
+    element: &BytesStart,
+) -> Result<String, thumbnail::error::Error> {
+    let mut text = String::new();
+    let mut depth = 0;
+
+    loop {
+        match reader.read_event().map_err(svg_error)? {
+            Event::Start(_) => depth += 1,
+            Event::End(end) => {
+                if depth == 0 && end.name() == element.name() {
+                    return Ok(text);
+                }
+                depth -= 1;
+            }
+            Event::Text(content) => {
+                text.push_str(&content.decode().map_err(svg_error)?);
+            }
+            Event::CData(content) => {
+                text.push_str(&content.decode().map_err(svg_error)?);
+            }
+            Event::GeneralRef(reference) => {
+                if let Some(c) = resolve_reference(&reference) {
+                    text.push(c);
+                }
+            }
+            Event::Eof => return Ok(text),
+            _ => (),
+        }
+    }
+}
+
+/// Character of a character reference or predefined entity
+fn resolve_reference(reference: &BytesRef) -> Option<char> {
+    if reference.is_char_ref() {
+        return reference.resolve_char_ref().ok().flatten();
+    }
+
+    match reference.as_ref() {
+        b"lt" => Some('<'),
+        b"gt" => Some('>'),
+        b"amp" => Some('&'),
+        b"apos" => Some('\''),
+        b"quot" => Some('"'),
+        _ => None,
+    }
+}
+
+fn is_local_reference(value: &str) -> bool {
+    let value = value
+        .trim()
+        .trim_matches(|c| c == '"' || c == '\'')
+        .trim()
+        .to_ascii_lowercase();
+
+    value.starts_with('#')
+        || SAFE_DATA_PREFIXES
+            .iter()
+            .any(|prefix| value.starts_with(prefix))
+}
+
+/// Whether the css loads other documents, css escapes could hide a reference so they
+/// count as one too
+fn has_external_reference(css: &str) -> bool {
+    let css = css.to_ascii_lowercase();
+    if css.contains('\\') || css.contains("@import") || css.contains("image-set(") {
+        return true;
+    }
+
+    css.match_indices("url(").any(|(start, _)| {
+        let reference = &css[start + "url(".len()..];
+        let end = reference.find(')').unwrap_or(reference.len());
+
+        !is_local_reference(&reference[..end])
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::thumbnail::svg::{is_markup, is_svg, render_svg, sanitize_svg};
+
+    #[test]
+    fn svg_is_sanitized() {
+        let svg = br##"<?xml version="1.0"?>
+<!DOCTYPE svg [<!ENTITY payload "<script>alert(1)</script>">]>
+<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="10" height="10" onload="alert(1)">
+  <!-- comment -->
+  <script>alert(1)</script>
+  <style>@import url(https://example.com/a.css); rect { fill: red }</style>
+  <style>rect { fill: url(#gradient) }</style>
+  <foreignObject><div xmlns="http://www.w3.org/1999/xhtml">html</div></foreignObject>
+  <a xlink:href="java&#x09;script:alert(1)"><rect width="5" height="5" fill="url(#gradient)"/></a>
+  <a href="#local"><text>&payload;&amp;</text></a>
+  <image href="https://example.com/tracker.png"/>
+  <image href="data:image/png;base64,AAAA"/>
+  <set attributeName="href" to="javascript:alert(1)"/>
+  <rect style="background: url('https://example.com/x.png')" onclick="alert(1)" ONMOUSEOVER="alert(1)"/>
+</svg>"##;
+
+        let sanitized = String::from_utf8(sanitize_svg(svg).unwrap()).unwrap();
+        let lowercase = sanitized.to_ascii_lowercase();
+        for removed in [
+            "script",
+            "onload",
+            "onclick",
+            "onmouseover",
+            "@import",
+            "foreignobject",
+            "example.com",
+            "<set",
+            "<!",
+            "payload",
+        ] {
+            assert!(!lowercase.contains(removed), "{removed} in {sanitized}");
+        }
+        for kept in [
+            "<svg",
+            "fill=\"url(#gradient)\"",
+            "rect { fill: url(#gradient) }",
+            "href=\"#local\"",
+            "&amp;",
+            "data:image/png;base64,AAAA",
+        ] {
+            assert!(sanitized.contains(kept), "{kept} not in {sanitized}");
+        }
+        assert!(is_svg(&sanitized.into_bytes()));
+
+        assert!(sanitize_svg(b"<html><body/></html>").is_err());
+        assert!(!is_svg(b"<html><svg/></html>"));
+        assert!(!is_svg(crate::utils::test_utils::TEST_IMAGE_BYTES));
+    }
+
+    #[test]
+    fn svg_after_long_prolog_is_detected() {
+        let mut svg = b"<?xml version=\"1.0\"?>\n<!--".to_vec();
+        svg.extend(std::iter::repeat_n(b' ', 16 * 1024));
+        svg.extend_from_slice(
+            b"-->\n<svg xmlns=\"http://www.w3.org/2000/svg\" onload=\"alert(1)\"/>",
+        );
+
+        assert!(is_markup(&svg[..8 * 1024]));
+        assert!(is_svg(&svg));
+        let sanitized = sanitize_svg(&svg).unwrap();
+        assert!(!String::from_utf8(sanitized).unwrap().contains("onload"));
+
+        assert!(is_markup(b"\xEF\xBB\xBF  <html/>"));
+        assert!(is_markup(b"   "));
+        assert!(!is_markup(crate::utils::test_utils::TEST_IMAGE_BYTES));
+        assert!(!is_markup(b"%PDF-1.7"));
+    }
+
+    #[test]
+    fn svg_is_rendered_into_size() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10"><rect width="20" height="10" fill="red"/></svg>"#;
+
+        let png = render_svg(svg, (128, 128)).unwrap();
+        let image = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!((128, 64), image.dimensions());
+        assert_eq!([255, 0, 0, 255], image.get_pixel(64, 32).0);
+    }
+}
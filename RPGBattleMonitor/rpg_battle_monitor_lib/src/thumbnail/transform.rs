@@ -0,0 +1,183 @@
+use std::{fmt::Display, io::Cursor};
+
+use image::{DynamicImage, GenericImageView, ImageFormat, ImageReader, imageops::FilterType};
+use serde::Deserialize;
+
+use crate::thumbnail;
+
+/// How an image is fitted into the requested width and height
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scales the image to fit inside of the size, keeping its aspect ratio
+    #[default]
+    Contain,
+    /// Scales the image to cover the size, keeping its aspect ratio and cropping the overflow
+    Cover,
+    /// Stretches the image to the size
+    Fill,
+}
+
+impl Display for Fit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fit::Contain => write!(f, "contain"),
+            Fit::Cover => write!(f, "cover"),
+            Fit::Fill => write!(f, "fill"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl OutputFormat {
+    /// Format of an image with the mime type, if it can be written
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        match ImageFormat::from_mime_type(mime)? {
+            ImageFormat::Png => Some(Self::Png),
+            ImageFormat::Jpeg => Some(Self::Jpeg),
+            ImageFormat::WebP => Some(Self::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn image_format(&self) -> ImageFormat {
+        match self {
+            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+            OutputFormat::Webp => ImageFormat::WebP,
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Png => write!(f, "png"),
+            OutputFormat::Jpeg => write!(f, "jpeg"),
+            OutputFormat::Webp => write!(f, "webp"),
+        }
+    }
+}
+
+/// Resizes and converts an image, a missing width or height follows the aspect ratio
+/// of the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transform {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+    pub format: OutputFormat,
+}
+
+impl Transform {
+    /// Identifies the variant among the variants of an image, e.g. `256xauto-cover.webp`
+    pub fn key(&self) -> String {
+        let dimension = |dimension: Option<u32>| {
+            dimension
+                .map(|dimension| dimension.to_string())
+                .unwrap_or_else(|| "auto".to_string())
+        };
+
+        format!(
+            "{}x{}-{}.{}",
+            dimension(self.width),
+            dimension(self.height),
+            self.fit,
+            self.format
+        )
+    }
+
+    /// Width and height of the variant of an image of the given size
+    pub fn target_dimensions(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        let scale = |dimension: u32, from: u32, to: u32| {
+            ((dimension as u64 * to as u64) / from.max(1) as u64).clamp(1, u32::MAX as u64) as u32
+        };
+
+        match (self.width, self.height) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, scale(height, width, w)),
+            (None, Some(h)) => (scale(width, height, h), h),
+            (None, None) => (width, height),
+        }
+    }
+
+    pub fn apply(&self, data: &[u8]) -> Result<Vec<u8>, thumbnail::error::Error> {
+        let image = ImageReader::new(Cursor::new(data))
+            .with_guessed_format()?
+            .decode()?;
+        let (width, height) = self.target_dimensions(image.dimensions());
+
+        let image = if (width, height) == image.dimensions() {
+            image
+        } else {
+            match self.fit {
+                Fit::Contain => image.resize(width, height, FilterType::CatmullRom),
+                Fit::Cover => image.resize_to_fill(width, height, FilterType::CatmullRom),
+                Fit::Fill => image.resize_exact(width, height, FilterType::CatmullRom),
+            }
+        };
+        // Jpeg has no alpha channel
+        let image = match self.format {
+            OutputFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8()),
+            _ => image,
+        };
+
+        let mut data = Vec::new();
+        image.write_to(&mut Cursor::new(&mut data), self.format.image_format())?;
+
+        Ok(data)
+    }
+}
+
+/// Width and height of an encoded image without decoding it
+pub fn image_dimensions(data: &[u8]) -> Result<(u32, u32), thumbnail::error::Error> {
+    Ok(ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .into_dimensions()?)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::thumbnail::transform::{Fit, OutputFormat, Transform};
+
+    #[test]
+    fn missing_dimensions_follow_the_aspect_ratio() {
+        let transform = |width, height| Transform {
+            width,
+            height,
+            fit: Fit::Cover,
+            format: OutputFormat::Webp,
+        };
+
+        assert_eq!(
+            (200, 100),
+            transform(Some(200), None).target_dimensions((800, 400))
+        );
+        assert_eq!(
+            (50, 100),
+            transform(None, Some(100)).target_dimensions((400, 800))
+        );
+        assert_eq!(
+            (64, 32),
+            transform(Some(64), Some(32)).target_dimensions((800, 800))
+        );
+        assert_eq!(
+            (800, 400),
+            transform(None, None).target_dimensions((800, 400))
+        );
+        assert_eq!(
+            (1000, 1),
+            transform(Some(1000), None).target_dimensions((4000, 1))
+        );
+
+        assert_eq!("256xauto-cover.webp", transform(Some(256), None).key());
+    }
+}
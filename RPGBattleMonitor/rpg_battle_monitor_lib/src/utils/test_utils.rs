@@ -1,17 +1,22 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use axum::Router;
-use axum_test::TestServer;
+use axum_test::{TestServer, TestWebSocket};
 use sea_orm::{Database, DatabaseConnection};
-use tokio::sync::Mutex;
 use uuid::Uuid;
 
 use crate::{
+    api::websockets::protocol::Event,
     cdn::filesystem::temp_file_adapter::TempFileStore,
+    config::{AssetGcConfig, ImageTransformConfig, UploadConfig, WebsocketConfig},
     database::setup::{create_database, run_migrations},
+    entity::codec::EntityCodecs,
     webserver::{
         router::app_state::{AppState, AppStateConfig},
-        services::{entity_queue::EntityQueue, scheduler::Scheduler},
+        services::{
+            broadcast::RoomBroadcaster, entity_locks::EntityLocks, entity_queues::EntityQueues,
+            scheduler::Scheduler, shutdown::ShutdownHandle,
+        },
     },
 };
 
@@ -26,6 +31,190 @@ pub(crate) fn new_test_app(router: Router) -> TestServer {
         .unwrap()
 }
 
+/// Websocket upgrades answer with 101 so success is not expected by default
+pub(crate) fn new_test_socket_app(router: Router) -> TestServer {
+    TestServer::builder()
+        .http_transport()
+        .build(router)
+        .unwrap()
+}
+
+/// Minimal socket.io client speaking the engine.io v4 text protocol over a websocket
+pub(crate) struct TestSocket {
+    websocket: TestWebSocket,
+    next_ack: u64,
+}
+
+/// Event received by a [`TestSocket`], `ack` is set when the server waits for an acknowledgement
+#[derive(Debug)]
+pub(crate) struct TestSocketEvent {
+    pub event: String,
+    pub data: serde_json::Value,
+    pub ack: Option<u64>,
+}
+
+impl TestSocket {
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub async fn connect(server: &TestServer, auth: serde_json::Value) -> Self {
+        let websocket = server
+            .get_websocket("/socket.io/?EIO=4&transport=websocket")
+            .await
+            .into_websocket()
+            .await;
+        let mut socket = Self {
+            websocket,
+            next_ack: 0,
+        };
+
+        let open = socket.receive().await.expect("Engine.io open packet");
+        assert!(open.starts_with('0'), "Unexpected open packet {open}");
+
+        socket.websocket.send_text(format!("40{auth}")).await;
+        let connect = socket.receive().await.expect("Socket.io connect packet");
+        assert!(connect.starts_with("40"), "Connection refused {connect}");
+
+        socket
+    }
+
+    pub async fn close(self) {
+        self.websocket.close().await;
+    }
+
+    /// Receives the next packet that is not a ping, `None` when nothing arrives within `within`
+    async fn receive_within(&mut self, within: Duration) -> Option<String> {
+        loop {
+            let text = tokio::time::timeout(within, self.websocket.receive_text())
+                .await
+                .ok()?;
+
+            if text == "2" {
+                self.websocket.send_text("3").await;
+                continue;
+            }
+
+            return Some(text);
+        }
+    }
+
+    async fn receive(&mut self) -> Option<String> {
+        self.receive_within(Self::TIMEOUT).await
+    }
+
+    pub async fn emit(&mut self, event: Event, data: impl serde::Serialize) {
+        let packet = serde_json::json!([event, data]);
+        self.websocket.send_text(format!("42{packet}")).await;
+    }
+
+    /// Emits the event and returns the acknowledgement of the server, events received
+    /// meanwhile are skipped
+    pub async fn emit_with_ack(
+        &mut self,
+        event: Event,
+        data: impl serde::Serialize,
+    ) -> serde_json::Value {
+        let id = self.next_ack;
+        self.next_ack += 1;
+        let packet = serde_json::json!([event, data]);
+        self.websocket.send_text(format!("42{id}{packet}")).await;
+
+        let prefix = format!("43{id}");
+        loop {
+            let text = self.receive().await.expect("Timed out waiting for an ack");
+            if let Some(packet) = text.strip_prefix(&prefix) {
+                let serde_json::Value::Array(mut values) = serde_json::from_str(packet).unwrap()
+                else {
+                    panic!("Malformed ack {text}");
+                };
+                return values.swap_remove(0);
+            }
+        }
+    }
+
+    pub async fn ack(&mut self, id: u64, data: impl serde::Serialize) {
+        let packet = serde_json::json!([data]);
+        self.websocket.send_text(format!("43{id}{packet}")).await;
+    }
+
+    /// Next event received within `within`
+    pub async fn next_event_within(&mut self, within: Duration) -> Option<TestSocketEvent> {
+        let text = self.receive_within(within).await?;
+        let packet = text
+            .strip_prefix("42")
+            .unwrap_or_else(|| panic!("Expected an event, received {text}"));
+
+        let start = packet
+            .find('[')
+            .unwrap_or_else(|| panic!("Malformed event {text}"));
+        let ack = (start > 0).then(|| packet[..start].parse().unwrap());
+
+        let serde_json::Value::Array(mut values) = serde_json::from_str(&packet[start..]).unwrap()
+        else {
+            panic!("Malformed event {text}");
+        };
+        let data = values.get_mut(1).map(serde_json::Value::take);
+
+        Some(TestSocketEvent {
+            event: values[0].as_str().unwrap().to_string(),
+            data: data.unwrap_or_default(),
+            ack,
+        })
+    }
+
+    pub async fn next_event(&mut self) -> TestSocketEvent {
+        self.next_event_within(Self::TIMEOUT)
+            .await
+            .expect("Timed out waiting for an event")
+    }
+
+    /// Skips events until `event` is received and returns its data
+    pub async fn wait_for(&mut self, event: Event) -> serde_json::Value {
+        let name = event.as_str();
+        loop {
+            let received = self.next_event().await;
+            if received.event == name {
+                return received.data;
+            }
+        }
+    }
+
+    /// Joins the game of the socket acknowledging every chunk
+    pub async fn join(&mut self) {
+        self.emit(Event::Join, ()).await;
+        loop {
+            let received = self.next_event().await;
+            if received.event == Event::JoinFinished.as_str() {
+                return;
+            }
+            if let Some(id) = received.ack {
+                self.ack(id, serde_json::json!({ "cancel": false })).await;
+            }
+        }
+    }
+}
+
+/// Animated GIF of 400x200 frames with the given delays in milliseconds
+pub(crate) fn animated_gif(delays: &[u32]) -> Vec<u8> {
+    use image::{Delay, Frame, Rgba, RgbaImage, codecs::gif::GifEncoder};
+
+    let mut data = Vec::new();
+    let mut encoder = GifEncoder::new(&mut data);
+    for (i, delay) in delays.iter().enumerate() {
+        let buffer = RgbaImage::from_pixel(400, 200, Rgba([(i * 80) as u8, 0, 0, 255]));
+        encoder
+            .encode_frame(Frame::from_parts(
+                buffer,
+                0,
+                0,
+                Delay::from_numer_denom_ms(*delay, 1),
+            ))
+            .unwrap();
+    }
+    drop(encoder);
+
+    data
+}
+
 pub(crate) fn get_random_filename() -> String {
     format!("test-file-{}.jpg", Uuid::new_v4())
 }
@@ -49,11 +238,20 @@ async fn create_test_database() -> DatabaseConnection {
 impl AppStateConfig<TempFileStore> {
     pub async fn get_test_config() -> AppStateConfig<TempFileStore> {
         let database = create_test_database().await;
+        let entity_codecs = EntityCodecs::default();
         Self {
             file_system_handler: TempFileStore::new(),
             database: database.clone(),
-            entity_queue: Arc::new(Mutex::new(EntityQueue::new(database))),
+            entity_queues: Arc::new(EntityQueues::new(database).with_codecs(entity_codecs.clone())),
+            entity_codecs,
+            entity_locks: Arc::new(EntityLocks::new()),
             scheduler: Scheduler::new(),
+            websocket_config: WebsocketConfig::default(),
+            upload_config: UploadConfig::default(),
+            image_transform_config: ImageTransformConfig::default(),
+            asset_gc_config: AssetGcConfig::default(),
+            broadcaster: RoomBroadcaster::default(),
+            shutdown: ShutdownHandle::new(),
         }
     }
 }
@@ -16,7 +16,8 @@ where
     ) -> core::result::Result<Self, Self::Rejection> {
         let fs_adapter = FSAdapter::new(state.get_fs_handler());
 
-        Ok(AssetManager::new(fs_adapter))
+        Ok(AssetManager::new(fs_adapter)
+            .with_image_transform_config(state.get_image_transform_config()))
     }
 }
 
@@ -30,5 +31,6 @@ where
         let fs_adapter = FSAdapter::new(state.get_fs_handler());
 
         AssetManager::new(fs_adapter)
+            .with_image_transform_config(state.get_image_transform_config())
     }
 }
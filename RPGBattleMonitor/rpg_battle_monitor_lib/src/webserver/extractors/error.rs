@@ -10,10 +10,20 @@ pub enum Error {
 
     #[error("Raw value extraction failed")]
     RawWebocketMessageExtractionFailed,
+
+    #[error("User token is missing")]
+    MissingUserToken,
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        todo!("Into response")
+        use axum::http::StatusCode;
+
+        tracing::error!(error = %self);
+
+        match self {
+            Self::MissingUserToken => StatusCode::UNAUTHORIZED.into_response(),
+            _ => todo!("Into response"),
+        }
     }
 }
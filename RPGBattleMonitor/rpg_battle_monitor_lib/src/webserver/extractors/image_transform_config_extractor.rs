@@ -0,0 +1,20 @@
+use axum::{extract::FromRequestParts, http::request::Parts};
+
+use crate::{
+    config::ImageTransformConfig,
+    webserver::{extractors::error::Error, router::app_state::AppStateTrait},
+};
+
+impl<S> FromRequestParts<S> for ImageTransformConfig
+where
+    S: AppStateTrait,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &S,
+    ) -> core::result::Result<Self, Self::Rejection> {
+        Ok(state.get_image_transform_config())
+    }
+}
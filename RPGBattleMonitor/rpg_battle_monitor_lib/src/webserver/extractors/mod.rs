@@ -1,7 +1,10 @@
 pub mod asset_extractor;
 pub mod database_connection_extractor;
 pub mod error;
+pub mod image_transform_config_extractor;
 pub mod local_fs_extractor;
+pub mod upload_config_extractor;
+pub mod user_extractor;
 
 #[macro_export]
 macro_rules! implement_manager_from_request {
@@ -0,0 +1,55 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::{FromRequestParts, OptionalFromRequestParts},
+    http::request::Parts,
+};
+
+use crate::webserver::extractors::error::Error;
+
+/// User making the request, identified by the same user token the websocket
+/// connection authenticates with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrentUser(pub String);
+
+impl CurrentUser {
+    pub const HEADER: &str = "x-user-token";
+
+    fn from_parts(parts: &Parts) -> Option<Self> {
+        parts
+            .headers
+            .get(Self::HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .map(|token| Self(token.to_string()))
+    }
+}
+
+impl<S> FromRequestParts<S> for CurrentUser
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> core::result::Result<Self, Self::Rejection> {
+        Self::from_parts(parts).ok_or(Error::MissingUserToken)
+    }
+}
+
+impl<S> OptionalFromRequestParts<S> for CurrentUser
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> core::result::Result<Option<Self>, Self::Rejection> {
+        Ok(Self::from_parts(parts))
+    }
+}
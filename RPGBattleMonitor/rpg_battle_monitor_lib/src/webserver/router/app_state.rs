@@ -1,16 +1,20 @@
 use std::sync::Arc;
 
 use sea_orm::DatabaseConnection;
-use tokio::sync::Mutex;
 
 use crate::{
     cdn::filesystem::{
         Adapter,
         local_adapter::{self, Local},
     },
-    config,
+    config::{self, AssetGcConfig, ImageTransformConfig, UploadConfig, WebsocketConfig},
     database::get_sea_orm_database,
-    webserver::services::{entity_queue::EntityQueue, scheduler::Scheduler},
+    entity::{codec::EntityCodecs, fields::EntityFields},
+    models::entity::EntityManager,
+    webserver::services::{
+        broadcast::RoomBroadcaster, entity_locks::EntityLocks, entity_queue::RetryPolicy,
+        entity_queues::EntityQueues, scheduler::Scheduler, shutdown::ShutdownHandle,
+    },
 };
 
 pub struct AppStateConfig<F>
@@ -19,19 +23,98 @@ where
 {
     pub file_system_handler: F,
     pub database: DatabaseConnection,
-    pub entity_queue: Arc<Mutex<EntityQueue>>,
+    pub entity_queues: Arc<EntityQueues>,
+    pub entity_codecs: EntityCodecs,
+    pub entity_locks: Arc<EntityLocks>,
     pub scheduler: Scheduler,
+    pub websocket_config: WebsocketConfig,
+    pub upload_config: UploadConfig,
+    pub image_transform_config: ImageTransformConfig,
+    pub asset_gc_config: AssetGcConfig,
+    pub broadcaster: RoomBroadcaster,
+    pub shutdown: ShutdownHandle,
 }
 
 impl AppStateConfig<local_adapter::Local> {
     pub async fn get_default_config() -> AppStateConfig<local_adapter::Local> {
         let database = Self::get_database().await;
-        let entity_queue = Arc::new(Mutex::new(EntityQueue::new(database.clone())));
+
+        let entity_fields = EntityFields::new(config::config().entity_fields.indexed.clone());
+        EntityManager::new()
+            .create_field_columns(&database, &entity_fields)
+            .await
+            .expect("Failed to create indexed entity field columns");
+
+        let codec_config = &config::config().entity_codec;
+        let entity_codecs = EntityCodecs::new(codec_config.codec)
+            .with_zstd_level(codec_config.zstd_level)
+            .with_fields(entity_fields);
+        entity_codecs
+            .load_dictionaries(&database)
+            .await
+            .expect("Failed to load entity dictionaries");
+
+        let queue_config = &config::config().entity_queue;
+        let new_entity_queues = || {
+            EntityQueues::new(database.clone())
+                .with_retry_policy(RetryPolicy {
+                    max_attempts: queue_config.max_flush_attempts,
+                    base_backoff: queue_config.retry_backoff,
+                    max_backoff: queue_config.max_retry_backoff,
+                })
+                .with_tombstone_retention(queue_config.tombstone_retention)
+                .with_codecs(entity_codecs.clone())
+        };
+        // Without a journal queued entities are lost on a crash but the server still runs
+        let entity_queues = new_entity_queues()
+            .with_journal(&queue_config.journal_path)
+            .unwrap_or_else(|e| {
+                tracing::error!(
+                    error = %e,
+                    "Failed to open entity journal in {}, continuing without a journal",
+                    queue_config.journal_path
+                );
+                new_entity_queues()
+            });
         Self {
             file_system_handler: Self::get_fs_handler_from_config(),
             database,
-            entity_queue,
+            entity_queues: Arc::new(entity_queues),
+            entity_codecs,
+            entity_locks: Arc::new(EntityLocks::new()),
             scheduler: Scheduler::new(),
+            websocket_config: config::config().websocket.clone(),
+            upload_config: config::config().upload.clone(),
+            image_transform_config: config::config().image_transform.clone(),
+            asset_gc_config: config::config().asset_gc.clone(),
+            broadcaster: Self::get_broadcaster().await,
+            shutdown: ShutdownHandle::new(),
+        }
+    }
+
+    async fn get_broadcaster() -> RoomBroadcaster {
+        let config = &config::config().broadcast;
+
+        let Some(url) = config.postgres_url.as_ref() else {
+            return RoomBroadcaster::default();
+        };
+
+        #[cfg(feature = "broadcast_postgres")]
+        {
+            use crate::webserver::services::broadcast::postgres_adapter::PostgresAdapter;
+
+            let adapter = PostgresAdapter::connect(url, config.channel.clone())
+                .await
+                .expect("Failed to connect to broadcast database");
+
+            RoomBroadcaster::new(std::sync::Arc::new(adapter))
+        }
+        #[cfg(not(feature = "broadcast_postgres"))]
+        {
+            tracing::warn!(
+                "BROADCAST_POSTGRES_URL {url} is set but broadcast_postgres feature is disabled, broadcasts stay within the process"
+            );
+            RoomBroadcaster::default()
         }
     }
 
@@ -56,8 +139,16 @@ pub trait AppStateTrait: Clone + Send + Sync + 'static {
 
     fn get_fs_handler(&self) -> Self::FsHandler;
     fn get_db(&self) -> DatabaseConnection;
-    fn get_entity_queue(&self) -> Arc<Mutex<EntityQueue>>;
+    fn get_entity_queues(&self) -> Arc<EntityQueues>;
+    fn get_entity_codecs(&self) -> EntityCodecs;
+    fn get_entity_locks(&self) -> Arc<EntityLocks>;
     fn get_scheduler(&self) -> Scheduler;
+    fn get_websocket_config(&self) -> WebsocketConfig;
+    fn get_upload_config(&self) -> UploadConfig;
+    fn get_image_transform_config(&self) -> ImageTransformConfig;
+    fn get_asset_gc_config(&self) -> AssetGcConfig;
+    fn get_broadcaster(&self) -> RoomBroadcaster;
+    fn get_shutdown(&self) -> ShutdownHandle;
 }
 
 #[derive(Debug)]
@@ -67,8 +158,16 @@ where
 {
     pub fs_handler: F,
     pub database: DatabaseConnection,
-    pub entity_queue: Arc<Mutex<EntityQueue>>,
+    pub entity_queues: Arc<EntityQueues>,
+    pub entity_codecs: EntityCodecs,
+    pub entity_locks: Arc<EntityLocks>,
     pub scheduler: Scheduler,
+    pub websocket_config: WebsocketConfig,
+    pub upload_config: UploadConfig,
+    pub image_transform_config: ImageTransformConfig,
+    pub asset_gc_config: AssetGcConfig,
+    pub broadcaster: RoomBroadcaster,
+    pub shutdown: ShutdownHandle,
 }
 
 impl<F> Clone for AppState<F>
@@ -79,8 +178,16 @@ where
         Self {
             fs_handler: self.fs_handler.clone(),
             database: self.database.clone(),
-            entity_queue: self.entity_queue.clone(),
+            entity_queues: self.entity_queues.clone(),
+            entity_codecs: self.entity_codecs.clone(),
+            entity_locks: self.entity_locks.clone(),
             scheduler: self.scheduler.clone(),
+            websocket_config: self.websocket_config.clone(),
+            upload_config: self.upload_config.clone(),
+            image_transform_config: self.image_transform_config.clone(),
+            asset_gc_config: self.asset_gc_config.clone(),
+            broadcaster: self.broadcaster.clone(),
+            shutdown: self.shutdown.clone(),
         }
     }
 }
@@ -93,8 +200,16 @@ where
         Self {
             fs_handler: config.file_system_handler,
             database: config.database,
-            entity_queue: config.entity_queue,
+            entity_queues: config.entity_queues,
+            entity_codecs: config.entity_codecs,
+            entity_locks: config.entity_locks,
             scheduler: config.scheduler,
+            websocket_config: config.websocket_config,
+            upload_config: config.upload_config,
+            image_transform_config: config.image_transform_config,
+            asset_gc_config: config.asset_gc_config,
+            broadcaster: config.broadcaster,
+            shutdown: config.shutdown,
         }
     }
 }
@@ -113,11 +228,43 @@ where
         self.database.clone()
     }
 
-    fn get_entity_queue(&self) -> Arc<Mutex<EntityQueue>> {
-        self.entity_queue.clone()
+    fn get_entity_queues(&self) -> Arc<EntityQueues> {
+        self.entity_queues.clone()
+    }
+
+    fn get_entity_codecs(&self) -> EntityCodecs {
+        self.entity_codecs.clone()
+    }
+
+    fn get_entity_locks(&self) -> Arc<EntityLocks> {
+        self.entity_locks.clone()
     }
 
     fn get_scheduler(&self) -> Scheduler {
         self.scheduler.clone()
     }
+
+    fn get_websocket_config(&self) -> WebsocketConfig {
+        self.websocket_config.clone()
+    }
+
+    fn get_upload_config(&self) -> UploadConfig {
+        self.upload_config.clone()
+    }
+
+    fn get_image_transform_config(&self) -> ImageTransformConfig {
+        self.image_transform_config.clone()
+    }
+
+    fn get_asset_gc_config(&self) -> AssetGcConfig {
+        self.asset_gc_config.clone()
+    }
+
+    fn get_broadcaster(&self) -> RoomBroadcaster {
+        self.broadcaster.clone()
+    }
+
+    fn get_shutdown(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
 }
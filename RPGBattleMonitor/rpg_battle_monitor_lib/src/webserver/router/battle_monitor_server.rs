@@ -1,10 +1,19 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use axum::http::{StatusCode, Uri};
 
 use crate::{
     api,
-    webserver::router::{app_state::AppStateTrait, public_files_router},
+    models::assets::AssetManager,
+    webserver::{
+        extractors::local_fs_extractor::FSAdapter,
+        router::{app_state::AppStateTrait, public_files_router},
+        services::{
+            asset_gc::AssetGc,
+            entity_recompressor::EntityRecompressor,
+            shutdown::{ShutdownHandle, shutdown_signal},
+        },
+    },
 };
 
 #[derive(Debug)]
@@ -33,10 +42,18 @@ impl<T: AppStateTrait + std::fmt::Debug> BattleMonitorWebServer<T> {
         }
     }
 
-    /// Function blocks and can panic if it fails to create TcpListener
+    /// Handle that can be used to stop the server, [`Self::serve`] returns
+    /// once queued entities are saved
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.state.get_shutdown()
+    }
+
+    /// Function blocks until the server is shut down by SIGINT, SIGTERM or the
+    /// shutdown handle and can panic if it fails to create TcpListener
     #[tracing::instrument]
     pub async fn serve(self, listener: Option<tokio::net::TcpListener>) {
         self.schedule_tasks();
+        tokio::spawn(Self::shutdown_on_request(self.state.clone()));
 
         let listener = match listener {
             Some(l) => l,
@@ -59,23 +76,174 @@ impl<T: AppStateTrait + std::fmt::Debug> BattleMonitorWebServer<T> {
             );
         }
 
-        axum::serve(listener, self.router).await.unwrap();
+        let shutdown = self.state.get_shutdown();
+        axum::serve(listener, self.router)
+            .with_graceful_shutdown(async move { shutdown.finished().await })
+            .await
+            .unwrap();
+
+        tracing::info!("Stopped BattleMonitorWebServer");
+    }
+
+    async fn shutdown_on_request(state: T) {
+        let shutdown = state.get_shutdown();
+        tokio::select! {
+            _ = shutdown_signal() => shutdown.shutdown(),
+            _ = shutdown.requested() => (),
+        }
+        tracing::info!("Shutting down BattleMonitorWebServer");
+
+        // Scheduled flushes hold the lock of their game until they are saved,
+        // the final flush of a game waits for its lock
+        state.get_scheduler().stop();
+        state.get_entity_queues().flush_all().await;
+
+        shutdown.finish();
     }
 
     fn schedule_tasks(&self) {
         self.schedule_entity_queue_flush_task();
+        self.schedule_tombstone_purge_task();
+        self.schedule_entity_recompress_task();
+        self.schedule_asset_gc_task();
     }
 
     fn schedule_entity_queue_flush_task(&self) {
-        let entity_queue = self.state.get_entity_queue();
+        let entity_queues = self.state.get_entity_queues();
         self.state
             .get_scheduler()
             .run(Duration::from_secs(5), move || {
-                let entity_queue = entity_queue.clone();
+                let entity_queues = entity_queues.clone();
+                async move {
+                    futures_util::future::join_all(entity_queues.flush_due()).await;
+                    entity_queues.drop_idle_shards();
+                }
+            });
+    }
+
+    fn schedule_tombstone_purge_task(&self) {
+        let entity_queues = self.state.get_entity_queues();
+        self.state
+            .get_scheduler()
+            .run(Duration::from_secs(60 * 60), move || {
+                let entity_queues = entity_queues.clone();
+                async move {
+                    match entity_queues.purge_tombstones().await {
+                        Ok(0) => (),
+                        Ok(purged) => tracing::debug!("Purged {purged} entity tombstones"),
+                        Err(e) => tracing::error!(error = %e, "Failed to purge entity tombstones"),
+                    }
+                }
+            });
+    }
+
+    fn schedule_entity_recompress_task(&self) {
+        let recompressor = Arc::new(EntityRecompressor::new(
+            self.state.get_db(),
+            self.state.get_entity_codecs(),
+        ));
+        self.state
+            .get_scheduler()
+            .run(Duration::from_secs(60), move || {
+                let recompressor = recompressor.clone();
                 async move {
-                    let mut lock = entity_queue.lock().await;
-                    lock.flush().await;
+                    match recompressor.run().await {
+                        Ok(0) => (),
+                        Ok(recompressed) => {
+                            tracing::debug!("Recompressed {recompressed} entities")
+                        }
+                        Err(e) => tracing::error!(error = %e, "Failed to recompress entities"),
+                    }
                 }
             });
     }
+
+    fn schedule_asset_gc_task(&self) {
+        let config = self.state.get_asset_gc_config();
+        let interval = config.interval;
+        let gc = Arc::new(AssetGc::new(
+            self.state.get_db(),
+            self.state.get_entity_codecs(),
+            AssetManager::new(FSAdapter::new(self.state.get_fs_handler())),
+            config,
+        ));
+        self.state.get_scheduler().run(interval, move || {
+            let gc = gc.clone();
+            async move {
+                match gc.run().await {
+                    Ok(report) if report.dry_run && !report.deleted.is_empty() => tracing::info!(
+                        "Asset garbage collection would delete {} assets: {:?}",
+                        report.deleted.len(),
+                        report.deleted
+                    ),
+                    Ok(report) if !report.deleted.is_empty() => {
+                        tracing::info!("Deleted {} unreferenced assets", report.deleted.len())
+                    }
+                    Ok(_) => (),
+                    Err(e) => tracing::error!(error = %e, "Failed to collect unreferenced assets"),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{
+        api::websockets::Action,
+        entity::{Entity, UId, UtcTimestamp, kind::EntityKind},
+        models::{entity::EntityManager, game::GameManager},
+        utils::test_utils::get_app_state_with_temp_file_store,
+        webserver::router::{
+            app_state::AppStateTrait, battle_monitor_server::BattleMonitorWebServer,
+        },
+    };
+
+    #[tokio::test]
+    async fn shutdown_flushes_entity_queue() {
+        let state = get_app_state_with_temp_file_store().await;
+        let game = GameManager::new()
+            .create_game(&state.get_db())
+            .await
+            .unwrap();
+        state
+            .get_entity_queues()
+            .get(game.id)
+            .await
+            .unwrap()
+            .lock()
+            .await
+            .push(Entity {
+                uid: UId("a".to_string()),
+                game: game.id,
+                kind: EntityKind("token".to_string()),
+                timestamp: UtcTimestamp(1),
+                action: Some(Arc::new(Action::Create)),
+                other_values: serde_json::json!({}),
+            })
+            .unwrap();
+
+        let server = BattleMonitorWebServer::new(state.clone());
+        let shutdown = server.shutdown_handle();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let serve = tokio::spawn(server.serve(Some(listener)));
+
+        shutdown.shutdown();
+        tokio::time::timeout(Duration::from_secs(10), serve)
+            .await
+            .expect("Server did not shut down")
+            .unwrap();
+
+        assert!(state.get_shutdown().is_shutting_down());
+        assert_eq!(0, state.get_entity_queues().health().await.queued);
+        assert_eq!(
+            1,
+            EntityManager::new()
+                .count_entities(&state.get_db(), game.id)
+                .await
+                .unwrap()
+        );
+    }
 }
@@ -0,0 +1,570 @@
+use std::collections::HashSet;
+
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::{ConnectionTrait, DatabaseConnection, TransactionTrait};
+use serde::Serialize;
+
+use crate::{
+    cdn::filesystem::Adapter,
+    config::AssetGcConfig,
+    entity::codec::EntityCodecs,
+    models::{
+        asset_entry::AssetEntryManager,
+        assets::{Asset, AssetManager, AssetType},
+        entity::EntityManager,
+        error::Result,
+    },
+};
+
+#[cfg(feature = "api_doc")]
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct AssetGcItem {
+    pub id: i32,
+    pub filename: String,
+    pub original_filename: String,
+    #[cfg_attr(feature = "api_doc", schema(value_type = Option<String>))]
+    pub unreferenced_since: Option<NaiveDateTime>,
+}
+
+impl From<&Asset> for AssetGcItem {
+    fn from(asset: &Asset) -> Self {
+        Self {
+            id: asset.id,
+            filename: asset.name.clone(),
+            original_filename: asset.original_filename.clone(),
+            unreferenced_since: asset.unreferenced_since,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct AssetGcReport {
+    /// Nothing was changed, `deleted` lists the assets that would be deleted
+    pub dry_run: bool,
+    pub referenced: usize,
+    /// Unreferenced assets that are still within the grace period
+    pub unreferenced: Vec<AssetGcItem>,
+    pub deleted: Vec<AssetGcItem>,
+}
+
+/// Deletes uploaded assets that stayed unreferenced for the grace period.
+///
+/// An asset is referenced if it is in the library of an owner or the payload of an
+/// entity contains its filename or the filename of one of its thumbnails, games do not
+/// reference assets on their own.
+/// Thumbnail and derived assets are deleted together with their image, derived assets
+/// that are not linked to an image are deleted after the grace period.
+///
+/// Entries and entities are checked again in the transaction that deletes the assets.
+/// Only saved entities are scanned, entities that are still queued or wait for a retry
+/// of a failed flush are not, so the grace period has to be longer than the flush
+/// interval and the retry window of the `EntityQueue`.
+pub struct AssetGc<F: Adapter> {
+    db: DatabaseConnection,
+    codecs: EntityCodecs,
+    asset_manager: AssetManager<F>,
+    config: AssetGcConfig,
+}
+
+impl<F: Adapter> AssetGc<F> {
+    const ENTITY_PAGE_SIZE: u64 = 500;
+
+    pub fn new(
+        db: DatabaseConnection,
+        codecs: EntityCodecs,
+        asset_manager: AssetManager<F>,
+        config: AssetGcConfig,
+    ) -> Self {
+        Self {
+            db,
+            codecs,
+            asset_manager,
+            config,
+        }
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.config.dry_run = dry_run;
+        self
+    }
+
+    pub async fn run(&self) -> Result<AssetGcReport> {
+        let referenced_names = self.referenced_names(&self.db).await?;
+        let library_assets = AssetEntryManager::new().list_asset_ids(&self.db).await?;
+
+        let assets = self
+            .asset_manager
+            .list_assets_of_type(&self.db, AssetType::File)
+            .await?;
+        let mut thumbnails = self
+            .asset_manager
+            .get_thumbnails_of_assets(
+                &self.db,
+                &assets.iter().map(|asset| asset.id).collect::<Vec<_>>(),
+            )
+            .await?;
+
+        let now = Utc::now().naive_utc();
+        let grace_period =
+            chrono::Duration::from_std(self.config.grace_period).unwrap_or(chrono::Duration::MAX);
+        let expires_before = now.checked_sub_signed(grace_period);
+
+        let mut report = AssetGcReport {
+            dry_run: self.config.dry_run,
+            ..Default::default()
+        };
+        let mut newly_referenced = Vec::new();
+        let mut newly_unreferenced = Vec::new();
+        let mut expired = Vec::new();
+
+        for mut asset in assets {
+            let thumbnails = thumbnails.remove(&asset.id).unwrap_or_default();
+            let referenced = library_assets.contains(&asset.id)
+                || referenced_names.contains(&asset.name)
+                || thumbnails
+                    .iter()
+                    .any(|(_, thumbnail)| referenced_names.contains(&thumbnail.name));
+
+            match (referenced, asset.unreferenced_since) {
+                (true, None) => report.referenced += 1,
+                (true, Some(_)) => {
+                    report.referenced += 1;
+                    newly_referenced.push(asset.id);
+                }
+                (false, None) => {
+                    asset.unreferenced_since = Some(now);
+                    newly_unreferenced.push(asset.id);
+                    report.unreferenced.push((&asset).into());
+                }
+                (false, Some(since)) => {
+                    if expires_before.is_some_and(|expires_before| since < expires_before) {
+                        expired.push(asset);
+                    } else {
+                        report.unreferenced.push((&asset).into());
+                    }
+                }
+            }
+        }
+
+        // Derived assets that no image links to can not be referenced, they are only
+        // kept for the grace period since the variant is linked after the asset is created
+        let unlinked = self
+            .asset_manager
+            .list_unlinked_derived_assets(&self.db)
+            .await?;
+        expired.extend(unlinked.into_iter().filter(|asset| {
+            !referenced_names.contains(&asset.name)
+                && expires_before.is_some_and(|expires_before| asset.created_at < expires_before)
+        }));
+
+        report.deleted = expired.iter().map(AssetGcItem::from).collect();
+        if self.config.dry_run {
+            return Ok(report);
+        }
+
+        self.asset_manager
+            .set_unreferenced_since(&self.db, &newly_referenced, None)
+            .await?;
+        self.asset_manager
+            .set_unreferenced_since(&self.db, &newly_unreferenced, Some(now))
+            .await?;
+
+        if expired.is_empty() {
+            return Ok(report);
+        }
+
+        let rescued = self.delete_expired(expired).await?;
+        report.referenced += rescued.len();
+        report.deleted.retain(|item| !rescued.contains(&item.id));
+
+        Ok(report)
+    }
+
+    /// Deletes the expired assets and their files, returns the ids of assets that were
+    /// referenced meanwhile and are kept
+    async fn delete_expired(&self, expired: Vec<Asset>) -> Result<Vec<i32>> {
+        // An upload of the same content or a saved entity can reference an expired
+        // asset after the scan, such assets are kept
+        let transaction = self.db.begin().await?;
+        let referenced_names = self.referenced_names(&transaction).await?;
+        let entry_manager = AssetEntryManager::new();
+        let mut rescued = Vec::new();
+        let mut deleted = Vec::new();
+        let mut thumbnails = self
+            .asset_manager
+            .get_thumbnails_of_assets(
+                &transaction,
+                &expired.iter().map(|asset| asset.id).collect::<Vec<_>>(),
+            )
+            .await?;
+        for asset in expired {
+            let thumbnails = thumbnails.remove(&asset.id).unwrap_or_default();
+            let referenced = entry_manager.has_entries(&transaction, asset.id).await?
+                || referenced_names.contains(&asset.name)
+                || thumbnails
+                    .iter()
+                    .any(|(_, thumbnail)| referenced_names.contains(&thumbnail.name));
+            if referenced {
+                rescued.push(asset.id);
+                continue;
+            }
+
+            deleted.extend(
+                self.asset_manager
+                    .delete_asset(&transaction, asset.id)
+                    .await?,
+            );
+        }
+        self.asset_manager
+            .set_unreferenced_since(&transaction, &rescued, None)
+            .await?;
+        transaction.commit().await?;
+
+        self.asset_manager.delete_files(&deleted).await;
+
+        Ok(rescued)
+    }
+
+    /// Last path segments of all strings in entity payloads, asset urls end in the filename
+    async fn referenced_names(&self, conn: &impl ConnectionTrait) -> Result<HashSet<String>> {
+        let entity_manager = EntityManager::new();
+        let mut names = HashSet::new();
+
+        let mut after = None::<(i32, String)>;
+        loop {
+            let page = entity_manager
+                .load_all_entities_page(
+                    conn,
+                    after.as_ref().map(|(game, uid)| (*game, uid.as_str())),
+                    Self::ENTITY_PAGE_SIZE,
+                )
+                .await?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            after = Some((last.game, last.uid.clone()));
+
+            // An entity that can not be read could reference any asset
+            if let Err(e) = self.codecs.load_missing_dictionaries(conn, &page).await {
+                tracing::error!(error = %e, "Failed to load entity dictionaries");
+            }
+            let entities = self
+                .codecs
+                .decompress_vec(page)
+                .map_err(|e| crate::models::error::Error::EntityUnreadable(Box::new(e)))?;
+
+            for entity in entities {
+                Self::collect_names(&entity.other_values, &mut names);
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn collect_names(value: &serde_json::Value, names: &mut HashSet<String>) {
+        match value {
+            serde_json::Value::String(s) => {
+                // Variants and posters are referenced with query parameters
+                let path = s.split(['?', '#']).next().unwrap_or_default();
+                if let Some(name) = path.rsplit('/').next().filter(|name| !name.is_empty()) {
+                    names.insert(name.to_string());
+                }
+            }
+            serde_json::Value::Array(values) => values
+                .iter()
+                .for_each(|value| Self::collect_names(value, names)),
+            serde_json::Value::Object(values) => values
+                .values()
+                .for_each(|value| Self::collect_names(value, names)),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use sea_orm::TransactionTrait;
+
+    use crate::{
+        api::websockets::Action,
+        cdn::filesystem::FileSystem,
+        config::AssetGcConfig,
+        entity::{Entity, UId, UtcTimestamp, kind::EntityKind},
+        models::{
+            asset_entry::AssetEntryManager,
+            assets::{AssetManager, AssetType},
+            entity::EntityManager,
+        },
+        utils::test_utils::{TEST_IMAGE_BYTES, TEST_PDF_BYTES, get_app_state_with_temp_file_store},
+        webserver::{router::app_state::AppStateTrait, services::asset_gc::AssetGc},
+    };
+
+    #[tokio::test]
+    async fn unreferenced_assets_are_deleted_after_grace_period() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+        let asset_manager = AssetManager::from(state.clone());
+
+        let transaction = db.begin().await.unwrap();
+        let image = asset_manager
+            .create(
+                &transaction,
+                "goblin.png".to_string(),
+                TEST_IMAGE_BYTES,
+                AssetType::File,
+            )
+            .await
+            .unwrap();
+        let thumbnails = asset_manager
+            .create_thumbnail_assets(&transaction, &image, Some(TEST_IMAGE_BYTES))
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        let pdf = asset_manager
+            .create(
+                &db,
+                "rules.pdf".to_string(),
+                TEST_PDF_BYTES,
+                AssetType::File,
+            )
+            .await
+            .unwrap();
+
+        // The image is referenced through the url of its thumbnail
+        let codecs = state.get_entity_codecs();
+        let token = Entity {
+            uid: UId("token".to_string()),
+            game: 1,
+            kind: EntityKind("token".to_string()),
+            timestamp: UtcTimestamp(1),
+            action: Some(Arc::new(Action::Create)),
+            other_values: serde_json::json!({
+                "image": format!("/api/assets/{}", thumbnails[0].name),
+            }),
+        };
+        EntityManager::new()
+            .save_entities(&db, vec![codecs.compress(token).unwrap()])
+            .await
+            .unwrap();
+
+        let gc = |grace_period: Duration, dry_run: bool| {
+            AssetGc::new(
+                db.clone(),
+                codecs.clone(),
+                AssetManager::from(state.clone()),
+                AssetGcConfig {
+                    grace_period,
+                    dry_run,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let report = gc(Duration::ZERO, false).run().await.unwrap();
+        assert_eq!(1, report.referenced);
+        assert_eq!(
+            vec![pdf.id],
+            report.unreferenced.iter().map(|a| a.id).collect::<Vec<_>>()
+        );
+        assert!(report.deleted.is_empty());
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let report = gc(Duration::ZERO, true).run().await.unwrap();
+        assert_eq!(
+            vec![pdf.id],
+            report.deleted.iter().map(|a| a.id).collect::<Vec<_>>()
+        );
+        assert!(
+            asset_manager
+                .get_by_id(&db, pdf.id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        let report = gc(Duration::from_secs(60), false).run().await.unwrap();
+        assert!(report.deleted.is_empty());
+
+        let report = gc(Duration::ZERO, false).run().await.unwrap();
+        assert_eq!(
+            vec![pdf.id],
+            report.deleted.iter().map(|a| a.id).collect::<Vec<_>>()
+        );
+        assert!(
+            asset_manager
+                .get_by_id(&db, pdf.id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            !state
+                .fs_handler
+                .file_exists(std::path::Path::new(&pdf.name))
+                .await
+                .unwrap()
+        );
+        assert!(
+            asset_manager
+                .get_by_id(&db, image.id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn assets_referenced_after_the_scan_are_not_deleted() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+        let asset_manager = AssetManager::from(state.clone());
+
+        let create = |name: &'static str, data: &'static [u8]| {
+            let asset_manager = AssetManager::from(state.clone());
+            let db = db.clone();
+            async move {
+                asset_manager
+                    .create(&db, name.to_string(), data, AssetType::File)
+                    .await
+                    .unwrap()
+            }
+        };
+        let image = create("goblin.png", TEST_IMAGE_BYTES).await;
+        let pdf = create("rules.pdf", TEST_PDF_BYTES).await;
+        let notes = create("notes.txt", b"goblin notes").await;
+
+        let gc = AssetGc::new(
+            db.clone(),
+            state.get_entity_codecs(),
+            AssetManager::from(state.clone()),
+            AssetGcConfig {
+                grace_period: Duration::ZERO,
+                ..Default::default()
+            },
+        );
+
+        // Both were expired when they were scanned, the image was uploaded again
+        // and the pdf was placed on the board before they are deleted
+        AssetEntryManager::new()
+            .create_entry(&db, "alice", None, None, image.id, "Goblin".to_string())
+            .await
+            .unwrap();
+        let codecs = state.get_entity_codecs();
+        let rules = Entity {
+            uid: UId("rules".to_string()),
+            game: 1,
+            kind: EntityKind("document".to_string()),
+            timestamp: UtcTimestamp(1),
+            action: Some(Arc::new(Action::Create)),
+            other_values: serde_json::json!({ "src": format!("/api/assets/{}", pdf.name) }),
+        };
+        EntityManager::new()
+            .save_entities(&db, vec![codecs.compress(rules).unwrap()])
+            .await
+            .unwrap();
+
+        let rescued = gc
+            .delete_expired(vec![image.clone(), pdf.clone(), notes.clone()])
+            .await
+            .unwrap();
+        assert_eq!(vec![image.id, pdf.id], rescued);
+        for asset in [&image, &pdf] {
+            assert!(
+                asset_manager
+                    .get_by_id(&db, asset.id)
+                    .await
+                    .unwrap()
+                    .is_some()
+            );
+        }
+        assert!(
+            asset_manager
+                .get_by_id(&db, notes.id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn variant_urls_reference_their_image_and_unlinked_variants_are_deleted() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+        let asset_manager = AssetManager::from(state.clone());
+
+        let image = asset_manager
+            .create(
+                &db,
+                "goblin.png".to_string(),
+                TEST_IMAGE_BYTES,
+                AssetType::File,
+            )
+            .await
+            .unwrap();
+        // Variant of a request that lost the race to link it
+        let unlinked = asset_manager
+            .create(&db, "".to_string(), TEST_PDF_BYTES, AssetType::Derived)
+            .await
+            .unwrap();
+
+        let codecs = state.get_entity_codecs();
+        let token = Entity {
+            uid: UId("token".to_string()),
+            game: 1,
+            kind: EntityKind("token".to_string()),
+            timestamp: UtcTimestamp(1),
+            action: Some(Arc::new(Action::Create)),
+            other_values: serde_json::json!({
+                "image": format!("/api/assets/{}?format=png&width=256#poster", image.name),
+            }),
+        };
+        EntityManager::new()
+            .save_entities(&db, vec![codecs.compress(token).unwrap()])
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let report = AssetGc::new(
+            db.clone(),
+            codecs,
+            AssetManager::from(state.clone()),
+            AssetGcConfig {
+                grace_period: Duration::ZERO,
+                ..Default::default()
+            },
+        )
+        .run()
+        .await
+        .unwrap();
+
+        assert_eq!(1, report.referenced);
+        assert_eq!(
+            vec![unlinked.id],
+            report.deleted.iter().map(|a| a.id).collect::<Vec<_>>()
+        );
+        assert!(
+            asset_manager
+                .get_by_id(&db, unlinked.id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            asset_manager
+                .get_by_id(&db, image.id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+}
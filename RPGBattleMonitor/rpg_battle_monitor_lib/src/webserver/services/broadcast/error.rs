@@ -0,0 +1,16 @@
+use thiserror::Error;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    SerdeJsonError(#[from] serde_json::Error),
+
+    #[cfg(feature = "broadcast_postgres")]
+    #[error(transparent)]
+    SqlxError(#[from] sqlx::Error),
+
+    #[error("Broadcast payload of {size} bytes exceeds the limit of {limit} bytes")]
+    PayloadTooLarge { size: usize, limit: usize },
+}
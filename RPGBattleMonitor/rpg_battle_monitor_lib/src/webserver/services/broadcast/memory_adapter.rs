@@ -0,0 +1,60 @@
+use futures_util::{
+    FutureExt, StreamExt,
+    future::BoxFuture,
+    stream::{self, BoxStream},
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::webserver::services::broadcast::{BroadcastAdapter, BroadcastMessage, error::Result};
+
+/// Broadcasts messages between nodes living in the same process, clones share
+/// the same channel so each clone can act as a separate node in tests
+#[derive(Debug, Clone)]
+pub struct MemoryAdapter {
+    sender: broadcast::Sender<BroadcastMessage>,
+}
+
+impl Default for MemoryAdapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryAdapter {
+    const CAPACITY: usize = 1024;
+
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(Self::CAPACITY);
+
+        Self { sender }
+    }
+}
+
+impl BroadcastAdapter for MemoryAdapter {
+    fn publish(&self, message: BroadcastMessage) -> BoxFuture<'_, Result<()>> {
+        // Sending fails only when there are no subscribers, nobody missed the message
+        self.sender.send(message).ok();
+
+        async { Ok(()) }.boxed()
+    }
+
+    fn subscribe(&self) -> BoxFuture<'_, Result<BoxStream<'static, BroadcastMessage>>> {
+        let receiver = self.sender.subscribe();
+
+        let stream = stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => return Some((message, receiver)),
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(
+                            "Broadcast subscriber lagged behind, skipped {skipped} messages"
+                        );
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        async move { Ok(stream.boxed()) }.boxed()
+    }
+}
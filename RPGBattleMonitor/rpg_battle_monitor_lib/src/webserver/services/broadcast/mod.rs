@@ -0,0 +1,528 @@
+//! Room broadcasts shared between server nodes
+//!
+//! Socket.io rooms only reach sockets connected to the same process, room
+//! broadcasts that have to reach every node are emitted locally and also
+//! published through a [`BroadcastAdapter`]. Every node forwards messages
+//! published by other nodes to its own sockets in the room.
+//!
+//! Each node persists only the actions received from its own sockets, remote
+//! actions are forwarded to clients but never pushed to the local `EntityQueue`.
+//! Every entity change is therefore flushed exactly once, by the node that
+//! received it, and concurrent changes from different nodes are resolved by
+//! entity timestamps when they are saved. A join asks every live node to flush the
+//! game and waits for their answers, or [`RoomBroadcaster::FLUSH_TIMEOUT`], before the
+//! entities are streamed.
+//!
+//! Entity locks are enforced per node, see `WebsocketConfig::entity_locks`, their
+//! state changes are broadcast to every node. Each node publishes the presence of its
+//! own sockets and sockets are sent the presence summed over all nodes. A node that
+//! sees another node in a room for the first time publishes its own presence again so
+//! both know each other.
+//!
+//! Nodes publish a heartbeat, a node that is not heard from for
+//! [`RoomBroadcaster::NODE_TIMEOUT`] is dropped together with its presence.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use futures_util::{StreamExt, future::BoxFuture, stream::BoxStream};
+use serde::{Deserialize, Serialize};
+use socketioxide::{SocketIo, extract::SocketRef, operators::BroadcastOperators};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    api::websockets::protocol::{Event, Presence},
+    webserver::services::entity_queues::EntityQueues,
+};
+
+pub mod error;
+pub mod memory_adapter;
+#[cfg(feature = "broadcast_postgres")]
+pub mod postgres_adapter;
+
+use error::Result;
+use memory_adapter::MemoryAdapter;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastMessage {
+    /// Id of the node that published the message
+    pub node: String,
+    pub payload: BroadcastPayload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BroadcastPayload {
+    /// Event for the sockets in the room
+    Event {
+        room: String,
+        event: Event,
+        data: serde_json::Value,
+    },
+    /// Asks every node to flush the queued entities of the game
+    FlushRequest {
+        id: String,
+        game: i32,
+    },
+    /// Answers the flush request with the id once the game is flushed
+    Flushed {
+        id: String,
+    },
+    Heartbeat,
+}
+
+pub trait BroadcastAdapter: Send + Sync + std::fmt::Debug {
+    /// Publishes the message to every subscribed node, including the publisher
+    fn publish(&self, message: BroadcastMessage) -> BoxFuture<'_, Result<()>>;
+    /// Stream of all published messages, the stream ends when the connection is lost
+    fn subscribe(&self) -> BoxFuture<'_, Result<BoxStream<'static, BroadcastMessage>>>;
+}
+
+#[derive(Debug, Clone)]
+pub struct RoomBroadcaster {
+    node: String,
+    adapter: Arc<dyn BroadcastAdapter>,
+    /// Presence of every node by room
+    presence: Arc<DashMap<String, HashMap<String, Presence>>>,
+    /// When each other node was heard from last
+    nodes: Arc<DashMap<String, Instant>>,
+    /// Nodes that answered each pending flush request of this node
+    flushes: Arc<DashMap<String, mpsc::UnboundedSender<String>>>,
+}
+
+impl Default for RoomBroadcaster {
+    fn default() -> Self {
+        Self::new(Arc::new(MemoryAdapter::new()))
+    }
+}
+
+impl RoomBroadcaster {
+    const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+    pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+    pub const NODE_TIMEOUT: Duration = Duration::from_secs(15);
+    pub const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn new(adapter: Arc<dyn BroadcastAdapter>) -> Self {
+        Self {
+            node: uuid::Uuid::new_v4().to_string(),
+            adapter,
+            presence: Arc::new(DashMap::new()),
+            nodes: Arc::new(DashMap::new()),
+            flushes: Arc::new(DashMap::new()),
+        }
+    }
+
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// Emits to every socket in the room except the sender, on this and all other nodes
+    pub async fn emit<T: Serialize>(
+        &self,
+        socket: &SocketRef,
+        room: String,
+        event: Event,
+        data: &T,
+    ) {
+        self.emit_with(socket.to(room.clone()), room, event, data)
+            .await;
+    }
+
+    /// Emits to every socket in the room including the sender, on this and all other nodes
+    pub async fn emit_within<T: Serialize>(
+        &self,
+        socket: &SocketRef,
+        room: String,
+        event: Event,
+        data: &T,
+    ) {
+        self.emit_with(socket.within(room.clone()), room, event, data)
+            .await;
+    }
+
+    async fn emit_with<T: Serialize>(
+        &self,
+        local: BroadcastOperators,
+        room: String,
+        event: Event,
+        data: &T,
+    ) {
+        local.emit(event, data).await.ok();
+        self.publish(room, event, data).await;
+    }
+
+    async fn publish<T: Serialize>(&self, room: String, event: Event, data: &T) {
+        let data = match serde_json::to_value(data) {
+            Ok(d) => d,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to serialize broadcast message");
+                return;
+            }
+        };
+
+        self.publish_payload(BroadcastPayload::Event { room, event, data })
+            .await;
+    }
+
+    async fn publish_payload(&self, payload: BroadcastPayload) {
+        let message = BroadcastMessage {
+            node: self.node.clone(),
+            payload,
+        };
+        if let Err(e) = self.adapter.publish(message).await {
+            tracing::error!(error = %e, "Failed to publish broadcast message");
+        }
+    }
+
+    /// Asks every live node to flush the queued entities of the game and waits until
+    /// they are flushed, nodes that do not answer within `FLUSH_TIMEOUT` are skipped
+    pub async fn flush_remote(&self, game: i32) {
+        let mut waiting = self.live_nodes();
+        if waiting.is_empty() {
+            return;
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let (sender, mut answers) = mpsc::unbounded_channel();
+        self.flushes.insert(id.clone(), sender);
+        self.publish_payload(BroadcastPayload::FlushRequest {
+            id: id.clone(),
+            game,
+        })
+        .await;
+
+        let all_answered = tokio::time::timeout(Self::FLUSH_TIMEOUT, async {
+            while let Some(node) = answers.recv().await {
+                waiting.remove(&node);
+                if waiting.is_empty() {
+                    return;
+                }
+            }
+        })
+        .await;
+        self.flushes.remove(&id);
+
+        if all_answered.is_err() {
+            tracing::warn!(
+                "Nodes {waiting:?} did not flush game {game} in time, their queued entities are not streamed"
+            );
+        }
+    }
+
+    fn live_nodes(&self) -> HashSet<String> {
+        self.nodes
+            .iter()
+            .filter(|node| node.value().elapsed() < Self::NODE_TIMEOUT)
+            .map(|node| node.key().clone())
+            .collect()
+    }
+
+    /// Forgets the nodes that were not heard from for `NODE_TIMEOUT` and returns the
+    /// rooms they were present in with the presence summed over the remaining nodes
+    fn drop_stale_nodes(&self) -> Vec<(String, Presence)> {
+        let stale = self
+            .nodes
+            .iter()
+            .filter(|node| node.value().elapsed() >= Self::NODE_TIMEOUT)
+            .map(|node| node.key().clone())
+            .collect::<Vec<_>>();
+        if stale.is_empty() {
+            return Vec::new();
+        }
+
+        for node in &stale {
+            tracing::warn!("Node {node} stopped reporting, dropping its presence");
+            self.nodes.remove(node);
+        }
+
+        let mut changed = Vec::new();
+        for mut nodes in self.presence.iter_mut() {
+            let before = nodes.len();
+            nodes.retain(|node, _| !stale.contains(node));
+            if nodes.len() != before {
+                changed.push((nodes.key().clone(), Self::sum_presence(&nodes)));
+            }
+        }
+
+        changed
+    }
+
+    /// Sends the presence of the room summed over all nodes to the sockets in the room
+    /// and publishes `local`, the presence of this node, to the other nodes
+    pub async fn emit_presence(&self, socket: &SocketRef, room: String, local: Presence) {
+        let (total, _) = self.record_presence(&self.node, &room, local.clone());
+
+        socket
+            .within(room.clone())
+            .emit(Event::Presence, &total)
+            .await
+            .ok();
+        self.publish(room, Event::Presence, &local).await;
+    }
+
+    /// Stores the presence of a node and returns the presence summed over all nodes,
+    /// the flag is set when the node was not present in the room before
+    fn record_presence(&self, node: &str, room: &str, presence: Presence) -> (Presence, bool) {
+        let mut nodes = self.presence.entry(room.to_string()).or_default();
+
+        let is_new = if presence.players == 0 && presence.spectators == 0 {
+            nodes.remove(node);
+            false
+        } else {
+            nodes.insert(node.to_string(), presence).is_none()
+        };
+
+        (Self::sum_presence(&nodes), is_new)
+    }
+
+    fn sum_presence(nodes: &HashMap<String, Presence>) -> Presence {
+        nodes
+            .values()
+            .fold(Presence::default(), |mut total, presence| {
+                total.players += presence.players;
+                total.spectators += presence.spectators;
+                total
+            })
+    }
+
+    async fn forward_presence(
+        &self,
+        io: &SocketIo,
+        node: &str,
+        room: String,
+        data: serde_json::Value,
+    ) {
+        let presence = match serde_json::from_value(data) {
+            Ok(p) => p,
+            Err(e) => {
+                tracing::warn!(error = %e, "Received malformed presence message");
+                return;
+            }
+        };
+
+        let (total, is_new) = self.record_presence(node, &room, presence);
+        io.to(room.clone()).emit(Event::Presence, &total).await.ok();
+
+        let local = self
+            .presence
+            .get(&room)
+            .and_then(|nodes| nodes.get(&self.node).cloned());
+        if is_new && let Some(local) = local {
+            self.publish(room, Event::Presence, &local).await;
+        }
+    }
+
+    async fn forward(
+        &self,
+        io: &SocketIo,
+        entity_queues: &Arc<EntityQueues>,
+        message: BroadcastMessage,
+    ) {
+        self.nodes.insert(message.node.clone(), Instant::now());
+
+        match message.payload {
+            BroadcastPayload::Event {
+                room,
+                event: Event::Presence,
+                data,
+            } => self.forward_presence(io, &message.node, room, data).await,
+            BroadcastPayload::Event { room, event, data } => {
+                io.to(room).emit(event, &data).await.ok();
+            }
+            BroadcastPayload::FlushRequest { id, game } => {
+                // Flushing waits for the database, other messages are forwarded meanwhile
+                let (broadcaster, entity_queues) = (self.clone(), entity_queues.clone());
+                tokio::spawn(async move {
+                    entity_queues.flush_game(game).await;
+                    broadcaster
+                        .publish_payload(BroadcastPayload::Flushed { id })
+                        .await;
+                });
+            }
+            BroadcastPayload::Flushed { id } => {
+                if let Some(answers) = self.flushes.get(&id) {
+                    answers.send(message.node).ok();
+                }
+            }
+            BroadcastPayload::Heartbeat => (),
+        }
+    }
+
+    pub fn is_remote(&self, message: &BroadcastMessage) -> bool {
+        message.node != self.node
+    }
+
+    /// Publishes a heartbeat every `HEARTBEAT_INTERVAL` and drops the presence of nodes
+    /// that stopped reporting
+    pub fn keep_alive(&self, io: SocketIo) -> JoinHandle<()> {
+        let broadcaster = self.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Self::HEARTBEAT_INTERVAL);
+            loop {
+                interval.tick().await;
+                broadcaster
+                    .publish_payload(BroadcastPayload::Heartbeat)
+                    .await;
+
+                for (room, total) in broadcaster.drop_stale_nodes() {
+                    io.to(room).emit(Event::Presence, &total).await.ok();
+                }
+            }
+        })
+    }
+
+    /// Forwards messages published by other nodes to the local sockets and answers their
+    /// flush requests, subscribes again when the subscription is lost
+    pub fn forward_remote(&self, io: SocketIo, entity_queues: Arc<EntityQueues>) -> JoinHandle<()> {
+        let broadcaster = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                match broadcaster.adapter.subscribe().await {
+                    Ok(mut messages) => {
+                        // Other nodes learn about this node without waiting for the heartbeat
+                        broadcaster
+                            .publish_payload(BroadcastPayload::Heartbeat)
+                            .await;
+
+                        while let Some(message) = messages.next().await {
+                            if broadcaster.is_remote(&message) {
+                                broadcaster.forward(&io, &entity_queues, message).await;
+                            }
+                        }
+                        tracing::warn!("Broadcast subscription ended");
+                    }
+                    Err(e) => {
+                        tracing::error!(error = %e, "Failed to subscribe to broadcast messages");
+                    }
+                }
+
+                tokio::time::sleep(Self::RESUBSCRIBE_DELAY).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, sync::Arc, time::Instant};
+
+    use futures_util::StreamExt;
+
+    use crate::{
+        api::websockets::protocol::{Event, Presence},
+        webserver::services::broadcast::{
+            BroadcastAdapter, BroadcastMessage, BroadcastPayload, RoomBroadcaster,
+            memory_adapter::MemoryAdapter,
+        },
+    };
+
+    fn message(broadcaster: &RoomBroadcaster) -> BroadcastMessage {
+        BroadcastMessage {
+            node: broadcaster.node().to_string(),
+            payload: BroadcastPayload::Event {
+                room: "room-1".to_string(),
+                event: Event::Action,
+                data: serde_json::json!({ "action": "update", "data": [] }),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn published_messages_reach_all_subscribers() {
+        let adapter = MemoryAdapter::new();
+        let first = adapter.clone();
+        let second = adapter.clone();
+
+        let mut first_messages = first.subscribe().await.unwrap();
+        let mut second_messages = second.subscribe().await.unwrap();
+
+        let broadcaster = RoomBroadcaster::new(Arc::new(adapter));
+        first.publish(message(&broadcaster)).await.unwrap();
+
+        let received = first_messages.next().await.unwrap();
+        let BroadcastPayload::Event { room, event, .. } = received.payload else {
+            panic!("Expected an event, got {:?}", received.payload);
+        };
+        assert_eq!("room-1", room);
+        assert_eq!(Event::Action, event);
+
+        let received = second_messages.next().await.unwrap();
+        assert_eq!(broadcaster.node(), received.node);
+    }
+
+    #[test]
+    fn own_messages_are_not_remote() {
+        let adapter = Arc::new(MemoryAdapter::new());
+        let first = RoomBroadcaster::new(adapter.clone());
+        let second = RoomBroadcaster::new(adapter);
+
+        assert!(!first.is_remote(&message(&first)));
+        assert!(second.is_remote(&message(&first)));
+    }
+
+    #[test]
+    fn presence_is_summed_over_nodes() {
+        let broadcaster = RoomBroadcaster::default();
+        let presence = |players, spectators| Presence {
+            players,
+            spectators,
+        };
+
+        let (total, is_new) = broadcaster.record_presence("node-1", "room-1", presence(1, 0));
+        assert_eq!((presence(1, 0), true), (total, is_new));
+
+        let (total, is_new) = broadcaster.record_presence("node-2", "room-1", presence(2, 1));
+        assert_eq!((presence(3, 1), true), (total, is_new));
+
+        let (total, is_new) = broadcaster.record_presence("node-1", "room-1", presence(2, 0));
+        assert_eq!((presence(4, 1), false), (total, is_new));
+
+        let (total, _) = broadcaster.record_presence("node-2", "room-2", presence(5, 0));
+        assert_eq!(presence(5, 0), total);
+
+        let (total, is_new) = broadcaster.record_presence("node-2", "room-1", presence(0, 0));
+        assert_eq!((presence(2, 0), false), (total, is_new));
+    }
+
+    #[test]
+    fn presence_of_stale_nodes_is_dropped() {
+        let broadcaster = RoomBroadcaster::default();
+        let presence = |players, spectators| Presence {
+            players,
+            spectators,
+        };
+        broadcaster.record_presence(broadcaster.node(), "room-1", presence(1, 0));
+        broadcaster.record_presence("node-2", "room-1", presence(2, 1));
+        broadcaster.record_presence("node-2", "room-2", presence(1, 0));
+        broadcaster.record_presence("node-3", "room-3", presence(1, 0));
+
+        let now = Instant::now();
+        broadcaster.nodes.insert("node-2".to_string(), now);
+        broadcaster.nodes.insert("node-3".to_string(), now);
+        assert!(broadcaster.drop_stale_nodes().is_empty());
+        assert_eq!(2, broadcaster.live_nodes().len());
+
+        broadcaster
+            .nodes
+            .insert("node-2".to_string(), now - RoomBroadcaster::NODE_TIMEOUT);
+        let mut changed = broadcaster.drop_stale_nodes();
+        changed.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            vec![
+                ("room-1".to_string(), presence(1, 0)),
+                ("room-2".to_string(), presence(0, 0)),
+            ],
+            changed
+        );
+        assert_eq!(
+            HashSet::from(["node-3".to_string()]),
+            broadcaster.live_nodes()
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use futures_util::{
+    FutureExt, StreamExt,
+    future::BoxFuture,
+    stream::{self, BoxStream},
+};
+use sqlx::{
+    PgPool,
+    postgres::{PgListener, PgPoolOptions},
+};
+
+use crate::webserver::services::broadcast::{
+    BroadcastAdapter, BroadcastMessage,
+    error::{Error, Result},
+};
+
+/// Broadcasts messages between nodes with Postgres `LISTEN/NOTIFY`
+///
+/// Messages that do not fit into a single notification are split into chunks
+/// of the form `{id}:{index}:{count}:{part}`, all chunks of a message are
+/// notified in one transaction so they are delivered together
+#[derive(Debug, Clone)]
+pub struct PostgresAdapter {
+    pool: PgPool,
+    channel: String,
+}
+
+impl PostgresAdapter {
+    /// `NOTIFY` payloads have to be shorter than 8000 bytes
+    pub const PAYLOAD_LIMIT: usize = 7999;
+    /// Leaves room for the chunk header
+    const CHUNK_SIZE: usize = Self::PAYLOAD_LIMIT - 64;
+    /// Largest message that is published, bounds the memory of partially received messages
+    pub const MESSAGE_LIMIT: usize = 8 * 1024 * 1024;
+
+    pub fn new(pool: PgPool, channel: impl Into<String>) -> Self {
+        Self {
+            pool,
+            channel: channel.into(),
+        }
+    }
+
+    pub async fn connect(url: &str, channel: impl Into<String>) -> Result<Self> {
+        let pool = PgPoolOptions::new().max_connections(2).connect(url).await?;
+
+        Ok(Self::new(pool, channel))
+    }
+}
+
+impl BroadcastAdapter for PostgresAdapter {
+    fn publish(&self, message: BroadcastMessage) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let payload = serde_json::to_string(&message)?;
+            if payload.len() > Self::MESSAGE_LIMIT {
+                return Err(Error::PayloadTooLarge {
+                    size: payload.len(),
+                    limit: Self::MESSAGE_LIMIT,
+                });
+            }
+
+            if payload.len() <= Self::PAYLOAD_LIMIT {
+                sqlx::query("SELECT pg_notify($1, $2)")
+                    .bind(&self.channel)
+                    .bind(payload)
+                    .execute(&self.pool)
+                    .await?;
+
+                return Ok(());
+            }
+
+            let mut transaction = self.pool.begin().await?;
+            for chunk in split_payload(&payload, Self::CHUNK_SIZE) {
+                sqlx::query("SELECT pg_notify($1, $2)")
+                    .bind(&self.channel)
+                    .bind(chunk)
+                    .execute(&mut *transaction)
+                    .await?;
+            }
+            transaction.commit().await?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn subscribe(&self) -> BoxFuture<'_, Result<BoxStream<'static, BroadcastMessage>>> {
+        async move {
+            let mut listener = PgListener::connect_with(&self.pool).await?;
+            listener.listen(&self.channel).await?;
+
+            // `recv` reconnects on its own, an error means the connection can not be recovered
+            let state = (listener, ChunkAssembler::default());
+            let stream = stream::unfold(state, |(mut listener, mut assembler)| async move {
+                loop {
+                    let notification = match listener.recv().await {
+                        Ok(n) => n,
+                        Err(e) => {
+                            tracing::error!(error = %e, "Postgres broadcast listener failed");
+                            return None;
+                        }
+                    };
+
+                    let payload = notification.payload();
+                    let parsed = if payload.starts_with('{') {
+                        serde_json::from_str(payload)
+                    } else {
+                        match assembler.push(payload) {
+                            Some(payload) => serde_json::from_str(&payload),
+                            None => continue,
+                        }
+                    };
+
+                    match parsed {
+                        Ok(message) => return Some((message, (listener, assembler))),
+                        Err(e) => {
+                            tracing::warn!(error = %e, "Received malformed broadcast message");
+                        }
+                    }
+                }
+            });
+
+            Ok(stream.boxed())
+        }
+        .boxed()
+    }
+}
+
+/// Splits the payload into chunks of at most `chunk_size` bytes, each chunk
+/// starts with the header needed to reassemble the payload
+fn split_payload(payload: &str, chunk_size: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = payload;
+    while !rest.is_empty() {
+        let mut end = chunk_size.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let (part, tail) = rest.split_at(end);
+        parts.push(part);
+        rest = tail;
+    }
+
+    let id = crate::utils::gen_uuid();
+    let count = parts.len();
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(index, part)| format!("{id}:{index}:{count}:{part}"))
+        .collect()
+}
+
+#[derive(Debug)]
+struct PendingMessage {
+    parts: Vec<Option<String>>,
+    received: usize,
+    started: Instant,
+}
+
+/// Reassembles chunked messages, chunks of messages that are never
+/// completed are dropped after [`ChunkAssembler::EXPIRY`]
+#[derive(Debug, Default)]
+struct ChunkAssembler {
+    pending: HashMap<String, PendingMessage>,
+}
+
+impl ChunkAssembler {
+    const EXPIRY: Duration = Duration::from_secs(30);
+    const MAX_CHUNKS: usize = PostgresAdapter::MESSAGE_LIMIT / PostgresAdapter::CHUNK_SIZE + 1;
+
+    /// Returns the whole payload once the last missing chunk of a message is pushed
+    fn push(&mut self, chunk: &str) -> Option<String> {
+        let mut fields = chunk.splitn(4, ':');
+        let (Some(id), Some(index), Some(count), Some(part)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            tracing::warn!("Received malformed broadcast chunk");
+            return None;
+        };
+        let (Ok(index), Ok(count)) = (index.parse::<usize>(), count.parse::<usize>()) else {
+            tracing::warn!("Received malformed broadcast chunk header");
+            return None;
+        };
+        if index >= count || count > Self::MAX_CHUNKS {
+            tracing::warn!("Received broadcast chunk {index} of {count}, ignoring");
+            return None;
+        }
+
+        self.pending
+            .retain(|_, pending| pending.started.elapsed() < Self::EXPIRY);
+
+        let pending = self
+            .pending
+            .entry(id.to_string())
+            .or_insert_with(|| PendingMessage {
+                parts: vec![None; count],
+                received: 0,
+                started: Instant::now(),
+            });
+        if pending.parts.len() != count {
+            tracing::warn!("Broadcast chunk count of message {id} changed, ignoring");
+            return None;
+        }
+
+        if pending.parts[index].replace(part.to_string()).is_none() {
+            pending.received += 1;
+        }
+        if pending.received < count {
+            return None;
+        }
+
+        let pending = self.pending.remove(id)?;
+        Some(pending.parts.into_iter().flatten().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::webserver::services::broadcast::postgres_adapter::{ChunkAssembler, split_payload};
+
+    #[test]
+    fn chunked_payload_is_reassembled() {
+        let payload = format!("{{\"data\":\"{}\"}}", "ä".repeat(50));
+        let chunks = split_payload(&payload, 16);
+        assert!(chunks.len() > 1);
+
+        let mut assembler = ChunkAssembler::default();
+        let mut reassembled = None;
+        for chunk in chunks.iter().rev() {
+            assert!(reassembled.is_none());
+            reassembled = assembler.push(chunk);
+        }
+
+        assert_eq!(Some(payload), reassembled);
+        assert!(assembler.pending.is_empty());
+    }
+
+    #[test]
+    fn interleaved_messages_are_kept_apart() {
+        let first = split_payload("first message", 4);
+        let second = split_payload("second message", 4);
+
+        let mut assembler = ChunkAssembler::default();
+        let mut completed = Vec::new();
+        for (a, b) in first.iter().zip(second.iter()) {
+            completed.extend(assembler.push(a));
+            completed.extend(assembler.push(b));
+        }
+        for chunk in &second[first.len()..] {
+            completed.extend(assembler.push(chunk));
+        }
+
+        assert_eq!(vec!["first message", "second message"], completed);
+    }
+
+    #[test]
+    fn malformed_chunks_are_ignored() {
+        let mut assembler = ChunkAssembler::default();
+
+        assert_eq!(None, assembler.push("no header"));
+        assert_eq!(None, assembler.push("id:2:2:part"));
+        assert_eq!(None, assembler.push("id:0:99999999:part"));
+        assert!(assembler.pending.is_empty());
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::entity::Entity;
+
+#[cfg(feature = "api_doc")]
+use utoipa::ToSchema;
+
+/// Entity that could not be saved after all flush attempts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    #[cfg_attr(feature = "api_doc", schema(value_type = Object))]
+    pub entity: Entity,
+    pub attempts: u32,
+    /// Error of the last failed flush
+    pub error: String,
+    #[cfg_attr(feature = "api_doc", schema(value_type = String))]
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Dead letters are kept in memory and, when backed by a file, appended
+/// to it so they survive restarts
+#[derive(Debug, Default)]
+pub struct DeadLetterStore {
+    path: Option<PathBuf>,
+    letters: Mutex<Vec<DeadLetter>>,
+}
+
+impl DeadLetterStore {
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let mut letters = Vec::new();
+        if path.exists() {
+            let reader = BufReader::new(File::open(&path)?);
+            for line in reader.lines() {
+                let line = line?;
+                if line.is_empty() {
+                    continue;
+                }
+
+                match serde_json::from_str(&line) {
+                    Ok(letter) => letters.push(letter),
+                    Err(e) => {
+                        tracing::warn!(error = %e, "Skipping malformed dead letter in {}", path.display());
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            path: Some(path),
+            letters: Mutex::new(letters),
+        })
+    }
+
+    /// Stores dead letters, they are kept in memory even if writing them to the file fails
+    pub fn add(&self, letters: Vec<DeadLetter>) -> io::Result<()> {
+        if letters.is_empty() {
+            return Ok(());
+        }
+
+        let mut lock = self.letters.lock().expect("Mutex is poisoned");
+        let written = self.append_to_file(&letters);
+        lock.extend(letters);
+
+        written
+    }
+
+    pub fn list(&self) -> Vec<DeadLetter> {
+        self.letters.lock().expect("Mutex is poisoned").clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.letters.lock().expect("Mutex is poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes the `count` oldest dead letters, letters added since they were listed
+    /// are kept. Nothing is removed when the file can not be rewritten
+    pub fn remove_oldest(&self, count: usize) -> io::Result<()> {
+        let mut lock = self.letters.lock().expect("Mutex is poisoned");
+        let count = count.min(lock.len());
+        self.rewrite_file(&lock[count..])?;
+        lock.drain(..count);
+
+        Ok(())
+    }
+
+    /// Replaces the file by the letters, the old file is kept until the new one is written
+    fn rewrite_file(&self, letters: &[DeadLetter]) -> io::Result<()> {
+        let Some(path) = self.path.as_ref() else {
+            return Ok(());
+        };
+
+        let rewritten = path.with_extension("rewrite");
+        let mut buffer = Vec::new();
+        for letter in letters {
+            serde_json::to_writer(&mut buffer, letter)?;
+            buffer.push(b'\n');
+        }
+
+        let mut file = File::create(&rewritten)?;
+        file.write_all(&buffer)?;
+        file.sync_all()?;
+        fs::rename(rewritten, path)
+    }
+
+    fn append_to_file(&self, letters: &[DeadLetter]) -> io::Result<()> {
+        let Some(path) = self.path.as_ref() else {
+            return Ok(());
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut buffer = Vec::new();
+        for letter in letters {
+            serde_json::to_writer(&mut buffer, letter)?;
+            buffer.push(b'\n');
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(&buffer)?;
+        file.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        api::websockets::Action,
+        entity::{Entity, UId, UtcTimestamp, kind::EntityKind},
+        webserver::services::entity_dead_letters::{DeadLetter, DeadLetterStore},
+    };
+
+    fn dead_letter(uid: &str) -> DeadLetter {
+        DeadLetter {
+            entity: Entity {
+                uid: UId(uid.to_string()),
+                game: 1,
+                kind: EntityKind("token".to_string()),
+                timestamp: UtcTimestamp(1),
+                action: Some(Arc::new(Action::Update)),
+                other_values: serde_json::json!({}),
+            },
+            attempts: 5,
+            error: "database is locked".to_string(),
+            failed_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn dead_letters_survive_reopen() {
+        let path =
+            std::env::temp_dir().join(format!("dead-letters-{}.jsonl", uuid::Uuid::new_v4()));
+
+        {
+            let store = DeadLetterStore::open(&path).unwrap();
+            store.add(vec![dead_letter("a")]).unwrap();
+            store.add(vec![dead_letter("b")]).unwrap();
+        }
+
+        let store = DeadLetterStore::open(&path).unwrap();
+        assert_eq!(2, store.len());
+
+        store.remove_oldest(1).unwrap();
+        assert_eq!("b", store.list()[0].entity.uid.0);
+        let reopened = DeadLetterStore::open(&path).unwrap();
+        assert_eq!(1, reopened.len());
+        assert_eq!("b", reopened.list()[0].entity.uid.0);
+
+        store.remove_oldest(1).unwrap();
+        assert!(store.is_empty());
+        assert!(DeadLetterStore::open(&path).unwrap().is_empty());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
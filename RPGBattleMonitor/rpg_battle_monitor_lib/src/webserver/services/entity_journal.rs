@@ -0,0 +1,334 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::entity::Entity;
+
+/// Write-ahead journal of entities accepted by the `EntityQueue`.
+///
+/// Entities are appended as JSON lines to the active segment and synced to disk
+/// before they are accepted, a failed append is cut off the segment again so it is
+/// never replayed and can not corrupt the next append. A flush rotates the journal
+/// to a new segment, sealed segments are removed once the flush is committed,
+/// segments that were never removed are replayed on startup.
+#[derive(Debug)]
+pub struct EntityJournal {
+    directory: PathBuf,
+    generation: u64,
+    file: File,
+    /// Length of the active segment up to the last accepted append
+    len: u64,
+    sealed: Vec<PathBuf>,
+}
+
+impl EntityJournal {
+    const EXTENSION: &str = "journal";
+
+    /// Opens the journal directory and returns entities of segments left over by
+    /// a previous run, oldest first. Left over segments are sealed and removed
+    /// with the next flush.
+    pub fn open(directory: impl AsRef<Path>) -> io::Result<(Self, Vec<Entity>)> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+
+        let segments = Self::segments(&directory)?;
+
+        let mut entities = Vec::new();
+        for (_, path) in &segments {
+            Self::replay(path, &mut entities)?;
+        }
+
+        let generation = segments.last().map(|(g, _)| g + 1).unwrap_or_default();
+        let journal = Self {
+            file: Self::create_segment(&directory, generation)?,
+            len: 0,
+            directory,
+            generation,
+            sealed: segments.into_iter().map(|(_, path)| path).collect(),
+        };
+
+        if !entities.is_empty() {
+            tracing::info!("Replayed {} entities from journal", entities.len());
+        }
+
+        Ok((journal, entities))
+    }
+
+    /// Appends entities to the active segment and syncs it to disk
+    pub fn append(&mut self, entities: &[Entity]) -> io::Result<()> {
+        if entities.is_empty() {
+            return Ok(());
+        }
+
+        let mut buffer = Vec::new();
+        for entity in entities {
+            serde_json::to_writer(&mut buffer, entity)?;
+            buffer.push(b'\n');
+        }
+
+        let written = self
+            .file
+            .write_all(&buffer)
+            .and_then(|_| self.file.sync_data());
+        if let Err(e) = written {
+            self.discard_unaccepted();
+            return Err(e);
+        }
+
+        self.len += buffer.len() as u64;
+        Ok(())
+    }
+
+    /// Cuts the active segment back to its accepted entries, a new segment is started
+    /// when the segment can not be truncated
+    fn discard_unaccepted(&mut self) {
+        let truncated = self
+            .file
+            .set_len(self.len)
+            .and_then(|_| self.file.sync_data());
+        let Err(e) = truncated else {
+            return;
+        };
+
+        tracing::error!(error = %e, "Failed to truncate journal segment, starting a new segment");
+        if let Err(e) = self.start_segment() {
+            tracing::error!(error = %e, "Failed to start a new journal segment");
+        }
+    }
+
+    /// Starts a new segment and returns all sealed segments, they can be removed
+    /// once every entity written before the rotation is saved
+    pub fn rotate(&mut self) -> io::Result<Vec<PathBuf>> {
+        self.start_segment()?;
+
+        Ok(std::mem::take(&mut self.sealed))
+    }
+
+    /// Seals the active segment and continues in a new one
+    fn start_segment(&mut self) -> io::Result<()> {
+        let generation = self.generation + 1;
+        let file = Self::create_segment(&self.directory, generation)?;
+
+        let previous = self.segment_path(self.generation);
+        self.file = file;
+        self.len = 0;
+        self.generation = generation;
+        self.sealed.push(previous);
+
+        Ok(())
+    }
+
+    /// Reads every segment in the directory without opening a journal,
+    /// returns the entities and the segments they were read from
+    pub fn read_segments(directory: impl AsRef<Path>) -> io::Result<(Vec<Entity>, Vec<PathBuf>)> {
+        let mut entities = Vec::new();
+        let mut paths = Vec::new();
+        for (_, path) in Self::segments(directory.as_ref())? {
+            Self::replay(&path, &mut entities)?;
+            paths.push(path);
+        }
+
+        Ok((entities, paths))
+    }
+
+    pub fn remove_segments(segments: &[PathBuf]) {
+        for segment in segments {
+            if let Err(e) = fs::remove_file(segment) {
+                tracing::error!(error = %e, "Failed to remove journal segment {}", segment.display());
+            }
+        }
+    }
+
+    fn segment_path(&self, generation: u64) -> PathBuf {
+        Self::segment_path_in(&self.directory, generation)
+    }
+
+    fn segment_path_in(directory: &Path, generation: u64) -> PathBuf {
+        directory.join(format!("{generation:020}.{}", Self::EXTENSION))
+    }
+
+    /// Creates the segment and syncs the directory, so the segment survives a crash
+    fn create_segment(directory: &Path, generation: u64) -> io::Result<File> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::segment_path_in(directory, generation))?;
+        Self::sync_directory(directory)?;
+
+        Ok(file)
+    }
+
+    #[cfg(unix)]
+    fn sync_directory(directory: &Path) -> io::Result<()> {
+        File::open(directory)?.sync_all()
+    }
+
+    /// Directories can not be opened as files on other platforms
+    #[cfg(not(unix))]
+    fn sync_directory(_directory: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn segments(directory: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(Self::EXTENSION) {
+                continue;
+            }
+
+            if let Some(generation) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok())
+            {
+                segments.push((generation, path));
+            }
+        }
+        segments.sort_by_key(|(generation, _)| *generation);
+
+        Ok(segments)
+    }
+
+    fn replay(path: &Path, entities: &mut Vec<Entity>) -> io::Result<()> {
+        let reader = BufReader::new(File::open(path)?);
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+
+            // A crash during an append can leave a partially written last line
+            match serde_json::from_str(&line) {
+                Ok(entity) => entities.push(entity),
+                Err(e) => {
+                    tracing::warn!(error = %e, "Skipping malformed journal entry in {}", path.display());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl EntityJournal {
+    /// Reopens the active segment read only, so the next append fails
+    pub(crate) fn fail_next_append(&mut self) {
+        self.file = File::open(self.segment_path(self.generation)).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, path::PathBuf, sync::Arc};
+
+    use crate::{
+        api::websockets::Action,
+        entity::{Entity, UId, UtcTimestamp, kind::EntityKind},
+        webserver::services::entity_journal::EntityJournal,
+    };
+
+    fn temp_directory() -> PathBuf {
+        std::env::temp_dir().join(format!("entity-journal-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn entity(uid: &str, timestamp: i64) -> Entity {
+        Entity {
+            uid: UId(uid.to_string()),
+            game: 1,
+            kind: EntityKind("token".to_string()),
+            timestamp: UtcTimestamp(timestamp),
+            action: Some(Arc::new(Action::Update)),
+            other_values: serde_json::json!({ "x": timestamp }),
+        }
+    }
+
+    #[test]
+    fn unflushed_entities_are_replayed() {
+        let directory = temp_directory();
+
+        {
+            let (mut journal, replayed) = EntityJournal::open(&directory).unwrap();
+            assert!(replayed.is_empty());
+
+            journal.append(&[entity("a", 1)]).unwrap();
+            journal.rotate().unwrap();
+            journal.append(&[entity("b", 2), entity("a", 3)]).unwrap();
+        }
+
+        let (_, replayed) = EntityJournal::open(&directory).unwrap();
+        let replayed = replayed
+            .iter()
+            .map(|e| (e.uid.0.as_str(), e.timestamp.0))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![("a", 1), ("b", 2), ("a", 3)], replayed);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn removed_segments_are_not_replayed() {
+        let directory = temp_directory();
+
+        {
+            let (mut journal, _) = EntityJournal::open(&directory).unwrap();
+            journal.append(&[entity("a", 1)]).unwrap();
+
+            let sealed = journal.rotate().unwrap();
+            journal.append(&[entity("b", 2)]).unwrap();
+            EntityJournal::remove_segments(&sealed);
+        }
+
+        let (mut journal, replayed) = EntityJournal::open(&directory).unwrap();
+        assert_eq!(1, replayed.len());
+        assert_eq!("b", replayed[0].uid.0);
+
+        // Replayed segments are sealed by the next rotation
+        let sealed = journal.rotate().unwrap();
+        assert_eq!(2, sealed.len());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn partially_written_entry_is_skipped() {
+        let directory = temp_directory();
+
+        {
+            let (mut journal, _) = EntityJournal::open(&directory).unwrap();
+            journal.append(&[entity("a", 1)]).unwrap();
+            journal.file.write_all(b"{\"uid\":\"b\",\"ga").unwrap();
+        }
+
+        let (_, replayed) = EntityJournal::open(&directory).unwrap();
+        assert_eq!(1, replayed.len());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn failed_append_is_cut_off_before_the_next_append() {
+        let directory = temp_directory();
+
+        {
+            let (mut journal, _) = EntityJournal::open(&directory).unwrap();
+            journal.append(&[entity("a", 1)]).unwrap();
+            journal.file.write_all(b"{\"uid\":\"b\",\"ga").unwrap();
+
+            journal.discard_unaccepted();
+            journal.append(&[entity("c", 3)]).unwrap();
+        }
+
+        let (_, replayed) = EntityJournal::open(&directory).unwrap();
+        let replayed = replayed
+            .iter()
+            .map(|e| e.uid.0.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["a", "c"], replayed);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+use std::time::{Duration, Instant};
+
+use dashmap::{DashMap, mapref::entry::Entry};
+
+use crate::webserver::services::entity_queue::GameIdAndUIdCombo;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityLock {
+    /// Socket id of the lock holder
+    pub holder: String,
+    pub expires_at: Instant,
+}
+
+impl EntityLock {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Instant::now()
+    }
+}
+
+/// Exclusive leases on entities, only the holder of a lease can create, update or delete
+/// the entity. Leases of deleted entities are released.
+///
+/// Leases are held in the memory of the node, see `WebsocketConfig::entity_locks`
+#[derive(Debug, Default)]
+pub struct EntityLocks {
+    locks: DashMap<GameIdAndUIdCombo, EntityLock>,
+}
+
+impl EntityLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grants or renews a lease, returns the current lock when it is held by someone else
+    pub fn lock(
+        &self,
+        key: GameIdAndUIdCombo,
+        holder: &str,
+        lease: Duration,
+    ) -> Result<EntityLock, EntityLock> {
+        let lock = EntityLock {
+            holder: holder.to_string(),
+            expires_at: Instant::now() + lease,
+        };
+
+        match self.locks.entry(key) {
+            Entry::Occupied(mut entry) => {
+                let existing = entry.get();
+                if existing.holder != holder && !existing.is_expired() {
+                    return Err(existing.clone());
+                }
+                entry.insert(lock.clone());
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(lock.clone());
+            }
+        }
+
+        Ok(lock)
+    }
+
+    /// Releases a lease, returns false when it is not held by the holder
+    pub fn unlock(&self, key: &GameIdAndUIdCombo, holder: &str) -> bool {
+        self.locks
+            .remove_if(key, |_, lock| lock.holder == holder)
+            .is_some()
+    }
+
+    /// Releases a lease whoever holds it, returns false when the entity was not locked
+    pub fn release(&self, key: &GameIdAndUIdCombo) -> bool {
+        self.locks.remove(key).is_some()
+    }
+
+    /// Releases a lease only if it was not renewed since `expires_at`
+    pub fn expire(&self, key: &GameIdAndUIdCombo, expires_at: Instant) -> bool {
+        self.locks
+            .remove_if(key, |_, lock| lock.expires_at == expires_at)
+            .is_some()
+    }
+
+    pub fn can_modify(&self, key: &GameIdAndUIdCombo, holder: &str) -> bool {
+        match self.locks.get(key) {
+            Some(lock) => lock.is_expired() || lock.holder == holder,
+            None => true,
+        }
+    }
+
+    /// Releases every lease held by the holder and returns the released entities
+    pub fn release_holder(&self, holder: &str) -> Vec<GameIdAndUIdCombo> {
+        let keys = self
+            .locks
+            .iter()
+            .filter(|lock| lock.holder == holder)
+            .map(|lock| lock.key().clone())
+            .collect::<Vec<_>>();
+
+        keys.into_iter()
+            .filter(|key| self.unlock(key, holder))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{
+        entity::UId,
+        webserver::services::{entity_locks::EntityLocks, entity_queue::GameIdAndUIdCombo},
+    };
+
+    const LEASE: Duration = Duration::from_secs(60);
+
+    fn key(uid: &str) -> GameIdAndUIdCombo {
+        GameIdAndUIdCombo::new(1, UId(uid.to_string()))
+    }
+
+    #[test]
+    fn lock_is_exclusive() {
+        let locks = EntityLocks::new();
+
+        assert!(locks.lock(key("a"), "socket-1", LEASE).is_ok());
+        assert!(locks.lock(key("a"), "socket-1", LEASE).is_ok());
+
+        let existing = locks.lock(key("a"), "socket-2", LEASE).unwrap_err();
+        assert_eq!("socket-1", existing.holder);
+
+        assert!(locks.can_modify(&key("a"), "socket-1"));
+        assert!(!locks.can_modify(&key("a"), "socket-2"));
+        assert!(locks.can_modify(&key("b"), "socket-2"));
+    }
+
+    #[test]
+    fn only_holder_can_unlock() {
+        let locks = EntityLocks::new();
+        locks.lock(key("a"), "socket-1", LEASE).unwrap();
+
+        assert!(!locks.unlock(&key("a"), "socket-2"));
+        assert!(locks.unlock(&key("a"), "socket-1"));
+        assert!(locks.can_modify(&key("a"), "socket-2"));
+    }
+
+    #[test]
+    fn released_lock_is_gone_for_every_holder() {
+        let locks = EntityLocks::new();
+        locks.lock(key("a"), "socket-1", LEASE).unwrap();
+
+        assert!(locks.release(&key("a")));
+        assert!(!locks.release(&key("a")));
+        assert!(locks.lock(key("a"), "socket-2", LEASE).is_ok());
+    }
+
+    #[test]
+    fn expired_lock_can_be_taken() {
+        let locks = EntityLocks::new();
+        locks.lock(key("a"), "socket-1", Duration::ZERO).unwrap();
+
+        assert!(locks.can_modify(&key("a"), "socket-2"));
+        assert!(locks.lock(key("a"), "socket-2", LEASE).is_ok());
+    }
+
+    #[test]
+    fn renewed_lock_does_not_expire() {
+        let locks = EntityLocks::new();
+        let first = locks.lock(key("a"), "socket-1", LEASE).unwrap();
+        locks.lock(key("a"), "socket-1", LEASE * 2).unwrap();
+
+        assert!(!locks.expire(&key("a"), first.expires_at));
+        assert!(!locks.can_modify(&key("a"), "socket-2"));
+    }
+
+    #[test]
+    fn holder_locks_are_released() {
+        let locks = EntityLocks::new();
+        locks.lock(key("a"), "socket-1", LEASE).unwrap();
+        locks.lock(key("b"), "socket-1", LEASE).unwrap();
+        locks.lock(key("c"), "socket-2", LEASE).unwrap();
+
+        let mut released = locks.release_holder("socket-1");
+        released.sort_by(|a, b| a.uid.0.cmp(&b.uid.0));
+
+        assert_eq!(vec![key("a"), key("b")], released);
+        assert!(!locks.can_modify(&key("c"), "socket-1"));
+    }
+}
@@ -1,14 +1,26 @@
 use std::iter::{Skip, Take};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use dashmap::iter::Iter;
+use dashmap::mapref::entry::Entry;
 use sea_orm::{DatabaseConnection, TransactionTrait};
+use serde::Serialize;
 use tokio::task::JoinHandle;
 
 use crate::api::websockets::Action;
+use crate::entity::codec::EntityCodecs;
 use crate::entity::error::{Error, Result};
 use crate::entity::{Entity, UId};
 use crate::models::entity::{CompressedEntityModel, EntityManager};
+use crate::webserver::services::entity_dead_letters::{DeadLetter, DeadLetterStore};
+use crate::webserver::services::entity_journal::EntityJournal;
+
+#[cfg(feature = "api_doc")]
+use utoipa::ToSchema;
 
 pub struct ChunkedEntityQueueIterator<'a> {
     chunk_size: usize,
@@ -43,7 +55,7 @@ impl<'a> Iterator for ChunkedEntityQueueIterator<'a> {
     }
 }
 
-#[derive(Debug, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct GameIdAndUIdCombo {
     pub game_id: i32,
     pub uid: UId,
@@ -59,22 +71,116 @@ impl GameIdAndUIdCombo {
     }
 }
 
+/// How failed flushes are retried before entities are moved to the dead letters
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Number of failed flushes after which an entity is dead lettered
+    pub max_attempts: u32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_backoff: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff doubles with every consecutive failure
+    pub fn backoff(&self, consecutive_failures: u32) -> Duration {
+        let exponent = consecutive_failures.saturating_sub(1).min(16);
+
+        self.base_backoff
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_backoff)
+    }
+}
+
+/// Ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum QueueStatus {
+    Healthy,
+    /// Flushes succeed but some entities were dead lettered
+    Degraded,
+    /// Last flush failed, entities are waiting for a retry
+    Retrying,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct QueueHealth {
+    pub status: QueueStatus,
+    pub queued: usize,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    #[cfg_attr(feature = "api_doc", schema(value_type = Option<String>))]
+    pub last_flushed_at: Option<DateTime<Utc>>,
+    #[cfg_attr(feature = "api_doc", schema(value_type = Option<String>))]
+    pub last_failed_at: Option<DateTime<Utc>>,
+    pub next_retry_in_ms: Option<u64>,
+    pub dead_letters: usize,
+}
+
+/// Flush outcomes shared with the spawned flush tasks
+#[derive(Debug, Default)]
+struct FlushState {
+    attempts: DashMap<GameIdAndUIdCombo, u32>,
+    inner: std::sync::Mutex<InnerFlushState>,
+}
+
+#[derive(Debug, Default)]
+struct InnerFlushState {
+    consecutive_failures: u32,
+    last_error: Option<String>,
+    last_flushed_at: Option<DateTime<Utc>>,
+    last_failed_at: Option<DateTime<Utc>>,
+    retry_at: Option<Instant>,
+    /// Journal segments of failed flushes, removed with the next successful flush
+    failed_segments: Vec<PathBuf>,
+}
+
+impl FlushState {
+    fn lock(&self) -> std::sync::MutexGuard<'_, InnerFlushState> {
+        self.inner.lock().expect("Mutex is poisoned")
+    }
+}
+
 #[derive(Debug)]
 pub struct EntityQueue {
-    pub(crate) entities: DashMap<GameIdAndUIdCombo, CompressedEntityModel>,
+    pub(crate) entities: Arc<DashMap<GameIdAndUIdCombo, CompressedEntityModel>>,
     db: DatabaseConnection,
+    codecs: EntityCodecs,
+    journal: Option<EntityJournal>,
+    retry_policy: RetryPolicy,
+    flush_state: Arc<FlushState>,
+    dead_letters: Arc<DeadLetterStore>,
 }
 
 impl Default for EntityQueue {
     fn default() -> Self {
         Self {
-            entities: DashMap::new(),
+            entities: Arc::new(DashMap::new()),
             db: DatabaseConnection::default(),
+            codecs: EntityCodecs::default(),
+            journal: None,
+            retry_policy: RetryPolicy::default(),
+            flush_state: Arc::new(FlushState::default()),
+            dead_letters: Arc::new(DeadLetterStore::in_memory()),
         }
     }
 }
 
 impl EntityQueue {
+    const DEAD_LETTERS_FILE: &str = "dead-letters.jsonl";
+
     pub fn new(db: DatabaseConnection) -> Self {
         Self {
             db,
@@ -82,16 +188,73 @@ impl EntityQueue {
         }
     }
 
+    /// Compresses queued entities with `codecs`, set it before opening the journal
+    pub fn with_codecs(mut self, codecs: EntityCodecs) -> Self {
+        self.codecs = codecs;
+        self
+    }
+
+    /// Backs the queue by a write-ahead journal in `journal_directory`,
+    /// entities left in the journal by a previous run are queued again
+    pub fn with_journal(mut self, journal_directory: impl AsRef<Path>) -> Result<Self> {
+        let journal_directory = journal_directory.as_ref();
+        let (journal, replayed) =
+            EntityJournal::open(journal_directory).map_err(Error::EntityJournalFailed)?;
+        let dead_letters = DeadLetterStore::open(journal_directory.join(Self::DEAD_LETTERS_FILE))
+            .map_err(Error::EntityJournalFailed)?;
+
+        self.journal = Some(journal);
+        self.dead_letters = Arc::new(dead_letters);
+        for entity in replayed {
+            self.insert(entity)?;
+        }
+
+        Ok(self)
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
     pub fn push(&mut self, entity: Entity) -> Result<()> {
-        let id = GameIdAndUIdCombo::new(entity.game, entity.uid.clone());
+        self.push_all(vec![entity])
+    }
 
-        if let Some(existing_entity) = self.entities.get(&id) {
-            if existing_entity.timestamp > entity.timestamp.0 {
-                return Ok(());
-            }
+    /// Queues entities once they are written to the journal,
+    /// entities older than the already queued ones are ignored
+    pub fn push_all(&mut self, entities: Vec<Entity>) -> Result<()> {
+        let entities = entities
+            .into_iter()
+            .filter(|entity| !self.is_outdated(entity))
+            .collect::<Vec<_>>();
+
+        if let Some(journal) = self.journal.as_mut() {
+            journal
+                .append(&entities)
+                .map_err(Error::EntityJournalFailed)?;
         }
 
-        let comporessed_entity = entity.try_into()?;
+        for entity in entities {
+            self.insert(entity)?;
+        }
+
+        Ok(())
+    }
+
+    fn is_outdated(&self, entity: &Entity) -> bool {
+        self.entities
+            .get(&GameIdAndUIdCombo::from_entity(entity))
+            .is_some_and(|existing_entity| existing_entity.timestamp > entity.timestamp.0)
+    }
+
+    fn insert(&self, entity: Entity) -> Result<()> {
+        if self.is_outdated(&entity) {
+            return Ok(());
+        }
+
+        let id = GameIdAndUIdCombo::from_entity(&entity);
+        let comporessed_entity = self.codecs.compress(entity)?;
         self.entities.insert(id, comporessed_entity);
 
         Ok(())
@@ -102,83 +265,450 @@ impl EntityQueue {
             .contains_key(&GameIdAndUIdCombo::from_entity(entity))
     }
 
+    /// Nothing is queued, failed or being flushed, the queue can be dropped
+    pub fn is_idle(&self) -> bool {
+        let state = self.flush_state.lock();
+
+        self.entities.is_empty()
+            && self.dead_letters.is_empty()
+            && state.consecutive_failures == 0
+            && state.failed_segments.is_empty()
+            // Running flush tasks hold a clone of the flush state
+            && Arc::strong_count(&self.flush_state) == 1
+    }
+
+    /// False while a failed flush is backing off
+    pub fn is_flush_due(&self) -> bool {
+        self.flush_state
+            .lock()
+            .retry_at
+            .is_none_or(|retry_at| retry_at <= Instant::now())
+    }
+
+    pub fn health(&self) -> QueueHealth {
+        let state = self.flush_state.lock();
+        let dead_letters = self.dead_letters.len();
+
+        let queued = self.entities.len();
+
+        // Failures are only retried while there is something left to save
+        let status = if state.consecutive_failures > 0 && queued > 0 {
+            QueueStatus::Retrying
+        } else if dead_letters > 0 {
+            QueueStatus::Degraded
+        } else {
+            QueueStatus::Healthy
+        };
+
+        QueueHealth {
+            status,
+            queued,
+            consecutive_failures: state.consecutive_failures,
+            last_error: state.last_error.clone(),
+            last_flushed_at: state.last_flushed_at,
+            last_failed_at: state.last_failed_at,
+            next_retry_in_ms: state.retry_at.map(|retry_at| {
+                retry_at
+                    .saturating_duration_since(Instant::now())
+                    .as_millis() as u64
+            }),
+            dead_letters,
+        }
+    }
+
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        self.dead_letters.list()
+    }
+
+    /// Queues all dead lettered entities again, returns the number of replayed entities.
+    /// Dead letters are removed only once their entities are queued
+    pub fn replay_dead_letters(&mut self) -> Result<usize> {
+        let letters = self.dead_letters.list();
+        let count = letters.len();
+
+        self.push_all(letters.into_iter().map(|letter| letter.entity).collect())?;
+        self.dead_letters
+            .remove_oldest(count)
+            .map_err(Error::EntityJournalFailed)?;
+
+        Ok(count)
+    }
+
     pub async fn flush(&mut self) -> Option<JoinHandle<()>> {
         if self.entities.is_empty() {
             return None;
         }
 
-        let map = std::mem::take(&mut self.entities);
-        let database = self.db.clone();
-
-        let save_task_handle = tokio::spawn(async move {
-            struct Accumulator {
-                save_entities: Vec<CompressedEntityModel>,
-                delete_entities: Vec<CompressedEntityModel>,
+        let mut segments = match self.journal.as_mut().map(EntityJournal::rotate) {
+            Some(Ok(segments)) => segments,
+            Some(Err(e)) => {
+                tracing::error!(error = %e, "Failed to rotate entity journal");
+                Vec::new()
             }
+            None => Vec::new(),
+        };
+        segments.append(&mut self.flush_state.lock().failed_segments);
 
-            tracing::info!("Flushing EntityQueue");
-            let transaction = match database.begin().await {
-                Ok(t) => t,
-                Err(e) => {
-                    tracing::error!("Failed to begin EntityQueue flush transaction: {}", e);
-                    return;
+        let keys = self
+            .entities
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+        let batch = keys
+            .into_iter()
+            .filter_map(|key| self.entities.remove(&key))
+            .collect::<Vec<_>>();
+
+        let task = FlushTask {
+            database: self.db.clone(),
+            codecs: self.codecs.clone(),
+            entities: self.entities.clone(),
+            retry_policy: self.retry_policy.clone(),
+            flush_state: self.flush_state.clone(),
+            dead_letters: self.dead_letters.clone(),
+        };
+
+        Some(tokio::spawn(task.run(batch, segments)))
+    }
+}
+
+struct FlushTask {
+    database: DatabaseConnection,
+    codecs: EntityCodecs,
+    entities: Arc<DashMap<GameIdAndUIdCombo, CompressedEntityModel>>,
+    retry_policy: RetryPolicy,
+    flush_state: Arc<FlushState>,
+    dead_letters: Arc<DeadLetterStore>,
+}
+
+impl FlushTask {
+    async fn run(
+        self,
+        batch: Vec<(GameIdAndUIdCombo, CompressedEntityModel)>,
+        segments: Vec<PathBuf>,
+    ) {
+        tracing::info!("Flushing EntityQueue");
+
+        match Self::save(&self.database, batch.clone()).await {
+            Ok(()) => {
+                for (key, _) in &batch {
+                    self.flush_state.attempts.remove(key);
                 }
-            };
 
-            let entity_manager = EntityManager::new();
+                let mut state = self.flush_state.lock();
+                state.consecutive_failures = 0;
+                state.last_error = None;
+                state.retry_at = None;
+                state.last_flushed_at = Some(Utc::now());
+                drop(state);
 
-            let entities = map.into_iter().collect::<Vec<_>>();
+                // Segments of failed flushes are kept and replayed on the next startup
+                EntityJournal::remove_segments(&segments);
+                tracing::info!("Flushed");
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to flush EntityQueue, entities are queued again");
+
+                let backoff = {
+                    let mut state = self.flush_state.lock();
+                    state.consecutive_failures += 1;
+                    state.last_error = Some(e.to_string());
+                    state.last_failed_at = Some(Utc::now());
+                    state.failed_segments.extend(segments);
+
+                    let backoff = self.retry_policy.backoff(state.consecutive_failures);
+                    state.retry_at = Some(Instant::now() + backoff);
+                    backoff
+                };
+                tracing::warn!("Retrying EntityQueue flush in {:?}", backoff);
+
+                self.requeue(batch, &e.to_string());
+            }
+        }
+    }
 
-            let valid_entities = match entity_manager
-                .filter_out_outdated_entities(&transaction, entities)
-                .await
-            {
-                Ok(e) => e,
-                Err(e) => {
-                    tracing::error!("Failed to filter out outdated entities: {}", e);
-                    return;
-                }
-            };
+    /// Merges a failed batch back keeping the newest state of every entity,
+    /// entities that ran out of attempts are dead lettered
+    fn requeue(&self, batch: Vec<(GameIdAndUIdCombo, CompressedEntityModel)>, error: &str) {
+        let mut dead_letters = Vec::new();
 
-            let init = Accumulator {
-                save_entities: Vec::new(),
-                delete_entities: Vec::new(),
+        for (key, entity) in batch {
+            let attempts = {
+                let mut attempts = self.flush_state.attempts.entry(key.clone()).or_insert(0);
+                *attempts += 1;
+                *attempts
             };
 
-            let accumulator = valid_entities.into_iter().fold(init, |mut acc, entity| {
-                if let Some(action) = entity.action.as_ref() {
-                    if **action == Action::Delete {
-                        acc.delete_entities.push(entity);
-                    } else {
-                        acc.save_entities.push(entity);
+            if attempts >= self.retry_policy.max_attempts {
+                self.flush_state.attempts.remove(&key);
+                match self.codecs.decompress(entity) {
+                    Ok(entity) => dead_letters.push(DeadLetter {
+                        entity,
+                        attempts,
+                        error: error.to_string(),
+                        failed_at: Utc::now(),
+                    }),
+                    Err(e) => {
+                        tracing::error!(error = %e, "Failed to decompress dead lettered entity {:?}", key);
                     }
                 }
-                acc
-            });
-
-            let save_entities = accumulator.save_entities;
-            let delete_entities = accumulator.delete_entities;
+                continue;
+            }
 
-            if let Err(e) = entity_manager
-                .save_valid_entities(&transaction, save_entities)
-                .await
-            {
-                tracing::error!("Failed to create/update entities: {}", e);
+            match self.entities.entry(key) {
+                Entry::Occupied(mut queued) => {
+                    if queued.get().timestamp < entity.timestamp {
+                        queued.insert(entity);
+                    }
+                }
+                Entry::Vacant(vacant) => {
+                    vacant.insert(entity);
+                }
             }
+        }
 
-            if let Err(e) = entity_manager
-                .delete_entities(&transaction, delete_entities)
-                .await
-            {
-                tracing::error!("Failed to delete entities: {}", e);
+        if !dead_letters.is_empty() {
+            tracing::error!("Moved {} entities to dead letters", dead_letters.len());
+            if let Err(e) = self.dead_letters.add(dead_letters) {
+                tracing::error!(error = %e, "Failed to persist dead letters");
             }
+        }
+    }
 
-            if let Err(e) = transaction.commit().await {
-                tracing::error!("Failed to commit EntityQueue flush transaction: {}", e);
+    async fn save(
+        database: &DatabaseConnection,
+        entities: Vec<(GameIdAndUIdCombo, CompressedEntityModel)>,
+    ) -> crate::models::error::Result<()> {
+        struct Accumulator {
+            save_entities: Vec<CompressedEntityModel>,
+            delete_entities: Vec<CompressedEntityModel>,
+        }
+
+        let init = Accumulator {
+            save_entities: Vec::new(),
+            delete_entities: Vec::new(),
+        };
+
+        let accumulator = entities.into_iter().fold(init, |mut acc, (_, entity)| {
+            match entity.action.as_deref() {
+                Some(Action::Delete) => acc.delete_entities.push(entity),
+                Some(Action::Other(_)) | None => (),
+                Some(_) => acc.save_entities.push(entity),
             }
-            tracing::info!("Flushed");
+            acc
         });
 
-        Some(save_task_handle)
+        // Outdated entities are skipped by the statements themselves
+        let transaction = database.begin().await?;
+        let entity_manager = EntityManager::new();
+
+        entity_manager
+            .save_entities(&transaction, accumulator.save_entities)
+            .await?;
+
+        entity_manager
+            .delete_entities(&transaction, accumulator.delete_entities)
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use sea_orm::DatabaseConnection;
+
+    use crate::{
+        api::websockets::Action,
+        entity::{Entity, UId, UtcTimestamp, kind::EntityKind},
+        models::entity::EntityManager,
+        utils::test_utils::get_app_state_with_temp_file_store,
+        webserver::{
+            router::app_state::AppStateTrait,
+            services::{
+                entity_dead_letters::DeadLetterStore,
+                entity_queue::{EntityQueue, GameIdAndUIdCombo, QueueStatus, RetryPolicy},
+            },
+        },
+    };
+
+    fn entity(uid: &str, timestamp: i64) -> Entity {
+        Entity {
+            uid: UId(uid.to_string()),
+            game: 1,
+            kind: EntityKind("token".to_string()),
+            timestamp: UtcTimestamp(timestamp),
+            action: Some(Arc::new(Action::Update)),
+            other_values: serde_json::json!({}),
+        }
+    }
+
+    #[test]
+    fn journaled_entities_are_queued_after_restart() {
+        let directory = std::env::temp_dir().join(format!("entity-queue-{}", uuid::Uuid::new_v4()));
+
+        {
+            let mut queue = EntityQueue::new(DatabaseConnection::default())
+                .with_journal(&directory)
+                .unwrap();
+            queue
+                .push_all(vec![entity("a", 2), entity("b", 1)])
+                .unwrap();
+            queue.push(entity("a", 1)).unwrap();
+        }
+
+        let queue = EntityQueue::new(DatabaseConnection::default())
+            .with_journal(&directory)
+            .unwrap();
+        assert!(queue.contains(&entity("a", 2)));
+        assert!(queue.contains(&entity("b", 1)));
+        assert_eq!(
+            2,
+            queue
+                .entities
+                .get(&GameIdAndUIdCombo::from_entity(&entity("a", 2)))
+                .unwrap()
+                .timestamp
+        );
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    fn queued_timestamp(queue: &EntityQueue, uid: &str) -> i64 {
+        queue
+            .entities
+            .get(&GameIdAndUIdCombo::from_entity(&entity(uid, 0)))
+            .unwrap()
+            .timestamp
+    }
+
+    #[tokio::test]
+    async fn failed_flush_is_queued_again() {
+        // Disconnected database fails every flush
+        let mut queue = EntityQueue::new(DatabaseConnection::default());
+        queue
+            .push_all(vec![entity("a", 2), entity("b", 1)])
+            .unwrap();
+
+        let handle = queue.flush().await.unwrap();
+        queue.push(entity("b", 3)).unwrap();
+        handle.await.unwrap();
+
+        assert_eq!(2, queued_timestamp(&queue, "a"));
+        assert_eq!(3, queued_timestamp(&queue, "b"));
+
+        let health = queue.health();
+        assert_eq!(QueueStatus::Retrying, health.status);
+        assert_eq!(1, health.consecutive_failures);
+        assert_eq!(2, health.queued);
+        assert!(health.last_error.is_some());
+        assert!(!queue.is_flush_due());
+    }
+
+    #[tokio::test]
+    async fn entities_are_dead_lettered_after_max_attempts() {
+        let mut queue =
+            EntityQueue::new(DatabaseConnection::default()).with_retry_policy(RetryPolicy {
+                max_attempts: 2,
+                base_backoff: Duration::ZERO,
+                max_backoff: Duration::ZERO,
+            });
+        queue.push(entity("a", 1)).unwrap();
+
+        queue.flush().await.unwrap().await.unwrap();
+        assert!(queue.is_flush_due());
+        queue.flush().await.unwrap().await.unwrap();
+
+        assert!(queue.entities.is_empty());
+        assert_eq!(1, queue.dead_letters().len());
+        assert_eq!(2, queue.dead_letters()[0].attempts);
+        assert_eq!(QueueStatus::Degraded, queue.health().status);
+
+        assert_eq!(1, queue.replay_dead_letters().unwrap());
+        assert!(queue.dead_letters().is_empty());
+        assert_eq!(1, queued_timestamp(&queue, "a"));
+    }
+
+    #[tokio::test]
+    async fn dead_letters_are_kept_when_replay_fails() {
+        let directory = std::env::temp_dir().join(format!("entity-queue-{}", uuid::Uuid::new_v4()));
+        let mut queue = EntityQueue::new(DatabaseConnection::default())
+            .with_retry_policy(RetryPolicy {
+                max_attempts: 1,
+                base_backoff: Duration::ZERO,
+                max_backoff: Duration::ZERO,
+            })
+            .with_journal(&directory)
+            .unwrap();
+        queue.push(entity("a", 1)).unwrap();
+        queue.flush().await.unwrap().await.unwrap();
+        assert_eq!(1, queue.dead_letters().len());
+
+        queue.journal.as_mut().unwrap().fail_next_append();
+        assert!(queue.replay_dead_letters().is_err());
+        assert!(queue.entities.is_empty());
+        assert_eq!(1, queue.dead_letters().len());
+        assert_eq!(
+            1,
+            DeadLetterStore::open(directory.join(EntityQueue::DEAD_LETTERS_FILE))
+                .unwrap()
+                .len()
+        );
+
+        assert_eq!(1, queue.replay_dead_letters().unwrap());
+        assert!(queue.dead_letters().is_empty());
+        assert_eq!(1, queued_timestamp(&queue, "a"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_flushes_keep_newest_entities() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+
+        // Two queues flushing the same entities, like two server nodes
+        let mut first = EntityQueue::new(db.clone());
+        let mut second = EntityQueue::new(db.clone());
+        for i in 0..50 {
+            let uid = format!("uid-{i}");
+            let (older, newer) = (i, i + 1);
+            if i % 2 == 0 {
+                first.push(entity(&uid, newer)).unwrap();
+                second.push(entity(&uid, older)).unwrap();
+            } else {
+                first.push(entity(&uid, older)).unwrap();
+                second.push(entity(&uid, newer)).unwrap();
+            }
+        }
+
+        let (first, second) = tokio::join!(first.flush(), second.flush());
+        first.unwrap().await.unwrap();
+        second.unwrap().await.unwrap();
+
+        let saved = EntityManager::new().load_entities(&db, 1).await.unwrap();
+        assert_eq!(50, saved.len());
+        for entity in saved {
+            let i = entity.uid["uid-".len()..].parse::<i64>().unwrap();
+            assert_eq!(i + 1, entity.timestamp, "{} was overwritten", entity.uid);
+        }
+    }
+
+    #[test]
+    fn retry_backoff_is_exponential() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_backoff: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(30),
+        };
+
+        assert_eq!(Duration::from_secs(5), policy.backoff(1));
+        assert_eq!(Duration::from_secs(10), policy.backoff(2));
+        assert_eq!(Duration::from_secs(20), policy.backoff(3));
+        assert_eq!(Duration::from_secs(30), policy.backoff(4));
     }
 }
@@ -0,0 +1,427 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use sea_orm::DatabaseConnection;
+use serde::Serialize;
+use tokio::{sync::Mutex, task::JoinHandle};
+
+use crate::{
+    entity::{
+        codec::EntityCodecs,
+        error::{Error, Result},
+    },
+    models::{entity::EntityManager, game::GameManager},
+    webserver::services::{
+        entity_dead_letters::DeadLetter,
+        entity_journal::EntityJournal,
+        entity_queue::{EntityQueue, QueueHealth, QueueStatus, RetryPolicy},
+    },
+};
+
+#[cfg(feature = "api_doc")]
+use utoipa::ToSchema;
+
+pub type EntityQueueShard = Arc<Mutex<EntityQueue>>;
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "api_doc", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct EntityQueuesHealth {
+    /// Worst status of all games
+    pub status: QueueStatus,
+    pub queued: usize,
+    pub dead_letters: usize,
+    pub games: BTreeMap<i32, QueueHealth>,
+}
+
+/// Entity queues sharded by game, every game has its own lock, journal and flushes
+/// so a busy game does not stall actions and joins of other games
+#[derive(Debug)]
+pub struct EntityQueues {
+    db: DatabaseConnection,
+    codecs: EntityCodecs,
+    journal_directory: Option<PathBuf>,
+    retry_policy: RetryPolicy,
+    tombstone_retention: Duration,
+    shards: DashMap<i32, EntityQueueShard>,
+}
+
+impl EntityQueues {
+    const SHARD_PREFIX: &str = "game-";
+    const DEFAULT_TOMBSTONE_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self {
+            db,
+            codecs: EntityCodecs::default(),
+            journal_directory: None,
+            retry_policy: RetryPolicy::default(),
+            tombstone_retention: Self::DEFAULT_TOMBSTONE_RETENTION,
+            shards: DashMap::new(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Compresses queued entities with `codecs`, set it before opening the journal
+    pub fn with_codecs(mut self, codecs: EntityCodecs) -> Self {
+        self.codecs = codecs;
+        self
+    }
+
+    pub fn with_tombstone_retention(mut self, tombstone_retention: Duration) -> Self {
+        self.tombstone_retention = tombstone_retention;
+        self
+    }
+
+    /// Journals every game in its own subdirectory of `journal_directory`,
+    /// games with entities left over by a previous run are opened right away
+    pub fn with_journal(mut self, journal_directory: impl AsRef<Path>) -> Result<Self> {
+        let journal_directory = journal_directory.as_ref().to_path_buf();
+        std::fs::create_dir_all(&journal_directory).map_err(Error::EntityJournalFailed)?;
+        self.journal_directory = Some(journal_directory.clone());
+
+        let entries = std::fs::read_dir(&journal_directory).map_err(Error::EntityJournalFailed)?;
+        for entry in entries {
+            let path = entry.map_err(Error::EntityJournalFailed)?.path();
+            let game_id = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(Self::SHARD_PREFIX))
+                .and_then(|id| id.parse::<i32>().ok());
+
+            if let Some(game_id) = game_id
+                && path.is_dir()
+            {
+                self.shards.insert(game_id, self.create_shard(game_id)?);
+            }
+        }
+
+        // Segments journaled before the queue was sharded by game
+        let (entities, segments) =
+            EntityJournal::read_segments(&journal_directory).map_err(Error::EntityJournalFailed)?;
+        for entity in entities {
+            self.get_or_create(entity.game)?
+                .try_lock()
+                .expect("Shard is not shared yet")
+                .push(entity)?;
+        }
+        EntityJournal::remove_segments(&segments);
+
+        Ok(self)
+    }
+
+    /// Returns the queue of the game, creating it if the game exists
+    pub async fn get(&self, game_id: i32) -> Result<EntityQueueShard> {
+        if let Some(shard) = self.shards.get(&game_id) {
+            return Ok(shard.clone());
+        }
+
+        let exists = GameManager::new()
+            .exists(&self.db, game_id)
+            .await
+            .map_err(Error::GameLookupFailed)?;
+        if !exists {
+            return Err(Error::GameNotFound(game_id));
+        }
+
+        self.get_or_create(game_id)
+    }
+
+    fn get_or_create(&self, game_id: i32) -> Result<EntityQueueShard> {
+        if let Some(shard) = self.shards.get(&game_id) {
+            return Ok(shard.clone());
+        }
+
+        let shard = self.create_shard(game_id)?;
+
+        Ok(self.shards.entry(game_id).or_insert(shard).clone())
+    }
+
+    /// Drops queues of games with nothing left to save, they are created again
+    /// with the next action of the game
+    pub fn drop_idle_shards(&self) {
+        self.shards.retain(|_, shard| {
+            // Shards that are shared or locked are in use
+            Arc::strong_count(shard) > 1 || !shard.try_lock().is_ok_and(|queue| queue.is_idle())
+        });
+    }
+
+    pub fn shards(&self) -> Vec<(i32, EntityQueueShard)> {
+        self.shards
+            .iter()
+            .map(|shard| (*shard.key(), shard.value().clone()))
+            .collect()
+    }
+
+    /// Flushes pending entities of a single game and waits until they are saved
+    pub async fn flush_game(&self, game_id: i32) {
+        let Some(shard) = self.shards.get(&game_id).map(|shard| shard.clone()) else {
+            return;
+        };
+
+        Self::flush_shard(game_id, &shard).await;
+    }
+
+    /// Flushes every game and waits until all of them are saved
+    pub async fn flush_all(&self) {
+        let shards = self.shards();
+        futures_util::future::join_all(
+            shards
+                .iter()
+                .map(|(game_id, shard)| Self::flush_shard(*game_id, shard)),
+        )
+        .await;
+    }
+
+    /// Flushes every game that is not backing off in its own task,
+    /// games that are already being flushed are skipped
+    pub fn flush_due(&self) -> Vec<JoinHandle<()>> {
+        self.shards()
+            .into_iter()
+            .map(|(game_id, shard)| {
+                tokio::spawn(async move {
+                    let Ok(mut lock) = shard.try_lock() else {
+                        return;
+                    };
+
+                    if lock.is_flush_due() {
+                        Self::wait_for_flush(game_id, &mut lock).await;
+                    }
+                })
+            })
+            .collect()
+    }
+
+    pub async fn health(&self) -> EntityQueuesHealth {
+        let mut games = BTreeMap::new();
+        for (game_id, shard) in self.shards() {
+            games.insert(game_id, shard.lock().await.health());
+        }
+
+        EntityQueuesHealth {
+            status: games
+                .values()
+                .map(|health| health.status)
+                .max()
+                .unwrap_or(QueueStatus::Healthy),
+            queued: games.values().map(|health| health.queued).sum(),
+            dead_letters: games.values().map(|health| health.dead_letters).sum(),
+            games,
+        }
+    }
+
+    pub async fn dead_letters(&self) -> Vec<DeadLetter> {
+        let mut dead_letters = Vec::new();
+        for (_, shard) in self.shards() {
+            dead_letters.extend(shard.lock().await.dead_letters());
+        }
+
+        dead_letters
+    }
+
+    /// Queues dead letters of every game again, returns the number of replayed entities
+    pub async fn replay_dead_letters(&self) -> Result<usize> {
+        let mut replayed = 0;
+        for (_, shard) in self.shards() {
+            replayed += shard.lock().await.replay_dead_letters()?;
+        }
+
+        Ok(replayed)
+    }
+
+    /// Purges tombstones of entities deleted longer than the retention ago,
+    /// queued entities older than a purged tombstone would be saved again
+    pub async fn purge_tombstones(&self) -> crate::models::error::Result<u64> {
+        let retention =
+            chrono::Duration::from_std(self.tombstone_retention).unwrap_or(chrono::Duration::MAX);
+        let deleted_before = Utc::now()
+            .checked_sub_signed(retention)
+            .unwrap_or(DateTime::<Utc>::MIN_UTC);
+
+        EntityManager::new()
+            .purge_tombstones(&self.db, deleted_before)
+            .await
+    }
+
+    async fn flush_shard(game_id: i32, shard: &EntityQueueShard) {
+        let mut lock = shard.lock().await;
+        Self::wait_for_flush(game_id, &mut lock).await;
+    }
+
+    /// Lock is held until the entities are saved
+    async fn wait_for_flush(game_id: i32, queue: &mut EntityQueue) {
+        if let Some(handle) = queue.flush().await
+            && let Err(e) = handle.await
+        {
+            tracing::error!(error = %e, "EntityQueue flush of game {game_id} failed");
+        }
+    }
+
+    fn create_shard(&self, game_id: i32) -> Result<EntityQueueShard> {
+        let queue = EntityQueue::new(self.db.clone())
+            .with_codecs(self.codecs.clone())
+            .with_retry_policy(self.retry_policy.clone());
+        let queue = match self.journal_directory.as_ref() {
+            Some(directory) => {
+                queue.with_journal(directory.join(format!("{}{game_id}", Self::SHARD_PREFIX)))?
+            }
+            None => queue,
+        };
+
+        Ok(Arc::new(Mutex::new(queue)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use sea_orm::DatabaseConnection;
+
+    use crate::{
+        api::websockets::Action,
+        entity::{Entity, UId, UtcTimestamp, error::Error, kind::EntityKind},
+        models::game::GameManager,
+        utils::test_utils::get_app_state_with_temp_file_store,
+        webserver::{
+            router::app_state::AppStateTrait,
+            services::{
+                entity_journal::EntityJournal, entity_queue::QueueStatus,
+                entity_queues::EntityQueues,
+            },
+        },
+    };
+
+    fn entity(game: i32, uid: &str) -> Entity {
+        Entity {
+            uid: UId(uid.to_string()),
+            game,
+            kind: EntityKind("token".to_string()),
+            timestamp: UtcTimestamp(1),
+            action: Some(Arc::new(Action::Update)),
+            other_values: serde_json::json!({}),
+        }
+    }
+
+    #[tokio::test]
+    async fn busy_game_does_not_block_other_games() {
+        let state = get_app_state_with_temp_file_store().await;
+        let game_manager = GameManager::new();
+        let busy_game = game_manager.create_game(&state.get_db()).await.unwrap();
+        let other_game = game_manager.create_game(&state.get_db()).await.unwrap();
+        let queues = EntityQueues::new(state.get_db());
+
+        let busy = queues.get(busy_game.id).await.unwrap();
+        let _busy_lock = busy.lock().await;
+
+        let other = queues.get(other_game.id).await.unwrap();
+        other.lock().await.push(entity(other_game.id, "a")).unwrap();
+
+        tokio::time::timeout(Duration::from_secs(1), queues.flush_game(other_game.id))
+            .await
+            .expect("Flush of another game was blocked");
+    }
+
+    #[tokio::test]
+    async fn queues_are_only_created_for_existing_games() {
+        let state = get_app_state_with_temp_file_store().await;
+        let queues = EntityQueues::new(state.get_db());
+
+        assert!(matches!(
+            queues.get(404).await,
+            Err(Error::GameNotFound(404))
+        ));
+        assert!(queues.shards().is_empty());
+    }
+
+    #[tokio::test]
+    async fn idle_shards_are_dropped_after_flush() {
+        let state = get_app_state_with_temp_file_store().await;
+        let game = GameManager::new()
+            .create_game(&state.get_db())
+            .await
+            .unwrap();
+        let queues = EntityQueues::new(state.get_db());
+
+        let shard = queues.get(game.id).await.unwrap();
+        shard.lock().await.push(entity(game.id, "a")).unwrap();
+
+        // Shared and queued shards are kept
+        queues.drop_idle_shards();
+        assert_eq!(1, queues.shards().len());
+        drop(shard);
+        queues.drop_idle_shards();
+        assert_eq!(1, queues.shards().len());
+
+        futures_util::future::join_all(queues.flush_due()).await;
+        queues.drop_idle_shards();
+        assert!(queues.shards().is_empty());
+    }
+
+    #[tokio::test]
+    async fn shards_are_reopened_from_journal() {
+        let directory =
+            std::env::temp_dir().join(format!("entity-queues-{}", uuid::Uuid::new_v4()));
+
+        {
+            let queues = EntityQueues::new(DatabaseConnection::default())
+                .with_journal(&directory)
+                .unwrap();
+            queues
+                .get_or_create(7)
+                .unwrap()
+                .lock()
+                .await
+                .push(entity(7, "a"))
+                .unwrap();
+        }
+
+        let queues = EntityQueues::new(DatabaseConnection::default())
+            .with_journal(&directory)
+            .unwrap();
+        let health = queues.health().await;
+        assert_eq!(QueueStatus::Healthy, health.status);
+        assert_eq!(1, health.queued);
+        assert_eq!(1, health.games[&7].queued);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unsharded_journal_is_moved_to_games() {
+        let directory =
+            std::env::temp_dir().join(format!("entity-queues-{}", uuid::Uuid::new_v4()));
+
+        {
+            let (mut journal, _) = EntityJournal::open(&directory).unwrap();
+            journal
+                .append(&[entity(1, "a"), entity(2, "b"), entity(2, "c")])
+                .unwrap();
+        }
+
+        let queues = EntityQueues::new(DatabaseConnection::default())
+            .with_journal(&directory)
+            .unwrap();
+        let health = queues.health().await;
+        assert_eq!(1, health.games[&1].queued);
+        assert_eq!(2, health.games[&2].queued);
+        assert!(
+            EntityJournal::read_segments(&directory)
+                .unwrap()
+                .0
+                .is_empty()
+        );
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,268 @@
+use std::sync::Mutex;
+
+use sea_orm::DatabaseConnection;
+
+use crate::{
+    entity::codec::{EntityCodec, EntityCodecs},
+    models::{
+        entity::{CompressedEntityModel, EntityManager},
+        entity_dictionary::EntityDictionaryManager,
+        error::Result,
+    },
+};
+
+/// Trains zstd dictionaries for entity kinds and recompresses payloads that are not
+/// compressed with the default codec and the newest dictionary of their kind, one
+/// batch per run so joins are not slowed down. With the default gzip codec
+/// existing payloads are left as they are, only opting into zstd rewrites them.
+///
+/// Entities of kinds that got indexed fields after they were saved are given their
+/// document the same way.
+#[derive(Debug)]
+pub struct EntityRecompressor {
+    db: DatabaseConnection,
+    codecs: EntityCodecs,
+    batch_size: u64,
+    /// Last recompressed game and uid, the next batch continues after it
+    cursor: Mutex<Option<(i32, String)>>,
+}
+
+impl EntityRecompressor {
+    const DEFAULT_BATCH_SIZE: u64 = 500;
+    /// Entities of a kind needed before a dictionary is trained
+    const MIN_DICTIONARY_SAMPLES: u64 = 1000;
+    const MAX_DICTIONARY_SAMPLES: u64 = 5000;
+    const DICTIONARY_SIZE: usize = 16 * 1024;
+
+    pub fn new(db: DatabaseConnection, codecs: EntityCodecs) -> Self {
+        Self {
+            db,
+            codecs,
+            batch_size: Self::DEFAULT_BATCH_SIZE,
+            cursor: Mutex::new(None),
+        }
+    }
+
+    pub fn with_batch_size(mut self, batch_size: u64) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Trains missing dictionaries and recompresses one batch,
+    /// returns the number of recompressed entities
+    pub async fn run(&self) -> Result<u64> {
+        if let Err(e) = self.codecs.load_dictionaries(&self.db).await {
+            tracing::error!(error = %e, "Failed to load entity dictionaries");
+        }
+
+        if self.codecs.default_codec() == EntityCodec::Zstd {
+            self.train_dictionaries().await?;
+        }
+
+        self.recompress_batch().await
+    }
+
+    async fn train_dictionaries(&self) -> Result<()> {
+        let entity_manager = EntityManager::new();
+
+        for kind in entity_manager.list_kinds(&self.db).await? {
+            if self.codecs.dictionary_of_kind(&kind).is_some() {
+                continue;
+            }
+
+            let entities = entity_manager
+                .load_entities_of_kind(&self.db, &kind, Self::MAX_DICTIONARY_SAMPLES)
+                .await?;
+            if (entities.len() as u64) < Self::MIN_DICTIONARY_SAMPLES {
+                continue;
+            }
+
+            let Some(dictionary) = self.train_dictionary(&kind, entities).await else {
+                continue;
+            };
+
+            let dictionary = EntityDictionaryManager::new()
+                .save_dictionary(&self.db, kind.clone(), dictionary)
+                .await?;
+            self.codecs.add_dictionary(&dictionary);
+            tracing::info!(
+                "Trained entity dictionary {} for kind {kind}",
+                dictionary.id
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn train_dictionary(
+        &self,
+        kind: &str,
+        entities: Vec<CompressedEntityModel>,
+    ) -> Option<Vec<u8>> {
+        let codecs = self.codecs.clone();
+        let trained = tokio::task::spawn_blocking(move || {
+            let samples = entities
+                .iter()
+                .filter_map(|entity| {
+                    codecs
+                        .decode(&entity.data, entity.codec, entity.dictionary)
+                        .ok()
+                })
+                .collect::<Vec<_>>();
+
+            zstd::dict::from_samples(&samples, Self::DICTIONARY_SIZE)
+        })
+        .await;
+
+        match trained {
+            Ok(Ok(dictionary)) => Some(dictionary),
+            Ok(Err(e)) => {
+                tracing::warn!(error = %e, "Failed to train entity dictionary for kind {kind}");
+                None
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Entity dictionary training panicked");
+                None
+            }
+        }
+    }
+
+    async fn recompress_batch(&self) -> Result<u64> {
+        let entity_manager = EntityManager::new();
+        let codec = self.codecs.default_codec();
+
+        let kind_dictionaries = self.codecs.kind_dictionaries();
+        let document_kinds = self.codecs.fields().document_kinds();
+        let cursor = self.cursor.lock().expect("Mutex is poisoned").clone();
+        let batch = entity_manager
+            .load_entities_to_recompress(
+                &self.db,
+                codec,
+                &kind_dictionaries,
+                &document_kinds,
+                cursor.as_ref().map(|(game, uid)| (*game, uid.as_str())),
+                self.batch_size,
+            )
+            .await?;
+
+        // Start over once the end is reached, rows that failed are retried then
+        let next_cursor = if (batch.len() as u64) < self.batch_size {
+            None
+        } else {
+            batch.last().map(|entity| (entity.game, entity.uid.clone()))
+        };
+
+        let mut recompressed = 0;
+        for entity in batch {
+            let replacement = match self
+                .codecs
+                .decompress(entity.clone())
+                .and_then(|decompressed| self.codecs.compress_with(decompressed, codec))
+            {
+                Ok(r) => r,
+                Err(e) => {
+                    tracing::warn!(error = %e, "Failed to recompress entity {} of game {}", entity.uid, entity.game);
+                    continue;
+                }
+            };
+
+            // Entities saved in the meantime are already compressed with the default codec
+            if entity_manager
+                .replace_payload(&self.db, &entity, replacement)
+                .await?
+            {
+                recompressed += 1;
+            }
+        }
+
+        *self.cursor.lock().expect("Mutex is poisoned") = next_cursor;
+
+        Ok(recompressed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        api::websockets::Action,
+        entity::{
+            Entity, UId, UtcTimestamp,
+            codec::{EntityCodec, EntityCodecs},
+            kind::EntityKind,
+        },
+        models::entity::EntityManager,
+        utils::test_utils::get_app_state_with_temp_file_store,
+        webserver::{
+            router::app_state::AppStateTrait, services::entity_recompressor::EntityRecompressor,
+        },
+    };
+
+    fn entity(i: usize) -> Entity {
+        Entity {
+            uid: UId(format!("uid-{i:04}")),
+            game: 1 + (i % 2) as i32,
+            kind: EntityKind("token".to_string()),
+            timestamp: UtcTimestamp(1),
+            action: Some(Arc::new(Action::Create)),
+            other_values: serde_json::json!({ "x": i, "y": i * 2, "name": format!("Goblin {i}") }),
+        }
+    }
+
+    #[tokio::test]
+    async fn old_payloads_are_recompressed() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+        let entity_manager = EntityManager::new();
+
+        let gzip = EntityCodecs::new(EntityCodec::Gzip);
+        let entities = (0..1200)
+            .map(|i| gzip.compress(entity(i)).unwrap())
+            .collect::<Vec<_>>();
+        for chunk in entities.chunks(100) {
+            entity_manager
+                .save_entities(&db, chunk.to_vec())
+                .await
+                .unwrap();
+        }
+
+        let codecs = EntityCodecs::new(EntityCodec::Zstd);
+        let recompressor = EntityRecompressor::new(db.clone(), codecs.clone()).with_batch_size(400);
+        let mut recompressed = 0;
+        for _ in 0..4 {
+            recompressed += recompressor.run().await.unwrap();
+        }
+        assert_eq!(1200, recompressed);
+        assert!(codecs.dictionary_of_kind("token").is_some());
+
+        let saved = entity_manager.load_entities(&db, 1).await.unwrap();
+        assert!(saved.iter().all(|e| e.codec == EntityCodec::Zstd));
+        assert!(saved.iter().all(|e| e.dictionary.is_some()));
+
+        let decompressed = codecs.decompress_vec(saved).unwrap();
+        let first = decompressed.iter().find(|e| e.uid.0 == "uid-0000").unwrap();
+        assert_eq!(entity(0).other_values, first.other_values);
+
+        assert_eq!(0, recompressor.run().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn gzip_payloads_are_kept_by_default() {
+        let state = get_app_state_with_temp_file_store().await;
+        let db = state.get_db();
+        let entity_manager = EntityManager::new();
+
+        let codecs = state.get_entity_codecs();
+        assert_eq!(EntityCodec::Gzip, codecs.default_codec());
+
+        let entities = (0..10)
+            .map(|i| codecs.compress(entity(i)).unwrap())
+            .collect::<Vec<_>>();
+        entity_manager.save_entities(&db, entities).await.unwrap();
+
+        let recompressor = EntityRecompressor::new(db.clone(), codecs.clone());
+        assert_eq!(0, recompressor.run().await.unwrap());
+        assert!(codecs.dictionary_of_kind("token").is_none());
+    }
+}
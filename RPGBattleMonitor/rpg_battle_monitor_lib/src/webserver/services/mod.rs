@@ -1,3 +1,12 @@
+pub mod asset_gc;
+pub mod broadcast;
+pub mod entity_dead_letters;
+pub mod entity_journal;
+pub mod entity_locks;
 pub mod entity_queue;
+pub mod entity_queues;
+pub mod entity_recompressor;
 pub mod scheduler;
+pub mod shutdown;
+pub mod transitive_buffer;
 pub mod websocket_auth;
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShutdownState {
+    Running,
+    /// New actions are refused while queued entities are being saved
+    ShuttingDown,
+    /// Queued entities are saved, remaining connections are closed
+    Finished,
+}
+
+/// Coordinates a graceful shutdown of the server, embedders can request a shutdown
+/// and wait until every queued entity is saved
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    state: Arc<watch::Sender<ShutdownState>>,
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        let (state, _) = watch::channel(ShutdownState::Running);
+
+        Self {
+            state: Arc::new(state),
+        }
+    }
+
+    pub fn state(&self) -> ShutdownState {
+        *self.state.borrow()
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.state() != ShutdownState::Running
+    }
+
+    /// Requests a shutdown, does nothing if one is already in progress
+    pub fn shutdown(&self) {
+        self.advance(ShutdownState::ShuttingDown);
+    }
+
+    pub(crate) fn finish(&self) {
+        self.advance(ShutdownState::Finished);
+    }
+
+    /// Resolves once a shutdown is requested
+    pub async fn requested(&self) {
+        self.wait_for(ShutdownState::ShuttingDown).await;
+    }
+
+    /// Resolves once the shutdown has finished
+    pub async fn finished(&self) {
+        self.wait_for(ShutdownState::Finished).await;
+    }
+
+    fn advance(&self, next: ShutdownState) {
+        self.state.send_if_modified(|state| {
+            if *state < next {
+                *state = next;
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    async fn wait_for(&self, state: ShutdownState) {
+        let mut receiver = self.state.subscribe();
+        // Sender lives in self so the channel can not be closed
+        receiver.wait_for(|current| *current >= state).await.ok();
+    }
+}
+
+/// Resolves on SIGINT or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!(error = %e, "Failed to listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received SIGINT"),
+        _ = terminate => tracing::info!("Received SIGTERM"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::webserver::services::shutdown::{ShutdownHandle, ShutdownState};
+
+    #[tokio::test]
+    async fn shutdown_advances_in_order() {
+        let handle = ShutdownHandle::new();
+        assert!(!handle.is_shutting_down());
+
+        let waiter = handle.clone();
+        let finished = tokio::spawn(async move { waiter.finished().await });
+
+        handle.shutdown();
+        handle.requested().await;
+        assert_eq!(ShutdownState::ShuttingDown, handle.state());
+        assert!(!finished.is_finished());
+
+        handle.finish();
+        handle.shutdown();
+        assert_eq!(ShutdownState::Finished, handle.state());
+
+        tokio::time::timeout(Duration::from_secs(1), finished)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
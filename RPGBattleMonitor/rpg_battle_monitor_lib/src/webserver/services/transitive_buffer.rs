@@ -0,0 +1,174 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::MutexGuard;
+
+use crate::entity::{ClientsideEntity, UId};
+
+/// Coalesces transitive entity states (drag previews, movement lines...) of a single socket,
+/// only the latest state of every entity is kept until the buffer is flushed
+#[derive(Debug)]
+pub struct TransitiveBuffer {
+    room: String,
+    interval: Duration,
+    inner: Mutex<InnerTransitiveBuffer>,
+    /// Held while taken frames are forwarded, so frames can not be
+    /// discarded in the middle of a flush
+    flushing: tokio::sync::Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct InnerTransitiveBuffer {
+    frames: HashMap<UId, ClientsideEntity>,
+    flush_scheduled: bool,
+    last_flush: Option<Instant>,
+    closed: bool,
+}
+
+impl TransitiveBuffer {
+    pub fn new(room: String, interval: Duration) -> Self {
+        Self {
+            room,
+            interval,
+            inner: Mutex::new(InnerTransitiveBuffer::default()),
+            flushing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn room(&self) -> &str {
+        &self.room
+    }
+
+    /// Buffers entities replacing any superseded state of the same entity.
+    /// Returns the delay after which the buffer has to be flushed when
+    /// no flush is scheduled yet
+    pub fn push(&self, entities: Vec<ClientsideEntity>) -> Option<Duration> {
+        let mut inner = self.inner.lock().expect("Mutex is poisoned");
+        if inner.closed {
+            return None;
+        }
+
+        for entity in entities {
+            inner.frames.insert(entity.uid.clone(), entity);
+        }
+
+        if inner.flush_scheduled || inner.frames.is_empty() {
+            return None;
+        }
+        inner.flush_scheduled = true;
+
+        let delay = match inner.last_flush {
+            Some(last_flush) => self.interval.saturating_sub(last_flush.elapsed()),
+            None => Duration::ZERO,
+        };
+
+        Some(delay)
+    }
+
+    /// Takes all buffered entities and allows a new flush to be scheduled
+    pub fn take(&self) -> Vec<ClientsideEntity> {
+        let mut inner = self.inner.lock().expect("Mutex is poisoned");
+
+        inner.flush_scheduled = false;
+        inner.last_flush = Some(Instant::now());
+
+        std::mem::take(&mut inner.frames).into_values().collect()
+    }
+
+    /// Waits for a running flush, the flush has to take and forward
+    /// the frames while holding the returned guard
+    pub async fn start_flush(&self) -> MutexGuard<'_, ()> {
+        self.flushing.lock().await
+    }
+
+    /// Drops buffered frames of entities that were persisted, so an older
+    /// transitive state is never forwarded after the persisted one
+    pub async fn discard<'a>(&self, uids: impl IntoIterator<Item = &'a UId>) {
+        let _flushing = self.flushing.lock().await;
+        let mut inner = self.inner.lock().expect("Mutex is poisoned");
+
+        for uid in uids {
+            inner.frames.remove(uid);
+        }
+    }
+
+    /// Drops every buffered frame and refuses new ones, a scheduled flush
+    /// will have nothing to forward once this returns
+    pub async fn close(&self) {
+        let _flushing = self.flushing.lock().await;
+        let mut inner = self.inner.lock().expect("Mutex is poisoned");
+
+        inner.closed = true;
+        inner.frames.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{
+        entity::{ClientsideEntity, UId, UtcTimestamp, kind::EntityKind},
+        webserver::services::transitive_buffer::TransitiveBuffer,
+    };
+
+    fn frame(uid: &str, timestamp: i64) -> ClientsideEntity {
+        ClientsideEntity {
+            uid: UId(uid.to_string()),
+            kind: EntityKind("token".to_string()),
+            timestamp: UtcTimestamp(timestamp),
+            other_values: serde_json::json!({}),
+        }
+    }
+
+    #[test]
+    fn superseded_frames_are_dropped() {
+        let buffer = TransitiveBuffer::new("room-1".to_string(), Duration::from_millis(50));
+
+        assert_eq!(Some(Duration::ZERO), buffer.push(vec![frame("a", 1)]));
+        assert_eq!(None, buffer.push(vec![frame("a", 2), frame("b", 1)]));
+        assert_eq!(None, buffer.push(vec![frame("a", 3)]));
+
+        let mut frames = buffer.take();
+        frames.sort_by(|a, b| a.uid.0.cmp(&b.uid.0));
+
+        assert_eq!(2, frames.len());
+        assert_eq!(3, frames[0].timestamp.0);
+        assert_eq!(1, frames[1].timestamp.0);
+        assert!(buffer.take().is_empty());
+    }
+
+    #[test]
+    fn flush_is_delayed_by_interval() {
+        let interval = Duration::from_secs(60);
+        let buffer = TransitiveBuffer::new("room-1".to_string(), interval);
+
+        buffer.push(vec![frame("a", 1)]);
+        buffer.take();
+
+        let delay = buffer.push(vec![frame("a", 2)]).unwrap();
+        assert!(delay > Duration::ZERO && delay <= interval);
+    }
+
+    #[tokio::test]
+    async fn persisted_and_closed_frames_are_not_flushed() {
+        let buffer = TransitiveBuffer::new("room-1".to_string(), Duration::from_millis(50));
+
+        buffer.push(vec![frame("a", 1), frame("b", 1)]);
+        buffer.discard([&UId("a".to_string())]).await;
+
+        let frames = buffer.take();
+        assert_eq!(1, frames.len());
+        assert_eq!("b", frames[0].uid.0);
+
+        buffer.push(vec![frame("b", 2)]);
+        buffer.close().await;
+
+        assert!(buffer.take().is_empty());
+        assert_eq!(None, buffer.push(vec![frame("b", 3)]));
+        assert!(buffer.take().is_empty());
+    }
+}
@@ -3,6 +3,7 @@ use futures_util::{FutureExt, future::BoxFuture};
 use serde::Deserialize;
 use thiserror::Error;
 use tower::{Layer, Service};
+use ts_rs::TS;
 
 use crate::webserver::router::app_state::AppStateTrait;
 
@@ -16,6 +17,9 @@ pub enum Error {
 
     #[error("AuthenticationFailed")]
     AuthenticationFailed,
+
+    #[error("Incompatible protocol version {client}, server uses protocol version {server}")]
+    IncompatibleProtocolVersion { client: u32, server: u32 },
 }
 
 impl Error {
@@ -27,6 +31,7 @@ impl Error {
         let status_code = match self {
             Error::AxumError(_) | Error::SerdeJsonError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Error::AuthenticationFailed => StatusCode::UNAUTHORIZED,
+            Error::IncompatibleProtocolVersion { .. } => StatusCode::BAD_REQUEST,
         };
 
         axum::response::Response::builder()
@@ -36,11 +41,35 @@ impl Error {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq, TS)]
+#[serde(rename_all = "kebab-case")]
+#[ts(export)]
+pub enum ConnectionRole {
+    #[default]
+    Player,
+    /// Read-only connection used for streams and projector displays
+    Spectator,
+}
+
+impl ConnectionRole {
+    pub fn can_write(&self) -> bool {
+        matches!(self, ConnectionRole::Player)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
+#[ts(export)]
 pub struct WebsocketAuthMessage {
     pub user_token: String,
     pub game: i32,
+    #[serde(default)]
+    #[ts(as = "Option<ConnectionRole>", optional)]
+    pub role: ConnectionRole,
+    /// Version of the socket protocol the client was built against
+    #[serde(default)]
+    #[ts(optional)]
+    pub protocol_version: Option<u32>,
 }
 
 impl WebsocketAuthMessage {